@@ -0,0 +1,358 @@
+//! comfy-fs as a library: [`ComfyFs`] connects with a [`Config`] and lists,
+//! copies, and syncs, so other Rust programs needn't run the binary
+
+use crate::client::{set_local_modified, RemoteFile};
+use crate::config::Config;
+use crate::connection::{ClientPool, ConnectionManager};
+use crate::retry::RetryPolicy;
+use crate::sync::{
+    refine_times, walk_local, SyncDirection, SyncOptions, SyncPlan, DEFAULT_TIME_TOLERANCE_SECS,
+};
+use crate::transfer::{
+    event_channel, ParallelTransfers, TransferEvent, TransferEvents, TransferJob,
+};
+use crate::walk::{WalkEntry, Walker};
+use anyhow::{bail, Context, Result};
+use futures::Stream;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{self, Poll};
+use tokio::task::JoinHandle;
+
+/// A connection to a file server and what can be done with it
+pub struct ComfyFs {
+    pool: ClientPool,
+    retry_policy: RetryPolicy,
+    validate_safetensors: bool,
+}
+
+impl ComfyFs {
+    /// Connect to the server `config` names, trying its other protocols if it
+    /// allows falling back. Transfers run up to its `max_concurrent_transfers`
+    /// at once, each on its own connection.
+    pub async fn connect(config: Config) -> Result<Self> {
+        let max_clients = config.concurrency()?;
+        let pool = ConnectionManager::new(config)
+            .connect_pool(max_clients)
+            .await?;
+        Ok(Self::new(pool))
+    }
+
+    /// Use clients that are already connected
+    pub fn new(clients: impl Into<ClientPool>) -> Self {
+        Self {
+            pool: clients.into(),
+            retry_policy: RetryPolicy::default(),
+            validate_safetensors: false,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Check the header of each `.safetensors` file after it's copied
+    #[must_use]
+    pub const fn with_safetensors_validation(mut self, validate: bool) -> Self {
+        self.validate_safetensors = validate;
+        self
+    }
+
+    /// The clients, for anything this type doesn't do
+    pub const fn pool(&self) -> &ClientPool {
+        &self.pool
+    }
+
+    /// What's in the directory at `path`
+    pub async fn list(&self, path: &str) -> Result<Vec<RemoteFile>> {
+        self.pool.primary().lock().await.list_files(path).await
+    }
+
+    /// The file or directory at `path`
+    pub async fn stat(&self, path: &str) -> Result<RemoteFile> {
+        self.pool.primary().lock().await.stat(path).await
+    }
+
+    /// Everything under `path`, at most `max_depth` directories down
+    pub async fn walk(&self, path: &str, max_depth: Option<usize>) -> Result<Vec<WalkEntry>> {
+        Walker::new(self.pool.primary(), 4)
+            .with_max_depth(max_depth)
+            .walk(path)
+            .await
+    }
+
+    /// Start downloading the file at `remote_path` to `local_path`
+    pub async fn download(
+        &self,
+        remote_path: &str,
+        local_path: impl Into<PathBuf>,
+    ) -> Result<Transfer> {
+        let file = self.stat(remote_path).await?;
+        if file.is_dir {
+            bail!("{} is a directory", remote_path);
+        }
+        let job = TransferJob::download(remote_path.to_string(), local_path.into(), file.size);
+        Ok(self.transfer(vec![job]))
+    }
+
+    /// Start uploading the file at `local_path` to `remote_path`
+    pub async fn upload(
+        &self,
+        local_path: impl Into<PathBuf>,
+        remote_path: &str,
+    ) -> Result<Transfer> {
+        let local_path = local_path.into();
+        let metadata = tokio::fs::metadata(&local_path)
+            .await
+            .with_context(|| format!("Could not read {:?}", local_path))?;
+        if metadata.is_dir() {
+            bail!("{:?} is a directory", local_path);
+        }
+        let job = TransferJob::upload(local_path, remote_path.to_string(), metadata.len());
+        Ok(self.transfer(vec![job]))
+    }
+
+    /// Start running `jobs`, as many at once as the pool has clients for
+    pub fn transfer(&self, jobs: Vec<TransferJob>) -> Transfer {
+        let (sender, events) = event_channel();
+        let transfers = ParallelTransfers::new(self.pool.clone(), self.pool.max_clients())
+            .with_retry_policy(self.retry_policy)
+            .with_safetensors_validation(self.validate_safetensors)
+            .with_events(sender);
+        let task = tokio::spawn(async move {
+            let results = transfers.run(&jobs).await;
+            TransferReport { jobs, results }
+        });
+        Transfer { events, task }
+    }
+
+    /// Copy what's missing or changed between `local` and `remote` the ways
+    /// `direction` allows, like `comfy-fs sync` without `--delete`. Files
+    /// changed on both sides go the way of the newer copy. Nothing is deleted
+    /// and no sync state is kept, so files moved since the last sync are
+    /// copied again rather than moved.
+    pub async fn sync(
+        &self,
+        local: &Path,
+        remote: &str,
+        direction: SyncDirection,
+    ) -> Result<SyncReport> {
+        let local_entries = if !local.exists() && direction.pulls() {
+            Vec::new()
+        } else {
+            walk_local(local, None)?
+        };
+        let mut remote_entries = self.walk(remote, None).await?;
+        let options = SyncOptions::new(direction);
+        refine_times(
+            &self.pool.primary(),
+            &local_entries,
+            remote,
+            &mut remote_entries,
+            DEFAULT_TIME_TOLERANCE_SECS,
+        )
+        .await;
+        let plan = SyncPlan::new(local, &local_entries, remote, &remote_entries, &options);
+
+        if direction.pulls() {
+            tokio::fs::create_dir_all(local).await?;
+        }
+        for dir in &plan.local_dirs {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        for dir in &plan.remote_dirs {
+            self.pool
+                .primary()
+                .lock()
+                .await
+                .create_directory(dir)
+                .await?;
+        }
+
+        let jobs =
+            plan.downloads
+                .iter()
+                .map(|t| TransferJob::download(t.remote_path.clone(), t.local_path.clone(), t.size))
+                .chain(plan.uploads.iter().map(|t| {
+                    TransferJob::upload(t.local_path.clone(), t.remote_path.clone(), t.size)
+                }))
+                .collect();
+        let transfers = self.transfer(jobs).finish().await?;
+        let (download_results, upload_results) = transfers.results.split_at(plan.downloads.len());
+
+        // Copies keep the times of their originals so the next sync sees them as
+        // unchanged. One that can't is only copied again then.
+        for (transfer, result) in plan.downloads.iter().zip(download_results) {
+            if result.is_ok() {
+                let _ = set_local_modified(&transfer.local_path, transfer.modified);
+            }
+        }
+        let client = self.pool.primary();
+        let mut client = client.lock().await;
+        if client.capabilities().set_modified {
+            for (transfer, result) in plan.uploads.iter().zip(upload_results) {
+                if result.is_ok() {
+                    let _ = client
+                        .set_modified(&transfer.remote_path, transfer.modified)
+                        .await;
+                }
+            }
+        }
+
+        Ok(SyncReport {
+            directories_created: plan.local_dirs.len() + plan.remote_dirs.len(),
+            downloads: plan.downloads.len(),
+            transfers,
+        })
+    }
+}
+
+/// Transfers under way. As a [`Stream`] it yields what happens to each job,
+/// ending once they're all done; [`Transfer::finish`] waits for the results.
+/// Dropping it cancels the jobs not yet done.
+pub struct Transfer {
+    events: TransferEvents,
+    task: JoinHandle<TransferReport>,
+}
+
+impl Transfer {
+    /// Wait for every job to finish or fail
+    pub async fn finish(mut self) -> Result<TransferReport> {
+        (&mut self.task)
+            .await
+            .context("Transfers stopped unexpectedly")
+    }
+}
+
+impl Stream for Transfer {
+    type Item = TransferEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().events).poll_next(cx)
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// How a batch of transfers went
+#[derive(Debug)]
+pub struct TransferReport {
+    pub jobs: Vec<TransferJob>,
+    /// For each job, in order, how many retries it took or why it failed
+    pub results: Vec<Result<u32>>,
+}
+
+impl TransferReport {
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+
+    /// The jobs that failed, with why
+    pub fn failures(&self) -> impl Iterator<Item = (&TransferJob, &anyhow::Error)> {
+        self.jobs
+            .iter()
+            .zip(&self.results)
+            .filter_map(|(job, result)| result.as_ref().err().map(|e| (job, e)))
+    }
+}
+
+/// What [`ComfyFs::sync`] did
+#[derive(Debug)]
+pub struct SyncReport {
+    /// Directories made on either side
+    pub directories_created: usize,
+    /// How many of the transfers, from the start, were downloads; the rest
+    /// were uploads
+    pub downloads: usize,
+    pub transfers: TransferReport,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use crate::client::FileServerClient;
+    use futures::StreamExt;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn comfy_fs(root: &Path) -> ComfyFs {
+        let client: Box<dyn FileServerClient> = Box::new(LocalClient::new(root.to_path_buf()));
+        ComfyFs::new(Arc::new(Mutex::new(client)))
+    }
+
+    #[tokio::test]
+    async fn test_download_events() {
+        let server = tempfile::tempdir().unwrap();
+        std::fs::create_dir(server.path().join("models")).unwrap();
+        std::fs::write(server.path().join("models/a.safetensors"), [7; 1000]).unwrap();
+        let fs = comfy_fs(server.path());
+        assert_eq!(fs.list("/models").await.unwrap()[0].name, "a.safetensors");
+
+        let local = tempfile::tempdir().unwrap();
+        let target = local.path().join("a.safetensors");
+        let mut transfer = fs.download("/models/a.safetensors", &target).await.unwrap();
+        let mut bytes = 0;
+        let mut last = None;
+        while let Some(event) = transfer.next().await {
+            if let TransferEvent::Bytes { bytes: n, .. } = event {
+                bytes += n;
+            }
+            last = Some(event);
+        }
+        assert_eq!(bytes, 1000);
+        assert_eq!(
+            last,
+            Some(TransferEvent::Finished {
+                index: 0,
+                retries: 0
+            })
+        );
+        assert!(transfer.finish().await.unwrap().is_success());
+        assert_eq!(std::fs::read(&target).unwrap(), [7; 1000]);
+
+        let missing = fs.download("/models/b.safetensors", &target).await;
+        assert!(missing.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let server = tempfile::tempdir().unwrap();
+        std::fs::create_dir(server.path().join("output")).unwrap();
+        std::fs::write(server.path().join("output/remote.png"), b"remote").unwrap();
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("run 1")).unwrap();
+        std::fs::write(local.path().join("run 1/local.png"), b"local").unwrap();
+
+        let fs = comfy_fs(server.path());
+        let report = fs
+            .sync(local.path(), "/output", SyncDirection::TwoWay)
+            .await
+            .unwrap();
+        assert!(report.transfers.is_success());
+        assert_eq!(report.directories_created, 1);
+        assert_eq!(report.downloads, 1);
+        assert_eq!(report.transfers.jobs.len(), 2);
+        assert_eq!(
+            std::fs::read(local.path().join("remote.png")).unwrap(),
+            b"remote"
+        );
+        assert_eq!(
+            std::fs::read(server.path().join("output/run 1/local.png")).unwrap(),
+            b"local"
+        );
+
+        // Nothing left to copy the second time
+        let report = fs
+            .sync(local.path(), "/output", SyncDirection::TwoWay)
+            .await
+            .unwrap();
+        assert!(report.transfers.jobs.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! A checklist for choosing some of many files, like the ones a pattern matched
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+
+use super::{format_bytes, TerminalGuard};
+
+/// How far Page Up and Page Down move
+const PAGE: isize = 10;
+
+/// Items to tick or untick, each with a size. All start ticked.
+pub struct Checklist {
+    title: String,
+    items: Vec<(String, u64)>,
+    checked: Vec<bool>,
+    selected: usize,
+    list_state: ListState,
+}
+
+/// How the user left the checklist
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Enter, with the indexes of the ticked items
+    Chosen(Vec<usize>),
+    /// Esc, q, or Ctrl-C
+    Cancelled,
+}
+
+impl Checklist {
+    pub fn new(title: impl Into<String>, items: Vec<(String, u64)>) -> Self {
+        Self {
+            title: title.into(),
+            checked: vec![true; items.len()],
+            items,
+            selected: 0,
+            list_state: ListState::default(),
+        }
+    }
+
+    /// Let the user choose on `terminal`, returning the indexes of the items
+    /// ticked when they press Enter, or `None` if they cancel
+    pub fn run<B: Backend>(mut self, terminal: &mut Terminal<B>) -> Result<Option<Vec<usize>>> {
+        loop {
+            terminal.draw(|f| self.render(f))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            // Windows reports releases too, which would undo each toggle
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.handle_key(key) {
+                Some(Outcome::Chosen(chosen)) => return Ok(Some(chosen)),
+                Some(Outcome::Cancelled) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Outcome> {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Outcome::Cancelled)
+            }
+            KeyCode::Esc | KeyCode::Char('q') => return Some(Outcome::Cancelled),
+            KeyCode::Enter => return Some(Outcome::Chosen(self.chosen())),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-PAGE),
+            KeyCode::PageDown => self.move_selection(PAGE),
+            KeyCode::Home | KeyCode::Char('g') => self.selected = 0,
+            KeyCode::End | KeyCode::Char('G') => self.selected = self.items.len().saturating_sub(1),
+            KeyCode::Char(' ') => self.toggle(),
+            KeyCode::Char('a') => self.toggle_all(),
+            _ => {}
+        }
+        None
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.items.is_empty() {
+            return;
+        }
+        self.selected = self
+            .selected
+            .saturating_add_signed(delta)
+            .min(self.items.len() - 1);
+    }
+
+    fn toggle(&mut self) {
+        if let Some(checked) = self.checked.get_mut(self.selected) {
+            *checked = !*checked;
+        }
+    }
+
+    /// Untick everything if it's all ticked, else tick everything
+    fn toggle_all(&mut self) {
+        let all = self.checked.iter().all(|&checked| checked);
+        self.checked.fill(!all);
+    }
+
+    fn chosen(&self) -> Vec<usize> {
+        (0..self.items.len()).filter(|&i| self.checked[i]).collect()
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(1)])
+            .split(frame.area());
+
+        let items: Vec<ListItem> = self
+            .items
+            .iter()
+            .zip(&self.checked)
+            .map(|((label, size), &checked)| {
+                let mark = if checked {
+                    Span::styled("[x] ", Style::default().fg(Color::Green))
+                } else {
+                    Span::raw("[ ] ")
+                };
+                ListItem::new(Line::from(vec![
+                    mark,
+                    Span::raw(label.as_str()),
+                    Span::raw(format!(" ({})", format_bytes(*size))),
+                ]))
+            })
+            .collect();
+        let chosen = self.chosen();
+        let total: u64 = chosen.iter().map(|&i| self.items[i].1).sum();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "{} - {} of {} selected, {}",
+                self.title,
+                chosen.len(),
+                self.items.len(),
+                format_bytes(total)
+            )))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        self.list_state.select(Some(self.selected));
+        frame.render_stateful_widget(list, chunks[0], &mut self.list_state);
+
+        let help = "Space: toggle  a: toggle all  Enter: start  Esc: cancel";
+        frame.render_widget(
+            Paragraph::new(help).style(Style::default().fg(Color::DarkGray)),
+            chunks[1],
+        );
+    }
+}
+
+/// Show a [`Checklist`] of `items` full screen, giving the terminal back after
+pub fn choose(title: impl Into<String>, items: Vec<(String, u64)>) -> Result<Option<Vec<usize>>> {
+    let mut guard = TerminalGuard::enter()?;
+    Checklist::new(title, items).run(guard.terminal())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn press(checklist: &mut Checklist, code: KeyCode) -> Option<Outcome> {
+        checklist.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn checklist() -> Checklist {
+        let items = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| (name.to_string(), 1024))
+            .collect();
+        Checklist::new("Download /output/*.png", items)
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut checklist = checklist();
+        press(&mut checklist, KeyCode::Down);
+        press(&mut checklist, KeyCode::Char(' '));
+        assert_eq!(
+            press(&mut checklist, KeyCode::Enter),
+            Some(Outcome::Chosen(vec![0, 2]))
+        );
+
+        // Not all ticked, so all get ticked; then all untick
+        press(&mut checklist, KeyCode::Char('a'));
+        assert_eq!(checklist.chosen(), [0, 1, 2]);
+        press(&mut checklist, KeyCode::Char('a'));
+        press(&mut checklist, KeyCode::End);
+        press(&mut checklist, KeyCode::Char(' '));
+        assert_eq!(checklist.chosen(), [2]);
+        assert_eq!(
+            press(&mut checklist, KeyCode::Esc),
+            Some(Outcome::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let mut checklist = checklist();
+        press(&mut checklist, KeyCode::Char(' '));
+        let mut terminal = Terminal::new(TestBackend::new(60, 8)).unwrap();
+        terminal.draw(|f| checklist.render(f)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("2 of 3 selected"));
+        assert!(screen.contains("[ ] a.png"));
+        assert!(screen.contains("[x] b.png"));
+    }
+}
@@ -0,0 +1,96 @@
+/// Narrows the file list down to names containing every word of the query,
+/// ignoring case, so `sdxl lora` finds `lora_SDXL_v2.safetensors`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    query: String,
+    /// The input line is open and keys go to the query
+    editing: bool,
+}
+
+impl Filter {
+    /// Open the input line, keeping the current query
+    pub fn start(&mut self) {
+        self.editing = true;
+    }
+
+    /// Close the input line and keep filtering by the query
+    pub fn finish(&mut self) {
+        self.editing = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.editing = false;
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub const fn is_editing(&self) -> bool {
+        self.editing
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.query.trim().is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.query
+            .split_whitespace()
+            .all(|word| name.contains(&word.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(query: &str) -> Filter {
+        let mut filter = Filter::default();
+        query.chars().for_each(|c| filter.push(c));
+        filter
+    }
+
+    #[test]
+    fn test_matches_every_word_ignoring_case() {
+        let filter = filter("sdxl lora");
+
+        assert!(filter.matches("lora_SDXL_v2.safetensors"));
+        assert!(!filter.matches("sdxl_base_1.0.safetensors"));
+        assert!(!filter.matches("lora_sd15.safetensors"));
+    }
+
+    #[test]
+    fn test_empty_query_matches_everything() {
+        let filter = filter("  ");
+
+        assert!(!filter.is_active());
+        assert!(filter.matches("anything.ckpt"));
+    }
+
+    #[test]
+    fn test_editing_state() {
+        let mut filter = filter("vae");
+        filter.start();
+        assert!(filter.is_editing());
+
+        filter.finish();
+        assert!(!filter.is_editing());
+        assert_eq!(filter.query(), "vae");
+
+        filter.start();
+        filter.clear();
+        assert!(!filter.is_editing());
+        assert!(!filter.is_active());
+    }
+}
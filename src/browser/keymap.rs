@@ -0,0 +1,440 @@
+use anyhow::{Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::config::config_dir;
+
+/// Something the browser can do from a key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Up,
+    Down,
+    Top,
+    Bottom,
+    HalfPageUp,
+    HalfPageDown,
+    Open,
+    Back,
+    Select,
+    DownloadSelected,
+    Upload,
+    Filter,
+    Transfers,
+    Messages,
+    Delete,
+    NewDirectory,
+    Rename,
+    Sort,
+    ReverseSort,
+    GoTo,
+    ToggleHidden,
+    GlobFilter,
+    DirectorySize,
+    SelectAll,
+    InvertSelection,
+    VisualSelect,
+    Preview,
+    CopyAddress,
+    SortByName,
+    SortBySize,
+    SortByModified,
+    SortByType,
+    Help,
+}
+
+impl Action {
+    /// What the action does, for the help overlay
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Quit => "Quit, or close the open panel",
+            Self::Up => "Move up",
+            Self::Down => "Move down",
+            Self::Top => "Jump to the first entry",
+            Self::Bottom => "Jump to the last entry",
+            Self::HalfPageUp => "Move up half a page",
+            Self::HalfPageDown => "Move down half a page",
+            Self::Open => "Open a directory or download a file",
+            Self::Back => "Go up one directory",
+            Self::Select => "Select or unselect the entry",
+            Self::DownloadSelected => "Download the selected files",
+            Self::Upload => "Pick local files to upload here",
+            Self::Filter => "Filter the list as you type",
+            Self::Transfers => "Show the transfer queue",
+            Self::Messages => "Show the message history",
+            Self::Delete => "Move the entry to the trash (asks first)",
+            Self::NewDirectory => "Create a directory",
+            Self::Rename => "Rename the entry",
+            Self::Sort => "Change the sort mode",
+            Self::ReverseSort => "Reverse the sort order",
+            Self::GoTo => "Type a path to jump to",
+            Self::ToggleHidden => "Hide or show dotfiles",
+            Self::GlobFilter => "Only show files matching a glob, like *.safetensors",
+            Self::DirectorySize => "Calculate the size of the directory",
+            Self::SelectAll => "Select every file shown",
+            Self::InvertSelection => "Invert the selection of the files shown",
+            Self::VisualSelect => "Start a range selection, then select the range",
+            Self::Preview => "Preview a PNG's ComfyUI metadata or a safetensors header",
+            Self::CopyAddress => "Copy the entry's address to open it in a file manager",
+            Self::SortByName => "Sort by name, again to reverse",
+            Self::SortBySize => "Sort by size, again to reverse",
+            Self::SortByModified => "Sort by modified time, again to reverse",
+            Self::SortByType => "Sort by type, again to reverse",
+            Self::Help => "Show this help",
+        }
+    }
+}
+
+/// The keys each action starts with before `keys.toml` is applied
+const DEFAULT_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::Quit, &["q", "esc"]),
+    (Action::Up, &["up", "k"]),
+    (Action::Down, &["down", "j"]),
+    (Action::Top, &["g g", "home"]),
+    (Action::Bottom, &["G", "end"]),
+    (Action::HalfPageUp, &["ctrl-u", "pageup"]),
+    (Action::HalfPageDown, &["ctrl-d", "pagedown"]),
+    (Action::Open, &["enter", "l", "right"]),
+    (Action::Back, &["backspace", "h", "left"]),
+    (Action::Select, &["space"]),
+    (Action::DownloadSelected, &["d"]),
+    (Action::Upload, &["u"]),
+    (Action::Filter, &["/"]),
+    (Action::Transfers, &["t"]),
+    (Action::Messages, &["m"]),
+    (Action::Delete, &["x"]),
+    (Action::NewDirectory, &["n"]),
+    (Action::Rename, &["f2"]),
+    (Action::Sort, &["s"]),
+    (Action::ReverseSort, &["r"]),
+    (Action::GoTo, &[":", "ctrl-l"]),
+    (Action::ToggleHidden, &["H"]),
+    (Action::GlobFilter, &["f"]),
+    (Action::DirectorySize, &["z"]),
+    (Action::SelectAll, &["a"]),
+    (Action::InvertSelection, &["A"]),
+    (Action::VisualSelect, &["V"]),
+    (Action::Preview, &["p"]),
+    (Action::CopyAddress, &["y"]),
+    (Action::SortByName, &["1"]),
+    (Action::SortBySize, &["2"]),
+    (Action::SortByModified, &["3"]),
+    (Action::SortByType, &["4"]),
+    (Action::Help, &["?"]),
+];
+
+/// Keys that only mean something in one place, and can't be remapped
+const FIXED_BINDINGS: &[(&str, &str)] = &[
+    ("n / N", "Next/previous match while a filter is active"),
+    ("Esc", "Cancel a range selection, or clear the filter"),
+    ("c / Delete", "Cancel a transfer, in the transfer panel"),
+    ("C", "Clear finished transfers, in the transfer panel"),
+    ("y", "Confirm a delete"),
+];
+
+/// A single key press, with Shift folded into the character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for KeyPress {
+    fn from(key: KeyEvent) -> Self {
+        // `G` arrives as Shift+G on some terminals and plain `G` on others
+        Self {
+            code: key.code,
+            modifiers: key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+        }
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            // Spelled the way keys.toml spells them rather than per platform
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Backspace => write!(f, "Backspace"),
+            KeyCode::Delete => write!(f, "Delete"),
+            code => write!(f, "{}", code),
+        }
+    }
+}
+
+/// What the keys pressed so far add up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys start a longer binding, like the first `g` of `gg`
+    Pending,
+    Unbound,
+}
+
+/// The keys one action is bound to in `keys.toml`, either `"x"` or `["x", "delete"]`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Key bindings for the browser: the defaults, with any overrides from
+/// `keys.toml` in the config directory.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: Vec<(Vec<KeyPress>, Action)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .flat_map(|(action, keys)| {
+                keys.iter().map(move |key| {
+                    (parse_keys(key).expect("default bindings are valid"), *action)
+                })
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl KeyMap {
+    /// The default bindings with `keys.toml` applied, if it exists
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_dir().map(|dir| dir.join("keys.toml")) else {
+            return Ok(Self::default());
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        Self::from_toml(&content)
+            .with_context(|| format!("Invalid key bindings in {}", path.display()))
+    }
+
+    /// The default bindings with the overrides in `content` applied.
+    ///
+    /// Each action listed replaces all of its default keys, and its keys are
+    /// taken away from whatever action had them before.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let overrides: BTreeMap<Action, Keys> = toml::from_str(content)?;
+        let mut keymap = Self::default();
+
+        for (action, keys) in overrides {
+            let keys = match keys {
+                Keys::One(key) => vec![key],
+                Keys::Many(keys) => keys,
+            };
+            let sequences = keys
+                .iter()
+                .map(|key| parse_keys(key))
+                .collect::<Result<Vec<_>>>()?;
+
+            keymap
+                .bindings
+                .retain(|(keys, bound)| *bound != action && !sequences.contains(keys));
+            keymap
+                .bindings
+                .extend(sequences.into_iter().map(|keys| (keys, action)));
+        }
+        Ok(keymap)
+    }
+
+    /// Every action with the keys bound to it, in the order of the default
+    /// table, followed by the keys that can't be remapped
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        let mut lines: Vec<(String, &'static str)> = DEFAULT_BINDINGS
+            .iter()
+            .map(|(action, _)| {
+                let keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, bound)| bound == action)
+                    .map(|(keys, _)| {
+                        keys.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+                    })
+                    .collect();
+                let keys = if keys.is_empty() {
+                    "(unbound)".to_string()
+                } else {
+                    keys.join(" / ")
+                };
+                (keys, action.description())
+            })
+            .collect();
+        lines.extend(
+            FIXED_BINDINGS
+                .iter()
+                .map(|(keys, description)| ((*keys).to_string(), *description)),
+        );
+        lines
+    }
+
+    /// Look up the keys pressed so far. An exact match wins over a longer
+    /// binding that starts with the same keys.
+    pub fn lookup(&self, keys: &[KeyPress]) -> Lookup {
+        if let Some((_, action)) = self.bindings.iter().find(|(bound, _)| bound == keys) {
+            return Lookup::Action(*action);
+        }
+        if self
+            .bindings
+            .iter()
+            .any(|(bound, _)| bound.len() > keys.len() && bound.starts_with(keys))
+        {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
+
+/// Parse a binding like `j`, `ctrl-d`, `pagedown`, or `g g` for a sequence
+fn parse_keys(spec: &str) -> Result<Vec<KeyPress>> {
+    // A lone space is the space bar, not an empty sequence
+    if spec == " " {
+        return Ok(vec![KeyPress {
+            code: KeyCode::Char(' '),
+            modifiers: KeyModifiers::NONE,
+        }]);
+    }
+    let keys = spec
+        .split_whitespace()
+        .map(|key| parse_key(key).ok_or_else(|| anyhow::anyhow!("Unknown key '{}'", key)))
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        anyhow::bail!("Empty key binding");
+    }
+    Ok(keys)
+}
+
+fn parse_key(spec: &str) -> Option<KeyPress> {
+    let (modifiers, name) = if let Some(name) = strip_prefix_ignore_case(spec, "ctrl-") {
+        (KeyModifiers::CONTROL, name)
+    } else if let Some(name) = strip_prefix_ignore_case(spec, "alt-") {
+        (KeyModifiers::ALT, name)
+    } else {
+        (KeyModifiers::NONE, spec)
+    };
+
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match name.to_lowercase().as_str() {
+            "esc" | "escape" => KeyCode::Esc,
+            "enter" | "return" => KeyCode::Enter,
+            "backspace" => KeyCode::Backspace,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            lower => KeyCode::F(lower.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(KeyPress { code, modifiers })
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    (head.eq_ignore_ascii_case(prefix) && s.len() > prefix.len()).then(|| &s[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode) -> KeyPress {
+        KeyPress::from(KeyEvent::from(code))
+    }
+
+    #[test]
+    fn test_default_bindings() {
+        let keymap = KeyMap::default();
+
+        assert_eq!(keymap.lookup(&[press(KeyCode::Char('j'))]), Lookup::Action(Action::Down));
+        assert_eq!(keymap.lookup(&[press(KeyCode::F(2))]), Lookup::Action(Action::Rename));
+        assert_eq!(keymap.lookup(&[press(KeyCode::Char('g'))]), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(&[press(KeyCode::Char('g')), press(KeyCode::Char('g'))]),
+            Lookup::Action(Action::Top)
+        );
+        assert_eq!(keymap.lookup(&[press(KeyCode::F(12))]), Lookup::Unbound);
+
+        // Shift is part of the character, Ctrl is not
+        let shift_g = KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.lookup(&[shift_g.into()]), Lookup::Action(Action::Bottom));
+        let ctrl_d = KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.lookup(&[ctrl_d.into()]), Lookup::Action(Action::HalfPageDown));
+    }
+
+    #[test]
+    fn test_overrides_replace_keys() {
+        let keymap = KeyMap::from_toml(
+            r#"
+            delete = ["d", "delete"]
+            download_selected = "ctrl-s"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(keymap.lookup(&[press(KeyCode::Char('d'))]), Lookup::Action(Action::Delete));
+        assert_eq!(keymap.lookup(&[press(KeyCode::Delete)]), Lookup::Action(Action::Delete));
+        // The old key for delete is freed up
+        assert_eq!(keymap.lookup(&[press(KeyCode::Char('x'))]), Lookup::Unbound);
+        let ctrl_s = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(
+            keymap.lookup(&[ctrl_s.into()]),
+            Lookup::Action(Action::DownloadSelected)
+        );
+    }
+
+    #[test]
+    fn test_help_lists_current_keys() {
+        let keymap = KeyMap::from_toml("delete = [\"ctrl-x\", \"delete\"]\nrename = []").unwrap();
+        let help = keymap.help();
+
+        let keys_for = |description: &str| {
+            help.iter()
+                .find(|(_, d)| *d == description)
+                .map(|(keys, _)| keys.as_str())
+                .unwrap()
+        };
+        assert_eq!(keys_for(Action::Top.description()), "g g / Home");
+        assert_eq!(keys_for(Action::Select.description()), "Space");
+        assert_eq!(keys_for(Action::Delete.description()), "Ctrl-x / Delete");
+        assert_eq!(keys_for(Action::Rename.description()), "(unbound)");
+        assert!(help.len() > DEFAULT_BINDINGS.len());
+    }
+
+    #[test]
+    fn test_invalid_overrides() {
+        assert!(KeyMap::from_toml("launch = \"l\"").is_err());
+        assert!(KeyMap::from_toml("quit = \"ctrl-\"").is_err());
+        assert!(KeyMap::from_toml("quit = \"pgdn\"").is_err());
+        assert!(KeyMap::from_toml("quit = []").is_ok());
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Local};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How many messages the history keeps
+const HISTORY_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "Info",
+            Self::Warn => "Warning",
+            Self::Error => "Error",
+        }
+    }
+
+    const fn color(self) -> Color {
+        match self {
+            Self::Info => Color::Cyan,
+            Self::Warn => Color::Yellow,
+            Self::Error => Color::Red,
+        }
+    }
+
+    /// How long the message stays in the status bar. Problems stay up longer
+    /// so they aren't missed.
+    const fn timeout(self) -> Duration {
+        match self {
+            Self::Info => Duration::from_secs(5),
+            Self::Warn | Self::Error => Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    pub time: DateTime<Local>,
+    shown_at: Instant,
+}
+
+/// Status messages for the browser, with the most recent one shown in the
+/// status bar until it times out.
+#[derive(Debug, Default)]
+pub struct Messages {
+    history: VecDeque<Message>,
+}
+
+impl Messages {
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(Level::Info, text.into());
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.push(Level::Warn, text.into());
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(Level::Error, text.into());
+    }
+
+    fn push(&mut self, level: Level, text: String) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(Message {
+            level,
+            text,
+            time: Local::now(),
+            shown_at: Instant::now(),
+        });
+    }
+
+    /// The latest message, unless it has timed out
+    pub fn current(&self) -> Option<&Message> {
+        self.history
+            .back()
+            .filter(|message| message.shown_at.elapsed() < message.level.timeout())
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Render the history newest first, starting `scroll` messages down
+    pub fn render_history(&self, frame: &mut Frame, area: Rect, scroll: usize) {
+        let items: Vec<ListItem> = self
+            .history
+            .iter()
+            .rev()
+            .map(|message| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        message.time.format("%H:%M:%S ").to_string(),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(
+                        format!("{:<8}", message.level.as_str()),
+                        Style::default().fg(message.level.color()),
+                    ),
+                    Span::raw(message.text.as_str()),
+                ]))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("Messages ({})", self.history.len())),
+            )
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        let mut state = ListState::default().with_selected(Some(scroll));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    /// Render the current message into the status area
+    pub fn render_current(&self, frame: &mut Frame, area: Rect) {
+        let Some(message) = self.current() else {
+            return;
+        };
+        let style = Style::default().fg(message.level.color());
+        let paragraph = Paragraph::new(Line::from(Span::styled(
+            message.text.as_str(),
+            style,
+        )))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(style)
+                .title(message.level.as_str()),
+        );
+        frame.render_widget(paragraph, area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_message_times_out() {
+        let mut messages = Messages::default();
+        assert!(messages.current().is_none());
+
+        messages.info("Downloaded a.safetensors");
+        assert_eq!(messages.current().unwrap().text, "Downloaded a.safetensors");
+
+        messages.history.back_mut().unwrap().shown_at -= Level::Info.timeout();
+        assert!(messages.current().is_none());
+        // Still in the history
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn test_errors_stay_up_longer_than_info() {
+        let mut messages = Messages::default();
+        messages.error("Connection lost");
+        messages.history.back_mut().unwrap().shown_at -= Level::Info.timeout();
+
+        assert_eq!(messages.current().unwrap().level, Level::Error);
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut messages = Messages::default();
+        for i in 0..HISTORY_LIMIT + 5 {
+            messages.warn(format!("message {}", i));
+        }
+
+        assert_eq!(messages.len(), HISTORY_LIMIT);
+        assert_eq!(messages.history.front().unwrap().text, "message 5");
+    }
+}
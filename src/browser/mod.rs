@@ -0,0 +1,2347 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{
+    backend::Backend,
+    layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, Clear, Gauge, Paragraph, Row, Table, TableState, Wrap},
+    Frame, Terminal,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use crate::client::{directory_size, paths, FileServerClient, RemoteFile};
+use crate::clipboard;
+use crate::config::default_download_dir;
+use crate::history::{History, Operation};
+use crate::location::Addresses;
+use crate::preview::{self, Preview};
+use crate::queue::JobQueue;
+use crate::sort::{self, SortKey};
+use crate::trash::{is_in_trash, Trash};
+pub use crate::sort::SortMode;
+use crate::utils::glob_match;
+use crate::walk::{totals_label, DirTotals, Walker};
+
+mod checklist;
+mod filter;
+mod keymap;
+mod messages;
+mod picker;
+mod terminal;
+mod transfers;
+
+pub use checklist::{choose, Checklist};
+use filter::Filter;
+use keymap::{Action, KeyPress, Lookup};
+pub use keymap::KeyMap;
+pub use terminal::TerminalGuard;
+use messages::Messages;
+use picker::LocalPicker;
+use transfers::{TransferKind, TransferQueue};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: DateTime<Local>,
+    pub is_dir: bool,
+    pub extension: Option<String>,
+}
+
+impl SortKey for FileEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn modified(&self) -> DateTime<Local> {
+        self.modified
+    }
+
+    fn extension(&self) -> &str {
+        self.extension.as_deref().unwrap_or("")
+    }
+}
+
+/// The columns of the file list, each named after the sort it stands for
+const COLUMNS: [SortMode; 4] = [SortMode::Name, SortMode::Size, SortMode::Modified, SortMode::Type];
+
+const COLUMN_WIDTHS: [Constraint; 4] = [
+    Constraint::Min(20),
+    Constraint::Length(10),
+    Constraint::Length(16),
+    Constraint::Length(12),
+];
+
+/// Frames of the spinner shown while a directory is loading
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// The result of listing a directory on a background task
+struct Listing {
+    path: String,
+    result: Result<Vec<RemoteFile>>,
+}
+
+/// A directory size calculated on a background task
+struct DirectorySize {
+    path: String,
+    result: Result<u64>,
+}
+
+/// The preview of a file, loading until `lines` arrives
+struct PreviewPanel {
+    path: String,
+    lines: Option<Vec<String>>,
+    scroll: usize,
+}
+
+/// A modal waiting for the user before a file operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Prompt {
+    /// Waiting for y/n before deleting the entry at `path`. For a directory,
+    /// `totals` is what's in it, `None` until it's been counted.
+    ConfirmDelete {
+        path: String,
+        name: String,
+        size: u64,
+        is_dir: bool,
+        totals: Option<DirTotals>,
+    },
+    /// Name for a new directory in the current one
+    NewDirectory { input: String },
+    /// New name for the entry at `from`
+    Rename { from: String, input: String },
+    /// A path to jump to, absolute or relative to the current directory
+    GoTo { input: String },
+    /// Glob that files must match to be listed, empty to show everything
+    Glob { input: String },
+}
+
+impl Prompt {
+    fn title(&self) -> &'static str {
+        match self {
+            Self::ConfirmDelete { .. } => "Delete",
+            Self::NewDirectory { .. } => "New directory",
+            Self::Rename { .. } => "Rename",
+            Self::GoTo { .. } => "Go to",
+            Self::Glob { .. } => "Show files matching",
+        }
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct FileBrowser {
+    current_path: String,
+    /// Everything in the current directory
+    all_entries: Vec<FileEntry>,
+    /// The entries shown, after the filter
+    entries: Vec<FileEntry>,
+    filter: Filter,
+    /// Files must match this to be listed. Unlike the filter, it stays set
+    /// when changing directory.
+    glob: Option<String>,
+    show_hidden: bool,
+    selected: usize,
+    sort_mode: SortMode,
+    reverse_sort: bool,
+    table_state: TableState,
+    /// Entries picked for download, in the order they were picked
+    selected_files: Vec<FileEntry>,
+    /// Where a range selection started, while one is in progress
+    visual_anchor: Option<usize>,
+    client: Arc<Mutex<Box<dyn FileServerClient>>>,
+    messages: Messages,
+    /// Scroll position in the message history, while it's open
+    history_scroll: Option<usize>,
+    /// Scroll position in the help overlay, while it's open
+    help_scroll: Option<usize>,
+    download_dir: PathBuf,
+    transfers: TransferQueue,
+    /// Selected row in the transfer panel, while it's open
+    transfer_panel: Option<usize>,
+    picker: Option<LocalPicker>,
+    /// When the listing for `current_path` was requested, while it's in flight
+    loading: Option<Instant>,
+    listing_tx: mpsc::UnboundedSender<Listing>,
+    listing_rx: mpsc::UnboundedReceiver<Listing>,
+    prompt: Option<Prompt>,
+    /// Results of delete, rename, and mkdir, sent back from their tasks
+    op_tx: mpsc::UnboundedSender<Result<String>>,
+    op_rx: mpsc::UnboundedReceiver<Result<String>>,
+    pending_ops: usize,
+    /// Sizes of directories measured with `z` this session, `None` while the
+    /// calculation is running
+    dir_sizes: HashMap<String, Option<u64>>,
+    size_tx: mpsc::UnboundedSender<DirectorySize>,
+    size_rx: mpsc::UnboundedReceiver<DirectorySize>,
+    preview: Option<PreviewPanel>,
+    preview_tx: mpsc::UnboundedSender<(String, Result<Preview>)>,
+    preview_rx: mpsc::UnboundedReceiver<(String, Result<Preview>)>,
+    /// What's in directories about to be deleted, counted before asking
+    count_tx: mpsc::UnboundedSender<(String, Result<DirTotals>)>,
+    count_rx: mpsc::UnboundedReceiver<(String, Result<DirTotals>)>,
+    keymap: KeyMap,
+    /// Keys pressed so far towards a multi-key binding like `g g`
+    pending_keys: Vec<KeyPress>,
+    /// Rows visible in the file list at the last render, for half-page moves
+    page_height: usize,
+    /// Where each breadcrumb was drawn at the last render: row, columns, and
+    /// the directory it leads to
+    breadcrumb_hits: Vec<(u16, std::ops::Range<u16>, String)>,
+    /// Where each column header was drawn at the last render
+    column_hits: Vec<(u16, std::ops::Range<u16>, SortMode)>,
+    /// The sort chosen in each directory this session
+    dir_sorts: HashMap<String, (SortMode, bool)>,
+    history: Option<History>,
+    /// How the server is reached from a file manager, for `y`. Not set when
+    /// browsing a local directory.
+    addresses: Option<Addresses>,
+}
+
+impl FileBrowser {
+    pub fn new(start_path: String, client: Arc<Mutex<Box<dyn FileServerClient>>>) -> Self {
+        let (listing_tx, listing_rx) = mpsc::unbounded_channel();
+        let (op_tx, op_rx) = mpsc::unbounded_channel();
+        let (size_tx, size_rx) = mpsc::unbounded_channel();
+        let (preview_tx, preview_rx) = mpsc::unbounded_channel();
+        let (count_tx, count_rx) = mpsc::unbounded_channel();
+        Self {
+            current_path: start_path,
+            all_entries: Vec::new(),
+            entries: Vec::new(),
+            filter: Filter::default(),
+            glob: None,
+            show_hidden: true,
+            selected: 0,
+            sort_mode: SortMode::Modified,
+            reverse_sort: false,
+            table_state: TableState::default(),
+            selected_files: Vec::new(),
+            visual_anchor: None,
+            client,
+            messages: Messages::default(),
+            history_scroll: None,
+            help_scroll: None,
+            download_dir: default_download_dir(),
+            transfers: TransferQueue::default(),
+            transfer_panel: None,
+            picker: None,
+            loading: None,
+            listing_tx,
+            listing_rx,
+            prompt: None,
+            op_tx,
+            op_rx,
+            pending_ops: 0,
+            dir_sizes: HashMap::new(),
+            size_tx,
+            size_rx,
+            preview: None,
+            preview_tx,
+            preview_rx,
+            count_tx,
+            count_rx,
+            keymap: KeyMap::default(),
+            pending_keys: Vec::new(),
+            page_height: 10,
+            breadcrumb_hits: Vec::new(),
+            column_hits: Vec::new(),
+            dir_sorts: HashMap::new(),
+            history: None,
+            addresses: None,
+        }
+    }
+
+    /// Save downloaded files into `dir` instead of `~/Downloads`
+    #[must_use]
+    pub fn with_download_dir(mut self, dir: PathBuf) -> Self {
+        self.download_dir = dir;
+        self
+    }
+
+    /// Use `keymap` instead of the default key bindings
+    #[must_use]
+    pub fn with_keymap(mut self, keymap: KeyMap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// Record transfers and deletions in `history`
+    #[must_use]
+    pub fn with_history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Copy addresses of server paths built from `addresses` with `y`
+    #[must_use]
+    pub fn with_addresses(mut self, addresses: Option<Addresses>) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    /// Show and work on the jobs saved in `queue` in the transfer panel,
+    /// starting the pending ones
+    pub fn with_job_queue(mut self, queue: JobQueue) -> Result<Self> {
+        self.transfers = TransferQueue::with_store(queue)?;
+        self.transfers.start_next(&self.client);
+        Ok(self)
+    }
+
+    #[allow(clippy::future_not_send)]
+    pub async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        // Load initial directory
+        self.load_directory();
+
+        loop {
+            self.poll_listing();
+            self.poll_operations();
+            self.poll_sizes();
+            self.poll_preview();
+            self.poll_counts();
+            self.poll_transfers().await;
+            terminal.draw(|f| self.render(f))?;
+
+            // Wake up regularly so the spinner and transfer gauge keep moving without key presses
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(key) => match self.handle_input(key) {
+                    Ok(false) => break,
+                    Ok(true) => continue,
+                    Err(e) => self.messages.error(e.to_string()),
+                },
+                Event::Mouse(mouse) => self.handle_mouse(mouse),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let constraints = if self.transfers.running().is_some() || self.messages.current().is_some() {
+            vec![
+                Constraint::Length(3), // Header
+                Constraint::Min(10),   // File list
+                Constraint::Length(3), // Transfer progress or message
+                Constraint::Length(3), // Status bar
+            ]
+        } else {
+            vec![
+                Constraint::Length(3), // Header
+                Constraint::Min(10),   // File list
+                Constraint::Length(3), // Status bar
+            ]
+        };
+        
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(constraints)
+            .split(frame.area());
+
+        // Header, with a breadcrumb for each directory in the path
+        let mut spans = vec![
+            Span::styled(
+                "Comfy File Browser",
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(" - "),
+        ];
+        self.breadcrumb_hits.clear();
+        let row = chunks[0].y + 1;
+        let mut column = chunks[0].x + 1 + spans.iter().map(|s| s.width() as u16).sum::<u16>();
+        for (i, (label, path)) in breadcrumbs(&self.current_path).into_iter().enumerate() {
+            if i > 0 {
+                spans.push(Span::styled(" › ", Style::default().fg(Color::DarkGray)));
+                column += 3;
+            }
+            let span = Span::styled(label, Style::default().fg(Color::Yellow));
+            let end = column + span.width() as u16;
+            self.breadcrumb_hits.push((row, column..end, path));
+            spans.push(span);
+            column = end;
+        }
+        spans.push(Span::raw(self.spinner().map(|c| format!(" {}", c)).unwrap_or_default()));
+        let header = Paragraph::new(Line::from(spans)).block(Block::default().borders(Borders::ALL));
+        frame.render_widget(header, chunks[0]);
+
+        if let Some(scroll) = self.history_scroll {
+            self.messages.render_history(frame, chunks[1], scroll);
+        } else if let Some(picker) = &mut self.picker {
+            picker.render(frame, chunks[1], &self.current_path);
+        } else if let Some(selected) = self.transfer_panel {
+            let split = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(chunks[1]);
+            self.render_files(frame, split[0]);
+            self.transfers.render(frame, split[1], selected);
+        } else {
+            self.render_files(frame, chunks[1]);
+        }
+
+        if let Some(prompt) = &self.prompt {
+            render_prompt(frame, chunks[1], prompt);
+        }
+        if let Some(panel) = &self.preview {
+            let name = panel.path.rsplit('/').next().unwrap_or(&panel.path);
+            let lines = panel.lines.as_ref().map_or_else(
+                || vec![Line::from(Span::styled("Loading…", Style::default().fg(Color::DarkGray)))],
+                |lines| lines.iter().map(|line| Line::from(line.as_str())).collect(),
+            );
+            render_popup(frame, chunks[1], name, lines, panel.scroll);
+        }
+        if let Some(scroll) = self.help_scroll {
+            render_help(frame, chunks[1], &self.keymap.help(), scroll);
+        }
+
+        // Transfer progress or the latest message (if any)
+        if let Some(transfer) = self.transfers.running() {
+            let verb = match transfer.kind {
+                TransferKind::Download => "Downloading",
+                TransferKind::Upload => "Uploading",
+            };
+            let mut title = format!("{} {}", verb, transfer.name());
+            let queued = self.transfers.queued_count();
+            if queued > 0 {
+                title.push_str(&format!(" (+{} queued)", queued));
+            }
+            let gauge = Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .gauge_style(Style::default().fg(Color::Green))
+                .ratio(transfer.ratio())
+                .label(transfer.progress_label());
+            frame.render_widget(gauge, chunks[2]);
+        } else {
+            self.messages.render_current(frame, chunks[2]);
+        }
+
+        // Status bar is always the last row
+        let help = if self.help_scroll.is_some() {
+            "↑↓: Scroll | ?/Esc: Close"
+        } else if self.preview.is_some() {
+            "↑↓: Scroll | p/Esc: Close"
+        } else if matches!(self.prompt, Some(Prompt::ConfirmDelete { .. })) {
+            "y: Delete | n/Esc: Cancel"
+        } else if matches!(self.prompt, Some(Prompt::GoTo { .. })) {
+            "Type a path | Enter: Go | Esc: Cancel"
+        } else if matches!(self.prompt, Some(Prompt::Glob { .. })) {
+            "Type a glob like *.safetensors | Enter: Apply (empty clears) | Esc: Cancel"
+        } else if self.prompt.is_some() {
+            "Type a name | Enter: Confirm | Esc: Cancel"
+        } else if self.visual_anchor.is_some() {
+            "Move to extend the range | V: Select range | Esc: Cancel"
+        } else if self.filter.is_editing() {
+            "Type to filter | ↑↓: Navigate | Enter: Keep filter | Esc: Clear"
+        } else if self.history_scroll.is_some() {
+            "↑↓: Scroll | m/Esc: Close"
+        } else if self.transfer_panel.is_some() {
+            "↑↓: Select | c: Cancel | C: Clear finished | t/Esc: Close"
+        } else if self.picker.is_some() {
+            "↑↓: Navigate | Enter: Open/Upload | Backspace: Go Up | Space: Select | Esc: Cancel"
+        } else {
+            "?: Help | ↑↓/jk: Navigate | Enter: Open/Download | Backspace: Go Up | Space: Select | d: Download selected | /: Filter | u: Upload | t: Transfers | x: Delete | n: New Dir | F2: Rename | s: Sort | r: Reverse | m: Messages | q: Quit"
+        };
+        let mut status_block = Block::default().borders(Borders::ALL);
+        if !self.selected_files.is_empty() {
+            let total: u64 = self.selected_files.iter().map(|e| e.size).sum();
+            status_block = status_block.title(format!(
+                "{} selected, {}",
+                self.selected_files.len(),
+                format_bytes(total)
+            ));
+        }
+        let status = if self.filter.is_editing() {
+            Paragraph::new(Line::from(vec![
+                Span::styled("/", Style::default().fg(Color::Yellow)),
+                Span::raw(self.filter.query()),
+                Span::styled("▏", Style::default().fg(Color::Yellow)),
+            ]))
+            .block(Block::default().borders(Borders::ALL).title(help))
+        } else {
+            Paragraph::new(Line::from(vec![Span::raw(help)])).block(status_block)
+        };
+        frame.render_widget(status, chunks[chunks.len() - 1]);
+    }
+
+    fn render_files(&mut self, frame: &mut Frame, area: Rect) {
+        let mut title = "Files".to_string();
+        if let Some(glob) = &self.glob {
+            title.push_str(&format!(" [Glob: {}]", glob));
+        }
+        if !self.show_hidden {
+            title.push_str(" [Dotfiles hidden]");
+        }
+        if self.filter.is_active() {
+            title.push_str(&format!(
+                " [Filter: {} - {}/{}]",
+                self.filter.query(),
+                self.entries.len(),
+                self.all_entries.len()
+            ));
+        }
+
+        if self.loading.is_some() {
+            let placeholder = Paragraph::new(Line::from(Span::styled(
+                "Loading…",
+                Style::default().fg(Color::DarkGray),
+            )))
+            .block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(placeholder, area);
+            return;
+        }
+
+        // Header, with an arrow on the column the list is sorted by
+        let arrow = if self.reverse_sort { " ↑" } else { " ↓" };
+        let header = Row::new(COLUMNS.iter().map(|mode| {
+            if *mode == self.sort_mode {
+                Cell::from(format!("{}{}", mode.as_str(), arrow))
+                    .style(Style::default().fg(Color::Yellow))
+            } else {
+                Cell::from(mode.as_str())
+            }
+        }))
+        .style(Style::default().add_modifier(Modifier::BOLD));
+
+        let visual_range = self.visual_range();
+        let rows: Vec<Row> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let style = if entry.is_dir {
+                    Style::default().fg(Color::Blue)
+                } else {
+                    Style::default()
+                };
+
+                let marker = if self.is_selected(&entry.path) {
+                    Span::styled("[x] ", Style::default().fg(Color::Green))
+                } else {
+                    Span::raw("[ ] ")
+                };
+                let icon = if entry.is_dir { "📁 " } else { "📄 " };
+                let name = Line::from(vec![
+                    marker,
+                    Span::styled(icon, style),
+                    Span::styled(entry.name.as_str(), style),
+                ]);
+
+                // Directories only have a size once they've been measured
+                let size = if entry.is_dir {
+                    self.dir_sizes.get(&entry.path).map_or_else(String::new, |size| {
+                        size.map_or_else(|| "…".to_string(), format_bytes)
+                    })
+                } else {
+                    format_bytes(entry.size)
+                };
+                let kind = if entry.is_dir {
+                    "dir".to_string()
+                } else {
+                    entry.extension.clone().unwrap_or_default()
+                };
+
+                let row = Row::new(vec![
+                    Cell::from(name),
+                    Cell::from(Line::from(size).alignment(Alignment::Right)),
+                    Cell::from(entry.modified.format("%Y-%m-%d %H:%M").to_string())
+                        .style(Style::default().fg(Color::DarkGray)),
+                    Cell::from(kind),
+                ]);
+                if visual_range.as_ref().is_some_and(|range| range.contains(&i)) {
+                    row.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    row
+                }
+            })
+            .collect();
+
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(area);
+        let table = Table::new(rows, COLUMN_WIDTHS)
+            .header(header)
+            .block(block)
+            .flex(Flex::Start)
+            .row_highlight_style(Style::default().bg(Color::DarkGray));
+
+        // Remember where the headers are so clicking one sorts by it. This is
+        // the same layout the table uses for its columns.
+        let columns = Layout::horizontal(COLUMN_WIDTHS)
+            .flex(Flex::Start)
+            .spacing(1)
+            .split(inner);
+        self.column_hits = columns
+            .iter()
+            .zip(COLUMNS)
+            .map(|(rect, mode)| (inner.y, rect.x..rect.x + rect.width, mode))
+            .collect();
+
+        // Minus the borders and the header row
+        self.page_height = usize::from(area.height.saturating_sub(3)).max(1);
+        self.table_state.select(Some(self.selected));
+        frame.render_stateful_widget(table, area, &mut self.table_state);
+    }
+
+    fn handle_input(&mut self, key: KeyEvent) -> Result<bool> {
+        if self.help_scroll.is_some() {
+            self.handle_help_input(key);
+            return Ok(true);
+        }
+        if self.preview.is_some() {
+            self.handle_preview_input(key);
+            return Ok(true);
+        }
+        if self.history_scroll.is_some() {
+            self.handle_history_input(key);
+            return Ok(true);
+        }
+        if self.prompt.is_some() {
+            self.handle_prompt_input(key);
+            return Ok(true);
+        }
+        if self.picker.is_some() {
+            return self.handle_picker_input(key);
+        }
+        if self.transfer_panel.is_some() {
+            self.handle_transfer_panel_input(key);
+            return Ok(true);
+        }
+        if self.filter.is_editing() {
+            return self.handle_filter_input(key);
+        }
+
+        if self.visual_anchor.is_some() && key.code == KeyCode::Esc {
+            self.visual_anchor = None;
+            return Ok(true);
+        }
+        if self.filter.is_active() && self.pending_keys.is_empty() {
+            match key.code {
+                // Esc drops an active filter before it quits
+                KeyCode::Esc => {
+                    self.clear_filter();
+                    return Ok(true);
+                }
+                KeyCode::Char('n') => {
+                    self.jump_match(1);
+                    return Ok(true);
+                }
+                KeyCode::Char('N') => {
+                    self.jump_match(-1);
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+
+        let Some(action) = self.resolve_key(key) else {
+            return Ok(true);
+        };
+        match action {
+            Action::Quit => return Ok(false),
+            Action::Up => self.move_selection(-1),
+            Action::Down => self.move_selection(1),
+            Action::Top => self.selected = 0,
+            Action::Bottom => self.selected = self.entries.len().saturating_sub(1),
+            Action::HalfPageUp => self.move_selection(-self.half_page()),
+            Action::HalfPageDown => self.move_selection(self.half_page()),
+            Action::Open => self.enter_selected()?,
+            Action::Back => self.go_up(),
+            Action::Select => self.toggle_selection(),
+            Action::Sort => self.cycle_sort_mode(),
+            Action::ReverseSort => self.toggle_reverse_sort(),
+            Action::Upload => self.open_picker()?,
+            Action::DownloadSelected => self.download_selected()?,
+            Action::Transfers => self.transfer_panel = Some(0),
+            Action::Messages => self.history_scroll = Some(0),
+            Action::Filter => self.filter.start(),
+            Action::Delete => self.prompt_delete(),
+            Action::NewDirectory => {
+                self.prompt = Some(Prompt::NewDirectory { input: String::new() });
+            }
+            Action::Rename => self.prompt_rename(),
+            Action::GoTo => {
+                self.prompt = Some(Prompt::GoTo {
+                    input: self.current_path.clone(),
+                });
+            }
+            Action::ToggleHidden => {
+                self.show_hidden = !self.show_hidden;
+                self.apply_filter();
+            }
+            Action::GlobFilter => {
+                self.prompt = Some(Prompt::Glob {
+                    input: self.glob.clone().unwrap_or_default(),
+                });
+            }
+            Action::DirectorySize => self.measure_selected(),
+            Action::SelectAll => self.select_all(),
+            Action::InvertSelection => self.invert_selection(),
+            Action::VisualSelect => self.visual_select(),
+            Action::Preview => self.preview_selected(),
+            Action::CopyAddress => self.copy_address(),
+            Action::SortByName => self.sort_by_column(SortMode::Name),
+            Action::SortBySize => self.sort_by_column(SortMode::Size),
+            Action::SortByModified => self.sort_by_column(SortMode::Modified),
+            Action::SortByType => self.sort_by_column(SortMode::Type),
+            Action::Help => self.help_scroll = Some(0),
+        }
+        Ok(true)
+    }
+
+    /// Clicking a column header sorts by it, and clicking a breadcrumb opens
+    /// that directory
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if mouse.kind != MouseEventKind::Down(MouseButton::Left) {
+            return;
+        }
+        let column = self
+            .column_hits
+            .iter()
+            .find(|(row, columns, _)| *row == mouse.row && columns.contains(&mouse.column));
+        if let Some((_, _, mode)) = column {
+            self.sort_by_column(*mode);
+            return;
+        }
+
+        let hit = self
+            .breadcrumb_hits
+            .iter()
+            .find(|(row, columns, _)| *row == mouse.row && columns.contains(&mouse.column));
+        if let Some((_, _, path)) = hit {
+            if *path != self.current_path {
+                self.open_directory(path.clone());
+            }
+        }
+    }
+
+    /// Keys while a preview is open
+    fn handle_preview_input(&mut self, key: KeyEvent) {
+        let action = self.resolve_key(key);
+        let Some(panel) = &mut self.preview else {
+            return;
+        };
+        let last = panel.lines.as_ref().map_or(0, |lines| lines.len().saturating_sub(1));
+
+        match action {
+            Some(Action::Preview | Action::Quit) => self.preview = None,
+            Some(Action::Up) => panel.scroll = panel.scroll.saturating_sub(1),
+            Some(Action::Down) => panel.scroll = (panel.scroll + 1).min(last),
+            Some(Action::HalfPageUp) => panel.scroll = panel.scroll.saturating_sub(10),
+            Some(Action::HalfPageDown) => panel.scroll = (panel.scroll + 10).min(last),
+            Some(Action::Top) => panel.scroll = 0,
+            Some(Action::Bottom) => panel.scroll = last,
+            _ => {}
+        }
+    }
+
+    /// Keys while the help overlay is open
+    fn handle_help_input(&mut self, key: KeyEvent) {
+        let Some(scroll) = self.help_scroll else {
+            return;
+        };
+        let last = self.keymap.help().len().saturating_sub(1);
+
+        self.help_scroll = match self.resolve_key(key) {
+            Some(Action::Help | Action::Quit) => None,
+            Some(Action::Up) => Some(scroll.saturating_sub(1)),
+            Some(Action::Down) => Some((scroll + 1).min(last)),
+            Some(Action::HalfPageUp) => Some(scroll.saturating_sub(10)),
+            Some(Action::HalfPageDown) => Some((scroll + 10).min(last)),
+            Some(Action::Top) => Some(0),
+            Some(Action::Bottom) => Some(last),
+            _ => Some(scroll),
+        };
+    }
+
+    /// Turn a key press into an action, waiting for more keys while the ones
+    /// pressed so far start a longer binding
+    fn resolve_key(&mut self, key: KeyEvent) -> Option<Action> {
+        self.pending_keys.push(KeyPress::from(key));
+        loop {
+            match self.keymap.lookup(&self.pending_keys) {
+                Lookup::Action(action) => {
+                    self.pending_keys.clear();
+                    return Some(action);
+                }
+                Lookup::Pending => return None,
+                // A key that doesn't continue the sequence starts over on its own
+                Lookup::Unbound if self.pending_keys.len() > 1 => {
+                    self.pending_keys.drain(..self.pending_keys.len() - 1);
+                }
+                Lookup::Unbound => {
+                    self.pending_keys.clear();
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn half_page(&self) -> i32 {
+        i32::try_from(self.page_height / 2).unwrap_or(i32::MAX).max(1)
+    }
+
+    /// Ask before deleting the selected entry. A directory's contents are
+    /// counted first so the prompt can say how much goes with it.
+    fn prompt_delete(&mut self) {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return;
+        };
+        self.prompt = Some(Prompt::ConfirmDelete {
+            path: entry.path.clone(),
+            name: entry.name.clone(),
+            size: entry.size,
+            is_dir: entry.is_dir,
+            totals: None,
+        });
+        if !entry.is_dir {
+            return;
+        }
+
+        let path = entry.path.clone();
+        let walker = Walker::new(self.client.clone(), 4);
+        let tx = self.count_tx.clone();
+        tokio::spawn(async move {
+            let result = walker.walk(&path).await.map(|entries| DirTotals::of(&entries));
+            // The receiver only goes away when the browser is closed
+            let _ = tx.send((path, result));
+        });
+    }
+
+    fn poll_counts(&mut self) {
+        while let Ok((path, result)) = self.count_rx.try_recv() {
+            // Skip counts for prompts that were cancelled
+            let Some(Prompt::ConfirmDelete { path: prompt_path, totals, .. }) = &mut self.prompt
+            else {
+                continue;
+            };
+            if *prompt_path != path {
+                continue;
+            }
+            match result {
+                Ok(counted) => *totals = Some(counted),
+                Err(e) => {
+                    self.prompt = None;
+                    self.messages.error(format!("Could not list {}: {}", path, e));
+                }
+            }
+        }
+    }
+
+    fn prompt_rename(&mut self) {
+        if let Some(entry) = self.entries.get(self.selected) {
+            self.prompt = Some(Prompt::Rename {
+                from: entry.path.clone(),
+                input: entry.name.clone(),
+            });
+        }
+    }
+
+    /// Keys while a prompt is open
+    fn handle_prompt_input(&mut self, key: KeyEvent) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+
+        match prompt {
+            Prompt::ConfirmDelete { is_dir, totals, .. } => {
+                if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+                    // Not until the prompt has said what's in the directory
+                    if *is_dir && totals.is_none() {
+                        return;
+                    }
+                    if let Some(Prompt::ConfirmDelete {
+                        path,
+                        name,
+                        size,
+                        is_dir,
+                        totals,
+                    }) = self.prompt.take()
+                    {
+                        let history = self.history.clone();
+                        let size = totals.map_or(size, |totals| totals.size);
+                        self.run_operation(move |client| {
+                            Box::pin(async move {
+                                let started = Instant::now();
+                                // What's in the trash already goes for good
+                                let permanent = is_in_trash(&path);
+                                let result = if permanent && is_dir {
+                                    client.delete_directory(&path, true).await
+                                } else if permanent {
+                                    client.delete_file(&path).await
+                                } else {
+                                    Trash::new().put(client.as_mut(), &path).await.map(drop)
+                                };
+                                if let Some(history) = history {
+                                    let elapsed = started.elapsed();
+                                    history.record(
+                                        Operation::Delete,
+                                        &path,
+                                        None,
+                                        size,
+                                        elapsed,
+                                        &result,
+                                    );
+                                }
+                                result?;
+                                if permanent {
+                                    Ok(format!("Deleted {}", name))
+                                } else {
+                                    Ok(format!("Moved {} to the trash", name))
+                                }
+                            })
+                        });
+                    }
+                } else {
+                    self.prompt = None;
+                }
+            }
+            Prompt::NewDirectory { input }
+            | Prompt::Rename { input, .. }
+            | Prompt::GoTo { input }
+            | Prompt::Glob { input } => match key.code {
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Enter => self.submit_prompt(),
+                _ => {}
+            },
+        }
+    }
+
+    /// Run the operation for a name prompt once the name has been entered
+    fn submit_prompt(&mut self) {
+        let Some(prompt) = self.prompt.take() else {
+            return;
+        };
+        match &prompt {
+            Prompt::GoTo { input } => {
+                match paths::resolve(&self.current_path, input.trim()) {
+                    Ok(path) => self.open_directory(path),
+                    Err(e) => {
+                        self.messages.error(e.to_string());
+                        self.prompt = Some(prompt);
+                    }
+                }
+                return;
+            }
+            Prompt::Glob { input } => {
+                let glob = input.trim();
+                self.glob = (!glob.is_empty()).then(|| glob.to_string());
+                self.apply_filter();
+                return;
+            }
+            _ => {}
+        }
+        let (Prompt::NewDirectory { input } | Prompt::Rename { input, .. }) = &prompt else {
+            return;
+        };
+
+        let name = input.trim().to_string();
+        if let Err(e) = validate_name(&name) {
+            self.messages.error(e.to_string());
+            // Keep the prompt open so the name can be fixed
+            self.prompt = Some(prompt);
+            return;
+        }
+        let target = format!("{}/{}", self.current_path.trim_end_matches('/'), name);
+
+        match prompt {
+            Prompt::NewDirectory { .. } => self.run_operation(move |client| {
+                Box::pin(async move {
+                    client.create_directory(&target).await?;
+                    Ok(format!("Created directory {}", name))
+                })
+            }),
+            Prompt::Rename { from, .. } => self.run_operation(move |client| {
+                Box::pin(async move {
+                    client.rename(&from, &target).await?;
+                    Ok(format!("Renamed {} to {}", from, name))
+                })
+            }),
+            Prompt::ConfirmDelete { .. } | Prompt::GoTo { .. } | Prompt::Glob { .. } => {}
+        }
+    }
+
+    /// Run a file operation on a background task. Its result is reported by
+    /// `poll_operations`, which also refreshes the listing.
+    fn run_operation<F>(&mut self, operation: F)
+    where
+        F: for<'a> FnOnce(
+                &'a mut Box<dyn FileServerClient>,
+            ) -> futures::future::BoxFuture<'a, Result<String>>
+            + Send
+            + 'static,
+    {
+        let client = self.client.clone();
+        let tx = self.op_tx.clone();
+        self.pending_ops += 1;
+        tokio::spawn(async move {
+            let mut guard = client.lock().await;
+            let result = operation(&mut guard).await;
+            // The receiver only goes away when the browser is closed
+            let _ = tx.send(result);
+        });
+    }
+
+    fn poll_operations(&mut self) {
+        while let Ok(result) = self.op_rx.try_recv() {
+            self.pending_ops -= 1;
+            match result {
+                Ok(message) => self.messages.info(message),
+                Err(e) => self.messages.error(e.to_string()),
+            }
+            self.load_directory();
+        }
+    }
+
+    /// Keys while the filter input line is open
+    fn handle_filter_input(&mut self, key: KeyEvent) -> Result<bool> {
+        match key.code {
+            KeyCode::Esc => self.clear_filter(),
+            KeyCode::Enter => self.filter.finish(),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.apply_filter();
+            }
+            KeyCode::Char(c) => {
+                self.filter.push(c);
+                self.apply_filter();
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn clear_filter(&mut self) {
+        self.filter.clear();
+        self.apply_filter();
+    }
+
+    /// Move to the next or previous match, wrapping around the ends
+    fn jump_match(&mut self, direction: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let len = self.entries.len() as isize;
+        self.selected = (self.selected as isize + direction).rem_euclid(len) as usize;
+    }
+
+    /// Rebuild the visible entries from the filter, glob, and dotfile setting,
+    /// keeping the selected entry selected when it's still shown
+    fn apply_filter(&mut self) {
+        let selected_path = self.entries.get(self.selected).map(|e| e.path.clone());
+
+        self.entries = self
+            .all_entries
+            .iter()
+            .filter(|entry| self.show_hidden || !entry.name.starts_with('.'))
+            // Directories stay visible so the glob doesn't get in the way of browsing
+            .filter(|entry| {
+                entry.is_dir || self.glob.as_ref().is_none_or(|glob| glob_match(&entry.name, glob))
+            })
+            .filter(|entry| self.filter.matches(&entry.name))
+            .cloned()
+            .collect();
+
+        self.selected = selected_path
+            .and_then(|path| self.entries.iter().position(|e| e.path == path))
+            .unwrap_or(0);
+    }
+
+    /// Keys while the message history is open
+    fn handle_history_input(&mut self, key: KeyEvent) {
+        let Some(scroll) = self.history_scroll else {
+            return;
+        };
+        let last = self.messages.len().saturating_sub(1);
+
+        self.history_scroll = match self.resolve_key(key) {
+            Some(Action::Messages | Action::Quit) => None,
+            Some(Action::Up) => Some(scroll.saturating_sub(1)),
+            Some(Action::Down) => Some((scroll + 1).min(last)),
+            Some(Action::HalfPageUp) => Some(scroll.saturating_sub(10)),
+            Some(Action::HalfPageDown) => Some((scroll + 10).min(last)),
+            Some(Action::Top) => Some(0),
+            Some(Action::Bottom) => Some(last),
+            _ => Some(scroll),
+        };
+    }
+
+    /// Keys while the local file picker is open
+    fn handle_picker_input(&mut self, key: KeyEvent) -> Result<bool> {
+        let action = self.resolve_key(key);
+        let Some(picker) = &mut self.picker else {
+            return Ok(true);
+        };
+
+        match action {
+            Some(Action::Quit) => self.picker = None,
+            Some(Action::Up) => picker.move_selection(-1),
+            Some(Action::Down) => picker.move_selection(1),
+            Some(Action::Top) => picker.move_selection(isize::MIN),
+            Some(Action::Bottom) => picker.move_selection(isize::MAX),
+            Some(Action::Select) => picker.toggle_mark(),
+            Some(Action::Back) => picker.go_up()?,
+            Some(Action::Open) => {
+                if let Some(files) = picker.enter()? {
+                    self.picker = None;
+                    self.queue_uploads(files);
+                }
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Keys while the transfer panel is open
+    fn handle_transfer_panel_input(&mut self, key: KeyEvent) {
+        let Some(selected) = self.transfer_panel else {
+            return;
+        };
+        let last = self.transfers.len().saturating_sub(1);
+
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Delete => {
+                if self.transfers.cancel(selected) {
+                    self.messages.warn("Transfer cancelled");
+                }
+            }
+            KeyCode::Char('C') => {
+                self.transfers.clear_finished();
+                self.transfer_panel = Some(0);
+            }
+            _ => match self.resolve_key(key) {
+                Some(Action::Transfers | Action::Quit) => self.transfer_panel = None,
+                Some(Action::Up) => self.transfer_panel = Some(selected.saturating_sub(1)),
+                Some(Action::Down) => self.transfer_panel = Some((selected + 1).min(last)),
+                Some(Action::Top) => self.transfer_panel = Some(0),
+                Some(Action::Bottom) => self.transfer_panel = Some(last),
+                _ => {}
+            },
+        }
+    }
+
+    /// Open the local file picker, starting in the current working directory
+    fn open_picker(&mut self) -> Result<()> {
+        self.picker = Some(LocalPicker::new(std::env::current_dir()?)?);
+        Ok(())
+    }
+
+    fn move_selection(&mut self, delta: i32) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let new_selected = (self.selected as i32 + delta)
+            .max(0)
+            .min(self.entries.len() as i32 - 1) as usize;
+        self.selected = new_selected;
+    }
+
+    fn enter_selected(&mut self) -> Result<()> {
+        // Entries from the previous directory are still around while the next one loads
+        if self.loading.is_some() {
+            return Ok(());
+        }
+        if let Some(entry) = self.entries.get(self.selected).cloned() {
+            if entry.is_dir {
+                self.open_directory(entry.path);
+            } else {
+                self.queue_download(&entry.path, entry.size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a download of `remote_path` into the download directory
+    fn queue_download(&mut self, remote_path: &str, size: u64) -> Result<()> {
+        let name = remote_path.rsplit('/').next().unwrap_or(remote_path);
+        if name.is_empty() {
+            anyhow::bail!("Nothing to download at {}", remote_path);
+        }
+        let local_path = self.download_dir.join(name);
+
+        self.transfers
+            .push(TransferKind::Download, remote_path.to_string(), local_path, size);
+        self.transfers.start_next(&self.client);
+        Ok(())
+    }
+
+    /// Queue downloads of the selected files
+    fn download_selected(&mut self) -> Result<()> {
+        if self.selected_files.is_empty() {
+            self.messages.warn("Select files with Space first");
+            return Ok(());
+        }
+
+        for entry in std::mem::take(&mut self.selected_files) {
+            if entry.is_dir {
+                continue;
+            }
+            self.queue_download(&entry.path, entry.size)?;
+        }
+        Ok(())
+    }
+
+    /// Queue uploads of `files` into the current remote directory
+    fn queue_uploads(&mut self, files: Vec<PathBuf>) {
+        for file in files {
+            let Some(name) = file.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let remote_path = format!("{}/{}", self.current_path.trim_end_matches('/'), name);
+            let size = std::fs::metadata(&file).map_or(0, |m| m.len());
+            self.transfers.push(TransferKind::Upload, remote_path, file, size);
+        }
+        self.transfers.start_next(&self.client);
+    }
+
+    /// Report finished transfers and start the next queued one
+    async fn poll_transfers(&mut self) {
+        while let Some(finished) = self.transfers.poll().await {
+            if let Some(history) = &self.history {
+                let operation = match finished.kind {
+                    TransferKind::Download => Operation::Download,
+                    TransferKind::Upload => Operation::Upload,
+                };
+                history.record(
+                    operation,
+                    &finished.remote_path,
+                    Some(&finished.local_path),
+                    finished.size,
+                    finished.elapsed,
+                    &finished.result,
+                );
+            }
+            let elapsed = finished.elapsed;
+            match (&finished.result, finished.kind) {
+                (Ok(()), TransferKind::Download) => self.messages.info(format!(
+                    "Downloaded {} to {} ({:.1}s)",
+                    finished.name,
+                    finished.local_path.display(),
+                    elapsed.as_secs_f64()
+                )),
+                (Ok(()), TransferKind::Upload) => self.messages.info(format!(
+                    "Uploaded {} to {} ({:.1}s)",
+                    finished.name,
+                    finished.remote_path,
+                    elapsed.as_secs_f64()
+                )),
+                (Err(e), _) => self
+                    .messages
+                    .error(format!("Transfer of {} failed: {}", finished.name, e)),
+            }
+
+            // Show uploads that landed in the directory being viewed
+            let parent = finished.remote_path.rsplit_once('/').map_or("", |(p, _)| p);
+            if finished.kind == TransferKind::Upload
+                && parent == self.current_path.trim_end_matches('/')
+            {
+                self.load_directory();
+            }
+            self.transfers.start_next(&self.client);
+        }
+    }
+
+    fn toggle_selection(&mut self) {
+        if let Some(entry) = self.entries.get(self.selected) {
+            if self.is_selected(&entry.path) {
+                let path = entry.path.clone();
+                self.selected_files.retain(|e| e.path != path);
+            } else {
+                self.selected_files.push(entry.clone());
+            }
+        }
+    }
+
+    fn is_selected(&self, path: &str) -> bool {
+        self.selected_files.iter().any(|e| e.path == path)
+    }
+
+    /// Add the files among the entries shown in `range` to the selection
+    fn select_files(&mut self, range: std::ops::RangeInclusive<usize>) {
+        let Some(shown) = self.entries.get(range) else {
+            return;
+        };
+        for entry in shown {
+            if !entry.is_dir && !self.selected_files.iter().any(|e| e.path == entry.path) {
+                self.selected_files.push(entry.clone());
+            }
+        }
+    }
+
+    fn select_all(&mut self) {
+        if !self.entries.is_empty() {
+            self.select_files(0..=self.entries.len() - 1);
+        }
+    }
+
+    /// Select the files shown that aren't selected and unselect the ones that are
+    fn invert_selection(&mut self) {
+        for entry in self.entries.iter().filter(|e| !e.is_dir) {
+            if let Some(i) = self.selected_files.iter().position(|e| e.path == entry.path) {
+                self.selected_files.remove(i);
+            } else {
+                self.selected_files.push(entry.clone());
+            }
+        }
+    }
+
+    /// Start a range selection at the cursor, or select the files between
+    /// where it started and the cursor
+    fn visual_select(&mut self) {
+        if let Some(range) = self.visual_range() {
+            self.select_files(range);
+            self.visual_anchor = None;
+        } else if !self.entries.is_empty() {
+            self.visual_anchor = Some(self.selected);
+        }
+    }
+
+    fn visual_range(&self) -> Option<std::ops::RangeInclusive<usize>> {
+        let anchor = self.visual_anchor?;
+        Some(anchor.min(self.selected)..=anchor.max(self.selected))
+    }
+
+    fn cycle_sort_mode(&mut self) {
+        self.set_sort(self.sort_mode.next(), self.reverse_sort);
+    }
+
+    fn toggle_reverse_sort(&mut self) {
+        self.set_sort(self.sort_mode, !self.reverse_sort);
+    }
+
+    /// Sort by `mode`, or flip the order if the list is already sorted by it
+    fn sort_by_column(&mut self, mode: SortMode) {
+        if mode == self.sort_mode {
+            self.set_sort(mode, !self.reverse_sort);
+        } else {
+            self.set_sort(mode, false);
+        }
+    }
+
+    /// Sort the current directory, remembering the choice for when it's opened again
+    fn set_sort(&mut self, mode: SortMode, reverse: bool) {
+        self.sort_mode = mode;
+        self.reverse_sort = reverse;
+        self.dir_sorts
+            .insert(self.current_path.clone(), (mode, reverse));
+        self.sort_entries();
+    }
+
+    fn go_up(&mut self) {
+        if self.current_path != "/" {
+            if let Some(parent) = PathBuf::from(&self.current_path).parent() {
+                self.open_directory(parent.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    /// Calculate the size of the selected directory on a background task,
+    /// keeping any earlier result on screen until the new one arrives
+    fn measure_selected(&mut self) {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return;
+        };
+        if !entry.is_dir {
+            self.messages.warn("Select a directory to calculate its size");
+            return;
+        }
+        if self.dir_sizes.get(&entry.path) == Some(&None) {
+            return;
+        }
+
+        let path = entry.path.clone();
+        self.dir_sizes.entry(path.clone()).or_insert(None);
+        let client = self.client.clone();
+        let tx = self.size_tx.clone();
+        tokio::spawn(async move {
+            let result = directory_size(client.lock().await.as_mut(), &path).await;
+            // The receiver only goes away when the browser is closed
+            let _ = tx.send(DirectorySize { path, result });
+        });
+    }
+
+    /// Read the selected file's metadata on a background task and show it
+    fn preview_selected(&mut self) {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return;
+        };
+        if entry.is_dir || !preview::is_previewable(&entry.name) {
+            self.messages
+                .warn("Previews are available for .png and .safetensors files");
+            return;
+        }
+
+        let path = entry.path.clone();
+        self.preview = Some(PreviewPanel {
+            path: path.clone(),
+            lines: None,
+            scroll: 0,
+        });
+        let client = self.client.clone();
+        let tx = self.preview_tx.clone();
+        tokio::spawn(async move {
+            let result = preview::fetch(client.lock().await.as_mut(), &path).await;
+            // The receiver only goes away when the browser is closed
+            let _ = tx.send((path, result));
+        });
+    }
+
+    /// Copy the address of the entry under the cursor, or of the current
+    /// directory if it's empty, so a teammate can open it
+    fn copy_address(&mut self) {
+        let Some(addresses) = &self.addresses else {
+            self.messages.warn("Only server paths have an address to copy");
+            return;
+        };
+        let path = self
+            .entries
+            .get(self.selected)
+            .map_or(&self.current_path, |entry| &entry.path);
+        match addresses
+            .preferred(path)
+            .and_then(|location| clipboard::copy(&location.address).map(|_| location))
+        {
+            Ok(location) => self.messages.info(format!("Copied {}", location.address)),
+            Err(e) => self.messages.error(format!("Could not copy the address: {:#}", e)),
+        }
+    }
+
+    fn poll_preview(&mut self) {
+        while let Ok((path, result)) = self.preview_rx.try_recv() {
+            // Skip previews that were closed before they loaded
+            let Some(panel) = self.preview.as_mut().filter(|panel| panel.path == path) else {
+                continue;
+            };
+            match result {
+                Ok(preview) => panel.lines = Some(preview.lines()),
+                Err(e) => {
+                    self.preview = None;
+                    self.messages
+                        .error(format!("Could not preview {}: {}", path, e));
+                }
+            }
+        }
+    }
+
+    fn poll_sizes(&mut self) {
+        while let Ok(measured) = self.size_rx.try_recv() {
+            match measured.result {
+                Ok(size) => {
+                    self.messages
+                        .info(format!("{}: {}", measured.path, format_bytes(size)));
+                    self.dir_sizes.insert(measured.path, Some(size));
+                }
+                Err(e) => {
+                    self.messages
+                        .error(format!("Could not measure {}: {}", measured.path, e));
+                    self.dir_sizes.remove(&measured.path);
+                }
+            }
+        }
+    }
+
+    /// Show `path`, starting from the top with no filter
+    fn open_directory(&mut self, path: String) {
+        // Directories that haven't been sorted yet use the default order
+        (self.sort_mode, self.reverse_sort) = self
+            .dir_sorts
+            .get(&path)
+            .copied()
+            .unwrap_or((SortMode::Modified, false));
+        self.current_path = path;
+        self.selected = 0;
+        self.visual_anchor = None;
+        self.filter.clear();
+        self.load_directory();
+    }
+
+    /// List the current directory on a background task. The result arrives
+    /// through `listing_rx` and is applied by `poll_listing`.
+    fn load_directory(&mut self) {
+        let client = self.client.clone();
+        let path = self.current_path.clone();
+        let tx = self.listing_tx.clone();
+
+        self.loading = Some(Instant::now());
+        tokio::spawn(async move {
+            let result = client.lock().await.list_files(&path).await;
+            // The receiver only goes away when the browser is closed
+            let _ = tx.send(Listing { path, result });
+        });
+    }
+
+    /// Apply any listings that finished since the last frame
+    fn poll_listing(&mut self) {
+        while let Ok(listing) = self.listing_rx.try_recv() {
+            // Skip results for directories the user has already left
+            if listing.path != self.current_path {
+                continue;
+            }
+            self.loading = None;
+
+            match listing.result {
+                Ok(remote_files) => self.set_entries(remote_files),
+                Err(e) => {
+                    self.all_entries.clear();
+                    self.entries.clear();
+                    self.messages
+                        .error(format!("Could not list {}: {}", listing.path, e));
+                }
+            }
+        }
+    }
+
+    fn set_entries(&mut self, remote_files: Vec<RemoteFile>) {
+        // Convert RemoteFile to FileEntry
+        self.all_entries = remote_files
+            .into_iter()
+            .map(|rf| FileEntry {
+                name: rf.name.clone(),
+                path: rf.path.clone(),
+                size: rf.size,
+                modified: rf.modified,
+                is_dir: rf.is_dir,
+                extension: if rf.is_dir {
+                    None
+                } else {
+                    rf.name
+                        .rfind('.')
+                        .map(|i| rf.name[i + 1..].to_string())
+                },
+            })
+            .collect();
+
+        self.sort_entries();
+    }
+
+    fn spinner(&self) -> Option<char> {
+        let started = self.loading?;
+        let frame = started.elapsed().as_millis() / 100;
+        Some(SPINNER[(frame % SPINNER.len() as u128) as usize])
+    }
+
+    fn sort_entries(&mut self) {
+        sort::sort_entries(&mut self.all_entries, self.sort_mode, self.reverse_sort);
+        self.apply_filter();
+    }
+}
+
+/// Names typed into the browser become a single path component
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        anyhow::bail!("Enter a name");
+    }
+    if name.contains('/') || name.contains('\\') {
+        anyhow::bail!("Names can't contain slashes: {}", name);
+    }
+    Ok(())
+}
+
+/// The label and full path of the root and each directory in `path`
+fn breadcrumbs(path: &str) -> Vec<(String, String)> {
+    let mut crumbs = vec![("/".to_string(), "/".to_string())];
+    let mut current = String::new();
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        current.push('/');
+        current.push_str(part);
+        crumbs.push((part.to_string(), current.clone()));
+    }
+    crumbs
+}
+
+/// Draw `prompt` as a box in the middle of `area`
+fn render_prompt(frame: &mut Frame, area: Rect, prompt: &Prompt) {
+    let width = area.width.min(60);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(3) / 2,
+        width,
+        height: 3.min(area.height),
+    };
+
+    let line = match prompt {
+        Prompt::ConfirmDelete {
+            path,
+            name,
+            is_dir,
+            totals,
+            ..
+        } => {
+            let (verb, rest) = if is_in_trash(path) {
+                ("Delete ", " for good? [y/N]")
+            } else {
+                ("Move ", " to the trash? [y/N]")
+            };
+            let name = if *is_dir {
+                let contents = totals.map_or_else(|| "counting…".to_string(), totals_label);
+                format!("{}/ ({})", name, contents)
+            } else {
+                name.clone()
+            };
+            Line::from(vec![
+                Span::raw(verb),
+                Span::styled(name, Style::default().fg(Color::Yellow)),
+                Span::raw(rest),
+            ])
+        }
+        Prompt::NewDirectory { input }
+        | Prompt::Rename { input, .. }
+        | Prompt::GoTo { input }
+        | Prompt::Glob { input } => Line::from(vec![
+            Span::raw(input.as_str()),
+            Span::styled("▏", Style::default().fg(Color::Yellow)),
+        ]),
+    };
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(line).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Yellow))
+                .title(prompt.title()),
+        ),
+        popup,
+    );
+}
+
+/// Draw the key bindings as a box over `area`, starting `scroll` lines down
+fn render_help(frame: &mut Frame, area: Rect, help: &[(String, &str)], scroll: usize) {
+    let key_width = help.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+    let lines: Vec<Line> = help
+        .iter()
+        .map(|(keys, description)| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:<width$}  ", keys, width = key_width),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(*description),
+            ])
+        })
+        .collect();
+    render_popup(frame, area, "Help", lines, scroll);
+}
+
+/// Draw `lines` in a box over `area`, starting `scroll` lines down
+fn render_popup(frame: &mut Frame, area: Rect, title: &str, lines: Vec<Line>, scroll: usize) {
+    let width = area.width.min(72);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y,
+        width,
+        height: area.height,
+    };
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .scroll((u16::try_from(scroll).unwrap_or(u16::MAX), 0))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Yellow))
+                    .title(title),
+            ),
+        popup,
+    );
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit_idx = 0;
+
+    while size >= 1024.0 && unit_idx < units.len() - 1 {
+        size /= 1024.0;
+        unit_idx += 1;
+    }
+
+    if unit_idx == 0 {
+        format!("{} {}", size as u64, units[unit_idx])
+    } else {
+        format!("{:.1} {}", size, units[unit_idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use messages::Level;
+
+    #[test]
+    fn test_sort_mode_cycle() {
+        assert_eq!(SortMode::Modified.next(), SortMode::Name);
+        assert_eq!(SortMode::Name.next(), SortMode::Size);
+        assert_eq!(SortMode::Size.next(), SortMode::Type);
+        assert_eq!(SortMode::Type.next(), SortMode::Modified);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1024), "1.0 KB");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(1048576), "1.0 MB");
+        assert_eq!(format_bytes(1073741824), "1.0 GB");
+        assert_eq!(format_bytes(1099511627776), "1.0 TB");
+    }
+
+    #[test]
+    fn test_toggle_selection() {
+        use crate::client::{RemoteFile, FileServerClient};
+        use async_trait::async_trait;
+        use std::path::Path;
+        
+        // Create a mock client
+        struct MockClient;
+        
+        #[async_trait]
+        impl FileServerClient for MockClient {
+            async fn connect(&mut self) -> Result<()> { Ok(()) }
+            async fn disconnect(&mut self) -> Result<()> { Ok(()) }
+            async fn list_files(&mut self, _path: &str) -> Result<Vec<RemoteFile>> { Ok(vec![]) }
+            async fn download_file(&mut self, _remote_path: &str, _local_path: &Path) -> Result<()> { Ok(()) }
+            async fn upload_file(&mut self, _local_path: &Path, _remote_path: &str) -> Result<()> { Ok(()) }
+            async fn create_directory(&mut self, _path: &str) -> Result<()> { Ok(()) }
+            async fn delete_file(&mut self, _path: &str) -> Result<()> { Ok(()) }
+            async fn delete_directory(&mut self, _path: &str, _recursive: bool) -> Result<()> {
+                Ok(())
+            }
+            async fn rename(&mut self, _from: &str, _to: &str) -> Result<()> { Ok(()) }
+            async fn get_file_size(&mut self, _path: &str) -> Result<u64> { Ok(0) }
+        }
+        
+        let client: Arc<Mutex<Box<dyn FileServerClient>>> = Arc::new(Mutex::new(Box::new(MockClient)));
+        let mut browser = FileBrowser::new("/".to_string(), client);
+        browser.entries = vec![FileEntry {
+            name: "test.txt".to_string(),
+            path: "/test.txt".to_string(),
+            size: 100,
+            modified: Local::now(),
+            is_dir: false,
+            extension: Some("txt".to_string()),
+        }];
+
+        assert!(browser.selected_files.is_empty());
+
+        browser.toggle_selection();
+        assert_eq!(browser.selected_files.len(), 1);
+        assert_eq!(browser.selected_files[0].path, "/test.txt");
+
+        browser.toggle_selection();
+        assert!(browser.selected_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_enter_downloads_file_to_download_dir() {
+        use crate::client::local::LocalClient;
+        use tempfile::TempDir;
+
+        let server_dir = TempDir::new().unwrap();
+        std::fs::write(server_dir.path().join("model.safetensors"), vec![7u8; 4096]).unwrap();
+        let download_dir = TempDir::new().unwrap();
+
+        let client: Arc<Mutex<Box<dyn FileServerClient>>> = Arc::new(Mutex::new(Box::new(
+            LocalClient::new(server_dir.path().to_path_buf()),
+        )));
+        let mut browser = FileBrowser::new("/".to_string(), client)
+            .with_download_dir(download_dir.path().join("nested"));
+        load(&mut browser).await;
+
+        browser.enter_selected().unwrap();
+        assert!(browser.transfers.running().is_some());
+        wait_for_transfer(&mut browser).await;
+
+        let message = browser.messages.current().unwrap();
+        assert_eq!(message.level, Level::Info);
+        assert!(message.text.starts_with("Downloaded model.safetensors"), "{}", message.text);
+        let downloaded = download_dir.path().join("nested/model.safetensors");
+        assert_eq!(std::fs::read(downloaded).unwrap().len(), 4096);
+    }
+
+    async fn wait_for_transfer(browser: &mut FileBrowser) {
+        while browser.transfers.running().is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_transfers().await;
+        }
+        wait_for_listing(browser).await;
+    }
+
+    async fn wait_for_listing(browser: &mut FileBrowser) {
+        while browser.loading.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_listing();
+        }
+    }
+
+    async fn load(browser: &mut FileBrowser) {
+        browser.load_directory();
+        wait_for_listing(browser).await;
+    }
+
+    fn local_browser(root: &Path, start_path: &str) -> FileBrowser {
+        use crate::client::local::LocalClient;
+
+        let client: Arc<Mutex<Box<dyn FileServerClient>>> =
+            Arc::new(Mutex::new(Box::new(LocalClient::new(root.to_path_buf()))));
+        FileBrowser::new(start_path.to_string(), client)
+    }
+
+    #[tokio::test]
+    async fn test_listing_loads_in_background() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("models")).unwrap();
+        std::fs::write(server_dir.path().join("models/a.ckpt"), b"a").unwrap();
+
+        let mut browser = local_browser(server_dir.path(), "/");
+        browser.load_directory();
+        assert!(browser.loading.is_some());
+        assert!(browser.spinner().is_some());
+
+        wait_for_listing(&mut browser).await;
+        assert_eq!(browser.entries.len(), 1);
+        assert!(browser.spinner().is_none());
+
+        // Enter the directory, then leave before its listing is applied
+        browser.enter_selected().unwrap();
+        assert_eq!(browser.current_path, "/models");
+        browser.go_up();
+        wait_for_listing(&mut browser).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        browser.poll_listing();
+
+        assert_eq!(browser.current_path, "/");
+        assert_eq!(browser.entries[0].name, "models");
+    }
+
+    #[tokio::test]
+    async fn test_listing_error_is_shown_in_status() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        let mut browser = local_browser(server_dir.path(), "/missing");
+        load(&mut browser).await;
+
+        assert!(browser.entries.is_empty());
+        let message = browser.messages.current().unwrap();
+        assert_eq!(message.level, Level::Error);
+        assert!(message.text.starts_with("Could not list /missing"), "{}", message.text);
+    }
+
+    #[tokio::test]
+    async fn test_upload_into_current_directory_and_refresh() {
+        use crate::client::local::LocalClient;
+        use tempfile::TempDir;
+
+        let server_dir = TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("input")).unwrap();
+        let local_dir = TempDir::new().unwrap();
+        let image = local_dir.path().join("cat.png");
+        let mask = local_dir.path().join("mask.png");
+        std::fs::write(&image, b"png").unwrap();
+        std::fs::write(&mask, b"mask").unwrap();
+
+        let client: Arc<Mutex<Box<dyn FileServerClient>>> = Arc::new(Mutex::new(Box::new(
+            LocalClient::new(server_dir.path().to_path_buf()),
+        )));
+        let mut browser = FileBrowser::new("/input".to_string(), client);
+        load(&mut browser).await;
+        assert!(browser.entries.is_empty());
+
+        browser.queue_uploads(vec![image, mask]);
+        wait_for_transfer(&mut browser).await;
+
+        let message = browser.messages.current().unwrap();
+        assert!(message.text.starts_with("Uploaded mask.png to /input/mask.png"), "{}", message.text);
+        assert_eq!(std::fs::read(server_dir.path().join("input/cat.png")).unwrap(), b"png");
+        assert_eq!(browser.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_message_history_scrolls() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        browser.messages.info("first");
+        browser.messages.warn("second");
+
+        let key = |code| KeyEvent::from(code);
+        browser.handle_input(key(KeyCode::Char('m'))).unwrap();
+        assert_eq!(browser.history_scroll, Some(0));
+
+        browser.handle_input(key(KeyCode::Down)).unwrap();
+        browser.handle_input(key(KeyCode::Down)).unwrap();
+        assert_eq!(browser.history_scroll, Some(1));
+
+        // q closes the history rather than quitting
+        assert!(browser.handle_input(key(KeyCode::Char('q'))).unwrap());
+        assert_eq!(browser.history_scroll, None);
+    }
+
+    #[tokio::test]
+    async fn test_filter_narrows_entries_as_you_type() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        for name in ["sdxl_base.safetensors", "lora_SDXL.safetensors", "vae.pt"] {
+            std::fs::write(server_dir.path().join(name), b"x").unwrap();
+        }
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        browser.sort_mode = SortMode::Name;
+        browser.sort_entries();
+        browser.selected = 0;
+
+        let key = |code| KeyEvent::from(code);
+        browser.handle_input(key(KeyCode::Char('/'))).unwrap();
+        for c in "SDXL".chars() {
+            browser.handle_input(key(KeyCode::Char(c))).unwrap();
+        }
+        let names: Vec<_> = browser.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["lora_SDXL.safetensors", "sdxl_base.safetensors"]);
+
+        // Keep the filter, then jump between matches with n/N
+        browser.handle_input(key(KeyCode::Enter)).unwrap();
+        browser.handle_input(key(KeyCode::Char('n'))).unwrap();
+        assert_eq!(browser.selected, 1);
+        browser.handle_input(key(KeyCode::Char('n'))).unwrap();
+        assert_eq!(browser.selected, 0);
+        browser.handle_input(key(KeyCode::Char('N'))).unwrap();
+        assert_eq!(browser.selected, 1);
+
+        // Esc clears the filter instead of quitting, and keeps the selection
+        assert!(browser.handle_input(key(KeyCode::Esc)).unwrap());
+        assert_eq!(browser.entries.len(), 3);
+        assert_eq!(browser.entries[browser.selected].name, "sdxl_base.safetensors");
+    }
+
+    async fn wait_for_operations(browser: &mut FileBrowser) {
+        while browser.pending_ops > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_operations();
+        }
+        wait_for_listing(browser).await;
+    }
+
+    fn type_text(browser: &mut FileBrowser, text: &str) {
+        for c in text.chars() {
+            browser.handle_input(KeyEvent::from(KeyCode::Char(c))).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_operations() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(server_dir.path().join("old.ckpt"), b"weights").unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+
+        // n: new directory
+        browser.handle_input(KeyEvent::from(KeyCode::Char('n'))).unwrap();
+        type_text(&mut browser, "loras");
+        browser.handle_input(KeyEvent::from(KeyCode::Enter)).unwrap();
+        wait_for_operations(&mut browser).await;
+        assert!(server_dir.path().join("loras").is_dir());
+        assert_eq!(browser.entries.len(), 2);
+
+        // F2: rename the file, replacing the suggested name
+        browser.selected = browser.entries.iter().position(|e| !e.is_dir).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::F(2))).unwrap();
+        for _ in "old.ckpt".chars() {
+            browser.handle_input(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        type_text(&mut browser, "new.ckpt");
+        browser.handle_input(KeyEvent::from(KeyCode::Enter)).unwrap();
+        wait_for_operations(&mut browser).await;
+        assert!(server_dir.path().join("new.ckpt").exists());
+        assert!(!server_dir.path().join("old.ckpt").exists());
+
+        // x: anything but y cancels, y moves it to the trash
+        browser.selected = browser.entries.iter().position(|e| !e.is_dir).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char('x'))).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(browser.prompt.is_none());
+        assert!(server_dir.path().join("new.ckpt").exists());
+
+        browser.handle_input(KeyEvent::from(KeyCode::Char('x'))).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char('y'))).unwrap();
+        wait_for_operations(&mut browser).await;
+        assert!(!server_dir.path().join("new.ckpt").exists());
+        assert_eq!(browser.messages.current().unwrap().text, "Moved new.ckpt to the trash");
+        let mut client = crate::client::local::LocalClient::new(server_dir.path().to_path_buf());
+        let trashed = crate::trash::list_trash(&mut client).await.unwrap();
+        assert_eq!(trashed[0].original_path, "/new.ckpt");
+
+        // A directory is counted before y does anything
+        std::fs::write(server_dir.path().join("loras/a.safetensors"), b"lora").unwrap();
+        browser.selected = browser.entries.iter().position(|e| e.name == "loras").unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char('x'))).unwrap();
+        while matches!(browser.prompt, Some(Prompt::ConfirmDelete { totals: None, .. })) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_counts();
+        }
+        let Some(Prompt::ConfirmDelete { totals, .. }) = &browser.prompt else {
+            panic!("the prompt closed");
+        };
+        assert_eq!(*totals, Some(DirTotals { size: 4, files: 1 }));
+        browser.handle_input(KeyEvent::from(KeyCode::Char('y'))).unwrap();
+        wait_for_operations(&mut browser).await;
+        assert!(!server_dir.path().join("loras").exists());
+        assert_eq!(browser.messages.current().unwrap().text, "Moved loras to the trash");
+    }
+
+    #[tokio::test]
+    async fn test_vim_navigation() {
+        use crossterm::event::KeyModifiers;
+
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("loras")).unwrap();
+        std::fs::write(server_dir.path().join("loras/style.safetensors"), b"x").unwrap();
+        for i in 0..30 {
+            std::fs::write(server_dir.path().join(format!("{:02}.png", i)), b"x").unwrap();
+        }
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        browser.page_height = 10;
+
+        type_text(&mut browser, "jjj");
+        assert_eq!(browser.selected, 3);
+        type_text(&mut browser, "k");
+        assert_eq!(browser.selected, 2);
+        type_text(&mut browser, "G");
+        assert_eq!(browser.selected, 30);
+
+        // A single g waits for the second one
+        type_text(&mut browser, "g");
+        assert_eq!(browser.selected, 30);
+        type_text(&mut browser, "g");
+        assert_eq!(browser.selected, 0);
+
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        browser.handle_input(ctrl('d')).unwrap();
+        assert_eq!(browser.selected, 5);
+        browser.handle_input(ctrl('u')).unwrap();
+        assert_eq!(browser.selected, 0);
+
+        // l opens the directory (listed first), h goes back up
+        type_text(&mut browser, "l");
+        assert_eq!(browser.current_path, "/loras");
+        wait_for_listing(&mut browser).await;
+        type_text(&mut browser, "h");
+        assert_eq!(browser.current_path, "/");
+    }
+
+    #[test]
+    fn test_help_overlay() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+
+        type_text(&mut browser, "?");
+        assert_eq!(browser.help_scroll, Some(0));
+        type_text(&mut browser, "jj");
+        assert_eq!(browser.help_scroll, Some(2));
+
+        // q closes the help rather than quitting
+        assert!(browser.handle_input(KeyEvent::from(KeyCode::Char('q'))).unwrap());
+        assert_eq!(browser.help_scroll, None);
+    }
+
+    #[test]
+    fn test_breadcrumbs() {
+        assert_eq!(breadcrumbs("/"), vec![("/".to_string(), "/".to_string())]);
+        let paths: Vec<_> = breadcrumbs("/models/loras")
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        assert_eq!(paths, vec!["/", "/models", "/models/loras"]);
+    }
+
+    #[tokio::test]
+    async fn test_go_to_path_and_click_breadcrumb() {
+        use ratatui::backend::TestBackend;
+
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(server_dir.path().join("models/loras")).unwrap();
+        std::fs::write(server_dir.path().join("models/loras/style.safetensors"), b"x").unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+
+        // : opens the prompt filled in with the current path
+        type_text(&mut browser, ":");
+        assert_eq!(browser.prompt, Some(Prompt::GoTo { input: "/".to_string() }));
+        type_text(&mut browser, "models/loras");
+        browser.handle_input(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(browser.current_path, "/models/loras");
+        wait_for_listing(&mut browser).await;
+        assert_eq!(browser.entries[0].name, "style.safetensors");
+
+        // Clicking the "models" breadcrumb goes back up to it
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|f| browser.render(f)).unwrap();
+        let (row, columns, _) = browser
+            .breadcrumb_hits
+            .iter()
+            .find(|(_, _, path)| path == "/models")
+            .cloned()
+            .unwrap();
+        browser.handle_mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column: columns.start,
+            row,
+            modifiers: crossterm::event::KeyModifiers::NONE,
+        });
+        assert_eq!(browser.current_path, "/models");
+    }
+
+    #[tokio::test]
+    async fn test_hidden_toggle_and_glob() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("loras")).unwrap();
+        std::fs::write(server_dir.path().join("loras/style.safetensors"), b"x").unwrap();
+        std::fs::write(server_dir.path().join("loras/notes.txt"), b"x").unwrap();
+        for name in [".cache", "model.safetensors", "preview.png"] {
+            std::fs::write(server_dir.path().join(name), b"x").unwrap();
+        }
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        assert_eq!(browser.entries.len(), 4);
+
+        type_text(&mut browser, "H");
+        assert_eq!(browser.entries.len(), 3);
+        assert!(browser.entries.iter().all(|e| !e.name.starts_with('.')));
+
+        // Directories stay visible under the glob
+        type_text(&mut browser, "f*.safetensors");
+        browser.handle_input(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let mut names: Vec<_> = browser.entries.iter().map(|e| e.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["loras", "model.safetensors"]);
+
+        // The glob carries over to other directories
+        browser.open_directory("/loras".to_string());
+        wait_for_listing(&mut browser).await;
+        assert_eq!(browser.entries.len(), 1);
+        assert_eq!(browser.entries[0].name, "style.safetensors");
+
+        // An empty glob clears it
+        type_text(&mut browser, "f");
+        for _ in "*.safetensors".chars() {
+            browser.handle_input(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        browser.handle_input(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(browser.glob, None);
+        assert_eq!(browser.entries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_directory_size_is_measured_and_cached() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(server_dir.path().join("models/loras")).unwrap();
+        std::fs::write(server_dir.path().join("models/a.ckpt"), vec![0u8; 1000]).unwrap();
+        std::fs::write(server_dir.path().join("models/loras/b.safetensors"), vec![0u8; 24]).unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+
+        type_text(&mut browser, "z");
+        assert_eq!(browser.dir_sizes.get("/models"), Some(&None));
+        while browser.dir_sizes.get("/models") == Some(&None) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_sizes();
+        }
+        assert_eq!(browser.dir_sizes.get("/models"), Some(&Some(1024)));
+        assert_eq!(browser.messages.current().unwrap().text, "/models: 1.0 KB");
+
+        // Still known after leaving and coming back
+        browser.open_directory("/models".to_string());
+        wait_for_listing(&mut browser).await;
+        browser.go_up();
+        wait_for_listing(&mut browser).await;
+        assert_eq!(browser.dir_sizes.get("/models"), Some(&Some(1024)));
+    }
+
+    #[tokio::test]
+    async fn test_select_all_invert_and_range() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("loras")).unwrap();
+        for (name, size) in [("a.png", 1), ("b.png", 2), ("c.png", 4), ("d.png", 8)] {
+            std::fs::write(server_dir.path().join(name), vec![0u8; size]).unwrap();
+        }
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        browser.sort_mode = SortMode::Name;
+        browser.sort_entries();
+        browser.selected = 0;
+        let selected_names = |browser: &FileBrowser| {
+            let mut names: Vec<_> = browser.selected_files.iter().map(|e| e.name.clone()).collect();
+            names.sort();
+            names
+        };
+
+        // Only files are selected
+        type_text(&mut browser, "a");
+        assert_eq!(selected_names(&browser), vec!["a.png", "b.png", "c.png", "d.png"]);
+
+        // Invert, with b.png unselected first
+        browser.selected = 2;
+        type_text(&mut browser, " A");
+        assert_eq!(selected_names(&browser), vec!["b.png"]);
+
+        // V from c.png down to d.png
+        browser.selected = 3;
+        type_text(&mut browser, "Vj");
+        assert_eq!(browser.visual_range(), Some(3..=4));
+        type_text(&mut browser, "V");
+        assert_eq!(browser.visual_anchor, None);
+        assert_eq!(selected_names(&browser), vec!["b.png", "c.png", "d.png"]);
+        assert_eq!(browser.selected_files.iter().map(|e| e.size).sum::<u64>(), 14);
+
+        // Esc drops a range without selecting it
+        browser.selected = 0;
+        type_text(&mut browser, "Vjj");
+        browser.handle_input(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(browser.visual_anchor, None);
+        assert_eq!(browser.selected_files.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_preview_safetensors_header() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        let header = r#"{"w": {"dtype": "BF16", "shape": [4, 4], "data_offsets": [0, 32]}}"#;
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        file.extend_from_slice(&[0; 32]);
+        std::fs::write(server_dir.path().join("vae.safetensors"), file).unwrap();
+        std::fs::write(server_dir.path().join("notes.txt"), b"x").unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        browser.sort_mode = SortMode::Name;
+        browser.sort_entries();
+
+        // Only some file types have previews
+        browser.selected = 0;
+        type_text(&mut browser, "p");
+        assert!(browser.preview.is_none());
+        assert_eq!(browser.messages.current().unwrap().level, Level::Warn);
+
+        browser.selected = 1;
+        type_text(&mut browser, "p");
+        while browser.preview.as_ref().is_some_and(|p| p.lines.is_none()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            browser.poll_preview();
+        }
+        let lines = browser.preview.as_ref().unwrap().lines.clone().unwrap();
+        assert_eq!(lines[0], "safetensors, 1 tensors, 16 parameters");
+
+        type_text(&mut browser, "p");
+        assert!(browser.preview.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_column_sort_is_remembered_per_directory() {
+        use ratatui::backend::TestBackend;
+
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(server_dir.path().join("loras")).unwrap();
+        std::fs::write(server_dir.path().join("b.png"), vec![0u8; 10]).unwrap();
+        std::fs::write(server_dir.path().join("a.png"), vec![0u8; 20]).unwrap();
+        let mut browser = local_browser(server_dir.path(), "/");
+        load(&mut browser).await;
+        let names = |browser: &FileBrowser| -> Vec<String> {
+            browser.entries.iter().map(|e| e.name.clone()).collect()
+        };
+
+        // 1 sorts by name, and again reverses it. Directories stay first.
+        type_text(&mut browser, "1");
+        assert_eq!(names(&browser), vec!["loras", "a.png", "b.png"]);
+        type_text(&mut browser, "1");
+        assert_eq!(names(&browser), vec!["loras", "b.png", "a.png"]);
+
+        // Subdirectories start with the default sort
+        browser.open_directory("/loras".to_string());
+        assert_eq!((browser.sort_mode, browser.reverse_sort), (SortMode::Modified, false));
+        wait_for_listing(&mut browser).await;
+
+        // Coming back restores the name sort
+        browser.go_up();
+        assert_eq!((browser.sort_mode, browser.reverse_sort), (SortMode::Name, true));
+        wait_for_listing(&mut browser).await;
+        assert_eq!(names(&browser), vec!["loras", "b.png", "a.png"]);
+
+        // Clicking the Size header sorts by size, largest first
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|f| browser.render(f)).unwrap();
+        let (row, columns, _) = browser
+            .column_hits
+            .iter()
+            .find(|(_, _, mode)| *mode == SortMode::Size)
+            .cloned()
+            .unwrap();
+        browser.handle_mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column: columns.start,
+            row,
+            modifiers: crossterm::event::KeyModifiers::NONE,
+        });
+        assert_eq!(names(&browser), vec!["loras", "a.png", "b.png"]);
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("loras").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_selected_queues_each_file() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(server_dir.path().join("a.png"), b"a").unwrap();
+        std::fs::write(server_dir.path().join("b.png"), b"bb").unwrap();
+        let download_dir = tempfile::TempDir::new().unwrap();
+
+        let mut browser = local_browser(server_dir.path(), "/")
+            .with_download_dir(download_dir.path().to_path_buf());
+        load(&mut browser).await;
+
+        browser.handle_input(KeyEvent::from(KeyCode::Char(' '))).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Down)).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char(' '))).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char('d'))).unwrap();
+        assert_eq!(browser.transfers.len(), 2);
+        assert!(browser.selected_files.is_empty());
+
+        wait_for_transfer(&mut browser).await;
+        assert_eq!(std::fs::read(download_dir.path().join("a.png")).unwrap(), b"a");
+        assert_eq!(std::fs::read(download_dir.path().join("b.png")).unwrap(), b"bb");
+
+        // The panel lists both, and clearing drops the finished ones
+        browser.handle_input(KeyEvent::from(KeyCode::Char('t'))).unwrap();
+        browser.handle_input(KeyEvent::from(KeyCode::Char('C'))).unwrap();
+        assert_eq!(browser.transfers.len(), 0);
+        browser.handle_input(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(browser.transfer_panel, None);
+    }
+}
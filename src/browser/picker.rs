@@ -0,0 +1,226 @@
+use anyhow::Result;
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState},
+    Frame,
+};
+use std::path::PathBuf;
+
+use super::format_bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LocalEntry {
+    name: String,
+    path: PathBuf,
+    size: u64,
+    is_dir: bool,
+}
+
+/// Browses the local filesystem to choose files for upload
+pub struct LocalPicker {
+    dir: PathBuf,
+    entries: Vec<LocalEntry>,
+    selected: usize,
+    marked: Vec<PathBuf>,
+    list_state: ListState,
+}
+
+impl LocalPicker {
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        let mut picker = Self {
+            dir,
+            entries: Vec::new(),
+            selected: 0,
+            marked: Vec::new(),
+            list_state: ListState::default(),
+        };
+        picker.load()?;
+        Ok(picker)
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            // Follow symlinks, and skip entries that can't be read at all
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+            entries.push(LocalEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: entry.path(),
+                size: metadata.len(),
+                is_dir: metadata.is_dir(),
+            });
+        }
+
+        // Directories first, then by name
+        entries.sort_by(|a, b| {
+            b.is_dir
+                .cmp(&a.is_dir)
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        self.entries = entries;
+        self.selected = 0;
+        Ok(())
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.selected = self
+            .selected
+            .saturating_add_signed(delta)
+            .min(self.entries.len() - 1);
+    }
+
+    /// Open the selected directory, or pick files when a file is selected.
+    ///
+    /// Returns the marked files (or just the selected one if nothing is marked)
+    /// once the user has chosen what to upload.
+    pub fn enter(&mut self) -> Result<Option<Vec<PathBuf>>> {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return Ok(None);
+        };
+
+        if entry.is_dir {
+            self.dir = entry.path.clone();
+            self.load()?;
+            return Ok(None);
+        }
+
+        if self.marked.is_empty() {
+            Ok(Some(vec![entry.path.clone()]))
+        } else {
+            Ok(Some(std::mem::take(&mut self.marked)))
+        }
+    }
+
+    pub fn go_up(&mut self) -> Result<()> {
+        if let Some(parent) = self.dir.parent() {
+            self.dir = parent.to_path_buf();
+            self.load()?;
+        }
+        Ok(())
+    }
+
+    /// Mark or unmark the selected file. Directories can't be uploaded.
+    pub fn toggle_mark(&mut self) {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return;
+        };
+        if entry.is_dir {
+            return;
+        }
+        if self.marked.contains(&entry.path) {
+            self.marked.retain(|p| p != &entry.path);
+        } else {
+            self.marked.push(entry.path.clone());
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, remote_dir: &str) {
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let mut spans = vec![if self.marked.contains(&entry.path) {
+                    Span::styled("[x] ", Style::default().fg(Color::Green))
+                } else {
+                    Span::raw("[ ] ")
+                }];
+
+                if entry.is_dir {
+                    let style = Style::default().fg(Color::Blue);
+                    spans.push(Span::styled("📁 ", style));
+                    spans.push(Span::styled(&entry.name, style));
+                } else {
+                    spans.push(Span::raw("📄 "));
+                    spans.push(Span::raw(&entry.name));
+                    spans.push(Span::raw(format!(" ({})", format_bytes(entry.size))));
+                }
+
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "Upload to {} - {}",
+                remote_dir,
+                self.dir.display()
+            )))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        self.list_state.select(Some(self.selected));
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn picker_dir() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("b.png"), b"png").unwrap();
+        std::fs::write(temp_dir.path().join("a.safetensors"), b"weights").unwrap();
+        std::fs::create_dir(temp_dir.path().join("loras")).unwrap();
+        std::fs::write(temp_dir.path().join("loras/style.safetensors"), b"lora").unwrap();
+        temp_dir
+    }
+
+    #[test]
+    fn test_lists_directories_first() {
+        let temp_dir = picker_dir();
+        let picker = LocalPicker::new(temp_dir.path().to_path_buf()).unwrap();
+
+        let names: Vec<_> = picker.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["loras", "a.safetensors", "b.png"]);
+    }
+
+    #[test]
+    fn test_enter_navigates_and_picks() {
+        let temp_dir = picker_dir();
+        let mut picker = LocalPicker::new(temp_dir.path().to_path_buf()).unwrap();
+
+        // Into loras/, pick the only file
+        assert_eq!(picker.enter().unwrap(), None);
+        assert_eq!(picker.dir, temp_dir.path().join("loras"));
+        assert_eq!(
+            picker.enter().unwrap(),
+            Some(vec![temp_dir.path().join("loras/style.safetensors")])
+        );
+
+        picker.go_up().unwrap();
+        assert_eq!(picker.dir, temp_dir.path());
+    }
+
+    #[test]
+    fn test_marked_files_are_picked_together() {
+        let temp_dir = picker_dir();
+        let mut picker = LocalPicker::new(temp_dir.path().to_path_buf()).unwrap();
+
+        // Directories can't be marked
+        picker.toggle_mark();
+        assert!(picker.marked.is_empty());
+
+        picker.move_selection(1);
+        picker.toggle_mark();
+        picker.move_selection(1);
+        picker.toggle_mark();
+
+        assert_eq!(
+            picker.enter().unwrap(),
+            Some(vec![
+                temp_dir.path().join("a.safetensors"),
+                temp_dir.path().join("b.png"),
+            ])
+        );
+        assert!(picker.marked.is_empty());
+    }
+}
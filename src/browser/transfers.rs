@@ -0,0 +1,578 @@
+use anyhow::{anyhow, Result};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState},
+    Frame,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::format_bytes;
+use crate::client::FileServerClient;
+use crate::queue::{JobQueue, JobState};
+use crate::transfer::{event_channel, TransferEvent, TransferEvents};
+use crate::utils::format_duration;
+pub use crate::transfer::TransferKind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferState {
+    Queued,
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+pub struct Transfer {
+    pub kind: TransferKind,
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    /// Expected size in bytes, 0 when unknown
+    pub size: u64,
+    pub state: TransferState,
+    /// Its job in the saved queue, if it's kept in one
+    job_id: Option<u64>,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+    /// Bytes the client has reported copying, once it has reported any
+    copied: Option<u64>,
+    task: Option<JoinHandle<Result<()>>>,
+    events: Option<TransferEvents>,
+}
+
+impl Transfer {
+    pub fn name(&self) -> String {
+        let name = match self.kind {
+            TransferKind::Download => self.remote_path.rsplit('/').next(),
+            TransferKind::Upload => self.local_path.file_name().and_then(|n| n.to_str()),
+        };
+        name.unwrap_or_default().to_string()
+    }
+
+    /// Bytes transferred so far, when that can be observed. Clients that don't
+    /// stream only report a file once it's all copied.
+    pub fn bytes_done(&self) -> Option<u64> {
+        match &self.state {
+            TransferState::Done => Some(self.size),
+            TransferState::Running => self.copied.map(|copied| copied.min(self.size)),
+            _ => None,
+        }
+    }
+
+    /// Catch up with the events of its task
+    fn update(&mut self) {
+        let Some(events) = &mut self.events else {
+            return;
+        };
+        while let Some(event) = events.try_next() {
+            match event {
+                // A retry starts the file over
+                TransferEvent::Started { .. } => self.copied = None,
+                TransferEvent::Bytes { bytes, .. } => {
+                    self.copied = Some(self.copied.unwrap_or(0) + bytes);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        match self.bytes_done() {
+            Some(done) if self.size > 0 => (done as f64 / self.size as f64).clamp(0.0, 1.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Average speed in bytes per second since the transfer started
+    #[allow(clippy::cast_precision_loss)]
+    pub fn speed(&self) -> Option<f64> {
+        let elapsed = self.elapsed.or_else(|| self.started.map(|s| s.elapsed()))?;
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return None;
+        }
+        Some(self.bytes_done()? as f64 / secs)
+    }
+
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn eta(&self) -> Option<Duration> {
+        let speed = self.speed().filter(|s| *s > 0.0)?;
+        let remaining = self.size.saturating_sub(self.bytes_done()?);
+        Some(Duration::from_secs((remaining as f64 / speed).ceil() as u64))
+    }
+
+    /// One-line progress summary, e.g. `45% 12.3 MB/s ETA 0:12`
+    pub fn progress_label(&self) -> String {
+        match &self.state {
+            TransferState::Queued => "queued".to_string(),
+            TransferState::Running => match self.bytes_done() {
+                Some(done) => {
+                    let mut label = format!("{} / {}", format_bytes(done), format_bytes(self.size));
+                    if let Some(speed) = self.speed() {
+                        label.push_str(&format!("  {}/s", format_speed(speed)));
+                    }
+                    if let Some(eta) = self.eta() {
+                        label.push_str(&format!("  ETA {}", format_duration(eta)));
+                    }
+                    label
+                }
+                None => format!(
+                    "{} ({})",
+                    format_bytes(self.size),
+                    format_duration(self.started.map(|s| s.elapsed()).unwrap_or_default())
+                ),
+            },
+            TransferState::Done => {
+                let elapsed = self.elapsed.unwrap_or_default();
+                format!("done in {:.1}s", elapsed.as_secs_f64())
+            }
+            TransferState::Failed(e) => format!("failed: {}", e),
+            TransferState::Cancelled => "cancelled".to_string(),
+        }
+    }
+}
+
+/// A finished transfer, for the browser to report
+pub struct Finished {
+    pub kind: TransferKind,
+    pub name: String,
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    pub size: u64,
+    pub elapsed: Duration,
+    pub result: Result<()>,
+}
+
+/// Downloads and uploads waiting for or using the connection.
+///
+/// Transfers share the browser's single client, so they run one at a time in
+/// the order they were queued.
+///
+/// With a saved queue, the panel works on the same jobs as `comfy-fs queue`:
+/// pending jobs show up as queued, and what's queued, finished, cancelled, or
+/// cleared here is saved there. Downloads then resume from partial files.
+#[derive(Default)]
+pub struct TransferQueue {
+    items: Vec<Transfer>,
+    store: Option<JobQueue>,
+}
+
+impl TransferQueue {
+    /// A queue kept in `store`, starting with its pending jobs
+    pub fn with_store(store: JobQueue) -> Result<Self> {
+        let items = store
+            .pending()?
+            .into_iter()
+            .map(|job| Transfer {
+                kind: job.kind,
+                remote_path: job.remote_path,
+                local_path: job.local_path,
+                size: job.size,
+                state: TransferState::Queued,
+                job_id: Some(job.id),
+                started: None,
+                elapsed: None,
+                copied: None,
+                task: None,
+                events: None,
+            })
+            .collect();
+        Ok(Self {
+            items,
+            store: Some(store),
+        })
+    }
+
+    pub fn push(&mut self, kind: TransferKind, remote_path: String, local_path: PathBuf, size: u64) {
+        let job_id = self.store.as_ref().and_then(|store| {
+            store
+                .add(kind, remote_path.clone(), local_path.clone(), size)
+                .map_err(|e| warn!("Could not save {} to the queue: {:#}", remote_path, e))
+                .ok()
+                .map(|job| job.id)
+        });
+        self.items.push(Transfer {
+            kind,
+            remote_path,
+            local_path,
+            size,
+            state: TransferState::Queued,
+            job_id,
+            started: None,
+            elapsed: None,
+            copied: None,
+            task: None,
+            events: None,
+        });
+    }
+
+    /// Start the next queued transfer if nothing is running
+    pub fn start_next(&mut self, client: &Arc<Mutex<Box<dyn FileServerClient>>>) {
+        if self.running().is_some() {
+            return;
+        }
+        let Some(item) = self
+            .items
+            .iter_mut()
+            .find(|item| item.state == TransferState::Queued)
+        else {
+            return;
+        };
+
+        let client = client.clone();
+        let (kind, size) = (item.kind, item.size);
+        let remote_path = item.remote_path.clone();
+        let local_path = item.local_path.clone();
+        // A saved job may be picked up again later, so it keeps what it got
+        let resume = item.job_id.is_some();
+        let (events, receiver) = event_channel();
+        item.task = Some(tokio::spawn(async move {
+            let mut client = client.lock().await;
+            events.send(TransferEvent::Started { index: 0, size });
+            let progress = events.progress(0);
+            let result = match kind {
+                TransferKind::Download => {
+                    if let Some(parent) = local_path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    if resume {
+                        client.resume_download(&remote_path, &local_path, progress).await
+                    } else {
+                        client
+                            .download_file_with_progress(&remote_path, &local_path, progress)
+                            .await
+                    }
+                }
+                TransferKind::Upload => {
+                    client
+                        .upload_file_with_progress(&local_path, &remote_path, progress)
+                        .await
+                }
+            };
+            events.send(match &result {
+                Ok(()) => TransferEvent::Finished {
+                    index: 0,
+                    retries: 0,
+                },
+                Err(e) => TransferEvent::Failed {
+                    index: 0,
+                    error: format!("{:#}", e),
+                },
+            });
+            result
+        }));
+        item.events = Some(receiver);
+        item.copied = None;
+        item.state = TransferState::Running;
+        item.started = Some(Instant::now());
+    }
+
+    /// Collect the transfer that finished since the last call, if any
+    pub async fn poll(&mut self) -> Option<Finished> {
+        for item in &mut self.items {
+            item.update();
+        }
+        let item = self.items.iter_mut().find(|item| {
+            item.state == TransferState::Running
+                && item.task.as_ref().is_some_and(JoinHandle::is_finished)
+        })?;
+        let task = item.task.take()?;
+        // Whatever it sent before finishing
+        item.update();
+        item.events = None;
+        let elapsed = item.started.map(|s| s.elapsed()).unwrap_or_default();
+        item.elapsed = Some(elapsed);
+
+        let result = match task.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(anyhow!(e)),
+        };
+        item.state = match &result {
+            Ok(_) => TransferState::Done,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+        if let (Some(store), Some(id)) = (&self.store, item.job_id) {
+            let state = match &result {
+                Ok(()) => JobState::Done,
+                Err(e) => JobState::Failed {
+                    error: format!("{:#}", e),
+                },
+            };
+            if let Err(e) = store.set_state(id, state) {
+                warn!("Could not update job {} in the queue: {:#}", id, e);
+            }
+        }
+
+        Some(Finished {
+            kind: item.kind,
+            name: item.name(),
+            remote_path: item.remote_path.clone(),
+            local_path: item.local_path.clone(),
+            size: item.size,
+            elapsed,
+            result,
+        })
+    }
+
+    /// Cancel a queued or running transfer. A running one is abandoned where it
+    /// is, which can leave a partial file behind.
+    pub fn cancel(&mut self, index: usize) -> bool {
+        let Some(item) = self.items.get_mut(index) else {
+            return false;
+        };
+        if !matches!(item.state, TransferState::Queued | TransferState::Running) {
+            return false;
+        }
+        if let Some(task) = item.task.take() {
+            task.abort();
+        }
+        item.events = None;
+        item.elapsed = item.started.map(|s| s.elapsed());
+        item.state = TransferState::Cancelled;
+        if let (Some(store), Some(id)) = (&self.store, item.job_id) {
+            if let Err(e) = store.remove(id) {
+                warn!("Could not remove job {} from the queue: {:#}", id, e);
+            }
+        }
+        true
+    }
+
+    /// Drop transfers that are done, failed, or cancelled, and the saved jobs
+    /// that are done or failed
+    pub fn clear_finished(&mut self) {
+        self.items
+            .retain(|item| matches!(item.state, TransferState::Queued | TransferState::Running));
+        if let Some(store) = &self.store {
+            if let Err(e) = store.clear_finished() {
+                warn!("Could not clear finished jobs from the queue: {:#}", e);
+            }
+        }
+    }
+
+    pub fn running(&self) -> Option<&Transfer> {
+        self.items
+            .iter()
+            .find(|item| item.state == TransferState::Running)
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.state == TransferState::Queued)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn render(&self, frame: &mut Frame, area: Rect, selected: usize) {
+        let items: Vec<ListItem> = self
+            .items
+            .iter()
+            .map(|item| {
+                let (icon, color) = match item.state {
+                    TransferState::Queued => ("…", Color::DarkGray),
+                    TransferState::Running => ("▶", Color::Cyan),
+                    TransferState::Done => ("✓", Color::Green),
+                    TransferState::Failed(_) => ("✗", Color::Red),
+                    TransferState::Cancelled => ("-", Color::DarkGray),
+                };
+                let arrow = match item.kind {
+                    TransferKind::Download => "↓",
+                    TransferKind::Upload => "↑",
+                };
+                let percent = match item.bytes_done() {
+                    Some(_) if item.state == TransferState::Running => {
+                        format!(" {:>3.0}%", item.ratio() * 100.0)
+                    }
+                    _ => String::new(),
+                };
+
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{} {} ", icon, arrow), Style::default().fg(color)),
+                    Span::raw(item.name()),
+                    Span::styled(percent, Style::default().fg(color)),
+                    Span::styled(
+                        format!("  {}", item.progress_label()),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "Transfers ({} queued)",
+                self.queued_count()
+            )))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        let mut state = ListState::default().with_selected(Some(selected));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn format_speed(bytes_per_sec: f64) -> String {
+    format_bytes(bytes_per_sec as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn local_client(root: &Path) -> Arc<Mutex<Box<dyn FileServerClient>>> {
+        Arc::new(Mutex::new(Box::new(LocalClient::new(root.to_path_buf()))))
+    }
+
+    async fn wait_for(queue: &mut TransferQueue) -> Finished {
+        loop {
+            if let Some(finished) = queue.poll().await {
+                return finished;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfers_run_one_at_a_time_in_order() {
+        let server_dir = TempDir::new().unwrap();
+        std::fs::write(server_dir.path().join("a.bin"), vec![1u8; 100]).unwrap();
+        std::fs::write(server_dir.path().join("b.bin"), vec![2u8; 200]).unwrap();
+        let local_dir = TempDir::new().unwrap();
+        let client = local_client(server_dir.path());
+
+        let mut queue = TransferQueue::default();
+        for (name, size) in [("a.bin", 100), ("b.bin", 200)] {
+            queue.push(
+                TransferKind::Download,
+                format!("/{}", name),
+                local_dir.path().join("out").join(name),
+                size,
+            );
+        }
+
+        queue.start_next(&client);
+        queue.start_next(&client);
+        assert_eq!(queue.running().unwrap().name(), "a.bin");
+        assert_eq!(queue.queued_count(), 1);
+
+        let finished = wait_for(&mut queue).await;
+        assert_eq!(finished.name, "a.bin");
+        assert!(finished.result.is_ok());
+        // Its progress came from the client as it copied
+        assert_eq!(queue.items[0].copied, Some(100));
+
+        queue.start_next(&client);
+        let finished = wait_for(&mut queue).await;
+        assert_eq!(finished.name, "b.bin");
+        assert_eq!(std::fs::read(local_dir.path().join("out/b.bin")).unwrap().len(), 200);
+
+        assert!(queue.items.iter().all(|item| item.state == TransferState::Done));
+        queue.clear_finished();
+        assert!(queue.items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued_transfer() {
+        let server_dir = TempDir::new().unwrap();
+        let mut queue = TransferQueue::default();
+        queue.push(TransferKind::Upload, "/a.png".into(), server_dir.path().join("a.png"), 3);
+
+        assert!(queue.cancel(0));
+        assert_eq!(queue.items[0].state, TransferState::Cancelled);
+        // Already finished
+        assert!(!queue.cancel(0));
+
+        queue.start_next(&local_client(server_dir.path()));
+        assert!(queue.running().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_transfer_is_reported() {
+        let server_dir = TempDir::new().unwrap();
+        let client = local_client(server_dir.path());
+        let mut queue = TransferQueue::default();
+        queue.push(
+            TransferKind::Download,
+            "/missing.bin".into(),
+            server_dir.path().join("local.bin"),
+            10,
+        );
+
+        queue.start_next(&client);
+        let finished = wait_for(&mut queue).await;
+        assert!(finished.result.is_err());
+        assert!(matches!(queue.items[0].state, TransferState::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_saved_queue_is_shared() {
+        let server_dir = TempDir::new().unwrap();
+        std::fs::write(server_dir.path().join("a.bin"), vec![1u8; 10]).unwrap();
+        let local_dir = TempDir::new().unwrap();
+        let store = JobQueue::new(local_dir.path().join("queue.json"));
+        // Added from the command line before the browser opened
+        store
+            .add(
+                TransferKind::Download,
+                "/a.bin".into(),
+                local_dir.path().join("a.bin"),
+                10,
+            )
+            .unwrap();
+
+        let mut queue = TransferQueue::with_store(store.clone()).unwrap();
+        assert_eq!(queue.queued_count(), 1);
+        queue.push(TransferKind::Upload, "/b.png".into(), local_dir.path().join("b.png"), 3);
+        assert_eq!(store.pending().unwrap().len(), 2);
+
+        queue.start_next(&local_client(server_dir.path()));
+        assert!(wait_for(&mut queue).await.result.is_ok());
+        assert!(queue.cancel(1));
+        let jobs = store.jobs().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].state, JobState::Done);
+
+        queue.clear_finished();
+        assert!(store.jobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_progress_estimates() {
+        let transfer = Transfer {
+            kind: TransferKind::Upload,
+            remote_path: "/a.bin".into(),
+            local_path: PathBuf::from("a.bin"),
+            size: 1000,
+            state: TransferState::Done,
+            job_id: None,
+            started: None,
+            elapsed: Some(Duration::from_secs(2)),
+            copied: None,
+            task: None,
+            events: None,
+        };
+
+        assert_eq!(transfer.bytes_done(), Some(1000));
+        assert_eq!(transfer.speed(), Some(500.0));
+        assert_eq!(transfer.eta(), Some(Duration::ZERO));
+        assert_eq!(transfer.progress_label(), "done in 2.0s");
+    }
+}
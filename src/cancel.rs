@@ -0,0 +1,88 @@
+//! Stopping transfers on Ctrl-C without leaving half-written files behind
+
+use crate::exit::{Exit, ExitStatus};
+use anyhow::Result;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A local file being downloaded, removed when dropped unless [`keep`](Self::keep)
+/// was called. Dropping a transfer's future part way, as Ctrl-C does, so cleans
+/// up what it wrote. A file the download never got to write to is left alone.
+pub struct PartialFile {
+    path: Option<PathBuf>,
+    before: Option<(u64, Option<SystemTime>)>,
+}
+
+impl PartialFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            before: Self::stamp(path),
+        }
+    }
+
+    fn stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()))
+    }
+
+    /// The download finished; leave the file where it is
+    pub fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            if Self::stamp(&path) != self.before {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Run `task` until it finishes or Ctrl-C is pressed. On Ctrl-C `task` is
+/// dropped where it is, which removes its [`PartialFile`]s and progress bars,
+/// and an error says so.
+pub async fn interruptible<T>(task: impl Future<Output = T>) -> Result<T> {
+    tokio::select! {
+        result = task => Ok(result),
+        _ = tokio::signal::ctrl_c() => {
+            Err(Exit::new(ExitStatus::Interrupted, "Interrupted").into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let abandoned = dir.path().join("abandoned.bin");
+        let finished = dir.path().join("finished.bin");
+        let untouched = dir.path().join("untouched.bin");
+        std::fs::write(&untouched, b"old").unwrap();
+
+        let partial = PartialFile::new(&abandoned);
+        std::fs::write(&abandoned, b"half").unwrap();
+        drop(partial);
+        let partial = PartialFile::new(&finished);
+        std::fs::write(&finished, b"whole").unwrap();
+        partial.keep();
+        // Failed before it started writing
+        drop(PartialFile::new(&untouched));
+
+        assert!(!abandoned.exists());
+        assert!(finished.exists());
+        assert!(untouched.exists());
+    }
+
+    #[tokio::test]
+    async fn test_interruptible_passes_results_through() {
+        assert_eq!(interruptible(async { 7 }).await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,222 @@
+//! Reading remote files without saving them locally
+
+use crate::client::FileServerClient;
+use anyhow::Result;
+use std::io::Write;
+
+/// How much `copy_to` asks for at a time
+const STREAM_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// How much `head_lines` and `tail_lines` ask for at a time
+const LINE_CHUNK: u64 = 64 * 1024;
+
+/// Write the whole file at `path` to `out`, a chunk at a time
+pub async fn copy_to(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    out: &mut impl Write,
+) -> Result<u64> {
+    let chunk_size = chunk_size(client, STREAM_CHUNK);
+    let mut offset = 0;
+    loop {
+        let chunk = client.read_range(path, offset, chunk_size).await?;
+        out.write_all(&chunk)?;
+        offset += chunk.len() as u64;
+        if (chunk.len() as u64) < chunk_size {
+            return Ok(offset);
+        }
+    }
+}
+
+/// `wanted`, or the whole file at once from servers where every ranged read
+/// downloads all of it
+fn chunk_size(client: &dyn FileServerClient, wanted: u64) -> u64 {
+    if client.capabilities().ranged_reads {
+        wanted
+    } else {
+        u64::MAX
+    }
+}
+
+/// The first `lines` lines of the file, reading only as far as needed
+pub async fn head_lines(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    lines: usize,
+) -> Result<Vec<u8>> {
+    let chunk_size = chunk_size(client, LINE_CHUNK);
+    head_lines_in_chunks(client, path, lines, chunk_size).await
+}
+
+async fn head_lines_in_chunks(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    lines: usize,
+    chunk_size: u64,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    if lines == 0 {
+        return Ok(data);
+    }
+
+    loop {
+        let chunk = client.read_range(path, data.len() as u64, chunk_size).await?;
+        let at_end = (chunk.len() as u64) < chunk_size;
+        data.extend_from_slice(&chunk);
+
+        let mut newlines = data.iter().enumerate().filter(|(_, b)| **b == b'\n');
+        if let Some((end, _)) = newlines.nth(lines - 1) {
+            data.truncate(end + 1);
+            return Ok(data);
+        }
+        if at_end {
+            return Ok(data);
+        }
+    }
+}
+
+/// The last `lines` lines of the file, reading backwards from the end
+pub async fn tail_lines(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    lines: usize,
+) -> Result<Vec<u8>> {
+    let chunk_size = chunk_size(client, LINE_CHUNK);
+    tail_lines_in_chunks(client, path, lines, chunk_size).await
+}
+
+async fn tail_lines_in_chunks(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    lines: usize,
+    chunk_size: u64,
+) -> Result<Vec<u8>> {
+    if lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut position = client.get_file_size(path).await?;
+    let mut data = Vec::new();
+    while position > 0 {
+        let len = chunk_size.min(position);
+        position -= len;
+        let mut chunk = client.read_range(path, position, len).await?;
+        chunk.extend_from_slice(&data);
+        data = chunk;
+
+        if let Some(start) = tail_start(&data, lines) {
+            return Ok(data.split_off(start));
+        }
+    }
+    Ok(data)
+}
+
+/// Where the last `lines` lines of `data` begin, if `data` holds all of them. A
+/// newline at the very end doesn't start another line.
+fn tail_start(data: &[u8], lines: usize) -> Option<usize> {
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    body.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(lines - 1)
+        .map(|(newline, _)| newline + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use crate::client::RemoteFile;
+    use std::path::Path;
+
+    fn client(contents: &[u8]) -> (tempfile::TempDir, LocalClient) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("log.txt"), contents).unwrap();
+        let client = LocalClient::new(dir.path().to_path_buf());
+        (dir, client)
+    }
+
+    #[tokio::test]
+    async fn test_copy_to() {
+        let (_dir, mut client) = client(b"one\ntwo\n");
+        let mut out = Vec::new();
+
+        assert_eq!(copy_to(&mut client, "/log.txt", &mut out).await.unwrap(), 8);
+        assert_eq!(out, b"one\ntwo\n");
+    }
+
+    #[tokio::test]
+    async fn test_head_lines() {
+        let (_dir, mut client) = client(b"one\ntwo\nthree\nfour");
+
+        for chunk in [1, 3, 64] {
+            let head = head_lines_in_chunks(&mut client, "/log.txt", 2, chunk).await.unwrap();
+            assert_eq!(head, b"one\ntwo\n");
+        }
+        let all = head_lines_in_chunks(&mut client, "/log.txt", 10, 3).await.unwrap();
+        assert_eq!(all, b"one\ntwo\nthree\nfour");
+        assert!(head_lines(&mut client, "/log.txt", 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tail_lines() {
+        let (_dir, mut client) = client(b"one\ntwo\nthree\nfour\n");
+
+        for chunk in [1, 3, 64] {
+            let tail = tail_lines_in_chunks(&mut client, "/log.txt", 2, chunk).await.unwrap();
+            assert_eq!(tail, b"three\nfour\n");
+        }
+        let all = tail_lines_in_chunks(&mut client, "/log.txt", 10, 3).await.unwrap();
+        assert_eq!(all, b"one\ntwo\nthree\nfour\n");
+    }
+
+    /// A server without ranged reads, counting how often files are downloaded
+    struct WholeFiles {
+        inner: LocalClient,
+        downloads: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl FileServerClient for WholeFiles {
+        async fn connect(&mut self) -> Result<()> { Ok(()) }
+        async fn disconnect(&mut self) -> Result<()> { Ok(()) }
+        async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+            self.inner.list_files(path).await
+        }
+        async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+            self.downloads += 1;
+            self.inner.download_file(remote_path, local_path).await
+        }
+        async fn upload_file(&mut self, _local_path: &Path, _remote_path: &str) -> Result<()> { Ok(()) }
+        async fn create_directory(&mut self, _path: &str) -> Result<()> { Ok(()) }
+        async fn delete_file(&mut self, _path: &str) -> Result<()> { Ok(()) }
+        async fn delete_directory(&mut self, _path: &str, _recursive: bool) -> Result<()> { Ok(()) }
+        async fn rename(&mut self, _from: &str, _to: &str) -> Result<()> { Ok(()) }
+        async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+            self.inner.get_file_size(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_whole_file_read_once_without_ranged_reads() {
+        let lines: String = (0..20_000).map(|n| format!("line {}\n", n)).collect();
+        let (_dir, inner) = client(lines.as_bytes());
+        let mut client = WholeFiles { inner, downloads: 0 };
+
+        let mut out = Vec::new();
+        copy_to(&mut client, "/log.txt", &mut out).await.unwrap();
+        assert_eq!(out, lines.as_bytes());
+        // Three of the usual chunks, but one download
+        let head = head_lines(&mut client, "/log.txt", 15_000).await.unwrap();
+        assert!(head.ends_with(b"line 14999\n"));
+        assert_eq!(client.downloads, 2);
+    }
+
+    #[test]
+    fn test_tail_start() {
+        assert_eq!(tail_start(b"a\nb\nc", 1), Some(4));
+        assert_eq!(tail_start(b"a\nb\nc\n", 2), Some(2));
+        assert_eq!(tail_start(b"a\nb\n", 2), None);
+    }
+}
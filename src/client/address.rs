@@ -0,0 +1,124 @@
+//! Server addresses as people write them, a host name or an IPv4 or IPv6
+//! address, and the order to try what a name resolves to
+
+use crate::config::IpPreference;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// The host part of the configured server, which can be a bare host, a
+/// `host:port`, or for HTTP a full URL. IPv6 addresses come without brackets.
+pub fn host_name(server: &str) -> String {
+    let server = server.split_once("://").map_or(server, |(_, rest)| rest);
+    let authority = server.split('/').next().unwrap_or(server);
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    if let Some(bracketed) = authority.strip_prefix('[') {
+        return bracketed.split(']').next().unwrap_or(bracketed).to_string();
+    }
+    match authority.rsplit_once(':') {
+        // A single colon is a port; more is an IPv6 address
+        Some((host, _)) if !host.contains(':') => host.to_string(),
+        _ => authority.to_string(),
+    }
+}
+
+/// `host:port`, with an IPv6 address in brackets so its colons aren't taken
+/// for the port's
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// `host` as it goes in a URL: an IPv6 address in brackets, anything else
+/// as it is
+pub fn url_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => host.to_string(),
+    }
+}
+
+/// `host` as it goes in a UNC path, which has no room for an IPv6 address's
+/// colons: those are written the Windows way, as `fd00--2.ipv6-literal.net`
+pub fn unc_host(host: &str) -> String {
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!(
+            "{}.ipv6-literal.net",
+            ip.to_string().replace(':', "-")
+        ),
+        _ => host.to_string(),
+    }
+}
+
+/// The addresses `address` (`host:port`) resolves to, those of the kind
+/// `preference` asks for first
+pub fn resolve(address: &str, preference: IpPreference) -> io::Result<Vec<SocketAddr>> {
+    let mut addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    order(&mut addresses, preference);
+    Ok(addresses)
+}
+
+/// Like [`resolve`], without blocking the runtime on the lookup
+pub async fn lookup(address: &str, preference: IpPreference) -> io::Result<Vec<SocketAddr>> {
+    let mut addresses: Vec<SocketAddr> = tokio::net::lookup_host(address).await?.collect();
+    order(&mut addresses, preference);
+    Ok(addresses)
+}
+
+/// Put the kind of address `preference` asks for first, keeping the
+/// resolver's order otherwise
+fn order(addresses: &mut [SocketAddr], preference: IpPreference) {
+    match preference {
+        IpPreference::Auto => {}
+        IpPreference::Ipv4 => addresses.sort_by_key(|address| !address.is_ipv4()),
+        IpPreference::Ipv6 => addresses.sort_by_key(|address| !address.is_ipv6()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_name() {
+        assert_eq!(host_name("192.168.1.10"), "192.168.1.10");
+        assert_eq!(host_name("files.local:2121"), "files.local");
+        assert_eq!(
+            host_name("https://user@files.example.com:8443/models/"),
+            "files.example.com"
+        );
+        assert_eq!(host_name("[fe80::1]:21"), "fe80::1");
+        assert_eq!(host_name("fe80::1"), "fe80::1");
+    }
+
+    #[test]
+    fn test_host_port() {
+        assert_eq!(host_port("files.local", 21), "files.local:21");
+        assert_eq!(host_port("10.0.0.1", 22), "10.0.0.1:22");
+        assert_eq!(host_port("fd00::2", 21), "[fd00::2]:21");
+        assert_eq!(host_port("[fd00::2]", 21), "[fd00::2]:21");
+        assert_eq!(url_host("fd00::2"), "[fd00::2]");
+        assert_eq!(url_host("files.local"), "files.local");
+        assert_eq!(unc_host("fd00::2"), "fd00--2.ipv6-literal.net");
+        assert_eq!(unc_host("192.168.1.10"), "192.168.1.10");
+
+        let mut addresses: Vec<SocketAddr> = ["[::1]:21", "127.0.0.1:21", "[::2]:21"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        order(&mut addresses, IpPreference::Ipv4);
+        assert_eq!(addresses[0], "127.0.0.1:21".parse().unwrap());
+        assert_eq!(addresses[1], "[::1]:21".parse().unwrap());
+        order(&mut addresses, IpPreference::Ipv6);
+        assert_eq!(addresses[2], "127.0.0.1:21".parse().unwrap());
+        assert_eq!(
+            resolve("[::1]:21", IpPreference::Ipv4).unwrap(),
+            ["[::1]:21".parse().unwrap()]
+        );
+    }
+}
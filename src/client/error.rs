@@ -0,0 +1,315 @@
+//! What went wrong talking to a server, sorted into the kinds of failure the
+//! CLI and the retry logic treat differently
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// libssh2 session errors, as `LIBSSH2_ERROR_*`
+const SSH_SOCKET_SEND: i32 = -7;
+const SSH_TIMEOUT: i32 = -9;
+const SSH_SOCKET_DISCONNECT: i32 = -13;
+const SSH_AUTHENTICATION_FAILED: i32 = -18;
+const SSH_PUBLICKEY_UNVERIFIED: i32 = -19;
+const SSH_SOCKET_RECV: i32 = -43;
+
+/// SFTP statuses, as `LIBSSH2_FX_*`
+pub(crate) const SFTP_NO_SUCH_FILE: i32 = 2;
+const SFTP_PERMISSION_DENIED: i32 = 3;
+const SFTP_NO_CONNECTION: i32 = 6;
+const SFTP_CONNECTION_LOST: i32 = 7;
+const SFTP_OP_UNSUPPORTED: i32 = 8;
+const SFTP_NO_SUCH_PATH: i32 = 10;
+
+/// A failure talking to a server. Clients return these inside `anyhow::Error`
+/// where they know what went wrong, and [`ClientError::classify`] works it out
+/// from the protocol libraries' own errors otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// Nothing at the path, made with [`ClientError::not_found`] by clients
+    NotFound(String),
+    /// The server refused to let this user do it
+    PermissionDenied(String),
+    /// The username or password was refused
+    AuthenticationFailed(String),
+    /// The server couldn't be reached, or the connection dropped
+    ConnectionLost(String),
+    /// The server stopped answering
+    Timeout(String),
+    /// The protocol or server can't do it at all
+    Unsupported(String),
+    /// Anything else the server or protocol library reported
+    Protocol(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(message)
+            | Self::PermissionDenied(message)
+            | Self::AuthenticationFailed(message)
+            | Self::ConnectionLost(message)
+            | Self::Timeout(message)
+            | Self::Unsupported(message)
+            | Self::Protocol(message) => f.write_str(message),
+        }
+    }
+}
+
+impl StdError for ClientError {}
+
+impl ClientError {
+    /// The error for a path that isn't on the server
+    pub fn not_found(path: &str) -> Self {
+        Self::NotFound(format!("No such file or directory: {}", path))
+    }
+
+    /// Whether trying again might work. Missing paths, refused permissions and
+    /// passwords, and things the server can't do will fail the same way again.
+    pub const fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectionLost(_) | Self::Timeout(_) | Self::Protocol(_)
+        )
+    }
+
+    /// What kind of failure `error` is, from the first error in its chain this
+    /// recognizes. Errors nothing recognizes are [`ClientError::Protocol`].
+    pub fn classify(error: &anyhow::Error) -> Self {
+        let message = error.to_string();
+        error
+            .chain()
+            .find_map(|cause| Self::recognize(cause, &message))
+            .unwrap_or(Self::Protocol(message))
+    }
+
+    fn recognize(cause: &(dyn StdError + 'static), message: &str) -> Option<Self> {
+        if let Some(error) = cause.downcast_ref::<Self>() {
+            return Some(error.clone());
+        }
+        if let Some(error) = cause.downcast_ref::<io::Error>() {
+            return Self::from_io(error.kind(), message);
+        }
+        if let Some(error) = cause.downcast_ref::<ssh2::Error>() {
+            return Self::from_ssh(error.code(), message);
+        }
+        if let Some(error) = cause.downcast_ref::<suppaftp::FtpError>() {
+            return Self::from_ftp(error, message);
+        }
+        if let Some(error) = cause.downcast_ref::<ureq::Error>() {
+            return Self::from_http(error, message);
+        }
+        #[cfg(feature = "native-smb")]
+        if let Some(error) = cause.downcast_ref::<smb::Error>() {
+            return Self::from_smb(error, message);
+        }
+        None
+    }
+
+    fn from_io(kind: io::ErrorKind, message: &str) -> Option<Self> {
+        use io::ErrorKind;
+        let message = message.to_string();
+        match kind {
+            ErrorKind::NotFound => Some(Self::NotFound(message)),
+            ErrorKind::PermissionDenied => Some(Self::PermissionDenied(message)),
+            // A rename from one disk to another, which only a copy can do
+            ErrorKind::CrossesDevices => Some(Self::Unsupported(message)),
+            // Sockets with a read timeout report it as WouldBlock on Unix
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Some(Self::Timeout(message)),
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof => Some(Self::ConnectionLost(message)),
+            _ => None,
+        }
+    }
+
+    fn from_ssh(code: ssh2::ErrorCode, message: &str) -> Option<Self> {
+        use ssh2::ErrorCode::{Session, SFTP};
+        let message = message.to_string();
+        match code {
+            SFTP(SFTP_NO_SUCH_FILE | SFTP_NO_SUCH_PATH) => Some(Self::NotFound(message)),
+            SFTP(SFTP_PERMISSION_DENIED) => Some(Self::PermissionDenied(message)),
+            SFTP(SFTP_NO_CONNECTION | SFTP_CONNECTION_LOST) => Some(Self::ConnectionLost(message)),
+            SFTP(SFTP_OP_UNSUPPORTED) => Some(Self::Unsupported(message)),
+            Session(SSH_AUTHENTICATION_FAILED | SSH_PUBLICKEY_UNVERIFIED) => {
+                Some(Self::AuthenticationFailed(message))
+            }
+            Session(SSH_TIMEOUT) => Some(Self::Timeout(message)),
+            Session(SSH_SOCKET_SEND | SSH_SOCKET_RECV | SSH_SOCKET_DISCONNECT) => {
+                Some(Self::ConnectionLost(message))
+            }
+            _ => None,
+        }
+    }
+
+    fn from_ftp(error: &suppaftp::FtpError, message: &str) -> Option<Self> {
+        use suppaftp::{FtpError, Status};
+        let message = message.to_string();
+        match error {
+            FtpError::ConnectionError(error) => {
+                Self::from_io(error.kind(), &message).or(Some(Self::ConnectionLost(message)))
+            }
+            FtpError::UnexpectedResponse(response) => match response.status {
+                Status::NotLoggedIn | Status::InvalidCredentials => {
+                    Some(Self::AuthenticationFailed(message))
+                }
+                // 550 covers both; servers say which in the text
+                Status::FileUnavailable | Status::BadFilename
+                    if String::from_utf8_lossy(&response.body)
+                        .to_ascii_lowercase()
+                        .contains("cross-device") =>
+                {
+                    Some(Self::Unsupported(message))
+                }
+                Status::FileUnavailable
+                    if String::from_utf8_lossy(&response.body)
+                        .to_ascii_lowercase()
+                        .contains("permission") =>
+                {
+                    Some(Self::PermissionDenied(message))
+                }
+                Status::FileUnavailable => Some(Self::NotFound(message)),
+                Status::NotAvailable | Status::TransferAborted => {
+                    Some(Self::ConnectionLost(message))
+                }
+                Status::NotImplemented | Status::NotImplementedParameter => {
+                    Some(Self::Unsupported(message))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn from_http(error: &ureq::Error, message: &str) -> Option<Self> {
+        let message = message.to_string();
+        match error {
+            ureq::Error::Status(404 | 410, _) => Some(Self::NotFound(message)),
+            ureq::Error::Status(401, _) => Some(Self::AuthenticationFailed(message)),
+            ureq::Error::Status(403, _) => Some(Self::PermissionDenied(message)),
+            ureq::Error::Status(405 | 501, _) => Some(Self::Unsupported(message)),
+            ureq::Error::Status(408 | 504, _) => Some(Self::Timeout(message)),
+            ureq::Error::Status(..) => None,
+            ureq::Error::Transport(transport) => transport
+                .source()
+                .and_then(|source| source.downcast_ref::<io::Error>())
+                .and_then(|error| Self::from_io(error.kind(), &message))
+                .or(Some(Self::ConnectionLost(message))),
+        }
+    }
+
+    #[cfg(feature = "native-smb")]
+    fn from_smb(error: &smb::Error, message: &str) -> Option<Self> {
+        use smb::Status;
+        let message = message.to_string();
+        let status = match error {
+            smb::Error::ReceivedErrorMessage(status, _)
+            | smb::Error::UnexpectedMessageStatus(status) => *status,
+            smb::Error::IoError(error) => return Self::from_io(error.kind(), &message),
+            smb::Error::ConnectionStopped => return Some(Self::ConnectionLost(message)),
+            smb::Error::OperationTimeout(..) => return Some(Self::Timeout(message)),
+            smb::Error::MissingPermissions(_) => return Some(Self::PermissionDenied(message)),
+            _ => return None,
+        };
+        Self::from_smb_status(&Status::try_display_as_status(status)).map(|kind| kind(message))
+    }
+
+    /// The kind of failure an NT status reports, by its name as smbclient and
+    /// the smb crate print it
+    pub(crate) fn from_smb_status(status: &str) -> Option<Kind> {
+        let status = status.to_ascii_uppercase().replace([' ', '_'], "");
+        SMB_STATUSES
+            .iter()
+            .find(|(name, _)| status.contains(name))
+            .map(|&(_, kind)| kind)
+    }
+}
+
+/// A [`ClientError`] variant, to be given its message
+pub(crate) type Kind = fn(String) -> ClientError;
+
+/// NT statuses by name, without the `NT_STATUS` and underscores
+const SMB_STATUSES: &[(&str, Kind)] = &[
+    ("OBJECTNAMENOTFOUND", ClientError::NotFound),
+    ("OBJECTPATHNOTFOUND", ClientError::NotFound),
+    ("NOSUCHFILE", ClientError::NotFound),
+    ("ACCESSDENIED", ClientError::PermissionDenied),
+    ("NOTSAMEDEVICE", ClientError::Unsupported),
+    ("LOGONFAILURE", ClientError::AuthenticationFailed),
+    ("WRONGPASSWORD", ClientError::AuthenticationFailed),
+    ("PASSWORDEXPIRED", ClientError::AuthenticationFailed),
+    ("ACCOUNTDISABLED", ClientError::AuthenticationFailed),
+    ("ACCOUNTLOCKEDOUT", ClientError::AuthenticationFailed),
+    ("IOTIMEOUT", ClientError::Timeout),
+    ("CONNECTIONREFUSED", ClientError::ConnectionLost),
+    ("CONNECTIONRESET", ClientError::ConnectionLost),
+    ("CONNECTIONDISCONNECTED", ClientError::ConnectionLost),
+    ("HOSTUNREACHABLE", ClientError::ConnectionLost),
+    ("NETWORKNAMEDELETED", ClientError::ConnectionLost),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_classify() {
+        let missing = anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound))
+            .context("Could not open /models/a.safetensors");
+        assert!(matches!(
+            ClientError::classify(&missing),
+            ClientError::NotFound(_)
+        ));
+
+        let refused: anyhow::Result<()> = Err(ClientError::AuthenticationFailed(
+            "SFTP authentication failed for alice".to_string(),
+        )
+        .into());
+        let refused = refused.context("Could not connect").unwrap_err();
+        assert_eq!(
+            ClientError::classify(&refused),
+            ClientError::AuthenticationFailed("SFTP authentication failed for alice".to_string())
+        );
+
+        let across = anyhow::Error::from(io::Error::from(io::ErrorKind::CrossesDevices));
+        assert!(matches!(
+            ClientError::classify(&across),
+            ClientError::Unsupported(_)
+        ));
+
+        let reset = anyhow::Error::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(ClientError::classify(&reset).is_retryable());
+        let other = ClientError::classify(&anyhow!("Server did not report a size"));
+        assert_eq!(
+            other,
+            ClientError::Protocol("Server did not report a size".to_string())
+        );
+        assert!(other.is_retryable());
+    }
+
+    #[test]
+    fn test_smb_statuses() {
+        let kind = |status| ClientError::from_smb_status(status).map(|kind| kind(String::new()));
+        assert_eq!(
+            kind("NT_STATUS_OBJECT_NAME_NOT_FOUND"),
+            Some(ClientError::NotFound(String::new()))
+        );
+        assert_eq!(
+            kind("Logon Failure"),
+            Some(ClientError::AuthenticationFailed(String::new()))
+        );
+        assert_eq!(
+            kind("NT_STATUS_ACCESS_DENIED"),
+            Some(ClientError::PermissionDenied(String::new()))
+        );
+        assert_eq!(
+            kind("NT_STATUS_NOT_SAME_DEVICE"),
+            Some(ClientError::Unsupported(String::new()))
+        );
+        assert_eq!(kind("NT_STATUS_DISK_FULL"), None);
+    }
+}
@@ -1,210 +1,713 @@
-use super::{FileServerClient, RemoteFile};
-use anyhow::Result;
+use super::proxy::Proxy;
+use super::{
+    connect_any, copy_chunks, delete_contents, finish_partial, format_mode, no_progress,
+    open_partial, stat_by_listing, Capabilities, FileServerClient, Progress, RemoteFile, Timeouts,
+};
+use crate::config::{FtpTls, IpPreference};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use chrono::Local;
+use chrono::{
+    DateTime, Datelike, Local, Month, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone,
+};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
+use std::net::TcpStream;
 use std::path::Path;
-use suppaftp::FtpStream;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use suppaftp::native_tls::TlsConnector;
+use suppaftp::types::Mode;
+use suppaftp::{NativeTlsConnector, NativeTlsFtpStream};
 
+/// Logged-in sessions kept between operations when the config doesn't say
+pub const DEFAULT_SESSIONS: usize = 4;
+
+/// How often idle sessions are sent a NOOP, so servers don't close them for
+/// being quiet
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Clones share their idle sessions
+#[derive(Clone)]
 pub struct FtpClient {
     host: String,
     username: String,
     password: String,
+    tls: FtpTls,
+    /// Logged-in sessions waiting for the next operation
+    sessions: Arc<Mutex<Vec<NativeTlsFtpStream>>>,
+    max_sessions: usize,
+    /// Whether the server lists with MLSD, once the first login has asked it
+    mlsd: Arc<OnceLock<bool>>,
+    timeouts: Timeouts,
+    ip_preference: IpPreference,
+    proxy: Option<Proxy>,
+    keepalive_started: bool,
 }
 
 impl FtpClient {
-    pub fn new(host: String, username: String, password: String) -> Self {
+    pub fn new(host: String, username: String, password: String, tls: FtpTls) -> Self {
         Self {
             host,
             username,
             password,
+            tls,
+            sessions: Arc::default(),
+            max_sessions: DEFAULT_SESSIONS,
+            mlsd: Arc::default(),
+            timeouts: Timeouts::default(),
+            ip_preference: IpPreference::Auto,
+            proxy: None,
+            keepalive_started: false,
+        }
+    }
+
+    /// Keep at most `max_sessions` logged-in sessions for reuse; 0 logs in
+    /// for every operation
+    #[must_use]
+    pub const fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.ip_preference = ip_preference;
+        self
+    }
+
+    /// Connect through `proxy`, or directly when it's `None`
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Run `op` on an idle session, logging in again when there is none or the
+    /// server has closed it. The session is kept for the next operation unless
+    /// `op` failed, since that can leave a reply unread.
+    async fn with_session<T, F>(&self, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut NativeTlsFtpStream) -> Result<T> + Send + 'static,
+    {
+        let client = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut ftp = client.take_session()?;
+            let result = op(&mut ftp);
+            if result.is_ok() {
+                client.keep_session(ftp);
+            } else {
+                let _ = ftp.quit();
+            }
+            result
+        })
+        .await?
+    }
+
+    fn take_session(&self) -> Result<NativeTlsFtpStream> {
+        loop {
+            let idle = self.sessions.lock().map_err(|_| anyhow!("FTP sessions poisoned"))?.pop();
+            match idle {
+                // Servers drop idle control connections, so check before using one
+                Some(mut ftp) => {
+                    if ftp.noop().is_ok() {
+                        return Ok(ftp);
+                    }
+                }
+                None => return self.connect_ftp(),
+            }
         }
     }
-    
-    fn connect_ftp(host: &str, username: &str, password: &str) -> Result<FtpStream> {
-        let mut ftp = FtpStream::connect(host)?;
-        ftp.login(username, password)?;
+
+    fn keep_session(&self, mut ftp: NativeTlsFtpStream) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if sessions.len() < self.max_sessions {
+                sessions.push(ftp);
+                return;
+            }
+        }
+        let _ = ftp.quit();
+    }
+
+    fn connect_ftp(&self) -> Result<NativeTlsFtpStream> {
+        let ftp = self.open_control()?;
+
+        let mut ftp = match self.tls {
+            // The proxy relay reads the replies, so it can't pass TLS on
+            FtpTls::Require if self.proxy.is_some() => {
+                return Err(anyhow!(
+                    "FTP can't use TLS through a proxy; use SFTP, or set --ftp-tls auto"
+                ))
+            }
+            FtpTls::Auto if self.proxy.is_some() => ftp,
+            FtpTls::Off => ftp,
+            FtpTls::Require => Self::secure(ftp, &self.host, true)
+                .map_err(|e| anyhow!("FTP server did not accept TLS: {}", e))?,
+            // A rejected AUTH TLS leaves the control connection unusable, so reconnect
+            FtpTls::Auto => match Self::secure(ftp, &self.host, false) {
+                Ok(ftp) => ftp,
+                Err(_) => self.open_control()?,
+            },
+        };
+
+        ftp.login(&self.username, &self.password)?;
+
+        // MLSD and MLST come with MLST in FEAT (RFC 3659)
+        if self.mlsd.get().is_none() {
+            match ftp.feat() {
+                Ok(features) => {
+                    let _ = self.mlsd.set(features.keys().any(|f| f.eq_ignore_ascii_case("MLST")));
+                }
+                // A reply that couldn't be read may still be waiting, so start over
+                Err(_) => {
+                    let _ = self.mlsd.set(false);
+                    let _ = ftp.quit();
+                    return self.connect_ftp();
+                }
+            }
+        }
         Ok(ftp)
     }
 
-    fn parse_list_line(line: &str) -> Option<RemoteFile> {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 9 {
+    fn lists_with_mlsd(&self) -> bool {
+        self.mlsd.get().copied().unwrap_or(false)
+    }
+
+    /// The control connection, which gives up on replies after the read timeout
+    fn open_control(&self) -> Result<NativeTlsFtpStream> {
+        let mut ftp = match &self.proxy {
+            // Only EPSV replies are relayed, since their port is all that changes
+            Some(proxy) => {
+                let relay =
+                    proxy.relay_ftp(&self.host, self.ip_preference, self.timeouts.connect)?;
+                let mut ftp = NativeTlsFtpStream::connect_timeout(relay, self.timeouts.connect)?;
+                ftp.set_mode(Mode::ExtendedPassive);
+                ftp
+            }
+            None => connect_any(&self.host, self.ip_preference, |addr| {
+                NativeTlsFtpStream::connect_timeout(addr, self.timeouts.connect)
+            })?,
+        };
+        self.limit_waits(ftp.get_ref())?;
+        // PASV can only answer with an IPv4 address, so IPv6 needs EPSV
+        if ftp.get_ref().peer_addr()?.is_ipv6() {
+            ftp.set_mode(Mode::ExtendedPassive);
+        }
+        Ok(ftp)
+    }
+
+    /// Make reads and writes on `stream` fail after the read timeout instead of
+    /// hanging on a dead server
+    fn limit_waits(&self, stream: &TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(self.timeouts.read))?;
+        stream.set_write_timeout(Some(self.timeouts.read))?;
+        Ok(())
+    }
+
+    /// NOOP each idle session, dropping the ones that don't answer. They're
+    /// taken out while it runs, so an operation starting meanwhile logs in anew.
+    fn keep_alive(sessions: &Mutex<Vec<NativeTlsFtpStream>>) {
+        let Ok(idle) = sessions.lock().map(|mut sessions| std::mem::take(&mut *sessions)) else {
+            return;
+        };
+        let alive: Vec<_> = idle
+            .into_iter()
+            .filter_map(|mut ftp| ftp.noop().ok().map(|()| ftp))
+            .collect();
+        if let Ok(mut sessions) = sessions.lock() {
+            sessions.extend(alive);
+        }
+    }
+
+    /// Upgrade the control connection with `AUTH TLS` before any credentials are sent.
+    ///
+    /// Opportunistic (`auto`) sessions skip certificate verification, since file servers on
+    /// the local network are usually reached by IP with a self-signed certificate.
+    fn secure(ftp: NativeTlsFtpStream, host: &str, verify: bool) -> Result<NativeTlsFtpStream> {
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(!verify)
+            .danger_accept_invalid_hostnames(!verify)
+            .build()?;
+
+        Ok(ftp.into_secure(NativeTlsConnector::from(connector), Self::domain(host))?)
+    }
+
+    /// The name or address in `host`, without the port or IPv6 brackets
+    fn domain(host: &str) -> &str {
+        let domain = host.rsplit_once(':').map_or(host, |(domain, _)| domain);
+        domain.trim_start_matches('[').trim_end_matches(']')
+    }
+
+    /// An MLSD line, `type=file;size=12345;modify=20241115103000; name`. Its
+    /// facts are labelled and its times exact, in UTC, so nothing is guessed
+    /// from columns. The entries for the directory itself and its parent are
+    /// left out.
+    fn parse_mlsd_line(line: &str) -> Option<RemoteFile> {
+        let (facts, name) = line.split_once(' ')?;
+        if name.is_empty() {
             return None;
         }
 
+        let mut file = RemoteFile {
+            name: name.to_string(),
+            path: name.to_string(),
+            size: 0,
+            modified: Local::now(),
+            is_dir: false,
+            permissions: None,
+        };
+        for fact in facts.split(';').filter(|fact| !fact.is_empty()) {
+            let (key, value) = fact.split_once('=')?;
+            match key.to_ascii_lowercase().as_str() {
+                "type" => match value.to_ascii_lowercase().as_str() {
+                    "dir" => file.is_dir = true,
+                    "cdir" | "pdir" => return None,
+                    // Files, and links and devices as `OS.unix=slink:target`
+                    _ => {}
+                },
+                "size" => file.size = value.parse().ok()?,
+                "modify" => {
+                    // Fractions of a second can follow
+                    let seconds = value.split('.').next().unwrap_or(value);
+                    let modified = NaiveDateTime::parse_from_str(seconds, "%Y%m%d%H%M%S").ok()?;
+                    file.modified = Local.from_utc_datetime(&modified);
+                }
+                "unix.mode" => {
+                    file.permissions = u32::from_str_radix(value, 8).ok().map(format_mode);
+                }
+                _ => {}
+            }
+        }
+        Some(file)
+    }
+
+    fn parse_list_line(line: &str) -> Option<RemoteFile> {
+        Self::parse_list_line_at(line, Local::now())
+    }
+
+    /// A LIST line in UNIX `ls -l` style or in the DOS style of Windows
+    /// servers. `now` settles the year of recent UNIX dates, which leave it out.
+    fn parse_list_line_at(line: &str, now: DateTime<Local>) -> Option<RemoteFile> {
+        Self::parse_dos_line(line).or_else(|| Self::parse_unix_line(line, now))
+    }
+
+    /// `-rw-r--r-- 1 user group 12345 Nov 15 10:30 name`
+    fn parse_unix_line(line: &str, now: DateTime<Local>) -> Option<RemoteFile> {
+        let (parts, name) = split_fields(line, 8)?;
+
         let is_dir = parts[0].starts_with('d');
         let size = parts[4].parse::<u64>().unwrap_or(0);
-        let name = parts[8..].join(" ");
+        let modified = parse_unix_date(parts[5], parts[6], parts[7], now).unwrap_or(now);
+
+        // The first column is the type followed by nine permission characters
+        let permissions = parts[0].get(1..10).map(ToString::to_string);
+
+        Some(RemoteFile {
+            name: name.to_string(),
+            path: name.to_string(),
+            size,
+            modified,
+            is_dir,
+            permissions,
+        })
+    }
 
-        // Parse date (simplified - in production would need better parsing)
-        let modified = Local::now(); // TODO: Parse actual date from FTP listing
+    /// `11-15-24  10:30AM  <DIR>  name` or `11-15-24  10:30AM  12345 name`
+    fn parse_dos_line(line: &str) -> Option<RemoteFile> {
+        let (parts, name) = split_fields(line, 3)?;
+        let modified = parse_dos_date(parts[0], parts[1])?;
+        let is_dir = parts[2].eq_ignore_ascii_case("<DIR>");
+        let size = if is_dir {
+            0
+        } else {
+            parts[2].parse::<u64>().ok()?
+        };
 
         Some(RemoteFile {
-            name: name.clone(),
-            path: name,
+            name: name.to_string(),
+            path: name.to_string(),
             size,
             modified,
             is_dir,
+            permissions: None,
         })
     }
 }
 
+/// The first `count` whitespace-separated fields of `line` and the rest of it,
+/// spaces and all, as long as there is a rest
+fn split_fields(line: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut fields = Vec::with_capacity(count);
+    let mut rest = line.trim_start();
+    while fields.len() < count {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    (!rest.is_empty()).then_some((fields, rest))
+}
+
+/// Whether a LIST line gives the time of day, which UNIX listings leave out
+/// for files more than about six months old
+fn has_listed_time(line: &str) -> bool {
+    match split_fields(line, 8) {
+        Some((parts, _)) if FtpClient::parse_dos_line(line).is_none() => parts[7].contains(':'),
+        _ => true,
+    }
+}
+
+/// A UNIX listing date: `Nov 15 10:30` within the last six months or so, and
+/// `Nov 15 2023` before that
+fn parse_unix_date(
+    month: &str,
+    day: &str,
+    time_or_year: &str,
+    now: DateTime<Local>,
+) -> Option<DateTime<Local>> {
+    let month = month.parse::<Month>().ok()?.number_from_month();
+    let day = day.parse::<u32>().ok()?;
+
+    let datetime = if let Some((hour, minute)) = time_or_year.split_once(':') {
+        let time = NaiveTime::from_hms_opt(hour.parse().ok()?, minute.parse().ok()?, 0)?;
+        // A date that would be more than a day ahead (allowing for time zones)
+        // is from last year
+        let this_year = NaiveDate::from_ymd_opt(now.year(), month, day)
+            .map(|date| date.and_time(time))
+            .filter(|datetime| *datetime <= now.naive_local() + TimeDelta::days(1));
+        match this_year {
+            Some(datetime) => datetime,
+            None => NaiveDate::from_ymd_opt(now.year() - 1, month, day)?.and_time(time),
+        }
+    } else {
+        NaiveDate::from_ymd_opt(time_or_year.parse().ok()?, month, day)?.and_time(NaiveTime::MIN)
+    };
+    Local.from_local_datetime(&datetime).earliest()
+}
+
+/// A DOS listing date, `11-15-24` or `11-15-2024`, and a time, `10:30AM` or `22:30`
+fn parse_dos_date(date: &str, time: &str) -> Option<DateTime<Local>> {
+    let date = NaiveDate::parse_from_str(date, "%m-%d-%Y")
+        .ok()
+        .filter(|date| date.year() >= 1000)
+        .or_else(|| NaiveDate::parse_from_str(date, "%m-%d-%y").ok())?;
+    let time = NaiveTime::parse_from_str(time, "%I:%M%p")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()?;
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
+
 #[async_trait]
 impl FileServerClient for FtpClient {
     async fn connect(&mut self) -> Result<()> {
-        // Test connection
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
-
-        tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            ftp.quit()?;
-            Ok::<_, anyhow::Error>(())
-        })
-        .await??;
-
+        // Logging in tests the settings, and the session is kept for later
+        self.with_session(|_| Ok(())).await?;
+
+        // Ends once every clone of this client is gone
+        if !self.keepalive_started {
+            self.keepalive_started = true;
+            let sessions = Arc::downgrade(&self.sessions);
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(KEEPALIVE_INTERVAL);
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    let Some(sessions) = sessions.upgrade() else {
+                        break;
+                    };
+                    let _ = tokio::task::spawn_blocking(move || Self::keep_alive(&sessions)).await;
+                }
+            });
+        }
         Ok(())
     }
 
     async fn disconnect(&mut self) -> Result<()> {
-        // Nothing to do - we create fresh connections for each operation
+        let sessions = std::mem::take(
+            &mut *self.sessions.lock().map_err(|_| anyhow!("FTP sessions poisoned"))?,
+        );
+        tokio::task::spawn_blocking(move || {
+            for mut ftp in sessions {
+                let _ = ftp.quit();
+            }
+        })
+        .await?;
         Ok(())
     }
 
     async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
         let path = path.to_string();
 
-        let files = tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            ftp.cwd(&path)?;
-            let list = ftp.list(None)?;
-            ftp.quit()?;
-
-            let files: Vec<RemoteFile> = list
-                .iter()
-                .filter_map(|line| FtpClient::parse_list_line(line))
-                .map(|mut file| {
+        let mlsd = self.lists_with_mlsd();
+
+        // Sessions go back to the pool, so the path is sent along rather than
+        // changed into, which would move where the next relative path starts
+        self.with_session(move |ftp| {
+            let dir = Some(path.as_str()).filter(|path| !path.is_empty());
+            if mlsd {
+                let mut files: Vec<_> = ftp
+                    .mlsd(dir)?
+                    .iter()
+                    .filter_map(|line| FtpClient::parse_mlsd_line(line))
+                    .collect();
+                for file in &mut files {
                     file.path = format!("{}/{}", path.trim_end_matches('/'), file.name);
-                    file
-                })
-                .collect();
+                }
+                return Ok(files);
+            }
 
-            Ok::<_, anyhow::Error>(files)
-        })
-        .await??;
+            // Without MLSD, LIST's text is parsed in both UNIX and DOS styles
+            let list = ftp.list(dir)?;
 
-        Ok(files)
+            let mut files = Vec::new();
+            let mut dated = Vec::new();
+            for line in &list {
+                if let Some(mut file) = FtpClient::parse_list_line(line) {
+                    file.path = format!("{}/{}", path.trim_end_matches('/'), file.name);
+                    dated.push(file.is_dir || has_listed_time(line));
+                    files.push(file);
+                }
+            }
+
+            // Older files are listed with only the day, so ask MDTM for their
+            // time, in UTC. A server without it fails the first one. Callers
+            // that need the second stat the file.
+            for (file, _) in files.iter_mut().zip(dated).filter(|(_, dated)| !dated) {
+                // An empty path is the directory the server starts in
+                let name = if dir.is_some() { &file.path } else { &file.name };
+                match ftp.mdtm(name) {
+                    Ok(modified) => file.modified = Local.from_utc_datetime(&modified),
+                    Err(_) => break,
+                }
+            }
+
+            Ok(files)
+        })
+        .await
     }
 
     async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
+        self.download_file_with_progress(remote_path, local_path, no_progress())
+            .await
+    }
+
+    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+        self.upload_file_with_progress(local_path, remote_path, no_progress())
+            .await
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
         let remote_path = remote_path.to_string();
         let local_path = local_path.to_path_buf();
+        let client = self.clone();
 
-        tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            let mut reader = ftp.retr_as_buffer(&remote_path)?;
-            let mut data = Vec::new();
-            reader.read_to_end(&mut data)?;
-            ftp.quit()?;
-
+        self.with_session(move |ftp| {
+            let mut stream = ftp.retr_as_stream(&remote_path)?;
+            client.limit_waits(stream.get_ref())?;
             let mut file = File::create(local_path)?;
-            file.write_all(&data)?;
-            Ok::<_, anyhow::Error>(())
+            copy_chunks(&mut stream, &mut file, &progress)?;
+            ftp.finalize_retr_stream(stream)?;
+            Ok(())
         })
-        .await??;
+        .await
+    }
 
-        Ok(())
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let remote_path = remote_path.to_string();
+        let local_path = local_path.to_path_buf();
+        let client = self.clone();
+
+        self.with_session(move |ftp| {
+            let size = ftp.size(&remote_path).ok().and_then(|size| u64::try_from(size).ok());
+            let (mut file, offset) = open_partial(&local_path, size, &progress)?;
+            if size != Some(offset) {
+                if offset > 0 {
+                    // REST moves the start of the next RETR
+                    ftp.resume_transfer(usize::try_from(offset)?)?;
+                }
+                let mut stream = ftp.retr_as_stream(&remote_path)?;
+                client.limit_waits(stream.get_ref())?;
+                copy_chunks(&mut stream, &mut file, &progress)?;
+                ftp.finalize_retr_stream(stream)?;
+            }
+            finish_partial(&local_path)?;
+            Ok(())
+        })
+        .await
     }
 
-    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
         let remote_path = remote_path.to_string();
         let local_path = local_path.to_path_buf();
+        let client = self.clone();
 
-        tokio::task::spawn_blocking(move || {
+        self.with_session(move |ftp| {
             let mut file = File::open(local_path)?;
-            let mut data = Vec::new();
-            file.read_to_end(&mut data)?;
-
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            ftp.put_file(&remote_path, &mut &data[..])?;
-            ftp.quit()?;
-            Ok::<_, anyhow::Error>(())
+            let mut stream = ftp.put_with_stream(&remote_path)?;
+            client.limit_waits(stream.get_ref())?;
+            copy_chunks(&mut file, &mut stream, &progress)?;
+            ftp.finalize_put_stream(stream)?;
+            Ok(())
         })
-        .await??;
+        .await
+    }
 
-        Ok(())
+    async fn upload_reader(
+        &mut self,
+        mut reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        let remote_path = remote_path.to_string();
+        let client = self.clone();
+
+        self.with_session(move |ftp| {
+            let mut stream = ftp.put_with_stream(&remote_path)?;
+            client.limit_waits(stream.get_ref())?;
+            let sent = copy_chunks(&mut reader, &mut stream, &progress)?;
+            ftp.finalize_put_stream(stream)?;
+            Ok(sent)
+        })
+        .await
     }
 
     async fn create_directory(&mut self, path: &str) -> Result<()> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
         let path = path.to_string();
-
-        tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            ftp.mkdir(&path)?;
-            ftp.quit()?;
-            Ok::<_, anyhow::Error>(())
-        })
-        .await??;
-
-        Ok(())
+        self.with_session(move |ftp| Ok(ftp.mkdir(&path)?)).await
     }
 
     async fn delete_file(&mut self, path: &str) -> Result<()> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
         let path = path.to_string();
+        self.with_session(move |ftp| Ok(ftp.rm(&path)?)).await
+    }
 
-        tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            ftp.rm(&path)?;
-            ftp.quit()?;
-            Ok::<_, anyhow::Error>(())
-        })
-        .await??;
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        if recursive {
+            delete_contents(self, path).await?;
+        }
 
-        Ok(())
+        let path = path.to_string();
+        self.with_session(move |ftp| Ok(ftp.rmdir(&path)?)).await
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let from = from.to_string();
+        let to = to.to_string();
+        self.with_session(move |ftp| Ok(ftp.rename(&from, &to)?)).await
     }
 
     async fn get_file_size(&mut self, path: &str) -> Result<u64> {
-        let host = self.host.clone();
-        let username = self.username.clone();
-        let password = self.password.clone();
         let path = path.to_string();
+        self.with_session(move |ftp| Ok(ftp.size(&path)? as u64)).await
+    }
 
-        let size = tokio::task::spawn_blocking(move || {
-            let mut ftp = Self::connect_ftp(&host, &username, &password)?;
-            let size: Result<u64, anyhow::Error> = match ftp.size(&path) {
-                Ok(size) => Ok(size as u64),
-                Err(e) => Err(e.into()),
-            };
-            ftp.quit()?;
-            size
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let file_path = path.trim_end_matches('/').to_string();
+        if self.lists_with_mlsd() {
+            return self
+                .with_session(move |ftp| {
+                    let line = ftp.mlst(Some(&file_path))?;
+                    // MLST names the entry by the path it was asked for
+                    let mut file = FtpClient::parse_mlsd_line(&line).ok_or_else(|| {
+                        anyhow!("Unexpected MLST reply for {}: {}", file_path, line)
+                    })?;
+                    file.name = file_path.rsplit('/').next().unwrap_or(&file_path).to_string();
+                    file.path = file_path;
+                    Ok(file)
+                })
+                .await;
+        }
+
+        // SIZE only answers for files, so anything else is looked up in its
+        // parent's listing. A refused SIZE or MDTM leaves the session usable.
+        let found = self
+            .with_session(move |ftp| {
+                let Ok(size) = ftp.size(&file_path) else {
+                    return Ok(None);
+                };
+                let modified = ftp.mdtm(&file_path).map_or_else(
+                    |_| Local::now(),
+                    |modified| Local.from_utc_datetime(&modified),
+                );
+                let name = file_path.rsplit('/').next().unwrap_or(&file_path).to_string();
+                Ok(Some(RemoteFile {
+                    name,
+                    path: file_path,
+                    size: size as u64,
+                    modified,
+                    is_dir: false,
+                    permissions: None,
+                }))
+            })
+            .await?;
+        match found {
+            Some(file) => Ok(file),
+            None => stat_by_listing(self, path).await,
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            resume: true,
+            rename: true,
+            set_modified: false,
+            server_side_copy: false,
+            // LIST times stop at the minute; MLSD gives the second
+            exact_times: self.lists_with_mlsd(),
+        }
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        // A session of its own, since cutting the transfer short leaves it unusable
+        let data = tokio::task::spawn_blocking(move || {
+            let mut ftp = client.connect_ftp()?;
+            // REST moves the start of the next RETR
+            ftp.resume_transfer(usize::try_from(offset)?)?;
+            let stream = ftp.retr_as_stream(&path)?;
+            client.limit_waits(stream.get_ref())?;
+            let mut data = Vec::new();
+            stream.take(len).read_to_end(&mut data)?;
+            // The transfer is cut short on purpose, so the server's reply to
+            // it and to QUIT can be an error
+            let _ = ftp.quit();
+            Ok::<_, anyhow::Error>(data)
         })
         .await??;
 
-        Ok(size)
+        Ok(data)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_parse_list_line_directory() {
@@ -228,20 +731,103 @@ mod tests {
         assert_eq!(entry.name, "test.pdf");
         assert!(!entry.is_dir);
         assert_eq!(entry.size, 12345);
+        assert_eq!(entry.permissions.as_deref(), Some("rw-r--r--"));
     }
 
     #[test]
     fn test_parse_list_line_with_spaces() {
-        let line = "-rw-r--r-- 1 user group 1024 Nov 15 10:30 my file name.txt";
+        let line = "-rw-r--r-- 1 user group 1024 Nov 15 10:30 my  file name.txt";
         let result = FtpClient::parse_list_line(line);
 
         assert!(result.is_some());
         let entry = result.unwrap();
-        assert_eq!(entry.name, "my file name.txt");
+        assert_eq!(entry.name, "my  file name.txt");
         assert!(!entry.is_dir);
         assert_eq!(entry.size, 1024);
     }
 
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_unix_dates() {
+        let now = local(2024, 3, 10, 12, 0);
+        let modified = |line| FtpClient::parse_list_line_at(line, now).unwrap().modified;
+
+        // Recent files show the time, and the year is the latest that isn't ahead
+        assert_eq!(
+            modified("-rw-r--r-- 1 user group 10 Feb 29 09:15 a.txt"),
+            local(2024, 2, 29, 9, 15)
+        );
+        assert_eq!(
+            modified("-rw-r--r-- 1 user group 10 Nov 15 10:30 a.txt"),
+            local(2023, 11, 15, 10, 30)
+        );
+        // Older ones show the year instead
+        assert_eq!(
+            modified("drwxr-xr-x 2 user group 4096 Jun  1  2021 old"),
+            local(2021, 6, 1, 0, 0)
+        );
+
+        // Only those need MDTM for their time
+        assert!(has_listed_time("-rw-r--r-- 1 user group 10 Feb 29 09:15 a.txt"));
+        assert!(!has_listed_time("-rw-r--r-- 1 user group 10 Jun  1  2021 12:00.txt"));
+        assert!(has_listed_time("01-02-2023  08:05            12345 test.pdf"));
+    }
+
+    #[test]
+    fn test_parse_dos_lines() {
+        let now = Local::now();
+        let entry = FtpClient::parse_list_line_at(
+            "11-15-24  10:30PM       <DIR>          My Documents",
+            now,
+        )
+        .unwrap();
+        assert_eq!(entry.name, "My Documents");
+        assert!(entry.is_dir);
+        assert_eq!(entry.size, 0);
+        assert_eq!(entry.modified, local(2024, 11, 15, 22, 30));
+        assert_eq!(entry.permissions, None);
+
+        let entry =
+            FtpClient::parse_list_line_at("01-02-2023  08:05            12345 test.pdf", now)
+                .unwrap();
+        assert_eq!(entry.name, "test.pdf");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, 12345);
+        assert_eq!(entry.modified, local(2023, 1, 2, 8, 5));
+    }
+
+    #[test]
+    fn test_parse_mlsd_lines() {
+        let entry = FtpClient::parse_mlsd_line(
+            "type=file;size=12345;modify=20241115103005.123;UNIX.mode=0644; my  file.txt",
+        )
+        .unwrap();
+        assert_eq!(entry.name, "my  file.txt");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, 12345);
+        let utc = NaiveDate::from_ymd_opt(2024, 11, 15)
+            .unwrap()
+            .and_hms_opt(10, 30, 5)
+            .unwrap();
+        assert_eq!(entry.modified, Local.from_utc_datetime(&utc));
+        assert_eq!(entry.permissions.as_deref(), Some("rw-r--r--"));
+
+        let entry = FtpClient::parse_mlsd_line("Type=dir;Modify=20210601000000; loras").unwrap();
+        assert!(entry.is_dir);
+        assert_eq!(entry.permissions, None);
+        let entry = FtpClient::parse_mlsd_line("type=OS.unix=slink:/x;size=1; link").unwrap();
+        assert!(!entry.is_dir);
+
+        // The directory itself and its parent
+        assert!(FtpClient::parse_mlsd_line("type=cdir;modify=20241115103005; .").is_none());
+        assert!(FtpClient::parse_mlsd_line("type=pdir;modify=20241115103005; ..").is_none());
+        assert!(FtpClient::parse_mlsd_line("type=file;size=big; a").is_none());
+        assert!(FtpClient::parse_mlsd_line("type=file;size=1;").is_none());
+    }
+
     #[test]
     fn test_parse_list_line_invalid() {
         let line = "invalid line";
@@ -249,16 +835,125 @@ mod tests {
         assert!(result.is_none());
     }
 
+    /// Just enough of a server to list `models/a` and `models/b` below the
+    /// login directory, resolving relative paths against its own CWD
+    fn serve_listings(control: TcpStream) -> std::io::Result<()> {
+        let listing = |dir: &str| match dir {
+            "/home/u" | "/home/u/models" => Some(""),
+            "/home/u/models/a" => Some("-rw-r--r-- 1 u g 3 Jan 01 10:00 a.bin\r\n"),
+            "/home/u/models/b" => Some("-rw-r--r-- 1 u g 5 Jun  1  2021 b.bin\r\n"),
+            _ => None,
+        };
+        let mut reader = BufReader::new(control.try_clone()?);
+        let mut control = control;
+        let mut cwd = "/home/u".to_string();
+        let mut data = None;
+        control.write_all(b"220 ready\r\n")?;
+
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            let (command, arg) = line.trim_end().split_once(' ').unwrap_or((line.trim_end(), ""));
+            let resolved = match arg {
+                "" => cwd.clone(),
+                arg if arg.starts_with('/') => arg.to_string(),
+                arg => format!("{}/{}", cwd, arg),
+            };
+            let reply = match command {
+                "USER" => "331 password".to_string(),
+                "PASS" => "230 logged in".to_string(),
+                "FEAT" => "211-Features\r\n SIZE\r\n MDTM\r\n211 End".to_string(),
+                "TYPE" | "NOOP" => "200 ok".to_string(),
+                "CWD" if listing(&resolved).is_some() => {
+                    cwd = resolved;
+                    "250 ok".to_string()
+                }
+                "MDTM" if resolved == "/home/u/models/b/b.bin" => "213 20210601123456".to_string(),
+                "PASV" => {
+                    let listener = TcpListener::bind("127.0.0.1:0")?;
+                    let port = listener.local_addr()?.port();
+                    data = Some(listener);
+                    format!("227 Passive (127,0,0,1,{},{})", port >> 8, port & 0xff)
+                }
+                "LIST" => match (listing(&resolved), data.take()) {
+                    (Some(entries), Some(listener)) => {
+                        control.write_all(b"150 listing\r\n")?;
+                        listener.accept()?.0.write_all(entries.as_bytes())?;
+                        "226 done".to_string()
+                    }
+                    _ => "550 no such directory".to_string(),
+                },
+                "QUIT" => break,
+                "CWD" | "MDTM" => "550 no such file".to_string(),
+                _ => "502 not implemented".to_string(),
+            };
+            control.write_all(format!("{}\r\n", reply).as_bytes())?;
+            line.clear();
+        }
+        control.write_all(b"221 bye\r\n")
+    }
+
+    #[tokio::test]
+    async fn test_list_sibling_directories_on_one_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let logins = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&logins);
+        std::thread::spawn(move || {
+            for control in listener.incoming().flatten() {
+                counted.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || serve_listings(control));
+            }
+        });
+
+        let mut client = FtpClient::new(host, "u".to_string(), "p".to_string(), FtpTls::Off)
+            .with_max_sessions(1);
+        client.connect().await.unwrap();
+        let a = client.list_files("models/a").await.unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].path, "models/a/a.bin");
+
+        // The first listing mustn't have moved where this relative path starts
+        let b = client.list_files("models/b").await.unwrap();
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].path, "models/b/b.bin");
+        let utc = NaiveDate::from_ymd_opt(2021, 6, 1)
+            .unwrap()
+            .and_hms_opt(12, 34, 56)
+            .unwrap();
+        assert_eq!(b[0].modified, Local.from_utc_datetime(&utc));
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+        client.disconnect().await.unwrap();
+    }
+
     #[test]
     fn test_ftp_client_creation() {
         let client = FtpClient::new(
             "192.168.1.1:21".to_string(),
             "user".to_string(),
             "pass".to_string(),
+            FtpTls::Auto,
         );
 
         assert_eq!(client.host, "192.168.1.1:21");
         assert_eq!(client.username, "user");
         assert_eq!(client.password, "pass");
+        assert_eq!(client.tls, FtpTls::Auto);
+        assert_eq!(client.max_sessions, DEFAULT_SESSIONS);
+
+        // Clones hand sessions to each other
+        let clone = client.clone().with_max_sessions(0);
+        assert!(Arc::ptr_eq(&clone.sessions, &client.sessions));
+        assert_eq!(clone.max_sessions, 0);
+    }
+
+    #[test]
+    fn test_tls_domain_strips_port() {
+        assert_eq!(FtpClient::domain("192.168.1.1:21"), "192.168.1.1");
+        assert_eq!(
+            FtpClient::domain("files.example.com:990"),
+            "files.example.com"
+        );
+        assert_eq!(FtpClient::domain("files.example.com"), "files.example.com");
+        assert_eq!(FtpClient::domain("[fd00::2]:21"), "fd00::2");
     }
 }
@@ -0,0 +1,735 @@
+use super::address::{self, url_host};
+use super::proxy::{Proxy, ProxyKind};
+use super::{
+    copy_chunks, no_progress, partial_path, stat_by_listing, Capabilities, ClientError,
+    FileServerClient, Progress, RemoteFile, Timeouts,
+};
+use crate::config::IpPreference;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use ureq::{Agent, AgentBuilder, MiddlewareNext};
+use url::Url;
+
+/// Read-only client for servers that expose files through an HTTP directory index,
+/// such as nginx `autoindex` (HTML or `autoindex_format json`).
+pub struct HttpClient {
+    base_url: Url,
+    auth_header: Option<String>,
+    agent: Agent,
+    timeouts: Timeouts,
+    ip_preference: IpPreference,
+    proxy: Option<Proxy>,
+    /// The index is nginx's JSON, with times to the second, rather than HTML,
+    /// which stops at the minute. Known once connected.
+    json_listings: bool,
+}
+
+/// One entry of an nginx `autoindex_format json` listing.
+#[derive(Debug, Deserialize)]
+struct JsonEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    mtime: Option<String>,
+    size: Option<u64>,
+}
+
+impl HttpClient {
+    /// `server` may be a bare host (`files.local:8080`, `[fd00::2]:8080`, `fd00::2`) or a
+    /// full URL with a path prefix.
+    pub fn new(server: &str, username: String, password: String) -> Result<Self> {
+        let base_url = if server.starts_with("http://") || server.starts_with("https://") {
+            Url::parse(server)?
+        } else {
+            Url::parse(&format!("http://{}", url_host(server)))?
+        };
+
+        let auth_header = if username.is_empty() {
+            None
+        } else {
+            let credentials = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", username, password));
+            Some(format!("Basic {}", credentials))
+        };
+
+        Ok(Self {
+            base_url,
+            auth_header,
+            agent: Self::agent(Timeouts::default(), IpPreference::Auto, None),
+            timeouts: Timeouts::default(),
+            ip_preference: IpPreference::Auto,
+            proxy: None,
+            json_listings: false,
+        })
+    }
+
+    #[must_use]
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self.agent = Self::agent(timeouts, self.ip_preference, self.proxy.as_ref());
+        self
+    }
+
+    #[must_use]
+    pub fn with_ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.ip_preference = ip_preference;
+        self.agent = Self::agent(self.timeouts, ip_preference, self.proxy.as_ref());
+        self
+    }
+
+    /// Send requests through `proxy`, or directly when it's `None`
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Result<Self> {
+        if let Some(proxy) = &proxy {
+            ureq::Proxy::new(proxy.url()).map_err(|e| anyhow!("Proxy {}: {}", proxy, e))?;
+        }
+        self.proxy = proxy;
+        self.agent = Self::agent(self.timeouts, self.ip_preference, self.proxy.as_ref());
+        Ok(self)
+    }
+
+    // The middleware's error type is ureq's
+    #[allow(clippy::result_large_err)]
+    fn agent(timeouts: Timeouts, ip_preference: IpPreference, proxy: Option<&Proxy>) -> Agent {
+        let mut builder = AgentBuilder::new()
+            .timeout_connect(timeouts.connect)
+            .timeout_read(timeouts.read)
+            .timeout_write(timeouts.read)
+            .resolver(move |netloc: &str| address::resolve(netloc, ip_preference));
+        // Checked by `with_proxy`
+        if let Some(url) = proxy.and_then(|proxy| ureq::Proxy::new(proxy.url()).ok()) {
+            builder = builder.proxy(url);
+        }
+        // ureq only logs in to an HTTP proxy to `CONNECT`, which http:// URLs don't
+        // use. https:// ones do, and the login mustn't go on to the server in them.
+        let authorization = proxy
+            .filter(|proxy| proxy.kind == ProxyKind::Http)
+            .and_then(Proxy::authorization);
+        if let Some(authorization) = authorization {
+            builder = builder.middleware(move |request: ureq::Request, next: MiddlewareNext| {
+                if request.url().starts_with("http://") {
+                    next.handle(request.set("Proxy-Authorization", &authorization))
+                } else {
+                    next.handle(request)
+                }
+            });
+        }
+        builder.build()
+    }
+
+    fn url_for(&self, path: &str, is_dir: bool) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|()| anyhow!("Invalid base URL: {}", self.base_url))?;
+            segments.pop_if_empty();
+            segments.extend(path.split('/').filter(|s| !s.is_empty()));
+            if is_dir {
+                segments.push("");
+            }
+        }
+        Ok(url)
+    }
+
+    fn request(agent: &Agent, auth: Option<&str>, method: &str, url: &Url) -> ureq::Request {
+        let request = agent.request_url(method, url);
+        match auth {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    fn parse_listing(base_path: &str, body: &str) -> Result<Vec<RemoteFile>> {
+        if body.trim_start().starts_with('[') {
+            Self::parse_json_listing(base_path, body)
+        } else {
+            Ok(Self::parse_html_listing(base_path, body))
+        }
+    }
+
+    fn parse_json_listing(base_path: &str, body: &str) -> Result<Vec<RemoteFile>> {
+        let entries: Vec<JsonEntry> = serde_json::from_str(body)?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let is_dir = entry.kind == "directory";
+                let modified = entry
+                    .mtime
+                    .and_then(|mtime| DateTime::parse_from_rfc2822(&mtime).ok())
+                    .map_or_else(Local::now, |mtime| mtime.with_timezone(&Local));
+
+                RemoteFile {
+                    path: format!("{}/{}", base_path.trim_end_matches('/'), entry.name),
+                    name: entry.name,
+                    size: if is_dir { 0 } else { entry.size.unwrap_or(0) },
+                    modified,
+                    is_dir,
+                    permissions: None,
+                }
+            })
+            .collect())
+    }
+
+    /// Parse the `<pre>` block nginx renders, one `<a href>` per line followed by
+    /// the modification time and size (`-` for directories).
+    fn parse_html_listing(base_path: &str, body: &str) -> Vec<RemoteFile> {
+        body.lines()
+            .filter_map(|line| Self::parse_html_line(base_path, line))
+            .collect()
+    }
+
+    fn parse_html_line(base_path: &str, line: &str) -> Option<RemoteFile> {
+        let start = line.find("<a href=\"")? + "<a href=\"".len();
+        let end = start + line[start..].find('"')?;
+        let href = &line[start..end];
+
+        // Skip parent links, sort links, and anything pointing off the listing
+        if href.starts_with("..")
+            || href.starts_with('?')
+            || href.starts_with('/')
+            || href.contains("://")
+        {
+            return None;
+        }
+
+        let is_dir = href.ends_with('/');
+        let name = percent_decode_str(href.trim_end_matches('/'))
+            .decode_utf8()
+            .ok()?
+            .to_string();
+        if name.is_empty() {
+            return None;
+        }
+
+        let details: Vec<&str> = line
+            .split("</a>")
+            .nth(1)
+            .map(|rest| rest.split_whitespace().collect())
+            .unwrap_or_default();
+
+        let modified = match details.as_slice() {
+            [date, time, ..] => {
+                NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%d-%b-%Y %H:%M")
+                    .ok()
+                    .map(|naive| Utc.from_utc_datetime(&naive).with_timezone(&Local))
+            }
+            _ => None,
+        }
+        .unwrap_or_else(Local::now);
+
+        let size = if is_dir {
+            0
+        } else {
+            details
+                .get(2)
+                .and_then(|size| Self::parse_size(size))
+                .unwrap_or(0)
+        };
+
+        Some(RemoteFile {
+            path: format!("{}/{}", base_path.trim_end_matches('/'), name),
+            name,
+            size,
+            modified,
+            is_dir,
+            permissions: None,
+        })
+    }
+
+    /// Sizes are exact bytes, or rounded like `12M` when `autoindex_exact_size` is off.
+    fn parse_size(size: &str) -> Option<u64> {
+        if let Ok(bytes) = size.parse::<u64>() {
+            return Some(bytes);
+        }
+
+        let (number, unit) = size.split_at(size.len().checked_sub(1)?);
+        let multiplier: u64 = match unit {
+            "K" => 1 << 10,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            "T" => 1 << 40,
+            _ => return None,
+        };
+        number.parse::<u64>().ok().map(|n| n * multiplier)
+    }
+}
+
+#[async_trait]
+impl FileServerClient for HttpClient {
+    async fn connect(&mut self) -> Result<()> {
+        // Test connection
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for("/", true)?;
+
+        self.json_listings = tokio::task::spawn_blocking(move || {
+            let response = Self::request(&agent, auth.as_deref(), "GET", &url).call()?;
+            Ok::<_, anyhow::Error>(response.content_type() == "application/json")
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        // Nothing to do - every request is independent
+        Ok(())
+    }
+
+    async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for(path, true)?;
+        let path = path.to_string();
+
+        let files = tokio::task::spawn_blocking(move || {
+            let body = Self::request(&agent, auth.as_deref(), "GET", &url)
+                .call()?
+                .into_string()?;
+            Self::parse_listing(&path, &body)
+        })
+        .await??;
+
+        Ok(files)
+    }
+
+    async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+        self.download_file_with_progress(remote_path, local_path, no_progress())
+            .await
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for(remote_path, false)?;
+        let local_path = local_path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            // Resume an interrupted download with a ranged GET, which the server
+            // only honours if the file is still the one the partial file began
+            let partial_path = partial_path(&local_path);
+            let validator_path = validator_path(&partial_path);
+            let validator = fs::read_to_string(&validator_path).ok();
+            let resume_from = match validator {
+                Some(_) => fs::metadata(&partial_path).map_or(0, |m| m.len()),
+                None => 0,
+            };
+
+            let mut request = Self::request(&agent, auth.as_deref(), "GET", &url);
+            if let Some(validator) = validator.filter(|_| resume_from > 0) {
+                request = request
+                    .set("Range", &format!("bytes={}-", resume_from))
+                    .set("If-Range", &validator);
+            }
+
+            let response = match request.call() {
+                // The partial file is already complete
+                Err(ureq::Error::Status(416, _)) if resume_from > 0 => None,
+                response => Some(response?),
+            };
+
+            if let Some(response) = response {
+                // A changed file, or a server without ranges, sends all of it again
+                let append = response.status() == 206;
+                if append {
+                    progress(resume_from);
+                } else if let Some(validator) = validator_of(&response) {
+                    fs::write(&validator_path, validator)?;
+                } else {
+                    remove_if_exists(&validator_path)?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(&partial_path)?;
+                copy_chunks(&mut response.into_reader(), &mut file, &progress)?;
+            } else {
+                progress(resume_from);
+            }
+
+            fs::rename(&partial_path, &local_path)?;
+            remove_if_exists(&validator_path)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    /// Downloads always go through a partial file and resume with a ranged GET
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        self.download_file_with_progress(remote_path, local_path, progress)
+            .await
+    }
+
+    async fn upload_file(&mut self, _local_path: &Path, _remote_path: &str) -> Result<()> {
+        Err(unsupported(
+            "Uploads are not supported over an HTTP directory index",
+        ))
+    }
+
+    async fn create_directory(&mut self, _path: &str) -> Result<()> {
+        Err(unsupported(
+            "Creating directories is not supported over an HTTP directory index",
+        ))
+    }
+
+    async fn delete_file(&mut self, _path: &str) -> Result<()> {
+        Err(unsupported(
+            "Deleting files is not supported over an HTTP directory index",
+        ))
+    }
+
+    async fn delete_directory(&mut self, _path: &str, _recursive: bool) -> Result<()> {
+        Err(unsupported(
+            "Deleting directories is not supported over an HTTP directory index",
+        ))
+    }
+
+    async fn rename(&mut self, _from: &str, _to: &str) -> Result<()> {
+        Err(unsupported(
+            "Renaming files is not supported over an HTTP directory index",
+        ))
+    }
+
+    async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for(path, false)?;
+        let path = path.to_string();
+
+        let size = tokio::task::spawn_blocking(move || {
+            let response = Self::request(&agent, auth.as_deref(), "HEAD", &url).call()?;
+            response
+                .header("Content-Length")
+                .and_then(|len| len.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("Server did not report a size for {}", path))
+        })
+        .await??;
+
+        Ok(size)
+    }
+
+    /// An HTML listing only has the minute a file changed, so the rest of its
+    /// time comes from its `Last-Modified` header
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let mut file = stat_by_listing(self, path).await?;
+        if file.is_dir || self.json_listings {
+            return Ok(file);
+        }
+
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for(path, false)?;
+
+        // The listed time is still close, so a server that won't say keeps it
+        let modified = tokio::task::spawn_blocking(move || {
+            let response = Self::request(&agent, auth.as_deref(), "HEAD", &url)
+                .call()
+                .ok()?;
+            DateTime::parse_from_rfc2822(response.header("Last-Modified")?).ok()
+        })
+        .await?;
+        if let Some(modified) = modified {
+            file.modified = modified.with_timezone(&Local);
+        }
+
+        Ok(file)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            resume: true,
+            rename: false,
+            set_modified: false,
+            server_side_copy: false,
+            exact_times: self.json_listings,
+        }
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let agent = self.agent.clone();
+        let auth = self.auth_header.clone();
+        let url = self.url_for(path, false)?;
+
+        let data = tokio::task::spawn_blocking(move || {
+            let range = format!("bytes={}-{}", offset, offset.saturating_add(len - 1));
+            let response = match Self::request(&agent, auth.as_deref(), "GET", &url)
+                .set("Range", &range)
+                .call()
+            {
+                // Asked for bytes past the end of the file
+                Err(ureq::Error::Status(416, _)) => return Ok(Vec::new()),
+                response => response?,
+            };
+
+            // Servers without range support send the whole file
+            let whole_file = response.status() != 206;
+            let mut reader = response.into_reader();
+            if whole_file {
+                io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+            }
+            let mut data = Vec::new();
+            reader.take(len).read_to_end(&mut data)?;
+            Ok::<_, anyhow::Error>(data)
+        })
+        .await??;
+
+        Ok(data)
+    }
+}
+
+/// The error for what an HTTP directory index can't do
+/// Where what identifies the remote file a partial download began is kept
+fn validator_path(partial_path: &Path) -> PathBuf {
+    let mut path = partial_path.as_os_str().to_owned();
+    path.push(".validator");
+    PathBuf::from(path)
+}
+
+/// What identifies the file in `response` for `If-Range`: a strong `ETag`, or
+/// else its `Last-Modified` time
+fn validator_of(response: &ureq::Response) -> Option<&str> {
+    response
+        .header("ETag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| response.header("Last-Modified"))
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn unsupported(message: &str) -> anyhow::Error {
+    ClientError::Unsupported(message.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NGINX_HTML: &str = r#"<html>
+<head><title>Index of /models/</title></head>
+<body>
+<h1>Index of /models/</h1><hr><pre><a href="../">../</a>
+<a href="loras/">loras/</a>                                             15-Nov-2024 10:30                   -
+<a href="sd%20xl.safetensors">sd xl.safetensors</a>                                 15-Nov-2024 10:31          6938078334
+<a href="notes.txt">notes.txt</a>                                          02-Jan-2024 08:00                 12K
+</pre><hr></body>
+</html>"#;
+
+    /// Answer each request on a local port with what `respond` makes of its
+    /// head, and return the server's address
+    fn serve(respond: impl Fn(&str) -> String + Send + 'static) -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut head = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut head).unwrap_or(0) > 2 {}
+                let _ = (&stream).write_all(respond(&head).as_bytes());
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_new_normalizes_server() {
+        let client = HttpClient::new("10.0.0.5:8080", String::new(), String::new()).unwrap();
+        assert_eq!(client.base_url.as_str(), "http://10.0.0.5:8080/");
+        assert!(client.auth_header.is_none());
+
+        let client =
+            HttpClient::new("https://files.example.com/share", "u".into(), "p".into()).unwrap();
+        assert_eq!(client.base_url.as_str(), "https://files.example.com/share");
+        assert_eq!(client.auth_header.as_deref(), Some("Basic dTpw"));
+    }
+
+    #[test]
+    fn test_url_for() {
+        let client = HttpClient::new(
+            "https://files.example.com/share/",
+            String::new(),
+            String::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            client
+                .url_for("/models/sd xl.safetensors", false)
+                .unwrap()
+                .as_str(),
+            "https://files.example.com/share/models/sd%20xl.safetensors"
+        );
+        assert_eq!(
+            client.url_for("/models/", true).unwrap().as_str(),
+            "https://files.example.com/share/models/"
+        );
+        assert_eq!(
+            client.url_for("/", true).unwrap().as_str(),
+            "https://files.example.com/share/"
+        );
+    }
+
+    #[test]
+    fn test_parse_html_listing() {
+        let files = HttpClient::parse_html_listing("/models/", NGINX_HTML);
+
+        assert_eq!(files.len(), 3);
+
+        assert_eq!(files[0].name, "loras");
+        assert_eq!(files[0].path, "/models/loras");
+        assert!(files[0].is_dir);
+        assert_eq!(files[0].size, 0);
+
+        assert_eq!(files[1].name, "sd xl.safetensors");
+        assert_eq!(files[1].size, 6_938_078_334);
+        assert!(!files[1].is_dir);
+        assert_eq!(
+            files[1].modified.with_timezone(&Utc).to_rfc3339(),
+            "2024-11-15T10:31:00+00:00"
+        );
+
+        assert_eq!(files[2].size, 12 * 1024);
+    }
+
+    #[test]
+    fn test_parse_json_listing() {
+        let body = r#"[
+{ "name":"loras", "type":"directory", "mtime":"Fri, 15 Nov 2024 10:30:00 GMT" },
+{ "name":"model.ckpt", "type":"file", "mtime":"Fri, 15 Nov 2024 10:31:00 GMT", "size":1024 }
+]"#;
+        let files = HttpClient::parse_listing("/models", body).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert!(files[0].is_dir);
+        assert_eq!(files[1].path, "/models/model.ckpt");
+        assert_eq!(files[1].size, 1024);
+        assert_eq!(files[1].modified.timestamp(), 1_731_666_660);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(HttpClient::parse_size("512"), Some(512));
+        assert_eq!(HttpClient::parse_size("3M"), Some(3 * 1024 * 1024));
+        assert_eq!(HttpClient::parse_size("-"), None);
+        assert_eq!(HttpClient::parse_size(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_stat_times() {
+        let server = serve(|head| {
+            if head.starts_with("HEAD ") {
+                "HTTP/1.1 200 OK\r\nLast-Modified: Tue, 02 Jan 2024 08:00:42 GMT\r\n\
+                 Content-Length: 12288\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    NGINX_HTML.len(),
+                    NGINX_HTML
+                )
+            }
+        });
+        let mut client = HttpClient::new(&server, String::new(), String::new()).unwrap();
+        client.connect().await.unwrap();
+
+        // An HTML listing stops at the minute, and the header has the seconds
+        assert!(!client.capabilities().exact_times);
+        let file = client.stat("/models/notes.txt").await.unwrap();
+        assert_eq!(
+            file.modified,
+            Utc.with_ymd_and_hms(2024, 1, 2, 8, 0, 42).unwrap()
+        );
+        assert!(client.stat("/models/loras").await.unwrap().is_dir);
+    }
+
+    #[tokio::test]
+    async fn test_resume_only_the_same_file() {
+        const BODY: &str = "0123456789";
+        let server = serve(|head| {
+            let header = |name: &str| {
+                head.lines()
+                    .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            };
+            let (status, body) = match (header("Range"), header("If-Range")) {
+                (Some(range), Some("\"v1\"")) => {
+                    let from: usize = range["bytes=".len()..range.len() - 1].parse().unwrap();
+                    ("206 Partial Content", &BODY[from..])
+                }
+                // Without a validator a range is anyone's guess
+                (Some(_), None) => ("206 Partial Content", "garbage"),
+                _ => ("200 OK", BODY),
+            };
+            format!(
+                "HTTP/1.1 {}\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+        });
+        let mut client = HttpClient::new(&server, String::new(), String::new()).unwrap();
+        let local = tempfile::TempDir::new().unwrap();
+        let local_path = local.path().join("model.bin");
+        let partial = partial_path(&local_path);
+
+        // The same file continues, a changed one or one of unknown origin starts over
+        for (kept, validator) in [
+            ("01234", Some("\"v1\"")),
+            ("abcde", Some("\"v0\"")),
+            ("abcde", None),
+            ("", None),
+        ] {
+            fs::write(&partial, kept).unwrap();
+            if let Some(validator) = validator {
+                fs::write(validator_path(&partial), validator).unwrap();
+            }
+            client
+                .download_file("/model.bin", &local_path)
+                .await
+                .unwrap();
+            assert_eq!(fs::read_to_string(&local_path).unwrap(), BODY);
+            assert!(!validator_path(&partial).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_rejected() {
+        let mut client = HttpClient::new("localhost", String::new(), String::new()).unwrap();
+
+        assert!(client.create_directory("/new").await.is_err());
+        assert!(client.delete_file("/file.txt").await.is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use super::{
+    copy_chunks, finish_partial, format_mode, open_partial, set_local_modified, Capabilities,
+    ClientError, FileServerClient, Progress, RemoteFile,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+/// Serves a local directory through the same interface as the network clients.
+///
+/// Remote paths are resolved relative to `root`, so `/models` maps to `<root>/models`.
+/// Useful for mounted network drives and for exercising the CLI without a server.
+pub struct LocalClient {
+    root: PathBuf,
+}
+
+impl LocalClient {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let mut resolved = self.root.clone();
+
+        for component in Path::new(path.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                _ => return Err(anyhow!("Path escapes the local root: {}", path)),
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    fn remote_file_from_metadata(
+        base_path: &str,
+        name: &str,
+        metadata: &std::fs::Metadata,
+    ) -> RemoteFile {
+        let modified = metadata
+            .modified()
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now());
+
+        RemoteFile {
+            name: name.to_string(),
+            path: format!("{}/{}", base_path.trim_end_matches('/'), name),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified,
+            is_dir: metadata.is_dir(),
+            permissions: Self::permissions(metadata),
+        }
+    }
+
+    #[cfg(unix)]
+    fn permissions(metadata: &std::fs::Metadata) -> Option<String> {
+        use std::os::unix::fs::PermissionsExt;
+        Some(format_mode(metadata.permissions().mode()))
+    }
+
+    #[cfg(not(unix))]
+    fn permissions(_metadata: &std::fs::Metadata) -> Option<String> {
+        None
+    }
+}
+
+#[async_trait]
+impl FileServerClient for LocalClient {
+    async fn connect(&mut self) -> Result<()> {
+        if !tokio::fs::metadata(&self.root).await?.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", self.root));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+        let dir = self.resolve(path)?;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(ToString::to_string) else {
+                continue;
+            };
+            // Follow symlinks so linked directories can be browsed like real ones,
+            // but list a broken or unreadable link as itself rather than failing
+            let metadata = match tokio::fs::metadata(entry.path()).await {
+                Ok(metadata) => metadata,
+                Err(_) => tokio::fs::symlink_metadata(entry.path()).await?,
+            };
+            files.push(Self::remote_file_from_metadata(path, &name, &metadata));
+        }
+
+        Ok(files)
+    }
+
+    async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+        tokio::fs::copy(self.resolve(remote_path)?, local_path).await?;
+        Ok(())
+    }
+
+    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+        tokio::fs::copy(local_path, self.resolve(remote_path)?).await?;
+        Ok(())
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        copy_file(
+            self.resolve(remote_path)?,
+            local_path.to_path_buf(),
+            progress,
+        )
+        .await
+    }
+
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let source = self.resolve(remote_path)?;
+        let local_path = local_path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut source = std::fs::File::open(source)?;
+            let len = source.metadata()?.len();
+            let (mut target, offset) = open_partial(&local_path, Some(len), &progress)?;
+            source.seek(SeekFrom::Start(offset))?;
+            copy_chunks(&mut source, &mut target, &progress)?;
+            finish_partial(&local_path)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
+        copy_file(
+            local_path.to_path_buf(),
+            self.resolve(remote_path)?,
+            progress,
+        )
+        .await
+    }
+
+    async fn upload_reader(
+        &mut self,
+        mut reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        let path = self.resolve(remote_path)?;
+        tokio::task::spawn_blocking(move || {
+            let mut target = std::fs::File::create(path)?;
+            Ok(copy_chunks(&mut reader, &mut target, &progress)?)
+        })
+        .await?
+    }
+
+    async fn create_directory(&mut self, path: &str) -> Result<()> {
+        tokio::fs::create_dir(self.resolve(path)?).await?;
+        Ok(())
+    }
+
+    async fn delete_file(&mut self, path: &str) -> Result<()> {
+        tokio::fs::remove_file(self.resolve(path)?).await?;
+        Ok(())
+    }
+
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        let dir = self.resolve(path)?;
+        if dir == self.root {
+            return Err(anyhow!("Refusing to delete the local root"));
+        }
+        if recursive {
+            tokio::fs::remove_dir_all(dir).await?;
+        } else {
+            tokio::fs::remove_dir(dir).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        tokio::fs::rename(self.resolve(from)?, self.resolve(to)?).await?;
+        Ok(())
+    }
+
+    async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+        Ok(tokio::fs::metadata(self.resolve(path)?).await?.len())
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let local = self.resolve(path)?;
+        // A broken link is still there, as it is in its directory's listing
+        let metadata = match tokio::fs::metadata(&local).await {
+            Err(_) => tokio::fs::symlink_metadata(&local).await,
+            metadata => metadata,
+        };
+        let metadata = match metadata {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ClientError::not_found(path).into())
+            }
+            metadata => metadata?,
+        };
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut file = Self::remote_file_from_metadata(parent, name, &metadata);
+        if path.is_empty() {
+            file.name = "/".to_string();
+            file.path = "/".to_string();
+        }
+        Ok(file)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            resume: true,
+            rename: true,
+            set_modified: true,
+            server_side_copy: false,
+            exact_times: true,
+        }
+    }
+
+    async fn set_modified(&mut self, path: &str, modified: DateTime<Local>) -> Result<()> {
+        set_local_modified(&self.resolve(path)?, modified)
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let mut file = tokio::fs::File::open(self.resolve(path)?).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut data = Vec::new();
+        file.take(len).read_to_end(&mut data).await?;
+        Ok(data)
+    }
+}
+
+/// Copy one file to another a chunk at a time, reporting each chunk to `progress`
+async fn copy_file(from: PathBuf, to: PathBuf, progress: Progress) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut source = std::fs::File::open(from)?;
+        let mut target = std::fs::File::create(to)?;
+        copy_chunks(&mut source, &mut target, &progress)?;
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{is_not_found, stat_by_listing};
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn client(temp_dir: &TempDir) -> LocalClient {
+        LocalClient::new(temp_dir.path().to_path_buf())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let client = LocalClient::new(PathBuf::from("/mnt/share"));
+
+        assert_eq!(client.resolve("/").unwrap(), PathBuf::from("/mnt/share"));
+        assert_eq!(
+            client.resolve("/models/./vae.safetensors").unwrap(),
+            PathBuf::from("/mnt/share/models/vae.safetensors")
+        );
+        assert!(client.resolve("/models/../../etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_files() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("models")).unwrap();
+        std::fs::write(temp_dir.path().join("models/a.txt"), b"hello").unwrap();
+        std::fs::create_dir(temp_dir.path().join("models/loras")).unwrap();
+
+        let mut client = client(&temp_dir);
+        client.connect().await.unwrap();
+
+        let mut files = client.list_files("/models").await.unwrap();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "a.txt");
+        assert_eq!(files[0].path, "/models/a.txt");
+        assert_eq!(files[0].size, 5);
+        assert!(!files[0].is_dir);
+        assert_eq!(files[1].path, "/models/loras");
+        assert!(files[1].is_dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_list_files_with_broken_link() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), b"hello").unwrap();
+        std::os::unix::fs::symlink("gone.txt", temp_dir.path().join("dangling")).unwrap();
+        let mut client = client(&temp_dir);
+
+        // The link is listed as itself and doesn't hide the rest
+        let mut files = client.list_files("/").await.unwrap();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].name.as_str(), files[0].size), ("a.txt", 5));
+        assert_eq!(files[1].name, "dangling");
+        assert!(!files[1].is_dir);
+
+        assert_eq!(client.stat("/dangling").await.unwrap().path, "/dangling");
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("models")).unwrap();
+        std::fs::write(temp_dir.path().join("models/a.txt"), b"hello").unwrap();
+        let mut client = client(&temp_dir);
+
+        // The same as finding the entry in its parent's listing
+        for path in ["/models/a.txt", "/models/", "/"] {
+            let file = client.stat(path).await.unwrap();
+            let listed = stat_by_listing(&mut client, path).await.unwrap();
+            assert_eq!(
+                (&file.name, &file.path, file.size, file.is_dir),
+                (&listed.name, &listed.path, listed.size, listed.is_dir)
+            );
+        }
+        assert_eq!(client.stat("/models/a.txt").await.unwrap().size, 5);
+
+        let error = client.stat("/models/b.txt").await.unwrap_err();
+        assert!(is_not_found(&error));
+        let error = stat_by_listing(&mut client, "/models/b.txt")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No such file or directory: /models/b.txt"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_operations() {
+        let temp_dir = TempDir::new().unwrap();
+        let local_dir = TempDir::new().unwrap();
+        let source = local_dir.path().join("source.txt");
+        std::fs::write(&source, b"payload").unwrap();
+
+        let mut client = client(&temp_dir);
+        client.create_directory("/uploads").await.unwrap();
+        client
+            .upload_file(&source, "/uploads/copy.txt")
+            .await
+            .unwrap();
+        assert_eq!(client.get_file_size("/uploads/copy.txt").await.unwrap(), 7);
+
+        let target = local_dir.path().join("back.txt");
+        client
+            .download_file("/uploads/copy.txt", &target)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"payload");
+
+        client
+            .rename("/uploads/copy.txt", "/uploads/renamed.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_file_size("/uploads/renamed.txt").await.unwrap(),
+            7
+        );
+
+        client.delete_file("/uploads/renamed.txt").await.unwrap();
+        assert!(client.list_files("/uploads").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("models/loras")).unwrap();
+        std::fs::write(temp_dir.path().join("models/loras/a.safetensors"), b"a").unwrap();
+        std::fs::create_dir(temp_dir.path().join("empty")).unwrap();
+
+        let mut client = client(&temp_dir);
+        assert!(client.delete_directory("/models", false).await.is_err());
+        client.delete_directory("/empty", false).await.unwrap();
+        client.delete_directory("/models", true).await.unwrap();
+        assert!(client.list_files("/").await.unwrap().is_empty());
+
+        assert!(client.delete_directory("/", true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_modified() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), b"a").unwrap();
+        let modified = Local.timestamp_opt(1_704_067_200, 0).unwrap();
+
+        let mut client = client(&temp_dir);
+        client.set_modified("/a.txt", modified).await.unwrap();
+        let listed = client.list_files("/").await.unwrap();
+        assert_eq!(listed[0].modified, modified);
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() {
+        let temp_dir = TempDir::new().unwrap();
+        let local = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("model.bin"), b"0123456789").unwrap();
+        let target = local.path().join("model.bin");
+        // What an interrupted attempt left
+        std::fs::write(local.path().join("model.bin.part"), b"0123").unwrap();
+
+        let reported = Arc::new(AtomicU64::new(0));
+        let counter = reported.clone();
+        let progress: Progress = Arc::new(move |n| {
+            counter.fetch_add(n, Ordering::Relaxed);
+        });
+        let mut client = client(&temp_dir);
+        client
+            .resume_download("/model.bin", &target, progress)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"0123456789");
+        assert!(!local.path().join("model.bin.part").exists());
+        assert_eq!(reported.load(Ordering::Relaxed), 10);
+
+        // A partial file longer than the remote one is stale and starts over
+        std::fs::write(local.path().join("model.bin.part"), b"stale and too long").unwrap();
+        client
+            .resume_download("/model.bin", &target, crate::client::no_progress())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_connect_requires_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("file.txt");
+        std::fs::write(&file, b"").unwrap();
+
+        assert!(LocalClient::new(file).connect().await.is_err());
+        assert!(LocalClient::new(temp_dir.path().join("missing"))
+            .connect()
+            .await
+            .is_err());
+    }
+}
@@ -1,10 +1,165 @@
+pub mod address;
+pub mod error;
 pub mod ftp;
+pub mod http;
+pub mod local;
+pub mod paths;
+pub mod proxy;
+pub mod sftp;
 pub mod smb;
+#[cfg(feature = "native-smb")]
+pub mod smb_native;
 
-use anyhow::Result;
+pub use error::ClientError;
+
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Local};
-use std::path::Path;
+use crate::config::IpPreference;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// How long to wait on a server before giving up, when the config doesn't say
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait on a server before giving up on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// To reach the server and log in
+    pub connect: Duration,
+    /// For each reply or chunk of data once connected
+    pub read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: DEFAULT_CONNECT_TIMEOUT,
+            read: DEFAULT_READ_TIMEOUT,
+        }
+    }
+}
+
+/// Call `connect` with each address `address` (`host:port`) resolves to until
+/// one works, so a host with an IPv6 and an IPv4 address is reached on either.
+/// The kind `preference` asks for is tried first.
+pub fn connect_any<T, E>(
+    address: &str,
+    preference: IpPreference,
+    mut connect: impl FnMut(SocketAddr) -> Result<T, E>,
+) -> Result<T>
+where
+    E: Into<anyhow::Error>,
+{
+    let mut last_error = None;
+    for addr in address::resolve(address, preference)? {
+        match connect(addr) {
+            Ok(connection) => return Ok(connection),
+            Err(e) => last_error = Some(e.into()),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("{} has no addresses", address)))
+}
+
+/// Size of the buffer files are streamed through, so a multi-GB checkpoint never
+/// has to fit in memory
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Called with the number of bytes copied since the last call, after each chunk
+pub type Progress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// A [`Progress`] that ignores every chunk
+pub fn no_progress() -> Progress {
+    Arc::new(|_| {})
+}
+
+/// Copy `reader` to `writer` a chunk at a time, reporting each chunk to
+/// `progress`. Returns the number of bytes copied.
+pub fn copy_chunks(
+    reader: &mut (impl Read + ?Sized),
+    writer: &mut (impl Write + ?Sized),
+    progress: &Progress,
+) -> io::Result<u64> {
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        progress(read as u64);
+    }
+    writer.flush()?;
+    Ok(copied)
+}
+
+/// Most that [`FileServerClient::upload_reader`] buffers for clients that
+/// can't send a stream as it's read
+pub const MAX_SPOOL_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Copy `reader` into a temp file, returning it and its size. Fails rather
+/// than fill the disk once there's more than `limit` bytes.
+pub fn spool(reader: impl Read, limit: u64) -> Result<(NamedTempFile, u64)> {
+    let mut file = NamedTempFile::new().context("Could not create a temp file")?;
+    // A byte past the limit tells a source that's too big from one that just fits
+    let size = io::copy(&mut reader.take(limit.saturating_add(1)), &mut file)
+        .context("Could not read the upload")?;
+    if size > limit {
+        bail!(
+            "The upload is over {} and this server can't take it as it's read; \
+             save it to a file and upload that, or use FTP, SFTP, or native SMB",
+            human_bytes::human_bytes(limit as f64)
+        );
+    }
+    file.flush()?;
+    Ok((file, size))
+}
+
+/// Where a resumable download of `local_path` is kept until it's complete
+pub fn partial_path(local_path: &Path) -> PathBuf {
+    let mut partial = local_path.as_os_str().to_owned();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
+
+/// Open the partial download of `local_path` to add to, reporting what it
+/// already holds to `progress`. Returns the file and the offset to continue
+/// from, which is 0 when there's nothing to keep, or when the partial file is
+/// longer than the remote file of `remote_len` bytes, which must have changed.
+pub fn open_partial(
+    local_path: &Path,
+    remote_len: Option<u64>,
+    progress: &Progress,
+) -> io::Result<(std::fs::File, u64)> {
+    let path = partial_path(local_path);
+    let mut offset = std::fs::metadata(&path).map_or(0, |m| m.len());
+    if remote_len.is_some_and(|len| offset > len) {
+        offset = 0;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
+        .open(&path)?;
+    progress(offset);
+    Ok((file, offset))
+}
+
+/// Move a completed partial download of `local_path` into place
+pub fn finish_partial(local_path: &Path) -> io::Result<()> {
+    std::fs::rename(partial_path(local_path), local_path)
+}
 
 #[derive(Debug, Clone)]
 pub struct RemoteFile {
@@ -13,6 +168,68 @@ pub struct RemoteFile {
     pub size: u64,
     pub modified: DateTime<Local>,
     pub is_dir: bool,
+    /// Unix permissions like `rwxr-xr-x`, when the server reports them
+    pub permissions: Option<String>,
+}
+
+/// A share an SMB server offers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub name: String,
+    /// What the server says it's for, often nothing
+    pub comment: String,
+}
+
+/// What a server can do beyond listing and whole-file transfers, so callers
+/// can pick an approach that works instead of finding out from an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// [`read_range`](FileServerClient::read_range) reads only the part asked
+    /// for, rather than downloading the whole file each time
+    pub ranged_reads: bool,
+    /// [`resume_download`](FileServerClient::resume_download) continues from
+    /// what already arrived
+    pub resume: bool,
+    /// Files and directories can be renamed and moved on the server
+    pub rename: bool,
+    /// [`set_modified`](FileServerClient::set_modified) works
+    pub set_modified: bool,
+    /// Files can be copied on the server, without passing through this machine
+    pub server_side_copy: bool,
+    /// Listings give modification times to the second. Where they don't,
+    /// [`stat`](FileServerClient::stat) has the exact time of a file.
+    pub exact_times: bool,
+}
+
+impl Capabilities {
+    /// What the trait's default methods give
+    pub const BASIC: Self = Self {
+        ranged_reads: false,
+        resume: false,
+        rename: true,
+        set_modified: false,
+        server_side_copy: false,
+        exact_times: true,
+    };
+}
+
+/// Whether `error` is a [`ClientError::NotFound`], so callers can tell a
+/// missing path from a failure to look it up
+pub fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(ClientError::classify(error), ClientError::NotFound(_))
+}
+
+/// Unix permission bits as `rwxr-xr-x`
+pub fn format_mode(mode: u32) -> String {
+    (0..9)
+        .map(|bit| {
+            if mode & (0o400 >> bit) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect()
 }
 
 #[async_trait]
@@ -24,5 +241,545 @@ pub trait FileServerClient: Send + Sync {
     async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()>;
     async fn create_directory(&mut self, path: &str) -> Result<()>;
     async fn delete_file(&mut self, path: &str) -> Result<()>;
+    /// Delete a directory, with everything in it when `recursive` is set.
+    /// Without `recursive` the directory has to be empty.
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()>;
+    /// Rename or move a file or directory on the server
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
     async fn get_file_size(&mut self, path: &str) -> Result<u64>;
+
+    /// The file or directory at `path`, failing with [`ClientError::NotFound`] when there's
+    /// nothing there.
+    ///
+    /// The default lists the parent directory. Clients that can ask about one
+    /// path override it.
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        stat_by_listing(self, path).await
+    }
+
+    /// What this server supports. Clients that override the default methods
+    /// say so here.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::BASIC
+    }
+
+    /// [`download_file`](Self::download_file), reporting bytes to `progress` as
+    /// they arrive.
+    ///
+    /// The default reports the whole file once it's down. Clients that stream
+    /// override it.
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        self.download_file(remote_path, local_path).await?;
+        // Progress is only for show, so a file that can't be measured counts as empty
+        progress(tokio::fs::metadata(local_path).await.map_or(0, |m| m.len()));
+        Ok(())
+    }
+
+    /// [`download_file_with_progress`](Self::download_file_with_progress) that
+    /// can be picked up where it stopped. The file is written to its
+    /// [`partial_path`] and only moved into place once complete, so calling this
+    /// again after an interruption continues from what arrived.
+    ///
+    /// The default downloads the whole file again. Clients that can start a
+    /// download part way override it.
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        self.download_file_with_progress(remote_path, &partial_path(local_path), progress)
+            .await?;
+        finish_partial(local_path)?;
+        Ok(())
+    }
+
+    /// [`upload_file`](Self::upload_file), reporting bytes to `progress` as
+    /// they're sent.
+    ///
+    /// The default reports the whole file once it's up. Clients that stream
+    /// override it.
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
+        self.upload_file(local_path, remote_path).await?;
+        progress(tokio::fs::metadata(local_path).await.map_or(0, |m| m.len()));
+        Ok(())
+    }
+
+    /// Upload everything `reader` gives as `remote_path`, for sources that can
+    /// only be read once, like standard input. Returns the number of bytes sent.
+    ///
+    /// The default buffers up to [`MAX_SPOOL_BYTES`] in a temp file and uploads
+    /// that. Clients that can send as they read override it.
+    async fn upload_reader(
+        &mut self,
+        reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        let (spooled, size) =
+            tokio::task::spawn_blocking(move || spool(reader, MAX_SPOOL_BYTES)).await??;
+        self.upload_file_with_progress(spooled.path(), remote_path, progress)
+            .await?;
+        Ok(size)
+    }
+
+    /// Set the modification time of the file at `path`, so uploads can keep the
+    /// local file's. Not every protocol allows it; the default says so.
+    async fn set_modified(&mut self, path: &str, _modified: DateTime<Local>) -> Result<()> {
+        Err(ClientError::Unsupported(format!(
+            "This server doesn't support setting the modification time of {}",
+            path
+        ))
+        .into())
+    }
+
+    /// Read up to `len` bytes of the file at `path` starting at `offset`.
+    /// Fewer bytes come back when the file ends first.
+    ///
+    /// The default downloads the whole file and keeps the part asked for.
+    /// Clients that can seek on the server override it.
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let temp_path = temp_file_path("range");
+        let result = self.download_file(path, &temp_path).await;
+        let data = match result {
+            Ok(()) => tokio::fs::read(&temp_path).await,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                return Err(e);
+            }
+        };
+        let _ = tokio::fs::remove_file(&temp_path).await;
+
+        let data = data?;
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+        let end = start.saturating_add(usize::try_from(len).unwrap_or(usize::MAX)).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+}
+
+/// Set the modification time of a local file
+pub fn set_local_modified(path: &Path, modified: DateTime<Local>) -> Result<()> {
+    let file = std::fs::File::options().write(true).open(path)?;
+    file.set_modified(modified.into())?;
+    Ok(())
+}
+
+/// A fresh path in the system temp directory for staging a download
+fn temp_file_path(purpose: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+        "comfy-fs-{}-{}-{}",
+        purpose,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Look up `path` by listing its parent directory, for servers that can't be
+/// asked about one path
+pub async fn stat_by_listing<C: FileServerClient + ?Sized>(
+    client: &mut C,
+    path: &str,
+) -> Result<RemoteFile> {
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        // The root has no parent to list, but it's there if it can be listed
+        client.list_files("/").await?;
+        return Ok(RemoteFile {
+            name: "/".to_string(),
+            path: "/".to_string(),
+            size: 0,
+            modified: Local::now(),
+            is_dir: true,
+            permissions: None,
+        });
+    }
+
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let parent = if parent.is_empty() { "/" } else { parent };
+    client
+        .list_files(parent)
+        .await?
+        .into_iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| ClientError::not_found(path).into())
+}
+
+/// Copy a file, or a directory and everything in it, to `to` on the same server by
+/// downloading and re-uploading through a temp file
+pub async fn copy_path(
+    client: &mut dyn FileServerClient,
+    from: &str,
+    to: &str,
+    is_dir: bool,
+) -> Result<()> {
+    // The copy would be listed as part of what's being copied, forever
+    if is_dir && paths::is_within(to, from)? {
+        bail!("Can't copy {} into itself ({})", from, to);
+    }
+    let temp_path = temp_file_path("copy");
+    let result = copy_with(client, from, to, is_dir, &temp_path).await;
+    let _ = tokio::fs::remove_file(&temp_path).await;
+    result
+}
+
+async fn copy_with(
+    client: &mut dyn FileServerClient,
+    from: &str,
+    to: &str,
+    is_dir: bool,
+    temp_path: &Path,
+) -> Result<()> {
+    if !is_dir {
+        client.download_file(from, temp_path).await?;
+        return client.upload_file(temp_path, to).await;
+    }
+
+    let mut pending = vec![(from.to_string(), to.to_string())];
+    while let Some((source, target)) = pending.pop() {
+        client.create_directory(&target).await?;
+        for entry in client.list_files(&source).await? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let entry_target = format!("{}/{}", target.trim_end_matches('/'), entry.name);
+            if entry.is_dir {
+                pending.push((entry.path, entry_target));
+            } else {
+                client.download_file(&entry.path, temp_path).await?;
+                client.upload_file(temp_path, &entry_target).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move `source` to `target` on the same server, renaming it where the server
+/// can. Servers that can't rename, or can't rename between the disks involved,
+/// get a copy and delete instead; any other failure to rename is returned, so
+/// nothing is copied over a target the server refused.
+///
+/// A `target` that already exists is refused unless `overwrite` is set, when
+/// it's deleted first. So is a `target` inside `source`.
+///
+/// Returns whether it had to copy.
+pub async fn move_path(
+    client: &mut dyn FileServerClient,
+    source: &RemoteFile,
+    target: &str,
+    overwrite: bool,
+) -> Result<bool> {
+    if paths::is_within(target, &source.path)? {
+        bail!("Can't move {} into itself ({})", source.path, target);
+    }
+    match client.stat(target).await {
+        Ok(existing) if overwrite => {
+            if existing.is_dir {
+                client.delete_directory(target, true).await?;
+            } else {
+                client.delete_file(target).await?;
+            }
+        }
+        Ok(_) => bail!("{} already exists (use --force to replace it)", target),
+        Err(e) if is_not_found(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    if client.capabilities().rename {
+        match client.rename(&source.path, target).await {
+            Ok(()) => return Ok(false),
+            Err(e) if matches!(ClientError::classify(&e), ClientError::Unsupported(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    copy_path(client, &source.path, target, source.is_dir).await?;
+    if source.is_dir {
+        client.delete_directory(&source.path, true).await?;
+    } else {
+        client.delete_file(&source.path).await?;
+    }
+    Ok(true)
+}
+
+/// Total size of the files under `path`, listing every subdirectory
+pub async fn directory_size(client: &mut dyn FileServerClient, path: &str) -> Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![path.to_string()];
+    while let Some(dir) = pending.pop() {
+        for entry in client.list_files(&dir).await? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            if entry.is_dir {
+                pending.push(entry.path);
+            } else {
+                total += entry.size;
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Create `path` and any missing parents, like `mkdir -p`
+pub async fn create_directory_all(client: &mut dyn FileServerClient, path: &str) -> Result<()> {
+    let mut current = String::new();
+    for part in path.split('/').filter(|part| !part.is_empty()) {
+        let parent = if current.is_empty() { "/".to_string() } else { current.clone() };
+        current = format!("{}/{}", current, part);
+
+        let existing = client
+            .list_files(&parent)
+            .await?
+            .into_iter()
+            .find(|f| f.name == part);
+        match existing {
+            Some(f) if f.is_dir => {}
+            Some(_) => bail!("{} exists and is not a directory", current),
+            None => client.create_directory(&current).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Delete everything inside `path`, leaving the directory itself
+pub async fn delete_contents(client: &mut dyn FileServerClient, path: &str) -> Result<()> {
+    for entry in client.list_files(path).await? {
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        if entry.is_dir {
+            client.delete_directory(&entry.path, true).await?;
+        } else {
+            client.delete_file(&entry.path).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client that can only download whole files, to exercise the default `read_range`
+    struct WholeFileClient;
+
+    #[async_trait]
+    impl FileServerClient for WholeFileClient {
+        async fn connect(&mut self) -> Result<()> { Ok(()) }
+        async fn disconnect(&mut self) -> Result<()> { Ok(()) }
+        async fn list_files(&mut self, _path: &str) -> Result<Vec<RemoteFile>> { Ok(vec![]) }
+        async fn download_file(&mut self, _remote_path: &str, local_path: &Path) -> Result<()> {
+            tokio::fs::write(local_path, b"0123456789").await?;
+            Ok(())
+        }
+        async fn upload_file(&mut self, _local_path: &Path, _remote_path: &str) -> Result<()> { Ok(()) }
+        async fn create_directory(&mut self, _path: &str) -> Result<()> { Ok(()) }
+        async fn delete_file(&mut self, _path: &str) -> Result<()> { Ok(()) }
+        async fn delete_directory(&mut self, _path: &str, _recursive: bool) -> Result<()> { Ok(()) }
+        async fn rename(&mut self, _from: &str, _to: &str) -> Result<()> { Ok(()) }
+        async fn get_file_size(&mut self, _path: &str) -> Result<u64> { Ok(10) }
+    }
+
+    /// A local directory whose renames fail with `error`
+    struct RenameFails {
+        inner: local::LocalClient,
+        error: fn(String) -> ClientError,
+    }
+
+    #[async_trait]
+    impl FileServerClient for RenameFails {
+        async fn connect(&mut self) -> Result<()> { Ok(()) }
+        async fn disconnect(&mut self) -> Result<()> { Ok(()) }
+        async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+            self.inner.list_files(path).await
+        }
+        async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+            self.inner.download_file(remote_path, local_path).await
+        }
+        async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+            self.inner.upload_file(local_path, remote_path).await
+        }
+        async fn create_directory(&mut self, path: &str) -> Result<()> {
+            self.inner.create_directory(path).await
+        }
+        async fn delete_file(&mut self, path: &str) -> Result<()> {
+            self.inner.delete_file(path).await
+        }
+        async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+            self.inner.delete_directory(path, recursive).await
+        }
+        async fn rename(&mut self, from: &str, _to: &str) -> Result<()> {
+            Err((self.error)(format!("Could not rename {}", from)).into())
+        }
+        async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+            self.inner.get_file_size(path).await
+        }
+    }
+
+    #[test]
+    fn test_format_mode() {
+        assert_eq!(format_mode(0o755), "rwxr-xr-x");
+        assert_eq!(format_mode(0o100_644), "rw-r--r--");
+        assert_eq!(format_mode(0o600), "rw-------");
+    }
+
+    #[tokio::test]
+    async fn test_delete_contents() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("models/loras")).unwrap();
+        std::fs::write(temp_dir.path().join("models/loras/a.safetensors"), b"a").unwrap();
+        std::fs::write(temp_dir.path().join("models/b.ckpt"), b"b").unwrap();
+
+        let mut client = local::LocalClient::new(temp_dir.path().to_path_buf());
+        delete_contents(&mut client, "/models").await.unwrap();
+
+        assert!(client.list_files("/models").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_directory_all() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp_dir.path().join("models")).unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), b"").unwrap();
+
+        let mut client = local::LocalClient::new(temp_dir.path().to_path_buf());
+        create_directory_all(&mut client, "/models/loras/styles/").await.unwrap();
+        assert!(temp_dir.path().join("models/loras/styles").is_dir());
+
+        // Already there
+        create_directory_all(&mut client, "/models/loras").await.unwrap();
+        assert!(create_directory_all(&mut client, "/notes.txt/sub").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("models/loras")).unwrap();
+        std::fs::write(temp_dir.path().join("models/loras/a.safetensors"), b"lora").unwrap();
+        std::fs::write(temp_dir.path().join("models/b.ckpt"), b"ckpt").unwrap();
+
+        let mut client = local::LocalClient::new(temp_dir.path().to_path_buf());
+        copy_path(&mut client, "/models/b.ckpt", "/b-copy.ckpt", false).await.unwrap();
+        copy_path(&mut client, "/models", "/backup", true).await.unwrap();
+
+        assert_eq!(std::fs::read(temp_dir.path().join("b-copy.ckpt")).unwrap(), b"ckpt");
+        assert_eq!(
+            std::fs::read(temp_dir.path().join("backup/loras/a.safetensors")).unwrap(),
+            b"lora"
+        );
+        assert_eq!(std::fs::read(temp_dir.path().join("backup/b.ckpt")).unwrap(), b"ckpt");
+    }
+
+    #[tokio::test]
+    async fn test_move_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("models/loras")).unwrap();
+        std::fs::write(temp_dir.path().join("models/a.ckpt"), b"new").unwrap();
+        std::fs::write(temp_dir.path().join("b.ckpt"), b"old").unwrap();
+
+        let mut client = local::LocalClient::new(temp_dir.path().to_path_buf());
+        let source = client.stat("/models/a.ckpt").await.unwrap();
+        assert!(move_path(&mut client, &source, "/b.ckpt", false).await.is_err());
+        assert_eq!(std::fs::read(temp_dir.path().join("b.ckpt")).unwrap(), b"old");
+        assert!(!move_path(&mut client, &source, "/b.ckpt", true).await.unwrap());
+        assert_eq!(std::fs::read(temp_dir.path().join("b.ckpt")).unwrap(), b"new");
+
+        let models = client.stat("/models").await.unwrap();
+        for target in ["/models/loras/models", "//models/", "/models/x/.."] {
+            assert!(move_path(&mut client, &models, target, true).await.is_err());
+        }
+        assert!(temp_dir.path().join("models/loras").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_move_path_fallback() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp_dir.path().join("models")).unwrap();
+        std::fs::write(temp_dir.path().join("a.ckpt"), b"a").unwrap();
+        let inner = local::LocalClient::new(temp_dir.path().to_path_buf());
+        let mut client = RenameFails { inner, error: ClientError::PermissionDenied };
+        let source = client.stat("/a.ckpt").await.unwrap();
+
+        // Refused renames aren't worked around
+        assert!(move_path(&mut client, &source, "/models/a.ckpt", false).await.is_err());
+        assert!(temp_dir.path().join("a.ckpt").exists());
+        assert!(!temp_dir.path().join("models/a.ckpt").exists());
+
+        client.error = ClientError::Unsupported;
+        assert!(move_path(&mut client, &source, "/models/a.ckpt", false).await.unwrap());
+        assert!(!temp_dir.path().join("a.ckpt").exists());
+        assert_eq!(std::fs::read(temp_dir.path().join("models/a.ckpt")).unwrap(), b"a");
+    }
+
+    #[tokio::test]
+    async fn test_copy_chunks() {
+        let data = vec![7u8; CHUNK_SIZE * 2 + 10];
+        let chunks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = chunks.clone();
+        let progress: Progress = Arc::new(move |n| seen.lock().unwrap().push(n));
+
+        let mut copy = Vec::new();
+        assert_eq!(
+            copy_chunks(&mut &data[..], &mut copy, &progress).unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(copy, data);
+        let chunks = chunks.lock().unwrap().clone();
+        assert!(chunks.len() >= 3);
+        assert_eq!(chunks.iter().sum::<u64>(), data.len() as u64);
+
+        // Clients that don't stream report the whole file at the end
+        let temp_dir = tempfile::tempdir().unwrap();
+        let total = Arc::new(AtomicU64::new(0));
+        let counted = total.clone();
+        WholeFileClient
+            .download_file_with_progress(
+                "/f",
+                &temp_dir.path().join("f"),
+                Arc::new(move |n| {
+                    counted.fetch_add(n, Ordering::Relaxed);
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total.load(Ordering::Relaxed), 10);
+    }
+
+    #[tokio::test]
+    async fn test_spool() {
+        let (file, size) = spool(&b"tar data"[..], 8).unwrap();
+        assert_eq!(size, 8);
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"tar data");
+        assert!(spool(&b"tar data"[..], 7).is_err());
+
+        // Clients that can't stream upload a spooled copy
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut client = RenameFails {
+            inner: local::LocalClient::new(temp_dir.path().to_path_buf()),
+            error: ClientError::Unsupported,
+        };
+        let reader: Box<dyn Read + Send> = Box::new(io::Cursor::new(b"log".to_vec()));
+        let sent = client.upload_reader(reader, "/run.txt", no_progress()).await.unwrap();
+        assert_eq!(sent, 3);
+        assert_eq!(std::fs::read(temp_dir.path().join("run.txt")).unwrap(), b"log");
+    }
+
+    #[tokio::test]
+    async fn test_default_read_range() {
+        let mut client = WholeFileClient;
+
+        assert_eq!(client.read_range("/f", 2, 3).await.unwrap(), b"234");
+        assert_eq!(client.read_range("/f", 8, 100).await.unwrap(), b"89");
+        assert!(client.read_range("/f", 50, 1).await.unwrap().is_empty());
+    }
 }
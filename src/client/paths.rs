@@ -0,0 +1,293 @@
+//! Remote paths tidied and checked before they reach a server.
+//!
+//! Paths are put together from user input and from names servers list, so a
+//! stray `..` could reach above the share or login directory. [`PathGuard`]
+//! normalizes every path a client is given and refuses ones that climb out.
+
+use super::{Capabilities, ClientError, FileServerClient, Progress, RemoteFile};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use std::io::Read;
+use std::path::Path;
+
+/// `path` with duplicate slashes, `.` and `..` resolved. Absolute paths stay
+/// below `/`, the root of the share; relative ones stay below the directory
+/// the server starts in, which an empty path stands for. A trailing slash is
+/// kept, since callers use it to mean "into this directory".
+///
+/// Fails with [`ClientError::PermissionDenied`] when a `..` would climb above
+/// the root.
+pub fn normalize(path: &str) -> Result<String> {
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(outside_root(path));
+                }
+            }
+            // Windows servers split on backslashes too
+            part if part.split('\\').any(|piece| piece == "..") => {
+                return Err(outside_root(path));
+            }
+            part => parts.push(part),
+        }
+    }
+
+    let mut normalized = parts.join("/");
+    if absolute {
+        normalized.insert(0, '/');
+    }
+    if path.ends_with('/') && !normalized.is_empty() && !normalized.ends_with('/') {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Resolve `input` against the `current` directory into an absolute path,
+/// as [`normalize`] does
+pub fn resolve(current: &str, input: &str) -> Result<String> {
+    let joined = if input.starts_with('/') {
+        input.to_string()
+    } else {
+        format!("/{}/{}", current.trim_matches('/'), input)
+    };
+    let resolved = normalize(&joined)?;
+    Ok(match resolved.trim_end_matches('/') {
+        "" => "/".to_string(),
+        resolved => resolved.to_string(),
+    })
+}
+
+/// Whether `path` is the root of the share or the directory the server starts
+/// in, the places nothing should ever delete wholesale
+pub fn is_root(path: &str) -> Result<bool> {
+    Ok(normalize(path)?.trim_matches('/').is_empty())
+}
+
+/// Whether `path` is `dir` or somewhere below it
+pub fn is_within(path: &str, dir: &str) -> Result<bool> {
+    let path = normalize(path)?;
+    let dir = normalize(dir)?;
+    // Where a relative path is depends on the directory the server starts in
+    if path.starts_with('/') != dir.starts_with('/') {
+        return Ok(false);
+    }
+    let (path, dir) = (path.trim_end_matches('/'), dir.trim_end_matches('/'));
+    Ok(dir.is_empty()
+        || path == dir
+        || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/')))
+}
+
+/// Whether `name`, as a server listed it, is a single entry that's safe to
+/// join onto a path here or on the server
+pub fn is_safe_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && !name.contains('/')
+        && !name.split('\\').any(|piece| piece == "..")
+}
+
+fn outside_root(path: &str) -> anyhow::Error {
+    ClientError::PermissionDenied(format!("Path goes above the server's root: {}", path)).into()
+}
+
+/// A client that [`normalize`]s every path before passing it on, and leaves out
+/// listed entries whose names aren't [safe](is_safe_name)
+pub struct PathGuard {
+    inner: Box<dyn FileServerClient>,
+}
+
+impl PathGuard {
+    pub fn new(inner: Box<dyn FileServerClient>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl FileServerClient for PathGuard {
+    async fn connect(&mut self) -> Result<()> {
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.inner.disconnect().await
+    }
+
+    async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+        let mut files = self.inner.list_files(&normalize(path)?).await?;
+        files.retain(|file| is_safe_name(&file.name));
+        Ok(files)
+    }
+
+    async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+        self.inner
+            .download_file(&normalize(remote_path)?, local_path)
+            .await
+    }
+
+    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+        self.inner
+            .upload_file(local_path, &normalize(remote_path)?)
+            .await
+    }
+
+    async fn upload_reader(
+        &mut self,
+        reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        self.inner
+            .upload_reader(reader, &normalize(remote_path)?, progress)
+            .await
+    }
+
+    async fn create_directory(&mut self, path: &str) -> Result<()> {
+        self.inner.create_directory(&normalize(path)?).await
+    }
+
+    async fn delete_file(&mut self, path: &str) -> Result<()> {
+        self.inner.delete_file(&normalize(path)?).await
+    }
+
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        self.inner
+            .delete_directory(&normalize(path)?, recursive)
+            .await
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.inner.rename(&normalize(from)?, &normalize(to)?).await
+    }
+
+    async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+        self.inner.get_file_size(&normalize(path)?).await
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        self.inner.stat(&normalize(path)?).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        self.inner
+            .download_file_with_progress(&normalize(remote_path)?, local_path, progress)
+            .await
+    }
+
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        self.inner
+            .resume_download(&normalize(remote_path)?, local_path, progress)
+            .await
+    }
+
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
+        self.inner
+            .upload_file_with_progress(local_path, &normalize(remote_path)?, progress)
+            .await
+    }
+
+    async fn set_modified(&mut self, path: &str, modified: DateTime<Local>) -> Result<()> {
+        self.inner.set_modified(&normalize(path)?, modified).await
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.inner.read_range(&normalize(path)?, offset, len).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::is_not_found;
+    use crate::client::local::LocalClient;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("//models///loras/").unwrap(), "/models/loras/");
+        assert_eq!(normalize("/models/./loras/../vae").unwrap(), "/models/vae");
+        assert_eq!(
+            normalize("models//a.safetensors").unwrap(),
+            "models/a.safetensors"
+        );
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(normalize("/models/..").unwrap(), "/");
+        assert_eq!(normalize("models/..").unwrap(), "");
+
+        for escaping in [
+            "/..",
+            "/models/../../etc",
+            "..",
+            "a/../../b",
+            "/models/..\\..\\x",
+        ] {
+            let error = normalize(escaping).unwrap_err();
+            assert!(matches!(
+                ClientError::classify(&error),
+                ClientError::PermissionDenied(_)
+            ));
+        }
+
+        assert_eq!(resolve("/models", "/input").unwrap(), "/input");
+        assert_eq!(
+            resolve("/models", "loras/sdxl").unwrap(),
+            "/models/loras/sdxl"
+        );
+        assert_eq!(resolve("/models/loras", "../vae/").unwrap(), "/models/vae");
+        assert_eq!(resolve("/a", "//b/./c").unwrap(), "/b/c");
+        assert_eq!(resolve("/models", "..").unwrap(), "/");
+        assert!(resolve("/", "../..").is_err());
+        assert!(is_root("//").unwrap() && is_root("/models/./..").unwrap());
+        assert!(is_root("").unwrap() && is_root("a/..").unwrap());
+        assert!(!is_root("/models").unwrap());
+        assert!(is_within("/models//loras/", "/models").unwrap());
+        assert!(is_within("/models", "/models/").unwrap() && is_within("/a", "/").unwrap());
+        assert!(!is_within("/models-old", "/models").unwrap() && !is_within("/", "/a").unwrap());
+        assert!(is_safe_name("a b.safetensors"));
+        assert!(!is_safe_name("..") && !is_safe_name("a/b") && !is_safe_name("..\\x"));
+    }
+
+    #[tokio::test]
+    async fn test_path_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("share");
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        std::fs::write(dir.path().join("secret"), b"x").unwrap();
+        std::fs::write(root.join("models/a.bin"), b"abc").unwrap();
+
+        let mut client = PathGuard::new(Box::new(LocalClient::new(root)));
+        client.connect().await.unwrap();
+        assert_eq!(client.get_file_size("//models/./a.bin").await.unwrap(), 3);
+        let files = client.list_files("/models//").await.unwrap();
+        assert_eq!(files.len(), 1);
+
+        let error = client
+            .get_file_size("/models/../../secret")
+            .await
+            .unwrap_err();
+        assert!(!is_not_found(&error));
+        assert!(client.delete_file("/../secret").await.is_err());
+        assert!(dir.path().join("secret").exists());
+    }
+}
@@ -0,0 +1,694 @@
+use super::address::host_name;
+use super::error::SFTP_NO_SUCH_FILE;
+use super::proxy::Proxy;
+use super::{
+    connect_any, copy_chunks, delete_contents, finish_partial, format_mode, no_progress,
+    open_partial, Capabilities, ClientError, FileServerClient, Progress, RemoteFile, Timeouts,
+};
+use crate::config::{IpPreference, SftpHostKeys};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Local, TimeZone};
+use ssh2::{CheckResult, ErrorCode, FileStat, HashType, HostKeyType, Session};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::debug;
+
+/// Where OpenSSH keeps the host keys it has seen, and where new ones are saved
+pub fn default_known_hosts() -> Option<PathBuf> {
+    directories::UserDirs::new().map(|dirs| dirs.home_dir().join(".ssh").join("known_hosts"))
+}
+
+/// A host key that isn't in `known_hosts` yet, to be checked and trusted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownHostKey {
+    /// As OpenSSH shows it, `SHA256:` and the base64 of the key's hash
+    pub fingerprint: String,
+    /// The `known_hosts` line that trusts it
+    line: String,
+}
+
+/// Each operation opens its own session, on a clone in a blocking task
+#[derive(Clone)]
+pub struct SftpClient {
+    host: String,
+    username: String,
+    password: String,
+    timeouts: Timeouts,
+    ip_preference: IpPreference,
+    proxy: Option<Proxy>,
+    host_keys: SftpHostKeys,
+    known_hosts: Option<PathBuf>,
+}
+
+impl SftpClient {
+    pub fn new(host: String, username: String, password: String) -> Self {
+        Self {
+            host,
+            username,
+            password,
+            timeouts: Timeouts::default(),
+            ip_preference: IpPreference::Auto,
+            proxy: None,
+            host_keys: SftpHostKeys::default(),
+            known_hosts: default_known_hosts(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.ip_preference = ip_preference;
+        self
+    }
+
+    /// Connect through `proxy`, or directly when it's `None`
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// What to do about a host key that isn't known yet
+    #[must_use]
+    pub const fn with_host_keys(mut self, host_keys: SftpHostKeys) -> Self {
+        self.host_keys = host_keys;
+        self
+    }
+
+    /// Check host keys against this `known_hosts` file rather than OpenSSH's
+    #[must_use]
+    pub fn with_known_hosts(mut self, known_hosts: PathBuf) -> Self {
+        self.known_hosts = Some(known_hosts);
+        self
+    }
+
+    /// The server's host key if it isn't in `known_hosts` yet, so it can be
+    /// shown and [trusted](Self::trust) before connecting. Fails when the key
+    /// differs from the saved one.
+    pub async fn unknown_host_key(&self) -> Result<Option<UnknownHostKey>> {
+        let client = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let session = client.handshake()?;
+            let unknown = client.check_host_key(&session)?;
+            let _ = session.disconnect(None, "", None);
+            Ok(unknown)
+        })
+        .await?
+    }
+
+    /// Save `key` to `known_hosts`, so the server it came from is trusted
+    pub fn trust(&self, key: &UnknownHostKey) -> Result<()> {
+        let path = self.known_hosts_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not save the host key to {}", path.display()))?;
+        writeln!(file, "{}", key.line)?;
+        Ok(())
+    }
+
+    fn known_hosts_path(&self) -> Result<&Path> {
+        self.known_hosts
+            .as_deref()
+            .ok_or_else(|| anyhow!("No home directory to find ~/.ssh/known_hosts in"))
+    }
+
+    /// The host and port the server is reached at, as `known_hosts` names it
+    fn host_and_port(&self) -> (String, u16) {
+        let port = self
+            .host
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse().ok())
+            .unwrap_or(22);
+        (host_name(&self.host), port)
+    }
+
+    fn handshake(&self) -> Result<Session> {
+        let timeouts = self.timeouts;
+        let tcp = match &self.proxy {
+            Some(proxy) => proxy.connect(&self.host, self.ip_preference, timeouts.connect)?,
+            None => connect_any(&self.host, self.ip_preference, |addr| {
+                TcpStream::connect_timeout(&addr, timeouts.connect)
+            })?,
+        };
+        let mut session = Session::new()?;
+        // Every blocking call, handshake included, gives up after this long
+        session.set_timeout(u32::try_from(timeouts.read.as_millis()).unwrap_or(u32::MAX));
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        Ok(session)
+    }
+
+    /// Compare the server's host key with `known_hosts`. Returns the key when
+    /// the host isn't there, and fails when its saved key is different.
+    fn check_host_key(&self, session: &Session) -> Result<Option<UnknownHostKey>> {
+        let (key, key_type) = session
+            .host_key()
+            .ok_or_else(|| anyhow!("The SSH server at {} sent no host key", self.host))?;
+        let (host, port) = self.host_and_port();
+        let path = self.known_hosts_path()?;
+        match known_host(path, &host, port, key)? {
+            CheckResult::Match => Ok(None),
+            CheckResult::NotFound => {
+                let hash = session.host_key_hash(HashType::Sha256).unwrap_or_default();
+                Ok(Some(UnknownHostKey {
+                    fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)),
+                    line: known_hosts_line(&host, port, key, key_type)?,
+                }))
+            }
+            CheckResult::Mismatch => Err(ClientError::PermissionDenied(format!(
+                "The SSH host key of {} doesn't match the one saved in {}, so the server may \
+                 be an impostor. If its key really changed, remove the old one with \
+                 `ssh-keygen -R {}`",
+                self.host,
+                path.display(),
+                host
+            ))
+            .into()),
+            CheckResult::Failure => Err(anyhow!("Could not check the SSH host key of {}", host)),
+        }
+    }
+
+    fn connect_session(&self) -> Result<Session> {
+        let started = Instant::now();
+        let session = self.handshake()?;
+
+        // Before the password goes anywhere, make sure this is the server it was meant for
+        if let Some(key) = self.check_host_key(&session)? {
+            if self.host_keys != SftpHostKeys::AcceptNew {
+                return Err(ClientError::PermissionDenied(format!(
+                    "The SSH host key of {} isn't known yet ({}). Connect in a terminal to \
+                     check and trust it, add it to {} with ssh-keyscan, or set \
+                     `comfy-fs config --sftp-host-keys accept-new`",
+                    self.host,
+                    key.fingerprint,
+                    self.known_hosts_path()?.display()
+                ))
+                .into());
+            }
+            debug!("Trusting the new SSH host key of {}: {}", self.host, key.fingerprint);
+            self.trust(&key)?;
+        }
+        session.userauth_password(&self.username, &self.password)?;
+
+        if !session.authenticated() {
+            return Err(ClientError::AuthenticationFailed(format!(
+                "SFTP authentication failed for {}",
+                self.username
+            ))
+            .into());
+        }
+        debug!(
+            elapsed = ?started.elapsed(),
+            "SSH session to {} as {}", self.host, self.username
+        );
+
+        Ok(session)
+    }
+
+    fn remote_file_from_stat(base_path: &str, name: &str, stat: &FileStat) -> RemoteFile {
+        let modified = stat
+            .mtime
+            .and_then(|mtime| i64::try_from(mtime).ok())
+            .and_then(|mtime| Local.timestamp_opt(mtime, 0).single())
+            .unwrap_or_else(Local::now);
+
+        RemoteFile {
+            name: name.to_string(),
+            path: format!("{}/{}", base_path.trim_end_matches('/'), name),
+            size: stat.size.unwrap_or(0),
+            modified,
+            is_dir: stat.is_dir(),
+            permissions: stat.perm.map(format_mode),
+        }
+    }
+}
+
+/// How `known_hosts` names `host`: bare on port 22, as `[host]:port` otherwise
+fn known_hosts_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// The `known_hosts` line that trusts `key` for `host`
+fn known_hosts_line(host: &str, port: u16, key: &[u8], key_type: HostKeyType) -> Result<String> {
+    let name = match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => return Err(anyhow!("The SSH server's host key is of an unknown type")),
+    };
+    Ok(format!("{} {} {}", known_hosts_name(host, port), name, STANDARD.encode(key)))
+}
+
+/// Look `host` up in the `known_hosts` file at `path`, which may not exist yet.
+/// Lines libssh2 can't read, such as `@cert-authority` ones, are left out.
+fn known_host(path: &Path, host: &str, port: u16, key: &[u8]) -> Result<CheckResult> {
+    let mut known = Session::new()?.known_hosts()?;
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                let _ = known.read_str(line, ssh2::KnownHostFileKind::OpenSSH);
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Could not read {}", path.display()));
+        }
+    }
+    Ok(known.check_port(host, port, key))
+}
+
+#[async_trait]
+impl FileServerClient for SftpClient {
+    async fn connect(&mut self) -> Result<()> {
+        // Test connection
+        let client = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            session.sftp()?;
+            session.disconnect(None, "", None)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        // Nothing to do - we create fresh sessions for each operation
+        Ok(())
+    }
+
+    async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        let files = tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            let sftp = session.sftp()?;
+            debug!("SFTP readdir {}", path);
+            let entries = sftp.readdir(Path::new(&path))?;
+
+            let files: Vec<RemoteFile> = entries
+                .iter()
+                .filter_map(|(entry_path, stat)| {
+                    let name = entry_path.file_name()?.to_str()?;
+                    if name == "." || name == ".." {
+                        return None;
+                    }
+                    Some(Self::remote_file_from_stat(&path, name, stat))
+                })
+                .collect();
+
+            Ok::<_, anyhow::Error>(files)
+        })
+        .await??;
+
+        Ok(files)
+    }
+
+    async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+        self.download_file_with_progress(remote_path, local_path, no_progress())
+            .await
+    }
+
+    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+        self.upload_file_with_progress(local_path, remote_path, no_progress())
+            .await
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let client = self.clone();
+        let remote_path = remote_path.to_string();
+        let local_path = local_path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            let sftp = session.sftp()?;
+            debug!("SFTP open {}", remote_path);
+            let mut remote = sftp.open(Path::new(&remote_path))?;
+
+            let mut file = File::create(local_path)?;
+            copy_chunks(&mut remote, &mut file, &progress)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let client = self.clone();
+        let remote_path = remote_path.to_string();
+        let local_path = local_path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            let sftp = session.sftp()?;
+            let size = sftp.stat(Path::new(&remote_path))?.size;
+            let (mut file, offset) = open_partial(&local_path, size, &progress)?;
+            debug!("SFTP open {} from {}", remote_path, offset);
+            let mut remote = sftp.open(Path::new(&remote_path))?;
+            remote.seek(SeekFrom::Start(offset))?;
+            copy_chunks(&mut remote, &mut file, &progress)?;
+            finish_partial(&local_path)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
+        let client = self.clone();
+        let remote_path = remote_path.to_string();
+        let local_path = local_path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let mut file = File::open(local_path)?;
+
+            let session = client.connect_session()?;
+            let sftp = session.sftp()?;
+            debug!("SFTP create {}", remote_path);
+            let mut remote = sftp.create(Path::new(&remote_path))?;
+            copy_chunks(&mut file, &mut remote, &progress)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn upload_reader(
+        &mut self,
+        mut reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        let client = self.clone();
+        let remote_path = remote_path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            let sftp = session.sftp()?;
+            debug!("SFTP create {}", remote_path);
+            let mut remote = sftp.create(Path::new(&remote_path))?;
+            Ok(copy_chunks(&mut reader, &mut remote, &progress)?)
+        })
+        .await?
+    }
+
+    async fn create_directory(&mut self, path: &str) -> Result<()> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP mkdir {}", path);
+            session.sftp()?.mkdir(Path::new(&path), 0o755)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn delete_file(&mut self, path: &str) -> Result<()> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP remove {}", path);
+            session.sftp()?.unlink(Path::new(&path))?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        if recursive {
+            delete_contents(self, path).await?;
+        }
+
+        let client = self.clone();
+        let path = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP rmdir {}", path);
+            session.sftp()?.rmdir(Path::new(&path))?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let client = self.clone();
+        let from = from.to_string();
+        let to = to.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP rename {} to {}", from, to);
+            session
+                .sftp()?
+                .rename(Path::new(&from), Path::new(&to), None)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        let size = tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP stat {}", path);
+            let stat = session.sftp()?.stat(Path::new(&path))?;
+            stat.size
+                .ok_or_else(|| anyhow!("Server did not report a size for {}", path))
+        })
+        .await??;
+
+        Ok(size)
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let client = self.clone();
+        let path = path.trim_end_matches('/').to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP stat {}", path);
+            let remote_path = if path.is_empty() { "/" } else { path.as_str() };
+            let stat = match session.sftp()?.stat(Path::new(remote_path)) {
+                Err(e) if e.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE) => {
+                    return Err(ClientError::not_found(remote_path).into())
+                }
+                stat => stat?,
+            };
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+            let mut file = Self::remote_file_from_stat(parent, name, &stat);
+            if path.is_empty() {
+                file.name = "/".to_string();
+                file.path = "/".to_string();
+            }
+            Ok(file)
+        })
+        .await?
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            resume: true,
+            rename: true,
+            set_modified: true,
+            server_side_copy: false,
+            exact_times: true,
+        }
+    }
+
+    async fn set_modified(&mut self, path: &str, modified: DateTime<Local>) -> Result<()> {
+        let client = self.clone();
+        let path = path.to_string();
+        let mtime = u64::try_from(modified.timestamp())?;
+
+        tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            // SFTP sets both times together, so the access time goes along with it
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: None,
+                atime: Some(mtime),
+                mtime: Some(mtime),
+            };
+            debug!("SFTP setstat {} mtime={}", path, mtime);
+            session.sftp()?.setstat(Path::new(&path), stat)?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let client = self.clone();
+        let path = path.to_string();
+
+        let data = tokio::task::spawn_blocking(move || {
+            let session = client.connect_session()?;
+            debug!("SFTP read {} bytes of {} from {}", len, path, offset);
+            let mut remote = session.sftp()?.open(Path::new(&path))?;
+            remote.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::new();
+            remote.take(len).read_to_end(&mut data)?;
+            Ok::<_, anyhow::Error>(data)
+        })
+        .await??;
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(size: u64, perm: u32, mtime: u64) -> FileStat {
+        FileStat {
+            size: Some(size),
+            uid: None,
+            gid: None,
+            perm: Some(perm),
+            atime: None,
+            mtime: Some(mtime),
+        }
+    }
+
+    #[test]
+    fn test_sftp_client_creation() {
+        let client = SftpClient::new(
+            "192.168.1.1:22".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+        );
+
+        assert_eq!(client.host, "192.168.1.1:22");
+        assert_eq!(client.username, "user");
+        assert_eq!(client.password, "pass");
+    }
+
+    #[test]
+    fn test_known_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        let key = b"\0\0\0\x0bssh-ed25519\0\0\0\x200123456789abcdef0123456789abcdef";
+        let other = b"\0\0\0\x0bssh-ed25519\0\0\0\x20fedcba9876543210fedcba9876543210";
+
+        // No file yet is no hosts known
+        assert!(matches!(
+            known_host(&path, "nas", 22, key).unwrap(),
+            CheckResult::NotFound
+        ));
+
+        let client = SftpClient::new("nas:2222".to_string(), "u".to_string(), "p".to_string())
+            .with_known_hosts(path.clone());
+        let line = known_hosts_line("nas", 2222, key, HostKeyType::Ed25519).unwrap();
+        assert!(line.starts_with("[nas]:2222 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"));
+        let unknown = UnknownHostKey {
+            fingerprint: "SHA256:x".to_string(),
+            line,
+        };
+        std::fs::write(&path, "# comment\n@cert-authority * ssh-rsa AAAA\n").unwrap();
+        client.trust(&unknown).unwrap();
+
+        assert!(matches!(
+            known_host(&path, "nas", 2222, key).unwrap(),
+            CheckResult::Match
+        ));
+        assert!(matches!(
+            known_host(&path, "nas", 2222, other).unwrap(),
+            CheckResult::Mismatch
+        ));
+        // A key for one port says nothing about the others
+        assert!(matches!(
+            known_host(&path, "nas", 22, key).unwrap(),
+            CheckResult::NotFound
+        ));
+        assert_eq!(client.host_and_port(), ("nas".to_string(), 2222));
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("# comment\n"));
+    }
+
+    #[test]
+    fn test_remote_file_from_stat_file() {
+        let entry = SftpClient::remote_file_from_stat(
+            "/docs/",
+            "report.pdf",
+            &stat(1024, 0o100_644, 1_700_000_000),
+        );
+
+        assert_eq!(entry.name, "report.pdf");
+        assert_eq!(entry.path, "/docs/report.pdf");
+        assert_eq!(entry.size, 1024);
+        assert!(!entry.is_dir);
+        assert_eq!(entry.modified.timestamp(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_remote_file_from_stat_directory() {
+        let entry = SftpClient::remote_file_from_stat("/", "models", &stat(4096, 0o040_755, 0));
+
+        assert_eq!(entry.name, "models");
+        assert_eq!(entry.path, "/models");
+        assert!(entry.is_dir);
+    }
+}
@@ -1,16 +1,26 @@
-use super::{FileServerClient, RemoteFile};
-use anyhow::{anyhow, Result};
+use super::{
+    is_not_found, stat_by_listing, Capabilities, ClientError, FileServerClient, RemoteFile, Share,
+    Timeouts,
+};
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
-use chrono::Local;
+use chrono::{DateTime, Local, Month, NaiveDate, NaiveTime, TimeZone};
+use std::net::IpAddr;
 use std::path::Path;
 use std::process::Command;
+use std::time::Instant;
 use tokio::process::Command as TokioCommand;
+use tracing::debug;
 
 pub struct SmbClient {
     host: String,
     username: String,
     password: String,
     share: String,
+    port: u16,
+    timeouts: Timeouts,
+    /// Where to reach `host`, instead of looking its name up
+    address: Option<IpAddr>,
 }
 
 impl SmbClient {
@@ -20,27 +30,92 @@ impl SmbClient {
             username,
             password,
             share: share.unwrap_or_else(|| "share".to_string()),
+            port: 445,
+            timeouts: Timeouts::default(),
+            address: None,
         }
     }
 
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Connect to `address`, leaving the host only to name the server
+    pub fn with_address(mut self, address: IpAddr) -> Self {
+        self.address = Some(address);
+        self
+    }
+
 
     async fn run_smbclient_command(&self, args: &[&str]) -> Result<String> {
         let mut cmd = TokioCommand::new("smbclient");
         cmd.args(args);
+        cmd.arg("-p").arg(self.port.to_string());
+        if let Some(address) = self.address {
+            cmd.arg("-I").arg(address.to_string());
+        }
+        // Per request, so long transfers aren't cut off
+        cmd.arg("-t").arg(self.timeouts.read.as_secs().max(1).to_string());
         cmd.arg("-U").arg(format!("{}%{}", self.username, self.password));
         cmd.arg("-N"); // No password prompt
+        // Listing dates are parsed with English month names
+        cmd.env("LC_TIME", "C");
         
-        
+        debug!("smbclient {}", args.join(" "));
+        let started = Instant::now();
         let output = cmd.output().await?;
+        debug!(elapsed = ?started.elapsed(), status = %output.status, "smbclient finished");
         
         if !output.status.success() {
+            // smbclient reports some failures, like a missing file, on stdout
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("SMB command failed: {}", stderr));
+            let detail = if stderr.trim().is_empty() {
+                String::from_utf8_lossy(&output.stdout)
+            } else {
+                stderr
+            };
+            let message = format!("SMB command failed: {}", detail.trim());
+            let kind = detail
+                .split_whitespace()
+                .find(|word| word.starts_with("NT_STATUS_"))
+                .and_then(ClientError::from_smb_status)
+                .unwrap_or(ClientError::Protocol);
+            return Err(kind(message).into());
         }
         
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
+    /// The disk shares the server lists, leaving out hidden ones like `C$`.
+    /// The configured share needn't exist.
+    pub async fn list_shares(&self) -> Result<Vec<Share>> {
+        let server = format!("//{}", self.host);
+        let output = self.run_smbclient_command(&["-L", &server, "-g"]).await?;
+        Ok(Self::parse_share_list(&output))
+    }
+
+    /// Shares in `smbclient -L -g` output, where they're lines like
+    /// `Disk|models|Checkpoints and LoRAs`
+    fn parse_share_list(output: &str) -> Vec<Share> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '|');
+                let (kind, name) = (fields.next()?, fields.next()?);
+                (kind == "Disk" && !name.ends_with('$')).then(|| Share {
+                    name: name.to_string(),
+                    comment: fields.next().unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+
     fn parse_smbclient_list(&self, output: &str, base_path: &str) -> Vec<RemoteFile> {
         let mut files = Vec::new();
         
@@ -64,41 +139,36 @@ impl SmbClient {
             return None;
         }
         
-        // SMB output has fixed-width columns, need to parse more carefully
-        // First 35 chars are filename (padded), then attributes, size, date
-        
-        if line.len() < 36 {
-            return None;
+        // Parse from the right: the date is always the last five tokens, preceded
+        // by the size and (optionally) the attribute column. Whatever is left is
+        // the filename, which may itself contain spaces.
+        let mut rest = line;
+        let mut date = [""; 5];
+        for token in date.iter_mut().rev() {
+            (rest, *token) = Self::split_last_token(rest)?;
         }
-        
-        // Extract filename (first 35 chars, trimmed)
-        let name = line[..35].trim();
-        
+        let (rest, size_token) = Self::split_last_token(rest)?;
+        let size = size_token.parse::<u64>().ok()?;
+
+        let (name, attributes) = match Self::split_last_token(rest) {
+            Some((head, token)) if !head.trim().is_empty() && Self::is_attribute_token(token) => {
+                (head.trim(), token)
+            }
+            _ => (rest.trim(), ""),
+        };
+
         // Skip current and parent directory entries
-        if name == "." || name == ".." {
+        if name.is_empty() || name == "." || name == ".." {
             return None;
         }
-        
-        // Rest of the line contains attributes, size, and date
-        let rest = line[35..].trim();
-        let parts: Vec<&str> = rest.split_whitespace().collect();
-        
-        if parts.is_empty() {
-            return None;
-        }
-        
-        let attributes = parts[0];
+
         let is_dir = attributes.contains('D');
-        
-        let size = if parts.len() > 1 && !is_dir {
-            parts[1].parse::<u64>().unwrap_or(0)
-        } else {
-            0
-        };
-        
-        // Parse date - simplified approach
-        let modified = Local::now(); // TODO: Parse actual date from SMB output
-        
+        let size = if is_dir { 0 } else { size };
+
+        // An unreadable date shouldn't hide the file
+        let modified = Self::parse_date(&date).unwrap_or_else(Local::now);
+
+
         let path = if base_path == "/" {
             format!("/{}", name)
         } else {
@@ -111,9 +181,95 @@ impl SmbClient {
             size,
             modified,
             is_dir,
+            permissions: None,
         })
     }
 
+    /// The entry `allinfo` describes, as in:
+    ///
+    /// ```text
+    /// write_time:     Wed Dec 25 10:30:45 2024 CET
+    /// attributes: A (20)
+    /// stream: [::$DATA], 1234 bytes
+    /// ```
+    ///
+    /// Directories have no data stream, and are marked `D` in `attributes`.
+    fn parse_allinfo(output: &str, path: &str) -> Option<RemoteFile> {
+        let mut modified = None;
+        let mut attributes = None;
+        let mut size = 0;
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "write_time" => {
+                    let mut tokens = value.split_whitespace();
+                    let date = [(); 5].map(|()| tokens.next().unwrap_or(""));
+                    modified = Self::parse_date(&date);
+                }
+                "attributes" => attributes = value.split_whitespace().next(),
+                "stream" if value.starts_with("[::$DATA]") => {
+                    size = value
+                        .rsplit(',')
+                        .next()?
+                        .trim()
+                        .strip_suffix("bytes")?
+                        .trim()
+                        .parse()
+                        .ok()?;
+                }
+                _ => {}
+            }
+        }
+
+        let is_dir = attributes?.contains('D');
+        let path = path.trim_end_matches('/');
+        Some(RemoteFile {
+            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+            path: path.to_string(),
+            size: if is_dir { 0 } else { size },
+            modified: modified.unwrap_or_else(Local::now),
+            is_dir,
+            permissions: None,
+        })
+    }
+
+    /// `Wed Dec 25 10:30:45 2024`, in the server's local time. The weekday is
+    /// left out, since it adds nothing and is the part most likely translated.
+    fn parse_date([_weekday, month, day, time, year]: &[&str; 5]) -> Option<DateTime<Local>> {
+        let month = month.parse::<Month>().ok()?.number_from_month();
+        let date = NaiveDate::from_ymd_opt(year.parse().ok()?, month, day.parse().ok()?)?;
+        let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+        Local.from_local_datetime(&date.and_time(time)).earliest()
+    }
+
+    /// An smbclient command with each argument quoted, so names with spaces
+    /// stay whole. smbclient splits `-c` at every `;` and can't escape `"`, so
+    /// arguments with either, or with a line break, are refused instead.
+    fn command(name: &str, args: &[&str]) -> Result<String> {
+        let mut command = name.to_string();
+        for arg in args {
+            if let Some(c) = arg.chars().find(|c| matches!(c, '"' | ';' | '\n' | '\r')) {
+                bail!("smbclient commands can't carry {:?}, found in {:?}", c, arg);
+            }
+            command.push_str(&format!(" \"{}\"", arg));
+        }
+        Ok(command)
+    }
+
+    /// Splits off the last whitespace-separated token, returning `(head, token)`.
+    fn split_last_token(s: &str) -> Option<(&str, &str)> {
+        let s = s.trim_end();
+        let idx = s.rfind(char::is_whitespace)?;
+        Some((&s[..idx], &s[idx + 1..]))
+    }
+
+    fn is_attribute_token(token: &str) -> bool {
+        token.chars().all(|c| "ADHSRNV".contains(c))
+    }
+
     fn check_smbclient_available() -> Result<()> {
         let output = Command::new("smbclient")
             .arg("--version")
@@ -152,7 +308,7 @@ impl FileServerClient for SmbClient {
         let ls_command = if clean_path.is_empty() {
             "ls".to_string()
         } else {
-            format!("cd {}; ls", clean_path)
+            format!("{}; ls", Self::command("cd", &[clean_path])?)
         };
         
         let args = vec![&smb_path, "-c", &ls_command];
@@ -171,7 +327,7 @@ impl FileServerClient for SmbClient {
         }
         
         let local_str = local_path.to_string_lossy();
-        let get_command = format!("get {} {}", clean_remote, local_str);
+        let get_command = Self::command("get", &[clean_remote, &local_str])?;
         let args = vec![&smb_path, "-c", &get_command];
         
         self.run_smbclient_command(&args).await?;
@@ -183,7 +339,7 @@ impl FileServerClient for SmbClient {
         let clean_remote = remote_path.trim_start_matches('/');
         let local_str = local_path.to_string_lossy();
         
-        let put_command = format!("put {} {}", local_str, clean_remote);
+        let put_command = Self::command("put", &[&local_str, clean_remote])?;
         let args = vec![&smb_path, "-c", &put_command];
         
         self.run_smbclient_command(&args).await?;
@@ -194,7 +350,7 @@ impl FileServerClient for SmbClient {
         let smb_path = format!("//{}/{}", self.host, self.share);
         let clean_path = path.trim_start_matches('/');
         
-        let mkdir_command = format!("mkdir {}", clean_path);
+        let mkdir_command = Self::command("mkdir", &[clean_path])?;
         let args = vec![&smb_path, "-c", &mkdir_command];
         
         self.run_smbclient_command(&args).await?;
@@ -205,32 +361,81 @@ impl FileServerClient for SmbClient {
         let smb_path = format!("//{}/{}", self.host, self.share);
         let clean_path = path.trim_start_matches('/');
         
-        let del_command = format!("del {}", clean_path);
+        let del_command = Self::command("del", &[clean_path])?;
         let args = vec![&smb_path, "-c", &del_command];
         
         self.run_smbclient_command(&args).await?;
         Ok(())
     }
 
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        let smb_path = format!("//{}/{}", self.host, self.share);
+        let clean_path = path.trim_start_matches('/');
+
+        let command = Self::command(if recursive { "deltree" } else { "rmdir" }, &[clean_path])?;
+        let args = vec![&smb_path, "-c", &command];
+
+        self.run_smbclient_command(&args).await?;
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let smb_path = format!("//{}/{}", self.host, self.share);
+        let rename_command = Self::command(
+            "rename",
+            &[from.trim_start_matches('/'), to.trim_start_matches('/')],
+        )?;
+        let args = vec![&smb_path, "-c", &rename_command];
+
+        self.run_smbclient_command(&args).await?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: false,
+            resume: false,
+            rename: true,
+            set_modified: true,
+            server_side_copy: false,
+            exact_times: true,
+        }
+    }
+
+    async fn set_modified(&mut self, path: &str, modified: DateTime<Local>) -> Result<()> {
+        let smb_path = format!("//{}/{}", self.host, self.share);
+        // Create, access, write, and change times; -1 leaves one alone
+        let modified = modified.format("%Y:%m:%d-%H:%M:%S").to_string();
+        let utimes_command =
+            Self::command("utimes", &[path.trim_start_matches('/'), "-1", "-1", &modified, "-1"])?;
+        let args = vec![&smb_path, "-c", &utimes_command];
+
+        self.run_smbclient_command(&args).await?;
+        Ok(())
+    }
+
     async fn get_file_size(&mut self, path: &str) -> Result<u64> {
-        // For SMB, we'll list the parent directory and find the file
-        let parent_path = if let Some(pos) = path.rfind('/') {
-            &path[..pos]
-        } else {
-            "/"
-        };
-        
-        let filename = path.split('/').last().unwrap_or("");
-        
-        let files = self.list_files(parent_path).await?;
-        
-        for file in files {
-            if file.name == filename {
-                return Ok(file.size);
-            }
+        Ok(self.stat(path).await?.size)
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let clean_path = path.trim_matches('/');
+        if clean_path.is_empty() {
+            return stat_by_listing(self, path).await;
         }
-        
-        Err(anyhow!("File not found: {}", path))
+
+        let smb_path = format!("//{}/{}", self.host, self.share);
+        let allinfo_command = Self::command("allinfo", &[clean_path])?;
+        let args = vec![&smb_path, "-c", &allinfo_command];
+
+        let output = match self.run_smbclient_command(&args).await {
+            Err(e) if is_not_found(&e) => {
+                return Err(ClientError::not_found(path).into());
+            }
+            output => output?,
+        };
+        Self::parse_allinfo(&output, &format!("/{}", clean_path))
+            .ok_or_else(|| anyhow!("Could not read the details of {}: {}", path, output.trim()))
     }
 }
 
@@ -251,6 +456,10 @@ mod tests {
         assert_eq!(client.username, "user");
         assert_eq!(client.password, "pass");
         assert_eq!(client.share, "share");
+        assert_eq!(client.port, 445);
+
+        let client = client.with_port(1445);
+        assert_eq!(client.port, 1445);
     }
 
     #[test]
@@ -283,6 +492,7 @@ mod tests {
         assert_eq!(entry.name, "Documents");
         assert!(entry.is_dir);
         assert_eq!(entry.size, 0);
+        assert_eq!(entry.modified, Local.with_ymd_and_hms(2024, 12, 25, 10, 30, 45).unwrap());
         
         // Test file entry
         let file_line = "  report.pdf                        A     1024  Wed Dec 25 10:30:45 2024";
@@ -293,6 +503,18 @@ mod tests {
         assert!(!entry.is_dir);
         assert_eq!(entry.size, 1024);
         assert_eq!(entry.path, "/docs/report.pdf");
+
+        // Single-digit days are padded with a space
+        let line = "  my notes.txt                      A       12  Fri Mar  7 08:05:09 2025";
+        let entry = client.parse_list_line(line, "/").unwrap();
+        assert_eq!(entry.name, "my notes.txt");
+        assert_eq!(entry.modified, Local.with_ymd_and_hms(2025, 3, 7, 8, 5, 9).unwrap());
+
+        // A date that can't be read still lists the file
+        let line = "  a.txt                             A       12  Fr Mär  7 08:05:09 2025";
+        let entry = client.parse_list_line(line, "/").unwrap();
+        assert_eq!(entry.name, "a.txt");
+        assert_eq!(entry.size, 12);
     }
 
     #[test]
@@ -309,6 +531,75 @@ mod tests {
         assert!(client.parse_list_line("..   D        0  Wed Dec 25 10:30:45 2024", "/").is_none());
     }
 
+    #[test]
+    fn test_parse_allinfo() {
+        let output = "altname: SDXL_B~1.SAF
+create_time:    Mon Dec 23 09:00:00 2024 CET
+access_time:    Wed Dec 25 10:30:45 2024 CET
+write_time:     Wed Dec 25 10:30:45 2024 CET
+change_time:    Wed Dec 25 10:30:45 2024 CET
+attributes: A (20)
+stream: [::$DATA], 6938040682 bytes
+";
+        let file = SmbClient::parse_allinfo(output, "/models/sdxl base.safetensors").unwrap();
+        assert_eq!(file.name, "sdxl base.safetensors");
+        assert_eq!(file.size, 6_938_040_682);
+        assert!(!file.is_dir);
+        assert_eq!(
+            file.modified.format("%Y-%m-%d %H:%M:%S").to_string(),
+            "2024-12-25 10:30:45"
+        );
+
+        let output = "write_time:     Thu Dec  5 08:00:00 2024 CET\nattributes: D (10)\n";
+        let dir = SmbClient::parse_allinfo(output, "/models/").unwrap();
+        assert_eq!(dir.path, "/models");
+        assert!(dir.is_dir);
+        assert!(SmbClient::parse_allinfo("NT_STATUS_ACCESS_DENIED", "/models").is_none());
+    }
+
+    #[test]
+    fn test_parse_share_list() {
+        let output = "\
+Disk|models|Checkpoints and LoRAs
+IPC|IPC$|IPC Service (Samba 4.19)
+Disk|print$|Printer Drivers
+Disk|output|
+Printer|lobby|Lobby printer
+Workgroup|WORKGROUP|NAS
+";
+        assert_eq!(
+            SmbClient::parse_share_list(output),
+            [
+                Share {
+                    name: "models".to_string(),
+                    comment: "Checkpoints and LoRAs".to_string(),
+                },
+                Share {
+                    name: "output".to_string(),
+                    comment: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_quoting() {
+        assert_eq!(
+            SmbClient::command("get", &["my models/a b.safetensors", "/tmp/a b"]).unwrap(),
+            r#"get "my models/a b.safetensors" "/tmp/a b""#
+        );
+        assert_eq!(
+            SmbClient::command("del", &["it's $(rm -rf ~) & more"]).unwrap(),
+            r#"del "it's $(rm -rf ~) & more""#
+        );
+        assert_eq!(SmbClient::command("ls", &[]).unwrap(), "ls");
+
+        // A `;` would end the command and start another one
+        assert!(SmbClient::command("del", &["a.txt; deltree /"]).is_err());
+        assert!(SmbClient::command("del", &["say \"hi\".txt"]).is_err());
+        assert!(SmbClient::command("put", &["a\nb", "c"]).is_err());
+    }
+
     #[test] 
     fn test_check_smbclient_available() {
         // This test will fail if smbclient is not installed, which is expected
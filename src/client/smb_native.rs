@@ -0,0 +1,635 @@
+use super::{
+    delete_contents, finish_partial, no_progress, open_partial, Capabilities, ClientError,
+    FileServerClient, Progress, RemoteFile, Share, Timeouts,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, TimeZone};
+use futures::StreamExt;
+use smb::{
+    Client, ClientConfig, CreateOptions, DirAccessMask, Directory, FileAccessMask, FileAttributes,
+    FileBasicInformation, FileCreateArgs, FileDirectoryInformation, FileDispositionInformation,
+    FileNetworkOpenInformation, FileRenameInformation, GetLen, ReadAt, Resource, Status, UncPath,
+    WriteAt,
+};
+use smb_rpc::interface::ShareKind;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Size of each read/write request sent to the server.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Statuses for a path that isn't there: its name, or a directory on the way
+const NOT_FOUND: [u32; 2] = [
+    Status::ObjectNameNotFound as u32,
+    Status::ObjectPathNotFound as u32,
+];
+
+/// Seconds between the FILETIME epoch (1601-01-01) and the Unix epoch.
+const FILETIME_UNIX_OFFSET_SECS: i64 = 11_644_473_600;
+
+/// SMB2/3 client implemented in pure Rust, so no `smbclient` binary is needed.
+///
+/// Unlike [`super::smb::SmbClient`], a single authenticated session is kept open
+/// for the lifetime of the client instead of one process per operation.
+pub struct NativeSmbClient {
+    host: String,
+    username: String,
+    password: String,
+    share: String,
+    port: u16,
+    timeouts: Timeouts,
+    client: Option<Client>,
+}
+
+impl NativeSmbClient {
+    pub fn new(host: String, username: String, password: String, share: Option<String>) -> Self {
+        Self {
+            host,
+            username,
+            password,
+            share: share.unwrap_or_else(|| "share".to_string()),
+            port: 445,
+            client: None,
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::default();
+        config.connection.port = Some(self.port);
+        config.connection.timeout = Some(self.timeouts.read);
+        config
+    }
+
+    /// The disk shares the server lists, leaving out hidden ones like `C$`.
+    /// Asked over IPC$, so the configured share needn't exist.
+    pub async fn list_shares(&self) -> Result<Vec<Share>> {
+        let client = Client::new(self.client_config());
+        client
+            .ipc_connect(&self.host, &self.username, self.password.clone())
+            .await?;
+        let listed = client.list_shares(&self.host).await;
+        // The list is all that's wanted, so failing to log off doesn't matter
+        let _ = client.close().await;
+        Ok(listed?
+            .iter()
+            .filter_map(|share| {
+                let name = share.netname.as_ref()?.to_string();
+                let disk = share.share_type.kind() == ShareKind::Disk;
+                (disk && !name.ends_with('$')).then(|| Share {
+                    name,
+                    comment: share
+                        .remark
+                        .as_ref()
+                        .map(|remark| remark.to_string())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+
+    fn unc_path(&self, path: &str) -> Result<UncPath> {
+        let share = UncPath::new(&self.host)?.with_share(&self.share)?;
+        let relative = path.trim_matches('/').replace('/', "\\");
+
+        if relative.is_empty() {
+            Ok(share)
+        } else {
+            Ok(share.with_path(&relative))
+        }
+    }
+
+    fn client(&self) -> Result<&Client> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| anyhow!("Not connected to SMB share {}", self.share))
+    }
+
+    fn filetime_to_local(filetime: u64) -> DateTime<Local> {
+        let unix_secs =
+            i64::try_from(filetime / 10_000_000).unwrap_or(0) - FILETIME_UNIX_OFFSET_SECS;
+        Local
+            .timestamp_opt(unix_secs, 0)
+            .single()
+            .unwrap_or_else(Local::now)
+    }
+
+    fn local_to_filetime(time: DateTime<Local>) -> u64 {
+        u64::try_from(time.timestamp() + FILETIME_UNIX_OFFSET_SECS).unwrap_or(0) * 10_000_000
+    }
+
+    fn remote_file_from_info(base_path: &str, info: &FileDirectoryInformation) -> RemoteFile {
+        let name = info.file_name.to_string();
+        let is_dir = info.file_attributes.directory();
+
+        RemoteFile {
+            path: format!("{}/{}", base_path.trim_end_matches('/'), name),
+            name,
+            size: if is_dir { 0 } else { info.end_of_file },
+            modified: Self::filetime_to_local(*info.last_write_time),
+            is_dir,
+            permissions: None,
+        }
+    }
+
+    async fn open_file(&self, path: &str, access: FileAccessMask) -> Result<smb::File> {
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(path)?,
+                &FileCreateArgs::make_open_existing(access),
+            )
+            .await?;
+
+        if !resource.is_file() {
+            return Err(anyhow!("Not a file: {}", path));
+        }
+        Ok(resource.unwrap_file())
+    }
+}
+
+#[async_trait]
+impl FileServerClient for NativeSmbClient {
+    async fn connect(&mut self) -> Result<()> {
+        let client = Client::new(self.client_config());
+        let share_path = self.unc_path("/")?;
+        client
+            .share_connect(&share_path, &self.username, self.password.clone())
+            .await?;
+
+        self.client = Some(client);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            client.close().await?;
+        }
+        Ok(())
+    }
+
+    async fn list_files(&mut self, path: &str) -> Result<Vec<RemoteFile>> {
+        let access = DirAccessMask::new()
+            .with_list_directory(true)
+            .with_synchronize(true);
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(path)?,
+                &FileCreateArgs::make_open_existing(access.into()),
+            )
+            .await?;
+
+        if !resource.is_dir() {
+            return Err(anyhow!("Not a directory: {}", path));
+        }
+        let directory = Arc::new(resource.unwrap_dir());
+
+        let mut files = Vec::new();
+        let mut entries = Directory::query::<FileDirectoryInformation>(&directory, "*").await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let name = entry.file_name.to_string();
+            if name == "." || name == ".." {
+                continue;
+            }
+            files.push(Self::remote_file_from_info(path, &entry));
+        }
+        drop(entries);
+
+        directory.close().await?;
+        Ok(files)
+    }
+
+    async fn download_file(&mut self, remote_path: &str, local_path: &Path) -> Result<()> {
+        self.download_file_with_progress(remote_path, local_path, no_progress())
+            .await
+    }
+
+    async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()> {
+        self.upload_file_with_progress(local_path, remote_path, no_progress())
+            .await
+    }
+
+    async fn download_file_with_progress(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let file = self
+            .open_file(remote_path, FileAccessMask::new().with_generic_read(true))
+            .await?;
+        let len = file.get_len().await?;
+
+        let mut local = tokio::fs::File::create(local_path).await?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut offset = 0u64;
+
+        while offset < len {
+            let read = file.read_at(&mut buffer, offset).await?;
+            if read == 0 {
+                break;
+            }
+            local.write_all(&buffer[..read]).await?;
+            offset += read as u64;
+            progress(read as u64);
+        }
+
+        local.flush().await?;
+        file.close().await?;
+        Ok(())
+    }
+
+    async fn resume_download(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: Progress,
+    ) -> Result<()> {
+        let file = self
+            .open_file(remote_path, FileAccessMask::new().with_generic_read(true))
+            .await?;
+        let len = file.get_len().await?;
+
+        let (local, mut offset) = open_partial(local_path, Some(len), &progress)?;
+        let mut local = tokio::fs::File::from_std(local);
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+
+        while offset < len {
+            let read = file.read_at(&mut buffer, offset).await?;
+            if read == 0 {
+                break;
+            }
+            local.write_all(&buffer[..read]).await?;
+            offset += read as u64;
+            progress(read as u64);
+        }
+
+        local.flush().await?;
+        file.close().await?;
+        finish_partial(local_path)?;
+        Ok(())
+    }
+
+    async fn upload_file_with_progress(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<()> {
+        let mut local = tokio::fs::File::open(local_path).await?;
+
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(remote_path)?,
+                &FileCreateArgs::make_overwrite(FileAttributes::new(), CreateOptions::new()),
+            )
+            .await?;
+        let file = resource.unwrap_file();
+
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut offset = 0u64;
+
+        loop {
+            let read = local.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+
+            let mut written = 0;
+            while written < read {
+                written += file
+                    .write_at(&buffer[written..read], offset + written as u64)
+                    .await?;
+            }
+            offset += read as u64;
+            progress(read as u64);
+        }
+
+        file.close().await?;
+        Ok(())
+    }
+
+    async fn upload_reader(
+        &mut self,
+        mut reader: Box<dyn Read + Send>,
+        remote_path: &str,
+        progress: Progress,
+    ) -> Result<u64> {
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(remote_path)?,
+                &FileCreateArgs::make_overwrite(FileAttributes::new(), CreateOptions::new()),
+            )
+            .await?;
+        let file = resource.unwrap_file();
+
+        let mut offset = 0u64;
+        loop {
+            // Reads can block, as standard input's do, so each happens off the runtime
+            let (returned, chunk) = tokio::task::spawn_blocking(move || {
+                let mut buffer = vec![0u8; CHUNK_SIZE];
+                let read = loop {
+                    match reader.read(&mut buffer) {
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        read => break read,
+                    }
+                };
+                (
+                    reader,
+                    read.map(|read| {
+                        buffer.truncate(read);
+                        buffer
+                    }),
+                )
+            })
+            .await?;
+            reader = returned;
+            let chunk = chunk?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            let mut written = 0;
+            while written < chunk.len() {
+                written += file
+                    .write_at(&chunk[written..], offset + written as u64)
+                    .await?;
+            }
+            offset += chunk.len() as u64;
+            progress(chunk.len() as u64);
+        }
+
+        file.close().await?;
+        Ok(offset)
+    }
+
+    async fn create_directory(&mut self, path: &str) -> Result<()> {
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(path)?,
+                &FileCreateArgs::make_create_new(
+                    FileAttributes::new().with_directory(true),
+                    CreateOptions::new().with_directory_file(true),
+                ),
+            )
+            .await?;
+
+        resource.unwrap_dir().close().await?;
+        Ok(())
+    }
+
+    async fn delete_file(&mut self, path: &str) -> Result<()> {
+        let file = self
+            .open_file(
+                path,
+                FileAccessMask::new()
+                    .with_generic_read(true)
+                    .with_delete(true),
+            )
+            .await?;
+
+        file.set_info(FileDispositionInformation::default()).await?;
+        file.close().await?;
+        Ok(())
+    }
+
+    async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()> {
+        if recursive {
+            delete_contents(self, path).await?;
+        }
+
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(path)?,
+                &FileCreateArgs::make_open_existing(
+                    FileAccessMask::new()
+                        .with_generic_read(true)
+                        .with_delete(true),
+                ),
+            )
+            .await?;
+        if !resource.is_dir() {
+            return Err(anyhow!("Not a directory: {}", path));
+        }
+
+        let dir = resource.unwrap_dir();
+        dir.set_info(FileDispositionInformation::default()).await?;
+        dir.close().await?;
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        // Opened without a file/directory option so both can be renamed
+        let resource = self
+            .client()?
+            .create_file(
+                &self.unc_path(from)?,
+                &FileCreateArgs::make_open_existing(
+                    FileAccessMask::new()
+                        .with_generic_read(true)
+                        .with_delete(true),
+                ),
+            )
+            .await?;
+
+        // The new name is given relative to the share root
+        let target = to.trim_matches('/').replace('/', "\\");
+        let handle = match &resource {
+            Resource::File(file) => file.handle(),
+            Resource::Directory(dir) => dir.handle(),
+            Resource::Pipe(_) => return Err(anyhow!("Not a file or directory: {}", from)),
+        };
+        handle
+            .set_info(FileRenameInformation {
+                replace_if_exists: false.into(),
+                root_directory: 0,
+                file_name: target.as_str().into(),
+            })
+            .await?;
+        handle.close().await?;
+        Ok(())
+    }
+
+    async fn get_file_size(&mut self, path: &str) -> Result<u64> {
+        let file = self
+            .open_file(path, FileAccessMask::new().with_generic_read(true))
+            .await?;
+        let len = file.get_len().await?;
+        file.close().await?;
+        Ok(len)
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<RemoteFile> {
+        let access = FileAccessMask::new().with_file_read_attributes(true);
+        let resource = match self
+            .client()?
+            .create_file(
+                &self.unc_path(path)?,
+                &FileCreateArgs::make_open_existing(access),
+            )
+            .await
+        {
+            Err(
+                smb::Error::ReceivedErrorMessage(status, _)
+                | smb::Error::UnexpectedMessageStatus(status),
+            ) if NOT_FOUND.contains(&status) => {
+                return Err(ClientError::not_found(path).into());
+            }
+            resource => resource?,
+        };
+        let info = match &resource {
+            Resource::File(file) => file.query_info::<FileNetworkOpenInformation>().await,
+            Resource::Directory(dir) => dir.query_info::<FileNetworkOpenInformation>().await,
+            Resource::Pipe(_) => return Err(anyhow!("Not a file or directory: {}", path)),
+        };
+        match &resource {
+            Resource::File(file) => file.close().await?,
+            Resource::Directory(dir) => dir.close().await?,
+            Resource::Pipe(_) => {}
+        }
+        let info = info?;
+
+        let path = path.trim_end_matches('/');
+        let is_dir = info.file_attributes.directory();
+        Ok(RemoteFile {
+            name: path
+                .rsplit('/')
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("/")
+                .to_string(),
+            path: if path.is_empty() {
+                "/".to_string()
+            } else {
+                path.to_string()
+            },
+            size: if is_dir { 0 } else { info.end_of_file },
+            modified: Self::filetime_to_local(*info.last_write_time),
+            is_dir,
+            permissions: None,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranged_reads: true,
+            resume: true,
+            rename: true,
+            set_modified: true,
+            server_side_copy: false,
+            exact_times: true,
+        }
+    }
+
+    async fn set_modified(&mut self, path: &str, modified: DateTime<Local>) -> Result<()> {
+        let file = self
+            .open_file(path, FileAccessMask::new().with_file_write_attributes(true))
+            .await?;
+        // Zero times and attributes are left as they are
+        file.set_info(FileBasicInformation {
+            creation_time: 0.into(),
+            last_access_time: 0.into(),
+            last_write_time: Self::local_to_filetime(modified).into(),
+            change_time: 0.into(),
+            file_attributes: FileAttributes::new(),
+        })
+        .await?;
+        file.close().await?;
+        Ok(())
+    }
+
+    async fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let file = self
+            .open_file(path, FileAccessMask::new().with_generic_read(true))
+            .await?;
+        let end = offset.saturating_add(len).min(file.get_len().await?);
+
+        let mut data = Vec::new();
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut position = offset;
+        while position < end {
+            let want = usize::try_from(end - position).map_or(CHUNK_SIZE, |n| n.min(CHUNK_SIZE));
+            let read = file.read_at(&mut buffer[..want], position).await?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..read]);
+            position += read as u64;
+        }
+
+        file.close().await?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> NativeSmbClient {
+        NativeSmbClient::new(
+            "192.168.1.1".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_native_smb_client_creation() {
+        let client = client();
+
+        assert_eq!(client.host, "192.168.1.1");
+        assert_eq!(client.share, "share");
+        assert_eq!(client.port, 445);
+        assert!(client.client.is_none());
+
+        assert_eq!(client.with_port(4445).port, 4445);
+    }
+
+    #[test]
+    fn test_unc_path() {
+        let client = client();
+
+        let root = client.unc_path("/").unwrap();
+        assert_eq!(root.share(), Some("share"));
+        assert_eq!(root.path(), None);
+
+        let nested = client.unc_path("/models/checkpoints/").unwrap();
+        assert_eq!(nested.path(), Some("models\\checkpoints"));
+    }
+
+    #[test]
+    fn test_filetime_to_local() {
+        // 2024-01-01T00:00:00Z expressed as a FILETIME
+        let filetime = (1_704_067_200 + 11_644_473_600) * 10_000_000;
+        let modified = NativeSmbClient::filetime_to_local(filetime);
+        assert_eq!(modified.timestamp(), 1_704_067_200);
+        assert_eq!(NativeSmbClient::local_to_filetime(modified), filetime);
+    }
+
+    #[tokio::test]
+    async fn test_operations_require_connection() {
+        let mut client = client();
+        assert!(client.list_files("/").await.is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Compressing files before upload and decompressing them after download, for
+//! `upload --compress` and `download --decompress`
+
+use crate::client::partial_path;
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// What's added to the name of a file compressed this way, without the dot
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => "zst",
+            Self::Gzip => "gz",
+        }
+    }
+
+    /// The format a file name's extension says it's in, if it's one of these
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "zst" => Some(Self::Zstd),
+            "gz" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            _ => Err(anyhow!("Invalid compression '{}' (expected zstd or gzip)", s)),
+        }
+    }
+}
+
+/// `name` with the extension for `format` added, e.g. `a.json.zst`
+pub fn compressed_name(name: &str, format: Compression) -> String {
+    format!("{}.{}", name, format.extension())
+}
+
+/// Compress the file at `input` into `output`. Returns the compressed size.
+pub fn compress_file(input: &Path, output: &Path, format: Compression) -> Result<u64> {
+    let mut reader = BufReader::new(
+        File::open(input).with_context(|| format!("Could not open {}", input.display()))?,
+    );
+    let writer = BufWriter::new(File::create(output)?);
+    match format {
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+    }
+    Ok(fs::metadata(output)?.len())
+}
+
+/// Decompress the file at `input`, in `format`, into `output`
+pub fn decompress_file(input: &Path, output: &Path, format: Compression) -> Result<()> {
+    let reader = BufReader::new(File::open(input)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    match format {
+        Compression::Zstd => {
+            io::copy(&mut zstd::Decoder::new(reader)?, &mut writer)?;
+        }
+        Compression::Gzip => {
+            io::copy(&mut flate2::read::MultiGzDecoder::new(reader), &mut writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Replace a downloaded file whose name says it's compressed, like `a.json.zst`,
+/// with what it decompresses to, `a.json`. Returns the new path, or `None` when
+/// the name isn't one of the known formats and the file was left alone.
+pub fn decompress_download(path: &Path) -> Result<Option<PathBuf>> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(None);
+    };
+    let Some(format) = Compression::from_name(name) else {
+        return Ok(None);
+    };
+    let output = path.with_extension("");
+    // Written beside the result first, so a corrupt file leaves nothing half done
+    let partial = partial_path(&output);
+    if let Err(e) = decompress_file(path, &partial, format) {
+        let _ = fs::remove_file(&partial);
+        return Err(e.context(format!("Could not decompress {} as {}", path.display(), format)));
+    }
+    fs::rename(&partial, &output)?;
+    fs::remove_file(path)?;
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("workflow.json");
+        let content = br#"{"nodes": []}"#.repeat(1000);
+        fs::write(&original, &content).unwrap();
+
+        for format in [Compression::Zstd, Compression::Gzip] {
+            let compressed = dir.path().join(compressed_name("copy.json", format));
+            let size = compress_file(&original, &compressed, format).unwrap();
+            assert!(size < content.len() as u64 / 10);
+
+            let restored = decompress_download(&compressed).unwrap().unwrap();
+            assert_eq!(restored, dir.path().join("copy.json"));
+            assert_eq!(fs::read(&restored).unwrap(), content);
+            assert!(!compressed.exists());
+        }
+
+        // Unknown extensions are left alone
+        assert_eq!(decompress_download(&original).unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupt_download_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.json.zst");
+        fs::write(&path, b"not zstd").unwrap();
+
+        assert!(decompress_download(&path).is_err());
+        assert!(path.exists());
+        assert!(!dir.path().join("broken.json").exists());
+        assert!(!dir.path().join("broken.json.part").exists());
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert_eq!("GZIP".parse::<Compression>().unwrap(), Compression::Gzip);
+        assert!("xz".parse::<Compression>().is_err());
+        assert_eq!(Compression::from_name("a.tar.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_name("model.safetensors"), None);
+    }
+}
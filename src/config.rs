@@ -1,56 +1,651 @@
-use anyhow::Result;
-use directories::ProjectDirs;
+use crate::client::proxy::Proxy;
+use crate::client::Timeouts;
+use crate::connection::ConnectionManager;
+use crate::sort::SortMode;
+use crate::sync::SyncDirection;
+use anyhow::{bail, Context, Result};
+use directories::{ProjectDirs, UserDirs};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The config file format this build writes. Files from before the format had
+/// a version count as 0.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Steps that bring a config file up to date, the first taking version 0 to 1
+const MIGRATIONS: [fn(&mut Map<String, Value>); CONFIG_VERSION as usize] = [migrate_v0];
+
+/// A config that can be shared, e.g. committed to a team's repo. When it exists
+/// it's used instead of `config.json`.
+pub const SHARED_FILE: &str = "config.toml";
+
+/// Settings for this machine only, laid over [`SHARED_FILE`]. Saving writes
+/// here, so the shared file is never changed.
+pub const LOCAL_FILE: &str = "config.local.toml";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Format of the file this was read from or is saved to, see [`CONFIG_VERSION`]
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Left out of a shared config that each machine sets itself, like the
+    /// username
+    #[serde(default)]
     pub server_ip: String,
+    #[serde(default)]
     pub username: String,
     #[serde(skip_serializing)]
     pub password: Option<String>,
+    #[serde(default = "default_protocol")]
     pub default_protocol: Protocol,
     #[serde(default)]
     pub configured: bool,
+    #[serde(default)]
+    pub ftp_tls: FtpTls,
+    /// What to do about an SFTP server whose host key isn't known yet
+    #[serde(default)]
+    pub sftp_host_keys: SftpHostKeys,
+    /// Try the other protocols when the preferred one fails
+    #[serde(default = "default_fallback_enabled")]
+    pub fallback_enabled: bool,
+    #[serde(default = "default_smb_share")]
+    pub smb_share: String,
+    #[serde(default = "default_smb_port")]
+    pub smb_port: u16,
+    #[serde(default = "default_ftp_port")]
+    pub ftp_port: u16,
+    /// Whether to try IPv4 or IPv6 first when the server's name has both
+    #[serde(default)]
+    pub ip_preference: IpPreference,
+    /// Where connections to the server go through, e.g. `socks5://jump:1080`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Proxy>,
+    /// Logged-in FTP sessions kept for reuse between operations
+    #[serde(default = "default_ftp_sessions")]
+    pub ftp_sessions: usize,
+    /// Seconds to wait for the server to answer a connection attempt
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds to wait for each reply or chunk of data before giving up
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    /// Files `upload`, `download`, and `sync` copy at once unless `--parallel` says
+    #[serde(default = "default_max_concurrent_transfers")]
+    pub max_concurrent_transfers: usize,
+    /// Named servers that can be selected with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile used when `--profile` isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    /// Where the browser saves downloaded files, `~/Downloads` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_dir: Option<PathBuf>,
+    /// Seconds apart sync lets modification times be and still treat files as unchanged
+    #[serde(default = "default_sync_time_tolerance")]
+    pub sync_time_tolerance: u64,
+    /// Named sync pairs that can be run with `sync --job` or `sync --all`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sync_jobs: BTreeMap<String, SyncJob>,
+    /// What commands do when their flags aren't given
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub defaults: CommandDefaults,
+    /// The version of the file this was loaded from when it was older, so
+    /// saving can keep a copy of it
+    #[serde(skip)]
+    pub migrated_from: Option<u32>,
+}
+
+/// Connection settings for one named server. Unset fields fall back to the
+/// top-level settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smb_share: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smb_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ftp_port: Option<u16>,
+}
+
+/// A local directory and a remote one to sync, saved under a name
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncJob {
+    pub local: PathBuf,
+    pub remote: String,
+    #[serde(default)]
+    pub direction: SyncDirection,
+    /// Gitignore-style patterns to leave out, on top of `.comfyignore`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Patterns to keep even if excluded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+}
+
+/// Settings for each command to use in place of flags left out, so routine
+/// runs stay short. Flags given on the command line always win.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommandDefaults {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub download: DownloadDefaults,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upload: TransferDefaults,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub sync: TransferDefaults,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub list: ListDefaults,
+}
+
+/// Defaults for `download`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DownloadDefaults {
+    /// Local directory to download into, in place of the current one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+/// Defaults for `upload` or `sync`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransferDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Patterns always left out, on top of any `--exclude`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Defaults for `list`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortMode>,
+}
+
+impl CommandDefaults {
+    /// Check the numbers, since the file is edited by hand
+    pub fn check(&self) -> Result<()> {
+        let parallel = [
+            ("download", self.download.parallel),
+            ("upload", self.upload.parallel),
+            ("sync", self.sync.parallel),
+        ];
+        for (command, parallel) in parallel {
+            if let Some(parallel) = parallel {
+                crate::transfer::check_concurrency(parallel).with_context(|| {
+                    format!("defaults.{}.parallel in the config file is out of range", command)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Service name that passwords are stored under in the OS keyring
+pub(crate) const KEYRING_SERVICE: &str = "comfy-fs";
+
+const fn default_version() -> u32 {
+    CONFIG_VERSION
+}
+
+const fn default_protocol() -> Protocol {
+    Protocol::Smb
+}
+
+const fn default_fallback_enabled() -> bool {
+    true
+}
+
+fn default_smb_share() -> String {
+    "share".to_string()
+}
+
+const fn default_smb_port() -> u16 {
+    445
+}
+
+const fn default_ftp_port() -> u16 {
+    21
+}
+
+const fn default_ftp_sessions() -> usize {
+    crate::client::ftp::DEFAULT_SESSIONS
+}
+
+const fn default_connect_timeout() -> u64 {
+    crate::client::DEFAULT_CONNECT_TIMEOUT.as_secs()
+}
+
+const fn default_read_timeout() -> u64 {
+    crate::client::DEFAULT_READ_TIMEOUT.as_secs()
+}
+
+const fn default_max_concurrent_transfers() -> usize {
+    crate::transfer::DEFAULT_CONCURRENCY
+}
+
+const fn default_sync_time_tolerance() -> u64 {
+    crate::sync::DEFAULT_TIME_TOLERANCE_SECS
+}
+
+/// Saved capitalized, as it always has been; read in lowercase too, as people
+/// write it by hand
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Protocol {
+    #[serde(alias = "ftp")]
     Ftp,
+    #[serde(alias = "smb")]
     Smb,
+    #[serde(alias = "sftp")]
+    Sftp,
+    #[serde(alias = "http")]
+    Http,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Ftp => "FTP",
+            Self::Smb => "SMB",
+            Self::Sftp => "SFTP",
+            Self::Http => "HTTP",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ftp" => Ok(Self::Ftp),
+            "smb" => Ok(Self::Smb),
+            "sftp" => Ok(Self::Sftp),
+            "http" | "https" => Ok(Self::Http),
+            _ => Err(anyhow::anyhow!(
+                "Invalid protocol '{}' (expected smb, sftp, ftp, or http)",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for a single run from command-line flags or environment variables.
+/// They are applied on top of the loaded config and never saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overrides {
+    pub server_ip: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub protocol: Option<Protocol>,
+}
+
+impl Overrides {
+    /// Read `COMFY_FS_SERVER`, `COMFY_FS_USERNAME`, `COMFY_FS_PASSWORD`, and `COMFY_FS_PROTOCOL`.
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |key: &str| var(key).filter(|value| !value.is_empty());
+
+        Ok(Self {
+            server_ip: var("COMFY_FS_SERVER"),
+            username: var("COMFY_FS_USERNAME"),
+            password: var("COMFY_FS_PASSWORD"),
+            protocol: var("COMFY_FS_PROTOCOL")
+                .map(|protocol| protocol.parse())
+                .transpose()?,
+        })
+    }
+
+    /// Fill in anything not set here from `fallback`.
+    #[must_use]
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            server_ip: self.server_ip.or(fallback.server_ip),
+            username: self.username.or(fallback.username),
+            password: self.password.or(fallback.password),
+            protocol: self.protocol.or(fallback.protocol),
+        }
+    }
+}
+
+/// When to negotiate `AUTH TLS` on FTP connections.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FtpTls {
+    /// Use TLS when the server offers it, otherwise fall back to plain FTP
+    #[default]
+    Auto,
+    /// Refuse to log in unless a verified TLS session is established
+    Require,
+    /// Always use plain FTP
+    Off,
+}
+
+impl FromStr for FtpTls {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "require" => Ok(Self::Require),
+            "off" => Ok(Self::Off),
+            _ => Err(anyhow::anyhow!(
+                "Invalid FTP TLS mode '{}' (expected auto, require, or off)",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do about an SFTP server whose host key isn't in `~/.ssh/known_hosts`.
+/// A key that differs from the one saved there is always refused.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SftpHostKeys {
+    /// Ask in a terminal whether to trust and save it, and refuse it otherwise
+    #[default]
+    Ask,
+    /// Trust and save it without asking, as OpenSSH's `accept-new` does
+    AcceptNew,
+    /// Refuse it, so only hosts already in `known_hosts` are used
+    Strict,
+}
+
+impl FromStr for SftpHostKeys {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ask" => Ok(Self::Ask),
+            "accept-new" => Ok(Self::AcceptNew),
+            "strict" => Ok(Self::Strict),
+            _ => Err(anyhow::anyhow!(
+                "Invalid SFTP host key policy '{}' (expected ask, accept-new, or strict)",
+                s
+            )),
+        }
+    }
+}
+
+/// Which kind of address to try first when the server's name has both
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpPreference {
+    /// In the order the system's resolver gives them
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for IpPreference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "ipv4" => Ok(Self::Ipv4),
+            "ipv6" => Ok(Self::Ipv6),
+            _ => Err(anyhow::anyhow!(
+                "Invalid IP preference '{}' (expected auto, ipv4, or ipv6)",
+                s
+            )),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             server_ip: String::new(),
             username: String::new(),
             password: None,
-            default_protocol: Protocol::Smb,
+            default_protocol: default_protocol(),
             configured: false,
+            ftp_tls: FtpTls::Auto,
+            sftp_host_keys: SftpHostKeys::Ask,
+            fallback_enabled: true,
+            smb_share: default_smb_share(),
+            smb_port: default_smb_port(),
+            ftp_port: default_ftp_port(),
+            ip_preference: IpPreference::Auto,
+            proxy: None,
+            ftp_sessions: default_ftp_sessions(),
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            max_concurrent_transfers: default_max_concurrent_transfers(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            download_dir: None,
+            sync_time_tolerance: default_sync_time_tolerance(),
+            sync_jobs: BTreeMap::new(),
+            defaults: CommandDefaults::default(),
+            migrated_from: None,
+        }
+    }
+}
+
+/// Lay `overlay` over `base`, merging the tables both have
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
         }
     }
 }
 
+/// What's in `table` but not the same in `base`, looking inside the tables both
+/// have. What `base` has that `table` doesn't is left out, as an overlay can't
+/// remove it.
+fn table_difference(table: toml::Table, base: &toml::Table) -> toml::Table {
+    table
+        .into_iter()
+        .filter_map(|(key, value)| match (value, base.get(&key)) {
+            (toml::Value::Table(table), Some(toml::Value::Table(base))) => {
+                let difference = table_difference(table, base);
+                (!difference.is_empty()).then_some((key, toml::Value::Table(difference)))
+            }
+            (value, Some(base)) if value == *base => None,
+            (value, _) => Some((key, value)),
+        })
+        .collect()
+}
+
+/// Version 0, from before files had a version. Only `version` itself is new:
+/// every setting added since has a default for files without it.
+const fn migrate_v0(_config: &mut Map<String, Value>) {}
+
+/// The user's downloads folder, falling back to `~/Downloads` and then the
+/// current directory when the platform doesn't report one.
+pub fn default_download_dir() -> PathBuf {
+    UserDirs::new()
+        .map(|dirs| {
+            dirs.download_dir()
+                .map_or_else(|| dirs.home_dir().join("Downloads"), Path::to_path_buf)
+        })
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where comfy-fs keeps its settings, e.g. `~/.config/comfy-fs` on Linux
+pub fn config_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "comfy", "comfy-fs").map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Where comfy-fs keeps what it writes for itself, e.g. `~/.local/share/comfy-fs` on Linux
+pub fn data_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "comfy", "comfy-fs").map(|dirs| dirs.data_dir().to_path_buf())
+}
+
 impl Config {
+    /// Load the config file and fill in the password from the OS keyring.
     pub fn load() -> Result<Self> {
+        let mut config = Self::load_without_keyring()?;
+        config.load_keyring_password();
+        Ok(config)
+    }
+
+    pub fn load_without_keyring() -> Result<Self> {
+        if let Some((shared, local)) = Self::toml_paths() {
+            let shared_content = fs::read_to_string(&shared)?;
+            let local_content = match fs::read_to_string(&local) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                content => Some(content?),
+            };
+            return Self::from_toml(&shared_content, local_content.as_deref()).with_context(|| {
+                match local_content {
+                    Some(_) => format!(
+                        "Could not read {} and {}",
+                        shared.display(),
+                        local.display()
+                    ),
+                    None => format!("Could not read {}", shared.display()),
+                }
+            });
+        }
         if let Some(config_path) = Self::config_path() {
             if config_path.exists() {
-                let content = fs::read_to_string(config_path)?;
-                let config: Config = serde_json::from_str(&content)?;
-                return Ok(config);
+                let content = fs::read_to_string(&config_path)?;
+                return Self::from_json(&content)
+                    .with_context(|| format!("Could not read {}", config_path.display()));
             }
         }
         Ok(Self::default())
     }
 
+    /// Parse a config file of any version up to [`CONFIG_VERSION`], bringing
+    /// an older one up to date
+    pub fn from_json(content: &str) -> Result<Self> {
+        Self::from_value(serde_json::from_str(content)?)
+    }
+
+    /// Parse a shared TOML config with the `local` one laid over it. Tables in
+    /// both are merged; anything else set in `local` wins.
+    pub fn from_toml(shared: &str, local: Option<&str>) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(shared)?;
+        if let Some(local) = local {
+            merge_tables(&mut table, toml::from_str(local)?);
+        }
+        Self::from_value(serde_json::to_value(table)?)
+    }
+
+    fn from_value(mut value: Value) -> Result<Self> {
+        let config = value
+            .as_object_mut()
+            .context("The config file should hold a JSON object")?;
+        let version = match config.get("version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .context("The config file's version should be a whole number")?,
+        };
+        if version > CONFIG_VERSION {
+            bail!(
+                "The config file is version {}, but this comfy-fs only knows up to version {}; \
+                 upgrade comfy-fs",
+                version,
+                CONFIG_VERSION
+            );
+        }
+
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(config);
+        }
+        config.insert("version".to_string(), CONFIG_VERSION.into());
+        let mut config: Self = serde_json::from_value(value)?;
+        if version < CONFIG_VERSION {
+            config.migrated_from = Some(version);
+        }
+        Ok(config)
+    }
+
+    /// Look up the password for the current server and username, unless one is already set.
+    /// A missing entry or an unavailable keyring just leaves the password unset.
+    pub fn load_keyring_password(&mut self) {
+        if self.password.is_some() || self.server_ip.is_empty() || self.username.is_empty() {
+            return;
+        }
+        self.password = self.keyring_entry().and_then(|entry| entry.get_password().ok());
+    }
+
+    /// Save the password in the OS keyring under the current server and username.
+    pub fn store_keyring_password(&self) -> Result<()> {
+        let Some(password) = &self.password else {
+            return Ok(());
+        };
+        let entry = self
+            .keyring_entry()
+            .ok_or_else(|| anyhow::anyhow!("A server and username are needed to store a password"))?;
+        entry.set_password(password)?;
+        Ok(())
+    }
+
+    fn keyring_entry(&self) -> Option<keyring::Entry> {
+        if self.server_ip.is_empty() || self.username.is_empty() {
+            return None;
+        }
+        keyring::Entry::new(KEYRING_SERVICE, &Self::keyring_user(&self.server_ip, &self.username))
+            .ok()
+    }
+
+    fn keyring_user(server_ip: &str, username: &str) -> String {
+        format!("{}@{}", username, server_ip)
+    }
+
     pub fn save(&self) -> Result<()> {
+        if let Some((shared, local)) = Self::toml_paths() {
+            let shared = Self::from_toml(&fs::read_to_string(shared)?, None)?;
+            let content = toml::to_string_pretty(&self.local_overrides(&shared)?)?;
+            fs::write(&local, content)
+                .with_context(|| format!("Could not write {}", local.display()))?;
+            return Ok(());
+        }
         if let Some(config_path) = Self::config_path() {
             if let Some(parent) = config_path.parent() {
                 fs::create_dir_all(parent)?;
             }
+            // The old file is kept in case an older comfy-fs still needs it
+            if let Some(version) = self.migrated_from {
+                let backup = config_path.with_file_name(format!("config.v{}.json.bak", version));
+                if config_path.exists() && !backup.exists() {
+                    fs::copy(&config_path, &backup).with_context(|| {
+                        format!("Could not back up the config file to {}", backup.display())
+                    })?;
+                }
+            }
             let content = serde_json::to_string_pretty(self)?;
             fs::write(config_path, content)?;
         }
@@ -58,15 +653,127 @@ impl Config {
     }
 
     fn config_path() -> Option<PathBuf> {
-        ProjectDirs::from("com", "comfy", "comfy-fs")
-            .map(|dirs| dirs.config_dir().join("config.json"))
+        config_dir().map(|dir| dir.join("config.json"))
+    }
+
+    /// The shared and local TOML configs, when there's a shared one
+    fn toml_paths() -> Option<(PathBuf, PathBuf)> {
+        let dir = config_dir()?;
+        let shared = dir.join(SHARED_FILE);
+        shared.exists().then(|| (shared, dir.join(LOCAL_FILE)))
+    }
+
+    /// The settings that differ from `shared`, to save in [`LOCAL_FILE`]
+    pub fn local_overrides(&self, shared: &Self) -> Result<toml::Table> {
+        Ok(table_difference(
+            toml::Table::try_from(self)?,
+            &toml::Table::try_from(shared)?,
+        ))
+    }
+
+    /// The sync job called `name`
+    pub fn sync_job(&self, name: &str) -> Result<&SyncJob> {
+        self.sync_jobs.get(name).ok_or_else(|| {
+            if self.sync_jobs.is_empty() {
+                anyhow::anyhow!("Unknown sync job '{}' (none are defined in the config file)", name)
+            } else {
+                let names: Vec<_> = self.sync_jobs.keys().map(String::as_str).collect();
+                anyhow::anyhow!("Unknown sync job '{}' (expected {})", name, names.join(", "))
+            }
+        })
+    }
+
+    /// Settings for `name`, or for `default_profile` when no name is given.
+    ///
+    /// Returns the top-level settings unchanged when neither is set.
+    pub fn resolve_profile(&self, name: Option<&str>) -> Result<Self> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(self.clone());
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown profile '{}'", name))?;
+
+        let mut resolved = self.clone();
+        if let Some(server_ip) = &profile.server_ip {
+            resolved.server_ip.clone_from(server_ip);
+        }
+        if let Some(username) = &profile.username {
+            resolved.username.clone_from(username);
+        }
+        if let Some(protocol) = profile.protocol {
+            resolved.default_protocol = protocol;
+        }
+        if let Some(smb_share) = &profile.smb_share {
+            resolved.smb_share.clone_from(smb_share);
+        }
+        if let Some(smb_port) = profile.smb_port {
+            resolved.smb_port = smb_port;
+        }
+        if let Some(ftp_port) = profile.ftp_port {
+            resolved.ftp_port = ftp_port;
+        }
+        // A password belongs to one server and user, so don't carry it over to another
+        if resolved.server_ip != self.server_ip || resolved.username != self.username {
+            resolved.password = None;
+        }
+        // A profile with its own server and user is usable even before the wizard has run
+        if profile.server_ip.is_some() && profile.username.is_some() {
+            resolved.configured = true;
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &Overrides) {
+        let previous = (self.server_ip.clone(), self.username.clone());
+
+        if let Some(server_ip) = &overrides.server_ip {
+            self.server_ip.clone_from(server_ip);
+        }
+        if let Some(username) = &overrides.username {
+            self.username.clone_from(username);
+        }
+        if let Some(protocol) = overrides.protocol {
+            self.default_protocol = protocol;
+        }
+
+        if (self.server_ip.clone(), self.username.clone()) != previous {
+            self.password = None;
+            // Overrides are enough to connect without running the setup wizard
+            self.configured = true;
+        }
+        if let Some(password) = &overrides.password {
+            self.password = Some(password.clone());
+        }
+    }
+
+    /// How many files to copy at once, checked since the file can be edited by hand
+    pub fn concurrency(&self) -> Result<usize> {
+        crate::transfer::check_concurrency(self.max_concurrent_transfers)
+            .context("max_concurrent_transfers in the config file is out of range")
+    }
+
+    /// The timeouts in seconds as [`Timeouts`]; 0 is treated as 1 so nothing
+    /// gives up instantly
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(self.connect_timeout.max(1)),
+            read: Duration::from_secs(self.read_timeout.max(1)),
+        }
+    }
+
+    /// Directory the browser downloads into.
+    pub fn download_dir(&self) -> PathBuf {
+        self.download_dir.clone().unwrap_or_else(default_download_dir)
     }
 
     pub fn is_configured(&self) -> bool {
         self.configured && !self.server_ip.is_empty() && !self.username.is_empty()
     }
 
-    pub fn interactive_setup(&mut self) -> Result<()> {
+    pub async fn interactive_setup(&mut self) -> Result<()> {
         println!("\n🚀 Welcome to Comfy File Server CLI!");
         println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
         println!("\nThis tool helps you access the company file server easily.");
@@ -74,12 +781,19 @@ impl Config {
         println!("\x1b[36mhttps://www.notion.so/comfy-org/File-Server-Guide-2436d73d3650803f8aedcb7d2177d347?source=copy_link\x1b[0m\n");
         println!("Let's configure your connection settings:\n");
 
-        // Get server IP
-        print!("Server IP address: ");
-        io::stdout().flush()?;
-        let mut server_ip = String::new();
-        io::stdin().read_line(&mut server_ip)?;
-        self.server_ip = server_ip.trim().to_string();
+        // Get the server's name or address, or look for servers on the network to pick from
+        self.server_ip = loop {
+            print!("Server name or IP address (Enter to search the network): ");
+            io::stdout().flush()?;
+            let mut server_ip = String::new();
+            // Nothing more to read, so nothing to choose with either
+            if io::stdin().read_line(&mut server_ip)? == 0 || !server_ip.trim().is_empty() {
+                break server_ip.trim().to_string();
+            }
+            if let Some(host) = crate::discover::search_and_choose()? {
+                break host.address.to_string();
+            }
+        };
 
         // Get username
         print!("Username: ");
@@ -92,16 +806,22 @@ impl Config {
         self.password = Some(rpassword::prompt_password("Password (hidden - you won't see it when you type): ").unwrap_or_default());
 
         // Get preferred protocol
-        print!("\nPreferred protocol (1=SMB, 2=FTP) [default: 1]: ");
+        print!("\nPreferred protocol (1=SMB, 2=FTP, 3=SFTP, 4=HTTP) [default: 1]: ");
         io::stdout().flush()?;
         let mut protocol_choice = String::new();
         io::stdin().read_line(&mut protocol_choice)?;
         
         self.default_protocol = match protocol_choice.trim() {
             "2" => Protocol::Ftp,
+            "3" => Protocol::Sftp,
+            "4" => Protocol::Http,
             _ => Protocol::Smb,
         };
 
+        if self.default_protocol == Protocol::Smb {
+            self.smb_share = self.choose_share().await?;
+        }
+
         self.configured = true;
 
         println!("\n✅ Configuration complete!");
@@ -111,6 +831,51 @@ impl Config {
         self.save()?;
         Ok(())
     }
+
+    /// Ask which of the server's shares to use, logging in with what's been
+    /// entered, or for a share name if they can't be listed
+    async fn choose_share(&self) -> Result<String> {
+        println!("\nLooking up the shares on {}...", self.server_ip);
+        let shares = match ConnectionManager::new(self.clone()).list_shares().await {
+            Ok(shares) => shares,
+            Err(e) => {
+                println!("Could not list them: {}", e);
+                Vec::new()
+            }
+        };
+        if shares.is_empty() {
+            print!("Share name [default: {}]: ", self.smb_share);
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            let answer = answer.trim();
+            return Ok(if answer.is_empty() { &self.smb_share } else { answer }.to_string());
+        }
+
+        for (i, share) in shares.iter().enumerate() {
+            if share.comment.is_empty() {
+                println!("  {}. {}", i + 1, share.name);
+            } else {
+                println!("  {}. {} ({})", i + 1, share.name, share.comment);
+            }
+        }
+        let default = shares
+            .iter()
+            .position(|share| share.name.eq_ignore_ascii_case(&self.smb_share))
+            .unwrap_or(0);
+        print!("Share (number or name) [default: {}]: ", default + 1);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        // A name not in the list may be a hidden share, like `models$`
+        Ok(match answer.trim() {
+            "" => shares[default].name.clone(),
+            answer => match answer.parse::<usize>() {
+                Ok(n) if (1..=shares.len()).contains(&n) => shares[n - 1].name.clone(),
+                _ => answer.to_string(),
+            },
+        })
+    }
 }
 
 #[cfg(test)]
@@ -123,26 +888,67 @@ mod tests {
         assert_eq!(config.server_ip, "");
         assert_eq!(config.username, "");
         assert_eq!(config.password, None);
-        assert_eq!(config.default_protocol, Protocol::Ftp);
-        assert_eq!(config.configured, false);
+        assert_eq!(config.default_protocol, Protocol::Smb);
+        assert!(!config.configured);
+        assert_eq!(config.ftp_tls, FtpTls::Auto);
+        assert!(config.fallback_enabled);
+        assert_eq!(config.smb_share, "share");
+        assert_eq!(config.smb_port, 445);
+        assert_eq!(config.ftp_port, 21);
+        assert_eq!(config.max_concurrent_transfers, 4);
+        assert_eq!(config.timeouts(), Timeouts::default());
+        assert_eq!(config.download_dir, None);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let mut config = Config::default();
+        assert_eq!(config.concurrency().unwrap(), 4);
+        config.max_concurrent_transfers = 0;
+        assert!(config.concurrency().is_err());
+
+        // Older config files don't have the setting
+        let config: Config = serde_json::from_str(
+            r#"{"server_ip": "10.0.0.1", "username": "comfy", "default_protocol": "Ftp"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_concurrent_transfers, 4);
+    }
+
+    #[test]
+    fn test_download_dir() {
+        let mut config = Config::default();
+        assert_eq!(config.download_dir(), default_download_dir());
+
+        config.download_dir = Some(PathBuf::from("/data/models"));
+        assert_eq!(config.download_dir(), PathBuf::from("/data/models"));
+
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.download_dir, Some(PathBuf::from("/data/models")));
     }
 
     #[test]
     fn test_protocol_serialization() {
         let ftp = Protocol::Ftp;
         let smb = Protocol::Smb;
+        let sftp = Protocol::Sftp;
 
         let ftp_json = serde_json::to_string(&ftp).unwrap();
         let smb_json = serde_json::to_string(&smb).unwrap();
+        let sftp_json = serde_json::to_string(&sftp).unwrap();
 
         assert_eq!(ftp_json, "\"Ftp\"");
         assert_eq!(smb_json, "\"Smb\"");
+        assert_eq!(sftp_json, "\"Sftp\"");
 
         let ftp_decoded: Protocol = serde_json::from_str(&ftp_json).unwrap();
         let smb_decoded: Protocol = serde_json::from_str(&smb_json).unwrap();
+        let sftp_decoded: Protocol = serde_json::from_str(&sftp_json).unwrap();
 
         assert_eq!(ftp_decoded, Protocol::Ftp);
         assert_eq!(smb_decoded, Protocol::Smb);
+        assert_eq!(sftp_decoded, Protocol::Sftp);
     }
 
     #[test]
@@ -153,6 +959,12 @@ mod tests {
             password: Some("testpass".to_string()),
             default_protocol: Protocol::Smb,
             configured: true,
+            ftp_tls: FtpTls::Require,
+            fallback_enabled: false,
+            smb_share: "models".to_string(),
+            smb_port: 4445,
+            ftp_port: 2121,
+            ..Config::default()
         };
 
         let json = serde_json::to_string_pretty(&config).unwrap();
@@ -161,6 +973,7 @@ mod tests {
         assert!(!json.contains("testpass")); // Password should be skipped
         assert!(json.contains("Smb"));
         assert!(json.contains("true"));
+        assert!(json.contains("\"require\""));
 
         let decoded: Config = serde_json::from_str(&json).unwrap();
         assert_eq!(decoded.server_ip, "10.0.0.1");
@@ -168,6 +981,340 @@ mod tests {
         assert_eq!(decoded.password, None); // Password not serialized
         assert_eq!(decoded.default_protocol, Protocol::Smb);
         assert!(decoded.configured);
+        assert_eq!(decoded.ftp_tls, FtpTls::Require);
+        assert!(!decoded.fallback_enabled);
+        assert_eq!(decoded.smb_share, "models");
+        assert_eq!(decoded.smb_port, 4445);
+        assert_eq!(decoded.ftp_port, 2121);
+    }
+
+    #[test]
+    fn test_ftp_tls_parsing() {
+        assert_eq!("auto".parse::<FtpTls>().unwrap(), FtpTls::Auto);
+        assert_eq!("REQUIRE".parse::<FtpTls>().unwrap(), FtpTls::Require);
+        assert_eq!("off".parse::<FtpTls>().unwrap(), FtpTls::Off);
+        assert!("sometimes".parse::<FtpTls>().is_err());
+        assert_eq!(
+            "accept-new".parse::<SftpHostKeys>().unwrap(),
+            SftpHostKeys::AcceptNew
+        );
+        assert!("yes".parse::<SftpHostKeys>().is_err());
+
+        // Configs written before the option existed default to auto
+        let legacy = r#"{"server_ip":"10.0.0.1","username":"u","default_protocol":"Ftp","configured":true}"#;
+        let decoded: Config = serde_json::from_str(legacy).unwrap();
+        assert_eq!(decoded.ftp_tls, FtpTls::Auto);
+        assert_eq!(decoded.sftp_host_keys, SftpHostKeys::Ask);
+        assert!(decoded.fallback_enabled);
+        assert_eq!(decoded.smb_share, "share");
+        assert_eq!(decoded.ftp_port, 21);
+        assert_eq!(decoded.ip_preference, IpPreference::Auto);
+        assert_eq!("IPv6".parse::<IpPreference>().unwrap(), IpPreference::Ipv6);
+        assert!("ipv5".parse::<IpPreference>().is_err());
+    }
+
+    #[test]
+    fn test_migration() {
+        let v0 = r#"{"server_ip": "10.0.0.1", "default_protocol": "smb",
+            "profiles": {"lab": {"protocol": "sftp"}}, "smb_port": 4445}"#;
+        let config = Config::from_json(v0).unwrap();
+        assert_eq!(config.migrated_from, Some(0));
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.username, "");
+        assert_eq!(config.default_protocol, Protocol::Smb);
+        assert_eq!(config.profiles["lab"].protocol, Some(Protocol::Sftp));
+        assert_eq!(config.smb_port, 4445);
+
+        // Saved in the current format, which loads as it is
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""version":1"#));
+        let current = Config::from_json(&json).unwrap();
+        assert_eq!(current.migrated_from, None);
+        assert_eq!(current.smb_port, 4445);
+
+        let error = Config::from_json(r#"{"version": 99}"#).unwrap_err();
+        assert!(error.to_string().contains("upgrade comfy-fs"));
+        assert!(Config::from_json("[]").is_err());
+    }
+
+    #[test]
+    fn test_toml_overlay() {
+        let shared = r#"
+            version = 1
+            server_ip = "files.studio.local"
+            default_protocol = "smb"
+            smb_share = "models"
+
+            [profiles.render]
+            server_ip = "render.studio.local"
+            smb_share = "renders"
+        "#;
+        let local = r#"
+            username = "alice"
+            download_dir = "/data/downloads"
+            proxy = "socks5://jump.studio.example:1080"
+
+            [profiles.render]
+            username = "alice-render"
+        "#;
+        let config = Config::from_toml(shared, Some(local)).unwrap();
+        assert_eq!(config.server_ip, "files.studio.local");
+        assert_eq!(config.username, "alice");
+        assert_eq!(config.default_protocol, Protocol::Smb);
+        assert_eq!(config.smb_share, "models");
+        assert_eq!(config.download_dir, Some(PathBuf::from("/data/downloads")));
+        assert_eq!(
+            config.proxy.as_ref().map(ToString::to_string).as_deref(),
+            Some("socks5://jump.studio.example:1080")
+        );
+        let render = &config.profiles["render"];
+        assert_eq!(render.smb_share.as_deref(), Some("renders"));
+        assert_eq!(render.username.as_deref(), Some("alice-render"));
+
+        // Saving keeps only what this machine changed
+        let mut changed = config.clone();
+        changed.smb_port = 4445;
+        let shared = Config::from_toml(shared, None).unwrap();
+        let overrides = changed.local_overrides(&shared).unwrap();
+        let keys: Vec<&str> = overrides.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["download_dir", "profiles", "proxy", "smb_port", "username"]);
+        assert_eq!(
+            overrides["profiles"].to_string(),
+            r#"{ render = { username = "alice-render" } }"#
+        );
+    }
+
+    #[test]
+    fn test_command_defaults() {
+        let config = Config::from_toml(
+            r#"
+            [defaults.download]
+            dest = "/data/models"
+            parallel = 8
+
+            [defaults.sync]
+            exclude = ["*.tmp", ".cache/"]
+
+            [defaults.list]
+            sort = "size"
+            "#,
+            None,
+        )
+        .unwrap();
+        let defaults = &config.defaults;
+        assert_eq!(defaults.download.dest, Some(PathBuf::from("/data/models")));
+        assert_eq!(defaults.download.parallel, Some(8));
+        assert_eq!(defaults.download.retries, None);
+        assert_eq!(defaults.sync.exclude, ["*.tmp", ".cache/"]);
+        assert_eq!(defaults.upload, TransferDefaults::default());
+        assert_eq!(defaults.list.sort, Some(SortMode::Size));
+
+        // Only sections that set something are saved
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json["defaults"],
+            serde_json::json!({
+                "download": {"dest": "/data/models", "parallel": 8},
+                "sync": {"exclude": ["*.tmp", ".cache/"]},
+                "list": {"sort": "size"},
+            })
+        );
+        assert!(serde_json::to_value(Config::default())
+            .unwrap()
+            .get("defaults")
+            .is_none());
+
+        let mut defaults = config.defaults.clone();
+        assert!(defaults.check().is_ok());
+        defaults.sync.parallel = Some(0);
+        let error = defaults.check().unwrap_err();
+        assert!(error.to_string().contains("defaults.sync.parallel"));
+    }
+
+    fn config_with_profiles() -> Config {
+        let mut config = Config {
+            server_ip: "10.0.0.1".to_string(),
+            username: "prod".to_string(),
+            default_protocol: Protocol::Smb,
+            configured: true,
+            ..Config::default()
+        };
+        config.profiles.insert(
+            "staging".to_string(),
+            Profile {
+                server_ip: Some("10.0.0.2".to_string()),
+                protocol: Some(Protocol::Sftp),
+                ..Profile::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let config = config_with_profiles();
+
+        let staging = config.resolve_profile(Some("staging")).unwrap();
+        assert_eq!(staging.server_ip, "10.0.0.2");
+        assert_eq!(staging.default_protocol, Protocol::Sftp);
+        // Unset profile fields are inherited from the top level
+        assert_eq!(staging.username, "prod");
+        assert_eq!(staging.smb_share, "share");
+
+        let top_level = config.resolve_profile(None).unwrap();
+        assert_eq!(top_level.server_ip, "10.0.0.1");
+
+        assert!(config.resolve_profile(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_profile_drops_password_for_other_server() {
+        let mut config = config_with_profiles();
+        config.password = Some("prod-secret".to_string());
+        config.profiles.insert(
+            "same-server".to_string(),
+            Profile {
+                protocol: Some(Protocol::Ftp),
+                ..Profile::default()
+            },
+        );
+
+        assert_eq!(config.resolve_profile(Some("staging")).unwrap().password, None);
+        assert_eq!(
+            config
+                .resolve_profile(Some("same-server"))
+                .unwrap()
+                .password
+                .as_deref(),
+            Some("prod-secret")
+        );
+    }
+
+    #[test]
+    fn test_overrides_from_vars() {
+        let vars = |key: &str| match key {
+            "COMFY_FS_SERVER" => Some("10.0.0.9".to_string()),
+            "COMFY_FS_PROTOCOL" => Some("sftp".to_string()),
+            "COMFY_FS_USERNAME" => Some(String::new()),
+            _ => None,
+        };
+        let overrides = Overrides::from_vars(vars).unwrap();
+
+        assert_eq!(overrides.server_ip.as_deref(), Some("10.0.0.9"));
+        assert_eq!(overrides.protocol, Some(Protocol::Sftp));
+        // Empty variables count as unset
+        assert_eq!(overrides.username, None);
+        assert_eq!(overrides.password, None);
+
+        let invalid = |key: &str| (key == "COMFY_FS_PROTOCOL").then(|| "gopher".to_string());
+        assert!(Overrides::from_vars(invalid).is_err());
+    }
+
+    #[test]
+    fn test_overrides_precedence() {
+        let flags = Overrides {
+            server_ip: Some("flag-server".to_string()),
+            ..Overrides::default()
+        };
+        let env = Overrides {
+            server_ip: Some("env-server".to_string()),
+            username: Some("env-user".to_string()),
+            ..Overrides::default()
+        };
+
+        let merged = flags.or(env);
+        assert_eq!(merged.server_ip.as_deref(), Some("flag-server"));
+        assert_eq!(merged.username.as_deref(), Some("env-user"));
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let mut config = Config::default();
+        config.apply_overrides(&Overrides {
+            server_ip: Some("10.0.0.9".to_string()),
+            username: Some("ci".to_string()),
+            password: Some("secret".to_string()),
+            protocol: Some(Protocol::Sftp),
+        });
+
+        assert!(config.is_configured());
+        assert_eq!(config.server_ip, "10.0.0.9");
+        assert_eq!(config.password.as_deref(), Some("secret"));
+        assert_eq!(config.default_protocol, Protocol::Sftp);
+
+        // Switching servers drops a password meant for the old one
+        config.apply_overrides(&Overrides {
+            server_ip: Some("10.0.0.10".to_string()),
+            ..Overrides::default()
+        });
+        assert_eq!(config.password, None);
+    }
+
+    #[test]
+    fn test_keyring_user() {
+        assert_eq!(Config::keyring_user("10.0.0.1", "alice"), "alice@10.0.0.1");
+        // Nothing to look up without a server and username
+        assert!(Config::default().keyring_entry().is_none());
+    }
+
+    #[test]
+    fn test_resolve_default_profile() {
+        let mut config = config_with_profiles();
+        config.default_profile = Some("staging".to_string());
+
+        assert_eq!(config.resolve_profile(None).unwrap().server_ip, "10.0.0.2");
+
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.default_profile.as_deref(), Some("staging"));
+        assert_eq!(decoded.profiles, config.profiles);
+    }
+
+    #[test]
+    fn test_sync_jobs() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "server_ip": "10.0.0.1",
+                "username": "me",
+                "default_protocol": "Sftp",
+                "sync_jobs": {
+                    "models": {"local": "./models", "remote": "/models", "direction": "pull"},
+                    "workflows": {
+                        "local": "./workflows",
+                        "remote": "/workflows",
+                        "exclude": ["*.bak"]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let models = config.sync_job("models").unwrap();
+        assert_eq!(models.local, PathBuf::from("./models"));
+        assert_eq!(models.direction, SyncDirection::Pull);
+        let workflows = config.sync_job("workflows").unwrap();
+        assert_eq!(workflows.direction, SyncDirection::TwoWay);
+        assert_eq!(workflows.exclude, ["*.bak"]);
+
+        let e = config.sync_job("loras").unwrap_err().to_string();
+        assert!(e.contains("expected models, workflows"), "{}", e);
+        assert!(Config::default().sync_job("models").is_err());
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""direction":"two-way""#));
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.sync_jobs, config.sync_jobs);
+    }
+
+    #[test]
+    fn test_protocol_parsing() {
+        assert_eq!("smb".parse::<Protocol>().unwrap(), Protocol::Smb);
+        assert_eq!("SFTP".parse::<Protocol>().unwrap(), Protocol::Sftp);
+        assert_eq!("ftp".parse::<Protocol>().unwrap(), Protocol::Ftp);
+        assert_eq!("https".parse::<Protocol>().unwrap(), Protocol::Http);
+        assert!("gopher".parse::<Protocol>().is_err());
+
+        assert_eq!(Protocol::Sftp.to_string(), "SFTP");
     }
 
     #[test]
@@ -1,74 +1,387 @@
-use crate::client::{ftp::FtpClient, smb::SmbClient, FileServerClient};
-use crate::config::Config;
+use crate::client::address::{self, host_name, host_port, unc_host};
+#[cfg(feature = "native-smb")]
+use crate::client::smb_native::NativeSmbClient;
+use crate::client::{
+    ftp::FtpClient, http::HttpClient, paths::PathGuard, sftp::SftpClient, smb::SmbClient,
+    ClientError, FileServerClient, Share,
+};
+use crate::config::{Config, IpPreference, Protocol, SftpHostKeys};
 use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{debug, info};
+
+type SharedClient = Arc<Mutex<Box<dyn FileServerClient>>>;
+
+/// Opens one more client like the first
+type Connector = Arc<dyn Fn() -> BoxFuture<'static, Result<Box<dyn FileServerClient>>> + Send + Sync>;
 
 pub struct ConnectionManager {
     config: Config,
     client: Option<Arc<Mutex<Box<dyn FileServerClient>>>>,
+    /// The protocol `client` connected with
+    protocol: Option<Protocol>,
+    /// Whether to keep from saying which protocol got through
+    quiet: bool,
+    /// Whether questions can be asked on the terminal
+    prompt: bool,
 }
 
 impl ConnectionManager {
     pub fn new(config: Config) -> Self {
+        if let Some(password) = &config.password {
+            crate::logging::redact(password);
+        }
         Self {
             config,
             client: None,
+            protocol: None,
+            quiet: false,
+            prompt: false,
         }
     }
 
+    /// Ask on the terminal whether to trust an SFTP server whose host key
+    /// isn't known yet. Otherwise it's refused, unless the config says to
+    /// accept new keys.
+    #[must_use]
+    pub const fn with_prompt(mut self, prompt: bool) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Connect without saying which protocol got through. Failed attempts are
+    /// still reported.
+    #[must_use]
+    pub const fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// The protocol [`connect`](Self::connect) got through with, once it has
+    pub const fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+    }
+
+    /// A pool that starts with the connected client and opens up to
+    /// `max_clients - 1` more with the same protocol as transfers need them
+    pub async fn connect_pool(&mut self, max_clients: usize) -> Result<ClientPool> {
+        let client = self.connect().await?;
+        let Some(protocol) = self.protocol else {
+            return Ok(ClientPool::from(client));
+        };
+
+        let manager = Arc::new(Self::new(self.config.clone()).with_prompt(self.prompt));
+        let connector: Connector = Arc::new(move || {
+            let manager = manager.clone();
+            Box::pin(async move { manager.connect_with(protocol).await })
+        });
+        Ok(ClientPool::from(client).with_connector(connector, max_clients))
+    }
+
     pub async fn connect(&mut self) -> Result<Arc<Mutex<Box<dyn FileServerClient>>>> {
         if let Some(client) = &self.client {
             return Ok(client.clone());
         }
 
-        let password = self
-            .config
-            .password
-            .clone()
-            .ok_or_else(|| anyhow!("Password not configured"))?;
+        let protocols = self.protocol_order();
+        debug!(server = %self.config.server_ip, order = ?protocols, "connecting");
+        let mut attempts = protocols.iter().peekable();
+        let mut refused = false;
+
+        while let Some(&protocol) = attempts.next() {
+            match self.connect_with(protocol).await {
+                Ok(client) => {
+                    // On stderr so piped output (cat, find) stays clean
+                    if !self.quiet {
+                        eprintln!("Connected via {}", protocol);
+                    }
+                    let arc_client = Arc::new(Mutex::new(client));
+                    self.client = Some(arc_client.clone());
+                    self.protocol = Some(protocol);
+                    return Ok(arc_client);
+                }
+                Err(e) => {
+                    refused |= matches!(
+                        ClientError::classify(&e),
+                        ClientError::AuthenticationFailed(_)
+                    );
+                    match attempts.peek() {
+                        Some(next) => eprintln!(
+                            "{} connection failed: {}, trying {} fallback",
+                            protocol, e, next
+                        ),
+                        None => eprintln!("{} connection failed: {}", protocol, e),
+                    }
+                }
+            }
+        }
+
+        // A refused password is worth telling apart from a server that's down
+        let tried: Vec<String> = protocols.iter().map(ToString::to_string).collect();
+        let message = format!("Failed to connect to file server via {}", tried.join(", "));
+        Err(if refused {
+            ClientError::AuthenticationFailed(message)
+        } else {
+            ClientError::ConnectionLost(message)
+        }
+        .into())
+    }
+
+    /// The configured protocol first, then the others if fallback is enabled.
+    ///
+    /// HTTP directory indexes are a separate kind of server, so they are never
+    /// part of the fallback chain.
+    pub fn protocol_order(&self) -> Vec<Protocol> {
+        let preferred = self.config.default_protocol;
+        let mut order = vec![preferred];
+
+        if self.config.fallback_enabled && preferred != Protocol::Http {
+            order.extend(
+                [Protocol::Smb, Protocol::Sftp, Protocol::Ftp]
+                    .into_iter()
+                    .filter(|protocol| *protocol != preferred),
+            );
+        }
+
+        order
+    }
+
+    /// Connect with `protocol`, giving up once the connect timeout has passed
+    async fn connect_with(&self, protocol: Protocol) -> Result<Box<dyn FileServerClient>> {
+        let limit = self.config.timeouts().connect;
+        // Asked before the clock starts, since the answer can take a while
+        if protocol == Protocol::Sftp && self.prompt {
+            self.ask_to_trust_sftp_host().await?;
+        }
+        let started = Instant::now();
+        info!(%protocol, server = %self.config.server_ip, "trying");
+        let result = tokio::time::timeout(limit, self.open(protocol))
+            .await
+            .map_err(|_| {
+                ClientError::Timeout(format!(
+                    "No answer from {} after {}s",
+                    self.config.server_ip,
+                    limit.as_secs()
+                ))
+                .into()
+            })
+            .and_then(|result| result)
+            .map(|client| Box::new(PathGuard::new(client)) as Box<dyn FileServerClient>);
+        match &result {
+            Ok(_) => info!(%protocol, elapsed = ?started.elapsed(), "connected"),
+            Err(e) => info!(%protocol, elapsed = ?started.elapsed(), "failed: {:#}", e),
+        }
+        result
+    }
 
-        // Try SMB first
-        let mut smb_client = SmbClient::new(
-            self.config.server_ip.clone(),
+    fn sftp_client(&self) -> Result<SftpClient> {
+        Ok(SftpClient::new(
+            host_port(&self.config.server_ip, 22),
             self.config.username.clone(),
-            password.clone(),
-            Some("share".to_string()),
+            self.password()?,
+        )
+        .with_timeouts(self.config.timeouts())
+        .with_ip_preference(self.config.ip_preference)
+        .with_proxy(self.config.proxy.clone())
+        .with_host_keys(self.config.sftp_host_keys))
+    }
+
+    /// Show the host key of an SFTP server seen for the first time, and save
+    /// it if the answer is yes, as `ssh` does
+    async fn ask_to_trust_sftp_host(&self) -> Result<()> {
+        use std::io::{BufRead, Write};
+
+        if self.config.sftp_host_keys != SftpHostKeys::Ask {
+            return Ok(());
+        }
+        let client = self.sftp_client()?;
+        let limit = self.config.timeouts().connect;
+        let unknown = tokio::time::timeout(limit, client.unknown_host_key())
+            .await
+            .map_err(|_| {
+                ClientError::Timeout(format!(
+                    "No answer from {} after {}s",
+                    self.config.server_ip,
+                    limit.as_secs()
+                ))
+            })??;
+        let Some(key) = unknown else {
+            return Ok(());
+        };
+
+        eprintln!(
+            "The SSH host key of {} isn't known yet. Its fingerprint is {}.",
+            self.config.server_ip, key.fingerprint
         );
+        eprint!("Trust it and save it to known_hosts? [y/N] ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(ClientError::PermissionDenied(format!(
+                "Not trusting the SSH host key of {}",
+                self.config.server_ip
+            ))
+            .into());
+        }
+        client.trust(&key)
+    }
 
-        match smb_client.connect().await {
-            Ok(_) => {
-                println!("Connected via SMB");
-                let client: Box<dyn FileServerClient> = Box::new(smb_client);
-                let arc_client = Arc::new(Mutex::new(client));
-                self.client = Some(arc_client.clone());
-                return Ok(arc_client);
+    async fn open(&self, protocol: Protocol) -> Result<Box<dyn FileServerClient>> {
+        let timeouts = self.config.timeouts();
+        match protocol {
+            Protocol::Smb => self.connect_smb(self.password()?).await,
+            Protocol::Sftp => {
+                let mut sftp_client = self.sftp_client()?;
+                sftp_client.connect().await?;
+                Ok(Box::new(sftp_client))
             }
-            Err(e) => {
-                eprintln!("SMB connection failed: {}, trying FTP fallback", e);
+            Protocol::Ftp => {
+                let mut ftp_client = FtpClient::new(
+                    host_port(&self.config.server_ip, self.config.ftp_port),
+                    self.config.username.clone(),
+                    self.password()?,
+                    self.config.ftp_tls,
+                )
+                .with_max_sessions(self.config.ftp_sessions)
+                .with_timeouts(timeouts)
+                .with_ip_preference(self.config.ip_preference)
+                .with_proxy(self.config.proxy.clone());
+                ftp_client.connect().await?;
+                Ok(Box::new(ftp_client))
+            }
+            Protocol::Http => {
+                // Directory indexes are often public, so a password is optional
+                let mut http_client = HttpClient::new(
+                    &self.config.server_ip,
+                    self.config.username.clone(),
+                    self.config.password.clone().unwrap_or_default(),
+                )?
+                .with_timeouts(timeouts)
+                .with_ip_preference(self.config.ip_preference)
+                .with_proxy(self.config.proxy.clone())?;
+                http_client.connect().await?;
+                Ok(Box::new(http_client))
             }
         }
+    }
+
+    /// Neither SMB client can be given a connection to use, so SMB can't go
+    /// through a proxy
+    fn smb_direct(&self) -> Result<()> {
+        match &self.config.proxy {
+            Some(proxy) => Err(ClientError::Unsupported(format!(
+                "SMB can't go through the proxy {}; use SFTP, FTP, or HTTP",
+                proxy
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn password(&self) -> Result<String> {
+        self.config
+            .password
+            .clone()
+            .ok_or_else(|| anyhow!("Password not configured"))
+    }
 
-        // Fallback to FTP
-        let mut ftp_client = FtpClient::new(
-            format!("{}:21", self.config.server_ip),
+    /// The server's host for SMB, and the address to reach it at when that
+    /// isn't left to the SMB client: an IP address written as one, or the
+    /// first of the kind the preference asks for when a name has several
+    async fn smb_target(&self) -> (String, Option<IpAddr>) {
+        let host = host_name(&self.config.server_ip);
+        if let Ok(ip) = host.parse() {
+            return (host, Some(ip));
+        }
+        if self.config.ip_preference == IpPreference::Auto {
+            return (host, None);
+        }
+        let address = host_port(&host, self.config.smb_port);
+        let ip = address::lookup(&address, self.config.ip_preference)
+            .await
+            .ok()
+            .and_then(|addresses| addresses.first().map(SocketAddr::ip));
+        (host, ip)
+    }
+
+    #[cfg(feature = "native-smb")]
+    async fn native_smb_client(&self, password: String) -> NativeSmbClient {
+        let (host, ip) = self.smb_target().await;
+        NativeSmbClient::new(
+            ip.map_or(host, |ip| ip.to_string()),
             self.config.username.clone(),
             password,
-        );
+            Some(self.config.smb_share.clone()),
+        )
+        .with_port(self.config.smb_port)
+        .with_timeouts(self.config.timeouts())
+    }
 
-        match ftp_client.connect().await {
-            Ok(_) => {
-                println!("Connected via FTP");
-                let client: Box<dyn FileServerClient> = Box::new(ftp_client);
-                let arc_client = Arc::new(Mutex::new(client));
-                self.client = Some(arc_client.clone());
-                Ok(arc_client)
+    async fn smbclient(&self, password: String) -> SmbClient {
+        let (host, ip) = self.smb_target().await;
+        let client = SmbClient::new(
+            unc_host(&host),
+            self.config.username.clone(),
+            password,
+            Some(self.config.smb_share.clone()),
+        )
+        .with_port(self.config.smb_port)
+        .with_timeouts(self.config.timeouts());
+        match ip {
+            Some(ip) => client.with_address(ip),
+            None => client,
+        }
+    }
+
+    async fn connect_smb(&self, password: String) -> Result<Box<dyn FileServerClient>> {
+        self.smb_direct()?;
+        // Try native SMB first, so smbclient isn't required
+        #[cfg(feature = "native-smb")]
+        {
+            let mut native_client = self.native_smb_client(password.clone()).await;
+            match native_client.connect().await {
+                Ok(()) => return Ok(Box::new(native_client)),
+                Err(e) => {
+                    eprintln!("Native SMB connection failed: {}, trying smbclient", e);
+                }
             }
+        }
+
+        // Then SMB through the smbclient binary
+        let mut smb_client = self.smbclient(password).await;
+        smb_client.connect().await?;
+        Ok(Box::new(smb_client))
+    }
+
+    /// The disk shares on the SMB server, asked the ways [`connect`](Self::connect)
+    /// would: natively, then through smbclient
+    pub async fn list_shares(&self) -> Result<Vec<Share>> {
+        self.smb_direct()?;
+        let password = self.password()?;
+        #[cfg(feature = "native-smb")]
+        let native_error = match self
+            .native_smb_client(password.clone())
+            .await
+            .list_shares()
+            .await
+        {
+            Ok(shares) => return Ok(shares),
             Err(e) => {
-                eprintln!("FTP connection also failed: {}", e);
-                Err(anyhow!("Failed to connect to file server via both SMB and FTP"))
+                debug!(error = %e, "native share listing failed, trying smbclient");
+                e
             }
-        }
+        };
+        let listed = self.smbclient(password).await.list_shares().await;
+        // smbclient may just not be installed, so the native failure says more
+        #[cfg(feature = "native-smb")]
+        let listed = listed.map_err(|_| native_error);
+        listed
     }
 
     #[allow(dead_code)]
@@ -81,9 +394,127 @@ impl ConnectionManager {
     }
 }
 
+/// Clients for running operations side by side. Each has its own connection,
+/// so a transfer never waits for another one to finish with it.
+#[derive(Clone)]
+pub struct ClientPool {
+    clients: Arc<std::sync::Mutex<PoolSlots>>,
+    connector: Option<Connector>,
+    max_clients: usize,
+    /// Which client to wait for when all are busy
+    next: Arc<AtomicUsize>,
+}
+
+#[derive(Default)]
+struct PoolSlots {
+    clients: Vec<SharedClient>,
+    /// Connections being opened
+    opening: usize,
+    /// A connection failed to open, so the pool stays as it is
+    full: bool,
+}
+
+impl From<Arc<Mutex<Box<dyn FileServerClient>>>> for ClientPool {
+    /// A pool of just `client`, which runs one operation at a time
+    fn from(client: Arc<Mutex<Box<dyn FileServerClient>>>) -> Self {
+        Self {
+            clients: Arc::new(std::sync::Mutex::new(PoolSlots {
+                clients: vec![client],
+                ..PoolSlots::default()
+            })),
+            connector: None,
+            max_clients: 1,
+            next: Arc::default(),
+        }
+    }
+}
+
+impl ClientPool {
+    /// Open more clients with `connector` when all are busy, up to `max_clients`
+    #[must_use]
+    fn with_connector(mut self, connector: Connector, max_clients: usize) -> Self {
+        self.connector = Some(connector);
+        self.max_clients = max_clients.max(1);
+        self
+    }
+
+    /// The first client, for listings and other one-off operations
+    pub fn primary(&self) -> Arc<Mutex<Box<dyn FileServerClient>>> {
+        self.slots().clients[0].clone()
+    }
+
+    /// Most clients the pool will open, and so transfers it can run at once
+    pub const fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    /// Clients open right now
+    pub fn len(&self) -> usize {
+        self.slots().clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A client to use on its own: an idle one, a new one while there's room,
+    /// or else the next busy one in turn once it's free
+    pub async fn acquire(&self) -> OwnedMutexGuard<Box<dyn FileServerClient>> {
+        let clients = {
+            let mut slots = self.slots();
+            if let Some(idle) = slots.clients.iter().find_map(|c| c.clone().try_lock_owned().ok()) {
+                return idle;
+            }
+            let room = slots.clients.len() + slots.opening < self.max_clients;
+            if room && !slots.full && self.connector.is_some() {
+                slots.opening += 1;
+                None
+            } else {
+                Some(slots.clients.clone())
+            }
+        };
+
+        let clients = match (clients, &self.connector) {
+            (Some(clients), _) => clients,
+            (None, Some(connector)) => {
+                let opened = connector().await;
+                let added = {
+                    let mut slots = self.slots();
+                    slots.opening -= 1;
+                    match opened {
+                        Ok(client) => {
+                            let client = Arc::new(Mutex::new(client));
+                            slots.clients.push(client.clone());
+                            Ok(client)
+                        }
+                        // The server may limit connections; make do with the open ones
+                        Err(_) => {
+                            slots.full = true;
+                            Err(slots.clients.clone())
+                        }
+                    }
+                };
+                match added {
+                    Ok(client) => return client.lock_owned().await,
+                    Err(clients) => clients,
+                }
+            }
+            (None, None) => self.slots().clients.clone(),
+        };
+
+        let turn = self.next.fetch_add(1, Ordering::Relaxed) % clients.len();
+        clients[turn].clone().lock_owned().await
+    }
+
+    fn slots(&self) -> std::sync::MutexGuard<'_, PoolSlots> {
+        self.clients.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::local::LocalClient;
     use crate::client::RemoteFile;
     use crate::config::Protocol;
     use async_trait::async_trait;
@@ -102,10 +533,71 @@ mod tests {
             async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()>;
             async fn create_directory(&mut self, path: &str) -> Result<()>;
             async fn delete_file(&mut self, path: &str) -> Result<()>;
+            async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()>;
+            async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
             async fn get_file_size(&mut self, path: &str) -> Result<u64>;
         }
     }
 
+    fn local_pool(root: &Path, connector: Connector, max_clients: usize) -> ClientPool {
+        let first: Box<dyn FileServerClient> = Box::new(LocalClient::new(root.to_path_buf()));
+        ClientPool::from(Arc::new(Mutex::new(first))).with_connector(connector, max_clients)
+    }
+
+    #[tokio::test]
+    async fn test_client_pool_opens_clients_as_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let connector: Connector = {
+            let (opened, root) = (opened.clone(), dir.path().to_path_buf());
+            Arc::new(move || {
+                opened.fetch_add(1, Ordering::Relaxed);
+                let client: Box<dyn FileServerClient> = Box::new(LocalClient::new(root.clone()));
+                Box::pin(async move { Ok(client) })
+            })
+        };
+        let pool = local_pool(dir.path(), connector, 3);
+
+        let first = pool.acquire().await;
+        assert_eq!(pool.len(), 1);
+        let second = pool.acquire().await;
+        let third = pool.acquire().await;
+        assert_eq!(pool.len(), 3);
+
+        // An idle client is used again before any more are opened
+        drop(second);
+        let again = pool.acquire().await;
+        assert_eq!(pool.len(), 3);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+
+        // With every client busy and no room left, the next one free is used
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.list_files("/").await.is_ok() }
+        });
+        drop((first, third, again));
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_client_pool_keeps_going_when_connecting_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let connector: Connector =
+            Arc::new(|| Box::pin(async { Err(anyhow!("Too many connections")) }));
+        let pool = local_pool(dir.path(), connector, 4);
+
+        let first = pool.acquire().await;
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.list_files("/").await.is_ok() }
+        });
+        tokio::task::yield_now().await;
+        drop(first);
+        assert!(waiting.await.unwrap());
+        assert_eq!(pool.len(), 1);
+        assert!(Arc::ptr_eq(&pool.primary(), &pool.slots().clients[0]));
+    }
+
     #[test]
     fn test_connection_manager_creation() {
         let config = Config::default();
@@ -139,10 +631,12 @@ mod tests {
 
     #[test]
     fn test_connection_manager_with_custom_config() {
-        let mut config = Config::default();
-        config.server_ip = "10.0.0.1".to_string();
-        config.username = "testuser".to_string();
-        config.default_protocol = Protocol::Smb;
+        let config = Config {
+            server_ip: "10.0.0.1".to_string(),
+            username: "testuser".to_string(),
+            default_protocol: Protocol::Smb,
+            ..Config::default()
+        };
 
         let manager = ConnectionManager::new(config.clone());
 
@@ -153,10 +647,63 @@ mod tests {
 
     #[test]
     fn test_connection_manager_no_password() {
-        let mut config = Config::default();
-        config.password = None;
+        let config = Config {
+            password: None,
+            ..Config::default()
+        };
 
         let manager = ConnectionManager::new(config);
         assert!(manager.config.password.is_none());
     }
+
+    #[test]
+    fn test_protocol_order_prefers_configured_protocol() {
+        let config = Config {
+            default_protocol: Protocol::Sftp,
+            ..Config::default()
+        };
+        let manager = ConnectionManager::new(config);
+
+        assert_eq!(
+            manager.protocol_order(),
+            vec![Protocol::Sftp, Protocol::Smb, Protocol::Ftp]
+        );
+    }
+
+    #[test]
+    fn test_protocol_order_without_fallback() {
+        let config = Config {
+            default_protocol: Protocol::Smb,
+            fallback_enabled: false,
+            ..Config::default()
+        };
+        let manager = ConnectionManager::new(config);
+
+        assert_eq!(manager.protocol_order(), vec![Protocol::Smb]);
+    }
+
+    #[test]
+    fn test_protocol_order_http_never_falls_back() {
+        let config = Config {
+            default_protocol: Protocol::Http,
+            ..Config::default()
+        };
+        let manager = ConnectionManager::new(config);
+
+        assert_eq!(manager.protocol_order(), vec![Protocol::Http]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_without_password_fails() {
+        let config = Config {
+            server_ip: "127.0.0.1".to_string(),
+            default_protocol: Protocol::Ftp,
+            fallback_enabled: false,
+            ..Config::default()
+        };
+        let mut manager = ConnectionManager::new(config);
+
+        assert!(manager.connect().await.is_err());
+        assert!(manager.client.is_none());
+    }
 }
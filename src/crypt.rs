@@ -0,0 +1,275 @@
+//! Client-side encryption, for `upload --encrypt` and the `download`s that
+//! decrypt what it uploaded
+//!
+//! An encrypted file is [`MAGIC`], a random nonce, and then the content in
+//! chunks sealed with ChaCha20-Poly1305 in the STREAM construction, so files of
+//! any size are handled a chunk at a time and a truncated or reordered file is
+//! rejected rather than decrypted short.
+
+use crate::client::partial_path;
+use crate::config::KEYRING_SERVICE;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{rand_core::RngCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The first bytes of every encrypted file, and its format version
+pub const MAGIC: &[u8; 8] = b"CFSENC\x00\x01";
+
+/// Environment variable that gives the key instead of the keyring
+pub const KEY_VAR: &str = "COMFY_FS_ENCRYPTION_KEY";
+
+/// Keyring entry the key is kept in. There's one for every server, so a file
+/// can be read back from wherever it was copied.
+const KEYRING_USER: &str = "encryption-key";
+
+/// Bytes of content sealed together
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes the authentication tag adds to each chunk
+const TAG_SIZE: usize = 16;
+
+/// Bytes of the nonce after [`MAGIC`]: ChaCha20-Poly1305's 12, less the
+/// STREAM counter and last-chunk flag
+const NONCE_SIZE: usize = 7;
+
+/// A 256-bit key, written as base64 to copy between machines
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    /// A new random key
+    pub fn generate() -> Self {
+        Self(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.0)
+    }
+
+    /// The key from `COMFY_FS_ENCRYPTION_KEY`, else from the OS keyring unless
+    /// `use_keyring` is false. `None` when neither has one.
+    pub fn load(use_keyring: bool) -> Result<Option<Self>> {
+        if let Some(key) = std::env::var(KEY_VAR).ok().filter(|key| !key.is_empty()) {
+            return key.parse().map(Some).with_context(|| format!("Invalid {}", KEY_VAR));
+        }
+        if !use_keyring {
+            return Ok(None);
+        }
+        let Some(key) = keyring_entry().and_then(|entry| entry.get_password().ok()) else {
+            return Ok(None);
+        };
+        key.parse()
+            .map(Some)
+            .context("The encryption key in the keyring is damaged")
+    }
+
+    /// Save the key in the OS keyring, replacing any there
+    pub fn store(&self) -> Result<()> {
+        keyring_entry()
+            .ok_or_else(|| anyhow!("The OS keyring is not available"))?
+            .set_password(&self.to_base64())?;
+        Ok(())
+    }
+}
+
+impl FromStr for EncryptionKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(s.trim())
+            .map_err(|_| anyhow!("Invalid encryption key (expected base64)"))?;
+        if bytes.len() != 32 {
+            bail!("Invalid encryption key (expected 32 bytes, got {})", bytes.len());
+        }
+        Ok(Self(*Key::from_slice(&bytes)))
+    }
+}
+
+// Never print the key by accident
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(****)")
+    }
+}
+
+fn keyring_entry() -> Option<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).ok()
+}
+
+/// Encrypt the file at `input` into `output`. Returns the encrypted size.
+pub fn encrypt_file(input: &Path, output: &Path, key: &EncryptionKey) -> Result<u64> {
+    let mut reader = BufReader::new(
+        File::open(input).with_context(|| format!("Could not open {}", input.display()))?,
+    );
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&nonce)?;
+
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    let mut encryptor =
+        EncryptorBE32::<ChaCha20Poly1305>::from_aead(cipher, GenericArray::from_slice(&nonce));
+    let seal_failed = |_| anyhow!("Could not encrypt {}", input.display());
+    // A chunk is only sealed as the last once the next read comes back empty
+    let mut chunk = read_chunk(&mut reader, CHUNK_SIZE)?;
+    loop {
+        let next = read_chunk(&mut reader, CHUNK_SIZE)?;
+        if next.is_empty() {
+            writer.write_all(&encryptor.encrypt_last(chunk.as_slice()).map_err(seal_failed)?)?;
+            break;
+        }
+        writer.write_all(&encryptor.encrypt_next(chunk.as_slice()).map_err(seal_failed)?)?;
+        chunk = next;
+    }
+    writer.flush()?;
+    Ok(fs::metadata(output)?.len())
+}
+
+/// Decrypt the file at `input`, written by [`encrypt_file`], into `output`
+pub fn decrypt_file(input: &Path, output: &Path, key: &EncryptionKey) -> Result<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut header = [0u8; MAGIC.len() + NONCE_SIZE];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| anyhow!("{} is not an encrypted file", input.display()))?;
+    if &header[..MAGIC.len()] != MAGIC {
+        bail!("{} is not an encrypted file", input.display());
+    }
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    let nonce = GenericArray::from_slice(&header[MAGIC.len()..]);
+    let mut decryptor = DecryptorBE32::<ChaCha20Poly1305>::from_aead(cipher, nonce);
+    let open_failed = |_| {
+        anyhow!(
+            "Could not decrypt {}: the key is wrong or the file is damaged",
+            input.display()
+        )
+    };
+    let mut chunk = read_chunk(&mut reader, CHUNK_SIZE + TAG_SIZE)?;
+    loop {
+        let next = read_chunk(&mut reader, CHUNK_SIZE + TAG_SIZE)?;
+        if next.is_empty() {
+            writer.write_all(&decryptor.decrypt_last(chunk.as_slice()).map_err(open_failed)?)?;
+            break;
+        }
+        writer.write_all(&decryptor.decrypt_next(chunk.as_slice()).map_err(open_failed)?)?;
+        chunk = next;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Whether the file at `path` starts with [`MAGIC`]
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut start = [0u8; MAGIC.len()];
+    match File::open(path)?.read_exact(&mut start) {
+        Ok(()) => Ok(&start == MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decrypt a downloaded file in place if it's encrypted. Returns whether it
+/// was. An encrypted file without a key to read it is an error, and is kept.
+pub fn decrypt_download(path: &Path, key: Option<&EncryptionKey>) -> Result<bool> {
+    if !is_encrypted(path)? {
+        return Ok(false);
+    }
+    let key = key.ok_or_else(|| {
+        anyhow!(
+            "{} is encrypted; save its key with `comfy-fs config --encryption-key` or set {}",
+            path.display(),
+            KEY_VAR
+        )
+    })?;
+    let partial = partial_path(path);
+    if let Err(e) = decrypt_file(path, &partial, key) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, path)?;
+    Ok(true)
+}
+
+/// Up to `size` bytes, short only at the end of `reader`
+fn read_chunk(reader: &mut impl Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = EncryptionKey::generate();
+        // Empty, under one chunk, and exactly and just over whole chunks
+        for size in [0, 10, CHUNK_SIZE, 2 * CHUNK_SIZE + 1] {
+            let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let original = dir.path().join("secrets.json");
+            fs::write(&original, &content).unwrap();
+
+            let download = dir.path().join("download.json");
+            let encrypted_size = encrypt_file(&original, &download, &key).unwrap();
+            assert!(is_encrypted(&download).unwrap());
+            assert_ne!(fs::read(&download).unwrap(), content);
+            let chunks = size.div_ceil(CHUNK_SIZE).max(1);
+            assert_eq!(
+                encrypted_size as usize,
+                MAGIC.len() + NONCE_SIZE + size + chunks * TAG_SIZE
+            );
+
+            assert!(decrypt_download(&download, Some(&key)).unwrap());
+            assert_eq!(fs::read(&download).unwrap(), content);
+            // Plain files are left alone
+            assert!(!decrypt_download(&download, Some(&key)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_wrong_key_or_damage_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("a.txt");
+        fs::write(&original, vec![7u8; CHUNK_SIZE + 5]).unwrap();
+        let encrypted = dir.path().join("a.enc");
+        let key = EncryptionKey::generate();
+        encrypt_file(&original, &encrypted, &key).unwrap();
+
+        let other = EncryptionKey::generate();
+        assert!(decrypt_download(&encrypted, Some(&other)).is_err());
+        assert!(decrypt_download(&encrypted, None).is_err());
+        assert!(is_encrypted(&encrypted).unwrap());
+        assert!(!partial_path(&encrypted).exists());
+
+        // Dropping the last chunk must not pass for a shorter file
+        let mut truncated = fs::read(&encrypted).unwrap();
+        truncated.truncate(MAGIC.len() + NONCE_SIZE + CHUNK_SIZE + TAG_SIZE);
+        fs::write(&encrypted, truncated).unwrap();
+        assert!(decrypt_download(&encrypted, Some(&key)).is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = EncryptionKey::generate();
+        let parsed: EncryptionKey = key.to_base64().parse().unwrap();
+        assert_eq!(parsed.to_base64(), key.to_base64());
+        assert!("not base64!".parse::<EncryptionKey>().is_err());
+        assert!("c2hvcnQ=".parse::<EncryptionKey>().is_err());
+        assert_eq!(format!("{:?}", key), "EncryptionKey(****)");
+    }
+}
@@ -0,0 +1,524 @@
+//! `comfy-fs discover`: finding file servers on the local network, for when
+//! nobody remembers the address. Servers that announce themselves over mDNS or
+//! answer NetBIOS name queries are found by name; the rest by probing the ports
+//! SMB, SFTP, and FTP listen on.
+
+use crate::config::Protocol;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for each probe and for announcements unless told otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Probes run at once; most go unanswered and wait out the timeout
+const PROBE_THREADS: usize = 128;
+
+/// Networks bigger than this would take too long to probe address by address
+const MIN_PREFIX: u8 = 20;
+
+const MDNS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+const NETBIOS_PORT: u16 = 137;
+
+/// DNS record type of a pointer, as services are listed under
+const PTR: u16 = 12;
+/// The internet class, with the bit asking for the answer to come straight back
+const CLASS_IN_UNICAST: u16 = 0x8001;
+
+/// Each protocol with the name it's announced under over mDNS and its port,
+/// most preferred first
+const SERVICES: [(Protocol, &str, u16); 3] = [
+    (Protocol::Smb, "_smb._tcp.local", 445),
+    (Protocol::Sftp, "_sftp-ssh._tcp.local", 22),
+    (Protocol::Ftp, "_ftp._tcp.local", 21),
+];
+
+/// A block of IPv4 addresses to search, like `192.168.1.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Subnet {
+    fn new(address: Ipv4Addr, prefix: u8) -> Self {
+        let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+        Self {
+            network: Ipv4Addr::from(u32::from(address) & mask),
+            prefix,
+        }
+    }
+
+    /// The /24 around the address this machine would reach the internet from.
+    /// Finding it out sends nothing.
+    pub fn local() -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let address = socket
+            .connect((Ipv4Addr::new(192, 0, 2, 1), 9))
+            .and_then(|()| socket.local_addr())
+            .map(|address| address.ip());
+        match address {
+            Ok(IpAddr::V4(address)) if !address.is_loopback() && !address.is_unspecified() => {
+                Ok(Self::new(address, 24))
+            }
+            _ => Err(anyhow!(
+                "Could not tell which network this machine is on; give one with --subnet, \
+                 e.g. --subnet 192.168.1.0/24"
+            )),
+        }
+    }
+
+    /// The addresses hosts can have: all but the first and last, unless the
+    /// block is too small to spare them
+    pub fn hosts(&self) -> Vec<Ipv4Addr> {
+        let start = u32::from(self.network);
+        let size = 1u32 << (32 - u32::from(self.prefix));
+        let range = if size > 2 {
+            start + 1..start + size - 1
+        } else {
+            start..start + size
+        };
+        range.map(Ipv4Addr::from).collect()
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    /// `192.168.1.0/24`, or an address alone for the /24 around it
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix) = s.split_once('/').unwrap_or((s, "24"));
+        let address: Ipv4Addr = address
+            .parse()
+            .with_context(|| format!("Invalid subnet '{}' (expected e.g. 192.168.1.0/24)", s))?;
+        let prefix = prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| (MIN_PREFIX..=32).contains(prefix))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid subnet '{}' (expected a prefix from /{} to /32)",
+                    s,
+                    MIN_PREFIX
+                )
+            })?;
+        Ok(Self::new(address, prefix))
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// A machine that looks like a file server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Host {
+    pub address: Ipv4Addr,
+    /// What it calls itself over mDNS or NetBIOS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// What it can be reached with, most preferred first
+    pub protocols: Vec<Protocol>,
+}
+
+impl Host {
+    fn new(address: Ipv4Addr) -> Self {
+        Self {
+            address,
+            name: None,
+            protocols: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocols: Vec<String> = self.protocols.iter().map(ToString::to_string).collect();
+        match &self.name {
+            Some(name) => write!(f, "{} ({})", self.address, name)?,
+            None => write!(f, "{}", self.address)?,
+        }
+        write!(f, ": {}", protocols.join(", "))
+    }
+}
+
+/// File servers in `subnet` and any that announce themselves, in address
+/// order. Each way of looking waits up to `timeout` for answers.
+pub fn discover(subnet: &Subnet, timeout: Duration) -> Vec<Host> {
+    let (announced, open) = thread::scope(|scope| {
+        // A network without multicast just has nothing to announce
+        let announced = scope.spawn(|| query_mdns(timeout).unwrap_or_default());
+        let open = probe_ports(&subnet.hosts(), timeout);
+        (announced.join().unwrap_or_default(), open)
+    });
+
+    let mut hosts: BTreeMap<Ipv4Addr, Host> = BTreeMap::new();
+    for (address, protocol) in open {
+        add_protocol(
+            hosts.entry(address).or_insert_with(|| Host::new(address)),
+            protocol,
+        );
+    }
+    for (address, name, protocol) in announced {
+        let host = hosts.entry(address).or_insert_with(|| Host::new(address));
+        add_protocol(host, protocol);
+        host.name.get_or_insert(name);
+    }
+
+    let unnamed: Vec<Ipv4Addr> = hosts
+        .values()
+        .filter(|host| host.name.is_none())
+        .map(|host| host.address)
+        .collect();
+    for (address, name) in query_netbios(&unnamed, timeout).unwrap_or_default() {
+        if let Some(host) = hosts.get_mut(&address) {
+            host.name.get_or_insert(name);
+        }
+    }
+    hosts.into_values().collect()
+}
+
+fn add_protocol(host: &mut Host, protocol: Protocol) {
+    if !host.protocols.contains(&protocol) {
+        host.protocols.push(protocol);
+        host.protocols
+            .sort_by_key(|protocol| SERVICES.iter().position(|(known, ..)| known == protocol));
+    }
+}
+
+/// Search the network this machine is on and ask which server to use, for
+/// the setup wizard. `None` when nothing is found or nothing is chosen.
+pub fn search_and_choose() -> Result<Option<Host>> {
+    let subnet = match Subnet::local() {
+        Ok(subnet) => subnet,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(None);
+        }
+    };
+    println!("Searching {} for file servers...", subnet);
+    let hosts = discover(&subnet, DEFAULT_TIMEOUT);
+    if hosts.is_empty() {
+        println!("No file servers found; enter the address instead");
+        return Ok(None);
+    }
+    for line in table(&hosts) {
+        println!("{}", line);
+    }
+    Ok(choose(&hosts)?.cloned())
+}
+
+/// A numbered line for each of `hosts`, to [`choose`] from
+pub fn table(hosts: &[Host]) -> Vec<String> {
+    hosts
+        .iter()
+        .enumerate()
+        .map(|(i, host)| format!("  {}. {}", i + 1, host))
+        .collect()
+}
+
+/// Ask on the terminal which of `hosts` to use. `None` when the answer is
+/// left empty.
+pub fn choose(hosts: &[Host]) -> Result<Option<&Host>> {
+    loop {
+        print!("Use which server? [1-{}, Enter for none]: ", hosts.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=hosts.len()).contains(&n) => return Ok(Some(&hosts[n - 1])),
+            _ => println!("Enter a number from 1 to {}", hosts.len()),
+        }
+    }
+}
+
+/// Which of the ports in [`SERVICES`] take a connection on each of `addresses`
+fn probe_ports(addresses: &[Ipv4Addr], timeout: Duration) -> Vec<(Ipv4Addr, Protocol)> {
+    let probes = addresses.iter().flat_map(|&address| {
+        SERVICES
+            .iter()
+            .map(move |&(protocol, _, port)| (address, protocol, port))
+    });
+    let threads = PROBE_THREADS.min(addresses.len() * SERVICES.len());
+    let probes = Mutex::new(probes);
+    let open = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some((address, protocol, port)) =
+                    probes.lock().ok().and_then(|mut probes| probes.next())
+                {
+                    let target = SocketAddr::from((address, port));
+                    if TcpStream::connect_timeout(&target, timeout).is_ok() {
+                        if let Ok(mut open) = open.lock() {
+                            open.push((address, protocol));
+                        }
+                    }
+                }
+            });
+        }
+    });
+    open.into_inner().unwrap_or_default()
+}
+
+/// Hosts that announce a file service over mDNS, with the name they give it
+fn query_mdns(timeout: Duration) -> io::Result<Vec<(Ipv4Addr, String, Protocol)>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let services: Vec<&str> = SERVICES.iter().map(|&(_, service, _)| service).collect();
+    socket.send_to(&mdns_query(&services), MDNS)?;
+
+    let mut found = Vec::new();
+    for (address, packet) in receive(&socket, timeout)? {
+        for (service, instance) in parse_mdns_answers(&packet) {
+            let known = SERVICES
+                .iter()
+                .find(|(_, name, _)| name.eq_ignore_ascii_case(&service));
+            if let Some(&(protocol, ..)) = known {
+                found.push((address, instance, protocol));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The names `addresses` give in answer to a NetBIOS node status query
+fn query_netbios(addresses: &[Ipv4Addr], timeout: Duration) -> io::Result<Vec<(Ipv4Addr, String)>> {
+    if addresses.is_empty() {
+        return Ok(Vec::new());
+    }
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let query = node_status_query();
+    for &address in addresses {
+        // A host that can't be sent to just doesn't answer
+        let _ = socket.send_to(&query, (address, NETBIOS_PORT));
+    }
+    Ok(receive(&socket, timeout)?
+        .into_iter()
+        .filter_map(|(address, packet)| Some((address, parse_node_status(&packet)?)))
+        .collect())
+}
+
+/// Every packet that reaches `socket` within `timeout`, with who sent it
+fn receive(socket: &UdpSocket, timeout: Duration) -> io::Result<Vec<(Ipv4Addr, Vec<u8>)>> {
+    let deadline = Instant::now() + timeout;
+    let mut packets = Vec::new();
+    let mut buffer = [0; 9000];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(left))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, SocketAddr::V4(from))) => {
+                packets.push((*from.ip(), buffer[..length].to_vec()))
+            }
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break;
+            }
+            // Windows reports hosts with nothing on the port this way
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(packets)
+}
+
+/// A DNS query asking for the instances of each of `services`
+fn mdns_query(services: &[&str]) -> Vec<u8> {
+    let count = u16::try_from(services.len()).unwrap_or(u16::MAX);
+    let mut packet = vec![0, 0, 0, 0];
+    packet.extend(count.to_be_bytes());
+    packet.extend([0; 6]);
+    for service in services {
+        for label in service.split('.') {
+            packet.push(u8::try_from(label.len()).unwrap_or(u8::MAX));
+            packet.extend(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend(PTR.to_be_bytes());
+        packet.extend(CLASS_IN_UNICAST.to_be_bytes());
+    }
+    packet
+}
+
+/// The pointer records in an mDNS answer, as each service with the name of
+/// an instance of it, e.g. `_smb._tcp.local` and `NAS`
+fn parse_mdns_answers(packet: &[u8]) -> Vec<(String, String)> {
+    let count = |at: usize| -> Option<usize> {
+        Some(usize::from(u16::from_be_bytes([
+            *packet.get(at)?,
+            *packet.get(at + 1)?,
+        ])))
+    };
+    let mut found = Vec::new();
+    let Some(questions) = count(4) else {
+        return found;
+    };
+    let records = [6, 8, 10].iter().filter_map(|&at| count(at)).sum::<usize>();
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        let Some((_, end)) = read_name(packet, offset) else {
+            return found;
+        };
+        offset = end + 4;
+    }
+    for _ in 0..records {
+        let Some((service, at)) = read_name(packet, offset) else {
+            break;
+        };
+        let (Some(kind), Some(length)) = (count(at), count(at + 8)) else {
+            break;
+        };
+        let data = at + 10;
+        if kind == usize::from(PTR) {
+            let instance = read_name(packet, data).and_then(|(instance, _)| {
+                let name = instance.strip_suffix(&service)?.strip_suffix('.')?;
+                (!name.is_empty()).then(|| name.to_string())
+            });
+            if let Some(instance) = instance {
+                found.push((service, instance));
+            }
+        }
+        offset = data + length;
+    }
+    found
+}
+
+/// The domain name at `offset` in a DNS packet, following compression
+/// pointers, and where what comes after it starts
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounded, so pointers that loop give up rather than hang
+    for _ in 0..128 {
+        let length = *packet.get(offset)?;
+        if length == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+        if length & 0xC0 == 0xC0 {
+            end.get_or_insert(offset + 2);
+            offset = usize::from(u16::from_be_bytes([
+                length & 0x3F,
+                *packet.get(offset + 1)?,
+            ]));
+        } else {
+            let label = packet.get(offset + 1..offset + 1 + usize::from(length))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + usize::from(length);
+        }
+    }
+    None
+}
+
+/// A NetBIOS node status query, for every name the host has (`*`)
+fn node_status_query() -> Vec<u8> {
+    let mut packet = vec![0x13, 0x37, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    // The name is `*` padded to 16 bytes, each half-byte spelled as a letter
+    packet.push(32);
+    for byte in std::iter::once(b'*').chain([0; 15]) {
+        packet.extend([b'A' + (byte >> 4), b'A' + (byte & 0x0F)]);
+    }
+    packet.push(0);
+    // Node status, internet class
+    packet.extend([0, 0x21, 0, 1]);
+    packet
+}
+
+/// The machine's own name in a node status answer: the first unique name it
+/// has as a workstation or file server
+fn parse_node_status(packet: &[u8]) -> Option<String> {
+    let (_, at) = read_name(packet, 12)?;
+    // Past the type, class, time to live, and data length
+    let data = at + 10;
+    let count = usize::from(*packet.get(data)?);
+    packet
+        .get(data + 1..)?
+        .chunks_exact(18)
+        .take(count)
+        .find(|entry| entry[16] & 0x80 == 0 && matches!(entry[15], 0x00 | 0x20))
+        .map(|entry| String::from_utf8_lossy(&entry[..15]).trim_end().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet() {
+        let subnet: Subnet = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(subnet.to_string(), "192.168.1.0/24");
+        let hosts = subnet.hosts();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 168, 1, 254));
+
+        assert_eq!(
+            "10.0.0.9".parse::<Subnet>().unwrap().to_string(),
+            "10.0.0.0/24"
+        );
+        assert_eq!("10.0.0.9/32".parse::<Subnet>().unwrap().hosts().len(), 1);
+        assert!("10.0.0.0/8".parse::<Subnet>().is_err());
+        assert!("nas.local/24".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn test_parse_mdns_answers() {
+        // The query, answered with one pointer that names the service by a
+        // pointer back to the question
+        let mut packet = mdns_query(&["_smb._tcp.local"]);
+        packet[2] = 0x84;
+        packet[7] = 1;
+        packet.extend([0xC0, 12]);
+        packet.extend(PTR.to_be_bytes());
+        packet.extend([0, 1, 0, 0, 0x11, 0x94]);
+        let instance = [&[3][..], b"NAS", &[0xC0, 12]].concat();
+        packet.extend(u16::try_from(instance.len()).unwrap().to_be_bytes());
+        packet.extend(instance);
+
+        assert_eq!(
+            parse_mdns_answers(&packet),
+            [("_smb._tcp.local".to_string(), "NAS".to_string())]
+        );
+        assert!(parse_mdns_answers(&packet[..20]).is_empty());
+        // A pointer to itself
+        assert_eq!(read_name(&[0xC0, 0], 0), None);
+    }
+
+    #[test]
+    fn test_parse_node_status() {
+        let query = node_status_query();
+        assert_eq!(query.len(), 50);
+        assert_eq!(&query[13..17], b"CKAA");
+
+        let mut packet = query[..12].to_vec();
+        packet[2] = 0x84;
+        packet.extend(&query[12..46]);
+        packet.extend([0, 0x21, 0, 1, 0, 0, 0, 0, 0, 41, 2]);
+        // A group name first, then the machine's own
+        packet.extend(b"WORKGROUP      \x00\x84\x00");
+        packet.extend(b"FILESERVER     \x20\x04\x00");
+        assert_eq!(parse_node_status(&packet), Some("FILESERVER".to_string()));
+    }
+}
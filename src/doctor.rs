@@ -0,0 +1,351 @@
+//! `comfy-fs doctor`: finding out why the server can't be used, one check at a time
+
+use crate::client::address::host_name;
+use crate::client::FileServerClient;
+use crate::config::{Config, Protocol};
+use crate::connection::ConnectionManager;
+use serde::Serialize;
+use std::process::Command;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+type SharedClient = Arc<Mutex<Box<dyn FileServerClient>>>;
+
+/// SFTP always runs on the SSH port
+const SFTP_PORT: u16 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Not a problem for the configured protocol, but worth knowing
+    Warn,
+    Fail,
+    /// Not run because an earlier check failed
+    Skipped,
+}
+
+impl Status {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+            Self::Skipped => "skip",
+        }
+    }
+}
+
+/// The outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(name: &'static str, status: Status, detail: impl Into<String>, hint: &str) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn skipped(name: &'static str, reason: &str) -> Self {
+        Self {
+            name,
+            status: Status::Skipped,
+            detail: reason.to_string(),
+            hint: None,
+        }
+    }
+}
+
+/// Run every check against `config`, in order. Checks that need an earlier one
+/// to pass are skipped when it didn't.
+pub async fn diagnose(config: &Config) -> Vec<Check> {
+    let mut checks = vec![check_config(config)];
+    if checks[0].status == Status::Fail {
+        for name in ["dns", "ports", "smbclient", "login", "write"] {
+            checks.push(Check::skipped(name, "no server configured"));
+        }
+        return checks;
+    }
+
+    let reachable = if let Some(proxy) = &config.proxy {
+        // The proxy looks the name up and connects, so neither can be checked from here
+        let detail = format!("connections go through {}", proxy);
+        checks.push(Check::skipped("dns", &detail));
+        checks.push(Check::skipped("ports", &detail));
+        true
+    } else {
+        let host = host_name(&config.server_ip);
+        let resolved = check_dns(&host).await;
+        let reachable = resolved.status == Status::Ok;
+        checks.push(resolved);
+        checks.extend(check_ports(config, &host, reachable).await);
+        reachable
+    };
+
+    checks.push(check_smbclient(config.default_protocol == Protocol::Smb));
+
+    if !reachable {
+        checks.push(Check::skipped("login", "the server name didn't resolve"));
+        checks.push(Check::skipped("write", "the server name didn't resolve"));
+        return checks;
+    }
+    let (login, client) = check_login(config).await;
+    checks.push(login);
+    match client {
+        Some(client) if config.default_protocol != Protocol::Http => {
+            checks.push(check_write(client.lock().await.as_mut()).await);
+        }
+        Some(_) => checks.push(Check::skipped("write", "HTTP servers are read-only")),
+        None => checks.push(Check::skipped("write", "couldn't log in")),
+    }
+    checks
+}
+
+/// The checks as a table, each warning or failure followed by its hint
+pub fn table(checks: &[Check]) -> Vec<String> {
+    let mut lines = vec![format!("{:<10} {:<6} {}", "Check", "Status", "Detail")];
+    for check in checks {
+        lines.push(format!(
+            "{:<10} {:<6} {}",
+            check.name,
+            check.status.label(),
+            check.detail
+        ));
+        if let Some(hint) = &check.hint {
+            lines.push(format!("{:<17} → {}", "", hint));
+        }
+    }
+    lines
+}
+
+fn check_config(config: &Config) -> Check {
+    if !config.is_configured() {
+        return Check::problem(
+            "config",
+            Status::Fail,
+            "no server configured",
+            "Run `comfy-fs config` to set the server, username, and password",
+        );
+    }
+    if let Err(e) = config.concurrency() {
+        return Check::problem(
+            "config",
+            Status::Warn,
+            format!("{:#}", e),
+            "Fix it with `comfy-fs config --max-concurrent-transfers N`",
+        );
+    }
+    Check::ok(
+        "config",
+        format!(
+            "{}@{} over {}",
+            config.username, config.server_ip, config.default_protocol
+        ),
+    )
+}
+
+async fn check_dns(host: &str) -> Check {
+    match tokio::net::lookup_host((host, 0)).await {
+        Ok(addresses) => {
+            let addresses: Vec<String> = addresses.map(|a| a.ip().to_string()).collect();
+            Check::ok("dns", format!("{} is {}", host, addresses.join(", ")))
+        }
+        Err(e) => Check::problem(
+            "dns",
+            Status::Fail,
+            format!("can't resolve {}: {}", host, e),
+            "Check the server name, or use its IP address with `comfy-fs config --server`",
+        ),
+    }
+}
+
+/// Whether `port` accepts connections. Ports of protocols other than the
+/// configured one only matter for fallback, so they can only warn.
+async fn check_ports(config: &Config, host: &str, reachable: bool) -> Vec<Check> {
+    if config.default_protocol == Protocol::Http {
+        return vec![Check::skipped(
+            "ports",
+            "HTTP servers are checked by logging in",
+        )];
+    }
+    if !reachable {
+        return vec![Check::skipped("ports", "the server name didn't resolve")];
+    }
+    let timeout = config.timeouts().connect;
+    let mut checks = Vec::new();
+    for (name, protocol, port) in [
+        ("smb port", Protocol::Smb, config.smb_port),
+        ("ftp port", Protocol::Ftp, config.ftp_port),
+        ("sftp port", Protocol::Sftp, SFTP_PORT),
+    ] {
+        let needed = protocol == config.default_protocol;
+        checks.push(check_port(name, host, port, timeout, needed).await);
+    }
+    checks
+}
+
+async fn check_port(
+    name: &'static str,
+    host: &str,
+    port: u16,
+    timeout: std::time::Duration,
+    needed: bool,
+) -> Check {
+    let result = tokio::time::timeout(timeout, TcpStream::connect((host, port))).await;
+    let error = match result {
+        Ok(Ok(_)) => return Check::ok(name, format!("{} is open", port)),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => format!("no answer after {}s", timeout.as_secs()),
+    };
+    let (status, hint) = if needed {
+        (
+            Status::Fail,
+            "Check that the server is on and the service is running, and that no firewall \
+             or VPN is in the way",
+        )
+    } else {
+        (
+            Status::Warn,
+            "Only needed to fall back to this protocol; ignore it if the server doesn't offer it",
+        )
+    };
+    Check::problem(name, status, format!("{}: {}", port, error), hint)
+}
+
+fn check_smbclient(needed: bool) -> Check {
+    let output = Command::new("smbclient").arg("--version").output();
+    match output {
+        Ok(output) if output.status.success() => Check::ok(
+            "smbclient",
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ),
+        _ if cfg!(feature = "native-smb") => {
+            Check::ok("smbclient", "not installed, and not needed by this build")
+        }
+        _ => Check::problem(
+            "smbclient",
+            if needed { Status::Fail } else { Status::Warn },
+            "not installed",
+            "Install it (samba-client or smbclient package) to use SMB",
+        ),
+    }
+}
+
+async fn check_login(config: &Config) -> (Check, Option<SharedClient>) {
+    if config.password.is_none() {
+        let hint = "Save one with `comfy-fs config --password`, or pass --password-stdin";
+        return (
+            Check::problem("login", Status::Fail, "no password", hint),
+            None,
+        );
+    }
+
+    let mut manager = ConnectionManager::new(config.clone());
+    let client = match manager.connect().await {
+        Ok(client) => client,
+        Err(e) => {
+            let hint = "Check the username and password, and that the account may use \
+                        this protocol; `comfy-fs config` changes them";
+            return (
+                Check::problem("login", Status::Fail, format!("{:#}", e), hint),
+                None,
+            );
+        }
+    };
+    let protocol = manager.protocol().unwrap_or(config.default_protocol);
+    let detail = if protocol == config.default_protocol {
+        format!("logged in as {} over {}", config.username, protocol)
+    } else {
+        format!(
+            "logged in over {} after {} failed",
+            protocol, config.default_protocol
+        )
+    };
+    (Check::ok("login", detail), Some(client))
+}
+
+/// Upload a small file to the share root and delete it again
+async fn check_write(client: &mut dyn FileServerClient) -> Check {
+    let name = format!(".comfy-fs-doctor-{}", std::process::id());
+    let local = std::env::temp_dir().join(&name);
+    let remote = format!("/{}", name);
+    if let Err(e) = tokio::fs::write(&local, b"comfy-fs doctor\n").await {
+        return Check::problem(
+            "write",
+            Status::Warn,
+            format!("couldn't make a local test file: {}", e),
+            "Check that the temp directory is writable",
+        );
+    }
+
+    let uploaded = client.upload_file(&local, &remote).await;
+    let _ = tokio::fs::remove_file(&local).await;
+    if let Err(e) = uploaded {
+        return Check::problem(
+            "write",
+            Status::Fail,
+            format!("can't write to /: {:#}", e),
+            "Ask the server admin for write access to the share",
+        );
+    }
+    match client.delete_file(&remote).await {
+        Ok(()) => Check::ok("write", "created and deleted a file in /"),
+        Err(e) => Check::problem(
+            "write",
+            Status::Warn,
+            format!("wrote {} but couldn't delete it: {:#}", remote, e),
+            "Delete the test file by hand; the account may lack delete permission",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_diagnose_unconfigured() {
+        let checks = diagnose(&Config::default()).await;
+
+        assert_eq!(checks[0].status, Status::Fail);
+        assert!(checks[1..].iter().all(|c| c.status == Status::Skipped));
+        let lines = table(&checks);
+        assert_eq!(lines[0], "Check      Status Detail");
+        assert_eq!(lines[1], "config     FAIL   no server configured");
+        assert!(lines[2].contains("→ Run `comfy-fs config`"));
+    }
+
+    #[tokio::test]
+    async fn test_write_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = crate::client::local::LocalClient::new(dir.path().to_path_buf());
+
+        let check = check_write(&mut client).await;
+        assert_eq!(check.status, Status::Ok);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let mut client = crate::client::local::LocalClient::new(dir.path().join("missing"));
+        assert_eq!(check_write(&mut client).await.status, Status::Fail);
+    }
+}
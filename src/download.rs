@@ -1,83 +1,314 @@
-use crate::client::FileServerClient;
+use crate::cancel::PartialFile;
+use crate::compress::decompress_download;
+use crate::crypt::{decrypt_download, EncryptionKey};
+use crate::connection::ClientPool;
+use crate::history::{History, Operation};
+use crate::retry::RetryPolicy;
+use crate::safetensors::{check_download, is_safetensors};
+use crate::transfer::{EventSender, TransferEvent};
 use anyhow::Result;
+use chrono::{DateTime, Local};
 use futures::stream::{self, StreamExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use tracing::info;
+
+/// What to do when a download would replace an existing local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Ask before replacing each file
+    Prompt,
+    /// Always replace
+    Overwrite,
+    /// Never replace
+    SkipExisting,
+    /// Replace only when the remote file was modified after the local one
+    NewerOnly,
+}
+
+/// Answer to an "overwrite?" prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptAnswer {
+    Yes,
+    No,
+    /// Yes, and stop asking for the rest of the batch
+    All,
+}
+
+/// Applies an [`OverwritePolicy`] across a batch of downloads, remembering an
+/// "all" answer so the user is only asked until they say so.
+pub struct OverwriteGuard {
+    policy: OverwritePolicy,
+    overwrite_all: bool,
+}
+
+impl OverwriteGuard {
+    pub fn new(policy: OverwritePolicy) -> Self {
+        Self {
+            policy,
+            overwrite_all: false,
+        }
+    }
+
+    /// Whether a remote file last modified at `remote_modified` may be downloaded to
+    /// `local_path`. `ask` is only called when the policy is to prompt.
+    pub fn allow<F>(
+        &mut self,
+        local_path: &Path,
+        remote_modified: Option<DateTime<Local>>,
+        ask: F,
+    ) -> Result<bool>
+    where
+        F: FnOnce(&Path) -> Result<PromptAnswer>,
+    {
+        if !local_path.exists() || self.overwrite_all {
+            return Ok(true);
+        }
+
+        match self.policy {
+            OverwritePolicy::Overwrite => Ok(true),
+            OverwritePolicy::SkipExisting => Ok(false),
+            OverwritePolicy::NewerOnly => {
+                let local_modified = std::fs::metadata(local_path)?.modified()?;
+                // Without a remote timestamp there's nothing to compare, so download
+                Ok(remote_modified
+                    .is_none_or(|remote| remote > DateTime::<Local>::from(local_modified)))
+            }
+            OverwritePolicy::Prompt => match ask(local_path)? {
+                PromptAnswer::Yes => Ok(true),
+                PromptAnswer::No => Ok(false),
+                PromptAnswer::All => {
+                    self.overwrite_all = true;
+                    Ok(true)
+                }
+            },
+        }
+    }
+}
+
+/// Ask on the terminal whether to overwrite `local_path`. Anything but y/a means no.
+pub fn prompt_overwrite(local_path: &Path) -> Result<PromptAnswer> {
+    print!("Overwrite {:?}? [y/N/a] ", local_path);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => PromptAnswer::Yes,
+        "a" | "all" => PromptAnswer::All,
+        _ => PromptAnswer::No,
+    })
+}
+
+/// Undo what `upload` did to a downloaded file: decrypt it if it's encrypted,
+/// then with `decompress`, decompress it if its name says it's compressed.
+/// With `validate`, a `.safetensors` file that results is checked, and removed
+/// if it's damaged. Returns where the file ended up.
+pub fn finish_download(
+    path: PathBuf,
+    key: Option<&EncryptionKey>,
+    decompress: bool,
+    validate: bool,
+) -> Result<PathBuf> {
+    decrypt_download(&path, key)?;
+    let path = if decompress {
+        decompress_download(&path)?.unwrap_or(path)
+    } else {
+        path
+    };
+    if validate && is_safetensors(&path) {
+        check_download(&path)?;
+    }
+    Ok(path)
+}
 
 pub struct ParallelDownloader {
-    client: Arc<Mutex<Box<dyn FileServerClient>>>,
+    pool: ClientPool,
     max_concurrent: usize,
+    retry_policy: RetryPolicy,
+    recovered: AtomicUsize,
+    history: Option<History>,
+    /// Replace `.zst` and `.gz` downloads with what they decompress to
+    decompress: bool,
+    /// Decrypts files uploaded with `--encrypt`
+    key: Option<EncryptionKey>,
+    /// Check `.safetensors` files once they're downloaded
+    validate_safetensors: bool,
+    events: EventSender,
 }
 
 impl ParallelDownloader {
-    pub fn new(client: Arc<Mutex<Box<dyn FileServerClient>>>, max_concurrent: usize) -> Self {
+    /// Downloads only overlap when `client` is a pool that can open more clients
+    pub fn new(client: impl Into<ClientPool>, max_concurrent: usize) -> Self {
         Self {
-            client,
+            pool: client.into(),
             max_concurrent,
+            retry_policy: RetryPolicy::default(),
+            recovered: AtomicUsize::new(0),
+            history: None,
+            decompress: false,
+            key: None,
+            validate_safetensors: false,
+            events: EventSender::default(),
         }
     }
 
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Record each file's outcome, after its retries, in `history`
+    #[must_use]
+    pub fn with_history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Decompress downloaded files whose names say they're compressed
+    #[must_use]
+    pub const fn with_decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
+
+    /// Decrypt downloaded files that were encrypted with `key`. Without one, an
+    /// encrypted file fails, and is kept as it was downloaded.
+    #[must_use]
+    pub fn with_encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Remove downloaded `.safetensors` files that are cut short or damaged,
+    /// failing them, rather than leave them for ComfyUI to load
+    #[must_use]
+    pub const fn with_safetensors_validation(mut self, validate: bool) -> Self {
+        self.validate_safetensors = validate;
+        self
+    }
+
+    /// Send what happens to each file, by its index in the list given to
+    /// [`download_files`](Self::download_files), to `events`
+    #[must_use]
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Number of files that only succeeded after one or more retries.
+    pub fn recovered_count(&self) -> usize {
+        self.recovered.load(Ordering::Relaxed)
+    }
+
+    /// Download `files`, several at once, returning how each went in the order given
     pub async fn download_files(
         &self,
         files: Vec<(String, PathBuf)>, // (remote_path, local_path)
     ) -> Result<Vec<Result<()>>> {
-        let multi_progress = MultiProgress::new();
-
-        let results = stream::iter(files)
-            .map(|(remote_path, local_path)| {
-                let client = self.client.clone();
-                let pb = multi_progress.add(ProgressBar::new(0));
-
-                async move {
-                    self.download_single_file(client, remote_path, local_path, pb)
-                        .await
-                }
+        let mut results: Vec<_> = stream::iter(files.into_iter().enumerate())
+            .map(|(index, (remote_path, local_path))| async move {
+                let result = self.download_single_file(index, remote_path, local_path).await;
+                self.events.send(match &result {
+                    Ok(retries) => TransferEvent::Finished {
+                        index,
+                        retries: *retries,
+                    },
+                    Err(e) => TransferEvent::Failed {
+                        index,
+                        error: format!("{:#}", e),
+                    },
+                });
+                (index, result.map(|_| ()))
             })
             .buffer_unordered(self.max_concurrent)
-            .collect::<Vec<_>>()
+            .collect()
             .await;
 
-        Ok(results)
+        // Back in the order of `files`, whichever finished first
+        results.sort_by_key(|(index, _)| *index);
+        Ok(results.into_iter().map(|(_, result)| result).collect())
     }
 
+    /// Download one file, returning how many retries it took
     async fn download_single_file(
         &self,
-        client: Arc<Mutex<Box<dyn FileServerClient>>>,
+        index: usize,
         remote_path: String,
         local_path: PathBuf,
-        progress_bar: ProgressBar,
-    ) -> Result<()> {
-        // Set up progress bar style
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")?
-                .progress_chars("#>-"),
-        );
+    ) -> Result<u32> {
+        let size = AtomicU64::new(0);
+        let started = Instant::now();
+        let result = self
+            .retry_policy
+            .run(
+                // A retry starts the file over
+                || self.transfer(index, &remote_path, &local_path, &size),
+                |attempt, e| {
+                    self.events.send(TransferEvent::Retrying {
+                        index,
+                        attempt,
+                        error: format!("{:#}", e),
+                    });
+                },
+            )
+            .await;
+        if let Some(history) = &self.history {
+            history.record(
+                Operation::Download,
+                &remote_path,
+                Some(&local_path),
+                size.load(Ordering::Relaxed),
+                started.elapsed(),
+                &result,
+            );
+        }
+        let ((), retries) = result?;
 
-        let filename = remote_path.split('/').last().unwrap_or("file");
-        progress_bar.set_message(format!("Downloading {}", filename));
+        if retries > 0 {
+            self.recovered.fetch_add(1, Ordering::Relaxed);
+        }
+        let (key, decompress, validate) =
+            (self.key.clone(), self.decompress, self.validate_safetensors);
+        tokio::task::spawn_blocking(move || {
+            finish_download(local_path, key.as_ref(), decompress, validate)
+        })
+        .await??;
+        Ok(retries)
+    }
 
-        // Get file size first
-        let mut client_guard = client.lock().await;
-        let file_size = client_guard.get_file_size(&remote_path).await?;
-        drop(client_guard);
+    async fn transfer(
+        &self,
+        index: usize,
+        remote_path: &str,
+        local_path: &Path,
+        size: &AtomicU64,
+    ) -> Result<()> {
+        let mut client = self.pool.acquire().await;
 
-        progress_bar.set_length(file_size);
+        // Get file size first
+        let file_size = client.get_file_size(remote_path).await?;
+        size.store(file_size, Ordering::Relaxed);
+        self.events.send(TransferEvent::Started {
+            index,
+            size: file_size,
+        });
 
         // Create parent directory if needed
         if let Some(parent) = local_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        // Download the file
-        let mut client_guard = client.lock().await;
-        client_guard
-            .download_file(&remote_path, &local_path)
+        let partial = PartialFile::new(local_path);
+        let started = Instant::now();
+        client
+            .download_file_with_progress(remote_path, local_path, self.events.progress(index))
             .await?;
-
-        progress_bar.finish_with_message(format!("✓ {}", filename));
+        partial.keep();
+        info!(bytes = file_size, elapsed = ?started.elapsed(), "downloaded {}", remote_path);
         Ok(())
     }
 
@@ -88,9 +319,7 @@ impl ParallelDownloader {
         local_dir: &Path,
     ) -> Result<Vec<Result<()>>> {
         // List all files in the directory
-        let mut client_guard = self.client.lock().await;
-        let files = client_guard.list_files(remote_dir).await?;
-        drop(client_guard);
+        let files = self.pool.acquire().await.list_files(remote_dir).await?;
 
         // Filter out directories and prepare download list
         let download_list: Vec<(String, PathBuf)> = files
@@ -113,6 +342,8 @@ mod tests {
     use async_trait::async_trait;
     use chrono::Local;
     use mockall::mock;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
 
     mock! {
         TestClient {}
@@ -126,10 +357,63 @@ mod tests {
             async fn upload_file(&mut self, local_path: &Path, remote_path: &str) -> Result<()>;
             async fn create_directory(&mut self, path: &str) -> Result<()>;
             async fn delete_file(&mut self, path: &str) -> Result<()>;
+            async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()>;
+            async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
             async fn get_file_size(&mut self, path: &str) -> Result<u64>;
         }
     }
 
+    #[test]
+    fn test_overwrite_guard_missing_file_is_always_allowed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let missing = temp_dir.path().join("missing.txt");
+
+        let mut guard = OverwriteGuard::new(OverwritePolicy::SkipExisting);
+        assert!(guard
+            .allow(&missing, None, |_| panic!("should not ask"))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_overwrite_guard_policies() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let existing = temp_dir.path().join("existing.txt");
+        std::fs::write(&existing, b"local").unwrap();
+        let ask = |_: &Path| -> Result<PromptAnswer> { panic!("should not ask") };
+
+        assert!(OverwriteGuard::new(OverwritePolicy::Overwrite)
+            .allow(&existing, None, ask)
+            .unwrap());
+        assert!(!OverwriteGuard::new(OverwritePolicy::SkipExisting)
+            .allow(&existing, None, ask)
+            .unwrap());
+
+        let mut newer_only = OverwriteGuard::new(OverwritePolicy::NewerOnly);
+        let older = Local::now() - chrono::Duration::days(1);
+        let newer = Local::now() + chrono::Duration::days(1);
+        assert!(!newer_only.allow(&existing, Some(older), ask).unwrap());
+        assert!(newer_only.allow(&existing, Some(newer), ask).unwrap());
+    }
+
+    #[test]
+    fn test_overwrite_guard_prompt_all() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let existing = temp_dir.path().join("existing.txt");
+        std::fs::write(&existing, b"local").unwrap();
+
+        let mut guard = OverwriteGuard::new(OverwritePolicy::Prompt);
+        assert!(!guard
+            .allow(&existing, None, |_| Ok(PromptAnswer::No))
+            .unwrap());
+        assert!(guard
+            .allow(&existing, None, |_| Ok(PromptAnswer::All))
+            .unwrap());
+        // "All" sticks for the rest of the batch
+        assert!(guard
+            .allow(&existing, None, |_| panic!("should not ask again"))
+            .unwrap());
+    }
+
     #[tokio::test]
     async fn test_parallel_downloader_creation() {
         let mut mock_client = MockTestClient::new();
@@ -142,6 +426,42 @@ mod tests {
         assert_eq!(downloader.max_concurrent, 4);
     }
 
+    #[tokio::test]
+    async fn test_download_retries_transient_failures() {
+        let mut mock_client = MockTestClient::new();
+        mock_client.expect_get_file_size().returning(|_| Ok(10));
+
+        let mut seq = mockall::Sequence::new();
+        mock_client
+            .expect_download_file()
+            .times(2)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Err(anyhow::anyhow!("connection reset")));
+        mock_client
+            .expect_download_file()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, local_path| Ok(std::fs::write(local_path, b"0123456789")?));
+
+        let client: Box<dyn FileServerClient> = Box::new(mock_client);
+        let client = Arc::new(Mutex::new(client));
+
+        let downloader = ParallelDownloader::new(client, 1).with_retry_policy(RetryPolicy {
+            max_retries: 3,
+            base_delay: std::time::Duration::ZERO,
+            max_delay: std::time::Duration::ZERO,
+        });
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let results = downloader
+            .download_files(vec![("/a.bin".to_string(), temp_dir.path().join("a.bin"))])
+            .await
+            .unwrap();
+
+        assert!(results[0].is_ok());
+        assert_eq!(downloader.recovered_count(), 1);
+    }
+
     #[tokio::test]
     async fn test_download_directory_filters_directories() {
         let mut mock_client = MockTestClient::new();
@@ -158,6 +478,7 @@ mod tests {
                         size: 100,
                         modified: Local::now(),
                         is_dir: false,
+                        permissions: None,
                     },
                     RemoteFile {
                         name: "subdir".to_string(),
@@ -165,6 +486,7 @@ mod tests {
                         size: 0,
                         modified: Local::now(),
                         is_dir: true,
+                        permissions: None,
                     },
                     RemoteFile {
                         name: "file2.pdf".to_string(),
@@ -172,6 +494,7 @@ mod tests {
                         size: 200,
                         modified: Local::now(),
                         is_dir: false,
+                        permissions: None,
                     },
                 ])
             });
@@ -0,0 +1,111 @@
+//! Which local paths `sync` and `upload` leave alone, from `.comfyignore` and
+//! `--exclude`/`--include`
+
+use anyhow::{anyhow, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// The file in a sync's local root that lists paths to leave out, in gitignore syntax
+pub const IGNORE_FILE: &str = ".comfyignore";
+
+/// Gitignore-style rules matched against paths below a root
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    rules: Gitignore,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            rules: Gitignore::empty(),
+        }
+    }
+}
+
+impl PathFilter {
+    /// The rules in `root`'s `.comfyignore`, if it has one, then each
+    /// `exclude` pattern, then each `include` pattern as a `!pattern` line so
+    /// it wins over everything before it
+    pub fn new(root: &Path, exclude: &[String], include: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        let ignore_file = root.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(anyhow!("Invalid {}: {}", ignore_file.display(), e));
+            }
+        }
+        for pattern in exclude {
+            add_line(&mut builder, pattern)?;
+        }
+        for pattern in include {
+            add_line(&mut builder, &format!("!{}", pattern))?;
+        }
+        let rules = builder
+            .build()
+            .map_err(|e| anyhow!("Invalid ignore rules: {}", e))?;
+        Ok(Self { rules })
+    }
+
+    /// Whether `relative`, a `/`-separated path below the root, or any directory
+    /// it's in is left out
+    pub fn is_excluded(&self, relative: &str, is_dir: bool) -> bool {
+        !self.rules.is_empty()
+            && self
+                .rules
+                .matched_path_or_any_parents(relative, is_dir)
+                .is_ignore()
+    }
+}
+
+fn add_line(builder: &mut GitignoreBuilder, line: &str) -> Result<()> {
+    builder
+        .add_line(None, line)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Invalid pattern '{}': {}", line, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(ignore_file: Option<&str>, exclude: &[&str], include: &[&str]) -> PathFilter {
+        let dir = tempfile::tempdir().unwrap();
+        if let Some(content) = ignore_file {
+            std::fs::write(dir.path().join(IGNORE_FILE), content).unwrap();
+        }
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(ToString::to_string).collect::<Vec<_>>();
+        PathFilter::new(dir.path(), &strings(exclude), &strings(include)).unwrap()
+    }
+
+    #[test]
+    fn test_ignore_file() {
+        let filter = filter(
+            Some("# caches\n.git/\n__pycache__/\n*.tmp\n/scratch\n"),
+            &[],
+            &[],
+        );
+
+        assert!(filter.is_excluded(".git", true));
+        assert!(filter.is_excluded(".git/config", false));
+        assert!(filter.is_excluded("nodes/__pycache__/a.pyc", false));
+        assert!(filter.is_excluded("models/part.tmp", false));
+        assert!(filter.is_excluded("scratch", false));
+        assert!(!filter.is_excluded("models/scratch", false));
+        assert!(!filter.is_excluded("models/a.safetensors", false));
+        assert!(!filter.is_excluded(IGNORE_FILE, false));
+    }
+
+    #[test]
+    fn test_exclude_and_include() {
+        let filter = filter(Some("*.log\n"), &["*.tmp"], &["keep.log"]);
+
+        assert!(filter.is_excluded("a.tmp", false));
+        assert!(filter.is_excluded("run.log", false));
+        assert!(!filter.is_excluded("logs/keep.log", false));
+
+        assert!(!PathFilter::default().is_excluded("a.tmp", false));
+        let dir = tempfile::tempdir().unwrap();
+        assert!(PathFilter::new(dir.path(), &["a{b".to_string()], &[]).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! The status the program exits with, so scripts can tell failures apart
+
+use crate::client::ClientError;
+use crate::output::TransferSummary;
+use anyhow::Result;
+use std::fmt;
+
+/// Exit statuses other than 0 for success. The README lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Any failure not covered below
+    Failure = 1,
+    /// No server is configured, or the config file or a flag is invalid
+    Config = 2,
+    /// The server refused the username or password
+    AuthenticationFailed = 3,
+    /// A path isn't on the server or the local disk
+    NotFound = 4,
+    /// A batch ran to the end with some of its files or paths failed
+    Partial = 5,
+    /// The server couldn't be reached or stopped answering
+    Connection = 6,
+    /// The server or local disk refused permission
+    PermissionDenied = 7,
+    /// Ctrl-C was pressed
+    Interrupted = 130,
+}
+
+impl ExitStatus {
+    pub const fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The status to exit with after `error`: the one it was made with if it's
+    /// an [`Exit`], else by the kind of [`ClientError`] it is
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(exit) = error.chain().find_map(|cause| cause.downcast_ref::<Exit>()) {
+            return exit.status;
+        }
+        match ClientError::classify(error) {
+            ClientError::NotFound(_) => Self::NotFound,
+            ClientError::PermissionDenied(_) => Self::PermissionDenied,
+            ClientError::AuthenticationFailed(_) => Self::AuthenticationFailed,
+            ClientError::ConnectionLost(_) | ClientError::Timeout(_) => Self::Connection,
+            ClientError::Unsupported(_) | ClientError::Protocol(_) => Self::Failure,
+        }
+    }
+}
+
+/// An error that ends the program with a particular status
+#[derive(Debug)]
+pub struct Exit {
+    pub status: ExitStatus,
+    message: String,
+}
+
+impl Exit {
+    pub fn new(status: ExitStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// `error` as a problem with the configuration, keeping its message
+pub fn config(error: anyhow::Error) -> anyhow::Error {
+    Exit::new(ExitStatus::Config, format!("{:#}", error)).into()
+}
+
+/// The error for a batch that finished with some of it failed
+pub fn partial(message: impl Into<String>) -> anyhow::Error {
+    Exit::new(ExitStatus::Partial, message).into()
+}
+
+/// Fail with [`ExitStatus::Partial`] if any transfer in `summaries` failed, once
+/// they've been reported
+pub fn check_transfers(summaries: &[TransferSummary]) -> Result<()> {
+    let failed: usize = summaries.iter().map(|s| s.failed).sum();
+    if failed == 0 {
+        return Ok(());
+    }
+    let total: usize = summaries.iter().map(|s| s.succeeded + s.failed).sum();
+    Err(partial(format!("{} of {} transfers failed", failed, total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_status_of() {
+        let missing: Result<()> = Err(ClientError::not_found("/models/a.safetensors").into());
+        let missing = missing.context("Download failed").unwrap_err();
+        assert_eq!(ExitStatus::of(&missing), ExitStatus::NotFound);
+
+        let refused = anyhow::Error::from(ClientError::AuthenticationFailed(
+            "SFTP authentication failed for alice".to_string(),
+        ));
+        assert_eq!(ExitStatus::of(&refused).code(), 3);
+
+        let bad_config = config(anyhow!("Unknown profile 'studio'"));
+        assert_eq!(bad_config.to_string(), "Unknown profile 'studio'");
+        assert_eq!(ExitStatus::of(&bad_config), ExitStatus::Config);
+        assert_eq!(ExitStatus::of(&anyhow!("oops")), ExitStatus::Failure);
+    }
+
+    #[test]
+    fn test_check_transfers() {
+        let summary = |succeeded, failed| TransferSummary {
+            operation: "download",
+            succeeded,
+            skipped: 1,
+            failed,
+            recovered: 0,
+            bytes: 0,
+            elapsed_secs: 0.0,
+        };
+        check_transfers(&[summary(3, 0)]).unwrap();
+        let error = check_transfers(&[summary(3, 1), summary(0, 1)]).unwrap_err();
+        assert_eq!(error.to_string(), "2 of 5 transfers failed");
+        assert_eq!(ExitStatus::of(&error), ExitStatus::Partial);
+    }
+}
@@ -0,0 +1,144 @@
+//! Checksums of remote files, computed while streaming them
+
+use crate::cat;
+use crate::client::FileServerClient;
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => Err(anyhow!("Invalid hash algorithm '{}' (expected sha256 or blake3)", s)),
+        }
+    }
+}
+
+/// Hex digest of the file at `path`, read a chunk at a time without saving it
+pub async fn hash_remote(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            cat::copy_to(client, path, &mut hasher).await?;
+            Ok(to_hex(&hasher.finalize()))
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            cat::copy_to(client, path, &mut hasher).await?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+    }
+}
+
+/// Hex digest of a local file
+pub fn hash_local(path: &Path, algorithm: HashAlgorithm) -> Result<String> {
+    let mut file = File::open(path)?;
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(to_hex(&hasher.finalize()))
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+    }
+}
+
+/// Whether the local file at `local` has the same content as the remote one
+/// at `remote`, by checksum
+pub async fn same_content(
+    client: &mut dyn FileServerClient,
+    local: &Path,
+    remote: &str,
+) -> Result<bool> {
+    // BLAKE3 is the faster of the two, and the digests never leave here
+    let algorithm = HashAlgorithm::Blake3;
+    let path = local.to_path_buf();
+    let local_digest = tokio::task::spawn_blocking(move || hash_local(&path, algorithm)).await??;
+    Ok(hash_remote(client, remote, algorithm).await? == local_digest)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+
+    #[tokio::test]
+    async fn test_hash_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), b"hello\n").unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        assert_eq!(
+            hash_remote(&mut client, "/hello.txt", HashAlgorithm::Sha256).await.unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        assert_eq!(
+            hash_remote(&mut client, "/hello.txt", HashAlgorithm::Blake3).await.unwrap(),
+            blake3::hash(b"hello\n").to_hex().to_string()
+        );
+        assert_eq!(
+            hash_local(&dir.path().join("hello.txt"), HashAlgorithm::Sha256).unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_same_content() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.bin"), b"same").unwrap();
+        std::fs::write(dir.path().join("b.bin"), b"diff").unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        let local = dir.path().join("a.bin");
+        assert!(same_content(&mut client, &local, "/a.bin").await.unwrap());
+        assert!(!same_content(&mut client, &local, "/b.bin").await.unwrap());
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!("SHA-256".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Sha256);
+        assert_eq!("blake3".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Blake3);
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! A local record of every upload, download, and deletion, for `comfy-fs history`
+
+use crate::config::{data_dir, Config};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Upload,
+    Download,
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+            Self::Delete => "delete",
+        })
+    }
+}
+
+/// One line of the history file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub time: DateTime<Local>,
+    pub operation: Operation,
+    pub remote_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<PathBuf>,
+    /// Bytes moved, or deleted for a file
+    pub size: u64,
+    pub duration_ms: u64,
+    /// Why it failed; `None` when it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub server: String,
+    pub username: String,
+}
+
+impl HistoryEntry {
+    pub const fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Where the history is kept, e.g. `~/.local/share/comfy-fs/history.jsonl` on Linux
+pub fn history_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("history.jsonl"))
+}
+
+/// Appends what one run does to the history file, stamped with the server,
+/// user, and profile it was done as
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    profile: Option<String>,
+    server: String,
+    username: String,
+}
+
+impl History {
+    pub fn new(path: PathBuf, config: &Config, profile: Option<&str>) -> Self {
+        Self {
+            path,
+            profile: profile.map(str::to_string),
+            server: config.server_ip.clone(),
+            username: config.username.clone(),
+        }
+    }
+
+    /// The history in the data directory, if there is one
+    pub fn for_config(config: &Config, profile: Option<&str>) -> Option<Self> {
+        history_path().map(|path| Self::new(path, config, profile))
+    }
+
+    /// Add an entry. Failing to write it is logged rather than returned, so it
+    /// never fails the transfer it describes.
+    pub fn record<T>(
+        &self,
+        operation: Operation,
+        remote_path: &str,
+        local_path: Option<&Path>,
+        size: u64,
+        duration: Duration,
+        result: &Result<T>,
+    ) {
+        let entry = HistoryEntry {
+            time: Local::now(),
+            operation,
+            remote_path: remote_path.to_string(),
+            local_path: local_path.map(Path::to_path_buf),
+            size,
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            profile: self.profile.clone(),
+            server: self.server.clone(),
+            username: self.username.clone(),
+        };
+        if let Err(e) = self.append(&entry) {
+            warn!(
+                "Could not record {} of {} in {}: {:#}",
+                operation,
+                remote_path,
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // One write per line, so runs recording at the same time don't interleave
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Every entry in the history file at `path`, oldest first. Lines that can't be
+/// read, such as one cut short by a crash, are skipped.
+pub fn read_history(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Could not read {}: {}", path.display(), e)),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Which entries `history` shows
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub since: Option<DateTime<Local>>,
+    pub failed_only: bool,
+    pub profile: Option<String>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.since.is_none_or(|since| entry.time >= since)
+            && !(self.failed_only && entry.succeeded())
+            && self
+                .profile
+                .as_ref()
+                .is_none_or(|profile| entry.profile.as_ref() == Some(profile))
+    }
+}
+
+/// Totals for `history --stats`, for one operation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OperationStats {
+    pub operation: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Bytes of the entries that succeeded
+    pub bytes: u64,
+}
+
+/// Totals per operation, plus one for all of them
+pub fn history_stats(entries: &[HistoryEntry]) -> Vec<OperationStats> {
+    let mut stats: Vec<OperationStats> =
+        [Operation::Upload, Operation::Download, Operation::Delete]
+            .into_iter()
+            .map(|operation| OperationStats {
+                operation: operation.to_string(),
+                ..OperationStats::default()
+            })
+            .collect();
+    let mut total = OperationStats {
+        operation: "total".to_string(),
+        ..OperationStats::default()
+    };
+    for entry in entries {
+        let index = match entry.operation {
+            Operation::Upload => 0,
+            Operation::Download => 1,
+            Operation::Delete => 2,
+        };
+        for counts in [&mut stats[index], &mut total] {
+            if entry.succeeded() {
+                counts.succeeded += 1;
+                counts.bytes += entry.size;
+            } else {
+                counts.failed += 1;
+            }
+        }
+    }
+    stats.push(total);
+    stats
+}
+
+/// The entries as a table under a header, one line each
+pub fn history_table(entries: &[HistoryEntry]) -> Vec<String> {
+    let row = |cells: [&str; 6]| {
+        format!(
+            "{:<19}  {:<8}  {:>10}  {:>8}  {:<16}  {}",
+            cells[0], cells[1], cells[2], cells[3], cells[4], cells[5]
+        )
+    };
+    let mut lines = vec![row(["Time", "Action", "Size", "Took", "By", "Path"])];
+    for entry in entries {
+        let time = entry.time.format("%Y-%m-%d %H:%M:%S").to_string();
+        let size = human_bytes::human_bytes(entry.size as f64);
+        let took = format!("{:.1}s", entry.duration_ms as f64 / 1000.0);
+        let by = match &entry.profile {
+            Some(profile) => format!("{} ({})", entry.username, profile),
+            None => entry.username.clone(),
+        };
+        let mut line = row([
+            &time,
+            &entry.operation.to_string(),
+            &size,
+            &took,
+            &by,
+            &entry.remote_path,
+        ]);
+        if let Some(error) = &entry.error {
+            line.push_str(&format!("  FAILED: {}", error));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// The totals as a table under a header, one line per operation
+pub fn stats_table(stats: &[OperationStats]) -> Vec<String> {
+    let row = |cells: [&str; 4]| {
+        format!(
+            "{:<10} {:>9} {:>6} {:>10}",
+            cells[0], cells[1], cells[2], cells[3]
+        )
+    };
+    let mut lines = vec![row(["Operation", "Succeeded", "Failed", "Bytes"])];
+    for counts in stats {
+        lines.push(row([
+            &counts.operation,
+            &counts.succeeded.to_string(),
+            &counts.failed.to_string(),
+            &human_bytes::human_bytes(counts.bytes as f64),
+        ]));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/history.jsonl");
+        let config = Config {
+            server_ip: "files.local".to_string(),
+            username: "alice".to_string(),
+            ..Config::default()
+        };
+        let history = History::new(path.clone(), &config, Some("studio"));
+
+        let second = Duration::from_secs(1);
+        history.record(
+            Operation::Upload,
+            "/models/a.safetensors",
+            None,
+            100,
+            second,
+            &Ok(()),
+        );
+        history.record::<()>(
+            Operation::Download,
+            "/output/b.png",
+            Some(Path::new("b.png")),
+            20,
+            second,
+            &Err(anyhow!("timed out")),
+        );
+        history.record(Operation::Delete, "/output/c.png", None, 5, second, &Ok(()));
+        // A torn last line is skipped
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"time\":")
+            .unwrap();
+
+        let entries = read_history(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].username, "alice");
+        assert_eq!(entries[0].profile.as_deref(), Some("studio"));
+        assert_eq!(entries[1].error.as_deref(), Some("timed out"));
+        assert_eq!(entries[1].duration_ms, 1000);
+
+        let failed = HistoryFilter {
+            failed_only: true,
+            ..HistoryFilter::default()
+        };
+        let failed: Vec<_> = entries.iter().filter(|e| failed.matches(e)).collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].remote_path, "/output/b.png");
+
+        let future = HistoryFilter {
+            since: Some(Local::now() + chrono::Duration::hours(1)),
+            ..HistoryFilter::default()
+        };
+        assert!(!entries.iter().any(|e| future.matches(e)));
+
+        let stats = history_stats(&entries);
+        assert_eq!(stats[0].bytes, 100);
+        assert_eq!(stats[1].failed, 1);
+        assert_eq!(stats[1].bytes, 0);
+        assert_eq!(stats[3].succeeded, 2);
+        assert_eq!(stats[3].bytes, 105);
+    }
+
+    #[test]
+    fn test_missing_history_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_history(&dir.path().join("none.jsonl"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -4,9 +4,39 @@
     clippy::module_name_repetitions
 )]
 
+pub mod api;
 pub mod browser;
+pub mod cancel;
+pub mod cat;
 pub mod client;
+pub mod clipboard;
+pub mod compress;
 pub mod config;
 pub mod connection;
+pub mod crypt;
+pub mod discover;
+pub mod doctor;
 pub mod download;
+pub mod exclude;
+pub mod exit;
+pub mod hash;
+pub mod history;
+pub mod location;
+pub mod logging;
+pub mod output;
+pub mod outputs;
+pub mod preview;
+pub mod progress;
+pub mod queue;
+pub mod retry;
+pub mod safetensors;
+pub mod sort;
+pub mod sync;
+pub mod transfer;
+pub mod trash;
+pub mod upload;
 pub mod utils;
+pub mod walk;
+pub mod workflow;
+
+pub use api::ComfyFs;
@@ -0,0 +1,223 @@
+//! Addresses of remote paths that a file manager or another program can open,
+//! for `comfy-fs path` and the browser's `y` key
+
+use crate::client::address::{unc_host, url_host};
+use crate::config::{Config, Protocol};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use url::Url;
+
+/// One way to reach a remote path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// What opens it, e.g. `SMB` or `Windows`
+    pub kind: &'static str,
+    pub address: String,
+}
+
+/// What's needed from the config to address the server, without the password
+#[derive(Debug, Clone)]
+pub struct Addresses {
+    server: String,
+    username: String,
+    protocol: Protocol,
+    share: String,
+    smb_port: u16,
+    ftp_port: u16,
+}
+
+impl Addresses {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            server: config.server_ip.clone(),
+            username: config.username.clone(),
+            protocol: config.default_protocol,
+            share: config.smb_share.clone(),
+            smb_port: config.smb_port,
+            ftp_port: config.ftp_port,
+        }
+    }
+
+    /// Every address of `path`, the one for the configured protocol first
+    pub fn list(&self, path: &str) -> Result<Vec<Location>> {
+        let mut locations = vec![
+            Location {
+                kind: "SMB",
+                address: self.url(
+                    "smb",
+                    self.smb_port,
+                    445,
+                    &format!("{}/{}", self.share, path),
+                )?,
+            },
+            Location {
+                kind: "Windows",
+                address: self.unc(path),
+            },
+            Location {
+                kind: "FTP",
+                address: self.url("ftp", self.ftp_port, 21, path)?,
+            },
+            Location {
+                kind: "SFTP",
+                address: self.url("sftp", 22, 22, path)?,
+            },
+            Location {
+                kind: "HTTP",
+                address: self.http(path)?,
+            },
+        ];
+        let preferred = self.preferred_kind();
+        locations.sort_by_key(|location| location.kind != preferred);
+        Ok(locations)
+    }
+
+    /// The address of `path` for the configured protocol, as this platform's
+    /// file manager takes it: a UNC path for SMB on Windows, else a URL
+    pub fn preferred(&self, path: &str) -> Result<Location> {
+        let kind = self.preferred_kind();
+        self.list(path)?
+            .into_iter()
+            .find(|location| location.kind == kind)
+            .ok_or_else(|| anyhow!("No {} address for {}", kind, path))
+    }
+
+    fn preferred_kind(&self) -> &'static str {
+        match self.protocol {
+            Protocol::Smb if cfg!(windows) => "Windows",
+            Protocol::Smb => "SMB",
+            Protocol::Ftp => "FTP",
+            Protocol::Sftp => "SFTP",
+            Protocol::Http => "HTTP",
+        }
+    }
+
+    /// `scheme://server/path`, with the port only when it isn't the usual one
+    fn url(&self, scheme: &str, port: u16, usual_port: u16, path: &str) -> Result<String> {
+        let mut url = Url::parse(&format!("{}://{}/", scheme, url_host(&self.server)))
+            .map_err(|e| anyhow!("Invalid server address '{}': {}", self.server, e))?;
+        if port != usual_port {
+            url.set_port(Some(port))
+                .map_err(|()| anyhow!("Invalid server address '{}'", self.server))?;
+        }
+        // SFTP logs in as someone, and the others are usually shared
+        if scheme == "sftp" && !self.username.is_empty() {
+            let _ = url.set_username(&self.username);
+        }
+        push_segments(&mut url, path)?;
+        Ok(url.to_string())
+    }
+
+    /// `\\server\share\path`. Windows always uses port 445 for these.
+    fn unc(&self, path: &str) -> String {
+        let mut unc = format!(r"\\{}\{}", unc_host(&self.server), self.share);
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            unc.push('\\');
+            unc.push_str(segment);
+        }
+        unc
+    }
+
+    /// The server address may be a full URL for HTTP, as the HTTP client takes it
+    fn http(&self, path: &str) -> Result<String> {
+        let base = if self.server.starts_with("http://") || self.server.starts_with("https://") {
+            self.server.clone()
+        } else {
+            format!("http://{}", url_host(&self.server))
+        };
+        let mut url =
+            Url::parse(&base).map_err(|e| anyhow!("Invalid server address '{}': {}", base, e))?;
+        push_segments(&mut url, path)?;
+        Ok(url.to_string())
+    }
+}
+
+fn push_segments(url: &mut Url, path: &str) -> Result<()> {
+    let base = url.to_string();
+    let mut segments = url
+        .path_segments_mut()
+        .map_err(|()| anyhow!("Invalid base URL: {}", base))?;
+    segments.pop_if_empty();
+    segments.extend(path.split('/').filter(|s| !s.is_empty()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(protocol: Protocol) -> Addresses {
+        Addresses::new(&Config {
+            server_ip: "nas.local".to_string(),
+            username: "alice".to_string(),
+            default_protocol: protocol,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn test_list() {
+        let locations = addresses(Protocol::Ftp)
+            .list("/output/run 1/a.png")
+            .unwrap();
+        let addresses: Vec<_> = locations
+            .iter()
+            .map(|l| (l.kind, l.address.as_str()))
+            .collect();
+        let share = Config::default().smb_share;
+        assert_eq!(
+            addresses,
+            [
+                ("FTP", "ftp://nas.local/output/run%201/a.png"),
+                (
+                    "SMB",
+                    &*format!("smb://nas.local/{}/output/run%201/a.png", share)
+                ),
+                (
+                    "Windows",
+                    &*format!(r"\\nas.local\{}\output\run 1\a.png", share)
+                ),
+                ("SFTP", "sftp://alice@nas.local/output/run%201/a.png"),
+                ("HTTP", "http://nas.local/output/run%201/a.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preferred() {
+        let mut sftp = addresses(Protocol::Sftp);
+        assert_eq!(
+            sftp.preferred("/models").unwrap().address,
+            "sftp://alice@nas.local/models"
+        );
+        sftp.protocol = Protocol::Http;
+        sftp.server = "https://files.example.com/share/".to_string();
+        assert_eq!(
+            sftp.preferred("/models").unwrap().address,
+            "https://files.example.com/share/models"
+        );
+        let mut ftp = addresses(Protocol::Ftp);
+        ftp.ftp_port = 2121;
+        assert_eq!(ftp.preferred("/").unwrap().address, "ftp://nas.local:2121/");
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let mut smb = addresses(Protocol::Smb);
+        smb.server = "fd00::2".to_string();
+        smb.share = "models".to_string();
+        let locations = smb.list("/a.bin").unwrap();
+        let address = |kind| {
+            locations
+                .iter()
+                .find(|l| l.kind == kind)
+                .map(|l| l.address.as_str())
+        };
+        assert_eq!(address("SMB"), Some("smb://[fd00::2]/models/a.bin"));
+        assert_eq!(
+            address("Windows"),
+            Some(r"\\fd00--2.ipv6-literal.net\models\a.bin")
+        );
+        assert_eq!(address("HTTP"), Some("http://[fd00::2]/a.bin"));
+    }
+}
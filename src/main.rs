@@ -5,425 +5,3944 @@
     clippy::module_name_repetitions,
     clippy::uninlined_format_args,
     clippy::cast_precision_loss,
-    clippy::significant_drop_tightening
+    clippy::significant_drop_tightening,
+    clippy::too_many_lines,
+    clippy::unnecessary_debug_formatting
 )]
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use clap::{Args, Parser, Subcommand};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use comfy_fs::browser::{choose, FileBrowser, KeyMap, TerminalGuard};
+use comfy_fs::cancel::{interruptible, PartialFile};
+use comfy_fs::client::{
+    create_directory_all, directory_size, is_not_found, local::LocalClient, move_path,
+    no_progress, partial_path, paths::{self, PathGuard}, set_local_modified, ClientError,
+    FileServerClient, RemoteFile,
+};
+use comfy_fs::compress::{compress_file, compressed_name, Compression};
+use comfy_fs::config::{
+    CommandDefaults, Config, FtpTls, IpPreference, Overrides, Protocol, SftpHostKeys, SyncJob,
+};
+use comfy_fs::retry::{RetryPolicy, DEFAULT_RETRIES};
+use comfy_fs::sort::{sort_entries, SortMode};
+use comfy_fs::sync::state::{pair_key, state_path, FileState, SyncState};
+use comfy_fs::sync::watch::{LocalWatcher, DEFAULT_DEBOUNCE};
+use comfy_fs::sync::{
+    refine_times, walk_local, Conflict, ConflictPolicy, SyncDirection, SyncOptions, SyncPlan,
+};
+use comfy_fs::utils::{has_wildcards, parse_age, parse_size, split_glob};
+use comfy_fs::walk::{
+    child_usage, directory_totals, render_tree, sort_tree, totals_label, DirTotals, EntryKind,
+    FindFilter, NamePattern, Walker,
 };
-use ratatui::prelude::*;
-use std::io;
-use std::path::PathBuf;
+use comfy_fs::download::{prompt_overwrite, OverwriteGuard, OverwritePolicy};
+use comfy_fs::exclude::PathFilter;
+use comfy_fs::exit::{self, Exit, ExitStatus};
+use comfy_fs::hash::{hash_remote, same_content, HashAlgorithm};
+use comfy_fs::history::{
+    self, history_path, history_stats, read_history, History, HistoryFilter, Operation,
+};
+use comfy_fs::queue::{self, queue_path, JobQueue, JobState};
+use comfy_fs::output::{
+    summary_table, table_row, table_width, Column, FileRecord, OutputFormat, TransferSummary,
+};
+use comfy_fs::progress::{ProgressMode, ProgressView};
+use comfy_fs::transfer::{
+    check_concurrency, event_channel, parse_concurrency, ParallelTransfers, TransferJob,
+    TransferKind,
+};
+use comfy_fs::trash::{self, Trash};
+use comfy_fs::upload::{self, expand_globs, plan_targets, stdin_target, NameConflict, Target};
+use comfy_fs::outputs;
+use comfy_fs::safetensors;
+use comfy_fs::workflow::{self, Embedded};
+use comfy_fs::clipboard;
+use comfy_fs::location::Addresses;
+use comfy_fs::connection::ClientPool;
+use comfy_fs::crypt::{self, encrypt_file, EncryptionKey};
+use comfy_fs::discover::{self, Subnet};
+use comfy_fs::{cat, connection, doctor, download, logging};
+
+/// Progress messages go to stdout, or to stderr when stdout carries structured
+/// output. `--quiet` leaves them out.
+macro_rules! status {
+    ($output:expr, $($arg:tt)*) => {
+        if QUIET.load(Ordering::Relaxed) {
+            // Only errors and summaries get through
+        } else if $output.is_table() {
+            println!($($arg)*);
+        } else {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// `--quiet`, set once the command line is parsed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// How transfers show their progress, from `--progress` and `--quiet`
+static PROGRESS: OnceLock<ProgressMode> = OnceLock::new();
 
-mod browser;
-mod client;
-mod config;
-mod connection;
-mod download;
-mod utils;
+fn progress_mode() -> ProgressMode {
+    PROGRESS.get().copied().unwrap_or_default()
+}
+
+/// List what a dry run skipped, each with its size, and the total
+fn report_dry_run(output: OutputFormat, verb: &str, items: &[(String, u64)]) {
+    for (item, size) in items {
+        status!(output, "Would {} {} ({})", verb, item, human_bytes::human_bytes(*size as f64));
+    }
+    let total: u64 = items.iter().map(|(_, size)| size).sum();
+    status!(
+        output,
+        "Dry run: would {} {} file(s), {} in total",
+        verb,
+        items.len(),
+        human_bytes::human_bytes(total as f64)
+    );
+}
 
-use browser::FileBrowser;
-use config::Config;
-use utils::glob_match;
+/// Print `records` in a structured format. Returns false for tables, which the
+/// caller prints itself.
+fn print_records<T: serde::Serialize>(output: OutputFormat, records: &[T]) -> Result<bool> {
+    let Some(text) = output.render(records)? else {
+        return Ok(false);
+    };
+    if !text.is_empty() {
+        println!("{}", text);
+    }
+    Ok(true)
+}
 
 /// Helper function to ensure config has password, prompting if needed
-fn ensure_password(config: &mut Config) -> Result<()> {
+fn ensure_password(config: &mut Config, prompt: bool) -> Result<()> {
     if config.password.is_none() {
         use std::io::Write;
+
+        if !prompt {
+            return Err(exit::config(anyhow!(
+                "No password for {} on {}; set COMFY_FS_PASSWORD, pass --password-stdin, or \
+                 save one with `comfy-fs config`",
+                config.username,
+                config.server_ip
+            )));
+        }
         
         print!("Password (hidden - you won't see it when you type): ");
         std::io::stdout().flush()?;
         
         // Try to read password securely, fallback to regular input if needed
-        match rpassword::prompt_password("") {
-            Ok(password) => {
-                config.password = Some(password);
-            }
-            Err(_) => {
-                // Fallback to regular input if secure input fails
-                let mut password = String::new();
-                std::io::stdin().read_line(&mut password)?;
-                config.password = Some(password.trim().to_string());
-            }
+        if let Ok(password) = rpassword::prompt_password("") {
+            config.password = Some(password);
+        } else {
+            // Fallback to regular input if secure input fails
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            config.password = Some(password.trim().to_string());
         }
     }
     Ok(())
 }
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-#[command(propagate_version = true)]
-struct Cli {
-    #[command(subcommand)]
-    command: Option<Commands>,
+fn load_config(server: &ServerArgs) -> Result<Config> {
+    let config = if server.no_keyring {
+        Config::load_without_keyring()
+    } else {
+        Config::load()
+    };
+    config.map_err(exit::config)
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Upload files to the server
-    Upload {
-        /// Files to upload
-        #[arg(required = true)]
-        files: Vec<PathBuf>,
-
-        /// Destination directory on server
-        #[arg(short, long)]
-        dest: Option<String>,
-    },
-
-    /// Download files from the server
-    Download {
-        /// Remote path (supports wildcards)
-        path: String,
-
-        /// Local destination directory
-        #[arg(short, long, default_value = ".")]
-        dest: PathBuf,
-    },
-
-    /// List files on the server
-    List {
-        /// Directory to list
-        #[arg(default_value = "/")]
-        path: String,
-
-        /// Sort by: modified (default), name, size, type
-        #[arg(short, long, default_value = "modified")]
-        sort: String,
+/// Keep the password in the OS keyring so later runs don't prompt for it
+fn store_password(config: &Config, server: &ServerArgs) {
+    if server.no_keyring || config.password.is_none() {
+        return;
+    }
+    if let Err(e) = config.store_keyring_password() {
+        eprintln!("Could not save password to the keyring: {}", e);
+    }
+}
 
-        /// Reverse sort order
-        #[arg(short, long)]
-        reverse: bool,
-    },
+/// Make `host` the server, or the named profile's, switching to a protocol it
+/// offers if the one set isn't
+fn save_discovered(host: &discover::Host, server: &ServerArgs) -> Result<()> {
+    let mut config = load_config(server)?;
+    let address = host.address.to_string();
+    let switch = |current: Protocol| {
+        (!host.protocols.contains(&current))
+            .then(|| host.protocols.first().copied())
+            .flatten()
+    };
+    let username = if let Some(name) = &server.profile {
+        let default_protocol = config.default_protocol;
+        let profile = config.profiles.entry(name.clone()).or_default();
+        profile.server_ip = Some(address.clone());
+        if let Some(protocol) = switch(profile.protocol.unwrap_or(default_protocol)) {
+            profile.protocol = Some(protocol);
+        }
+        profile
+            .username
+            .clone()
+            .unwrap_or_else(|| config.username.clone())
+    } else {
+        config.server_ip.clone_from(&address);
+        if let Some(protocol) = switch(config.default_protocol) {
+            config.default_protocol = protocol;
+        }
+        config.configured = true;
+        config.username.clone()
+    };
+    config.save()?;
+    println!("Saved {} as the server", address);
+    if username.is_empty() {
+        let profile = server
+            .profile
+            .as_ref()
+            .map(|name| format!(" --profile {}", name))
+            .unwrap_or_default();
+        println!(
+            "Set the username to log in with: comfy-fs config{} --username NAME",
+            profile
+        );
+    }
+    Ok(())
+}
 
-    /// Browse server files interactively
-    Browse {
-        /// Starting directory
-        #[arg(default_value = "/")]
-        path: String,
-    },
+/// One pass of `sync`: compare both trees, then copy, move, and delete. `None`
+/// when nothing was changed, after a dry run or a cancelled deletion.
+async fn run_sync(
+    pool: &ClientPool,
+    pair: &SyncPair,
+    args: &SyncArgs,
+    server: &ServerArgs,
+    dry_run: bool,
+    output: OutputFormat,
+) -> Result<Option<Vec<TransferSummary>>> {
+    let client = &pool.primary();
+    let (local, remote, direction) = (&pair.local, &pair.remote, pair.direction);
+    let (overwrite, conflict) = (&args.overwrite, args.conflict);
+    let (max_depth, delete, yes) = (args.max_depth, args.delete, args.yes);
+    let retry_policy = RetryPolicy::new(args.retries.unwrap_or(DEFAULT_RETRIES));
 
-    /// Sync a local directory with the server
-    Sync {
-        /// Local directory
-        local: PathBuf,
+    status!(output, "Syncing {:?} with {} ({})", local, remote, direction);
+    let mut summaries = Vec::new();
 
-        /// Remote directory
-        remote: String,
-    },
+    // A pull can start from nothing; the directory is made unless it's a dry run
+    let mut local_entries = if !local.exists() && direction.pulls() {
+        Vec::new()
+    } else {
+        walk_local(local, max_depth)?
+    };
+    let mut remote_entries = Walker::new(client.clone(), 4)
+        .with_max_depth(max_depth)
+        .walk(remote)
+        .await?;
 
-    /// Interactive TUI mode
-    Interactive,
+    // Excluded paths are left alone on both sides: never copied, never deleted
+    let path_filter = PathFilter::new(local, &pair.exclude, &pair.include)?;
+    local_entries.retain(|e| !path_filter.is_excluded(&e.relative, e.is_dir));
+    let remote_prefix = format!("{}/", remote.trim_end_matches('/'));
+    remote_entries.retain(|e| {
+        let relative = e.file.path.strip_prefix(&remote_prefix).unwrap_or(&e.file.path);
+        !path_filter.is_excluded(relative, e.file.is_dir)
+    });
+    let config = load_config(server)?;
+    let time_tolerance = args.time_tolerance.unwrap_or(config.sync_time_tolerance);
+    refine_times(client, &local_entries, remote, &mut remote_entries, time_tolerance).await;
 
-    /// Configure server settings
-    Config {
-        /// Server IP address
-        #[arg(long)]
-        server: Option<String>,
+    // What the last sync of these directories left, kept per profile
+    let profile = server.profile.as_deref().or(config.default_profile.as_deref());
+    let state_file = state_path(profile)?;
+    let state_key = pair_key(local, remote);
+    let options = SyncOptions::new(direction)
+        .with_time_tolerance(time_tolerance)
+        .with_max_depth(max_depth)
+        .with_conflict(conflict)
+        .with_state(SyncState::load(&state_file, &state_key)?);
+    let mut plan = SyncPlan::new(local, &local_entries, remote, &remote_entries, &options);
 
-        /// Username
-        #[arg(long)]
-        username: Option<String>,
+    let deletions = match (delete, direction) {
+        (false, _) => Vec::new(),
+        (true, SyncDirection::Pull) => plan.local_deletions,
+        (true, _) => plan.remote_deletions,
+    };
+    if !deletions.is_empty() {
+        let place = if direction.pulls() { "locally" } else { "on the server" };
+        status!(output, "Will delete {} item(s) {}:", deletions.len(), place);
+        let mut total = DirTotals::default();
+        for deletion in &deletions {
+            if deletion.is_dir {
+                status!(output, "  {}/ ({})", deletion.relative, totals_label(deletion.totals));
+            } else {
+                let size = human_bytes::human_bytes(deletion.totals.size as f64);
+                status!(output, "  {} ({})", deletion.relative, size);
+            }
+            total.size += deletion.totals.size;
+            total.files += deletion.totals.files;
+        }
+        status!(output, "Total: {}", totals_label(total));
 
-        /// Password (will prompt if not provided)
-        #[arg(long)]
-        password: Option<String>,
-    },
-}
+        if !yes && !dry_run {
+            if !server.prompt {
+                bail!("Refusing to delete without confirmation; pass --yes");
+            }
+            if !confirm("Sync and delete these?")? {
+                println!("Cancelled");
+                return Ok(None);
+            }
+        }
+    }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    // Download files that don't exist locally or are different sizes
+    let mut overwrite_guard = OverwriteGuard::new(overwrite.policy(dry_run, server.prompt));
+    let mut to_download = Vec::new();
+    let mut skipped = 0;
+    for transfer in plan.downloads {
+        if !transfer.replaces
+            || overwrite_guard.allow(
+                &transfer.local_path,
+                Some(transfer.modified),
+                prompt_overwrite,
+            )?
+        {
+            to_download.push(transfer);
+        } else {
+            skipped += 1;
+        }
+    }
 
-    // Check if we need to run first-time setup
-    let mut config = Config::load()?;
-    if !config.is_configured() && !matches!(cli.command, Some(Commands::Config { .. })) {
-        config.interactive_setup()?;
+    // Settle files changed on both sides that the policy leaves open.
+    // Keeping both moves the local copy aside first, so that waits until
+    // the directories exist.
+    let today = Local::now().date_naive();
+    let mut keep_both = Vec::new();
+    for pending in std::mem::take(&mut plan.conflicts) {
+        let choice = match conflict {
+            ConflictPolicy::Ask if dry_run => {
+                let relative = &pending.relative;
+                status!(output, "Would ask about {}, changed on both sides", relative);
+                continue;
+            }
+            ConflictPolicy::Ask => prompt_conflict(&pending)?,
+            _ => Some(ConflictPolicy::KeepBoth),
+        };
+        match choice {
+            Some(ConflictPolicy::Local) => plan.uploads.push(pending.upload),
+            Some(ConflictPolicy::Remote) => to_download.push(pending.download),
+            Some(_) => keep_both.push(pending.keep_both(today)),
+            None => {
+                status!(output, "Leaving {} as it is on both sides", pending.relative);
+                skipped += 1;
+            }
+        }
     }
 
-    match cli.command {
-        None => {
-            // No command provided, launch interactive TUI
-            browse_mode("/".to_string()).await?;
+    if dry_run {
+        for dir in &plan.local_dirs {
+            status!(output, "Would create local directory {}", dir.display());
         }
-        Some(Commands::Interactive) => {
-            browse_mode("/".to_string()).await?;
+        for dir in &plan.remote_dirs {
+            status!(output, "Would create remote directory {}", dir);
         }
-        Some(Commands::Browse { path }) => {
-            browse_mode(path).await?;
+        let renames =
+            [("locally", &plan.local_renames), ("on the server", &plan.remote_renames)];
+        for (place, renames) in renames {
+            for rename in renames {
+                status!(output, "Would move {} to {} {}", rename.from, rename.to, place);
+            }
         }
-        Some(Commands::Upload { files, dest }) => {
-            let mut config = Config::load()?;
-            ensure_password(&mut config)?;
-            let mut conn_mgr = connection::ConnectionManager::new(config);
-            let client = conn_mgr.connect().await?;
-
-            let dest_path = dest.unwrap_or_else(|| "/".to_string());
-
-            println!("Uploading {} files to {}", files.len(), dest_path);
-
-            let mut successful = 0;
-            let mut failed = 0;
-
-            for file in files {
-                if !file.exists() {
-                    eprintln!("File not found: {:?}", file);
-                    failed += 1;
-                    continue;
-                }
-
-                let filename = file.file_name().and_then(|n| n.to_str()).unwrap_or("file");
-
-                let remote_path = format!("{}/{}", dest_path.trim_end_matches('/'), filename);
+        for (download, upload) in &keep_both {
+            status!(
+                output,
+                "Would keep both copies of {}, moving the local one to {}",
+                download.relative,
+                upload.relative
+            );
+        }
+        for (verb, transfers) in [("download", &to_download), ("upload", &plan.uploads)] {
+            if !transfers.is_empty() {
+                let items: Vec<_> = transfers
+                    .iter()
+                    .map(|t| (t.relative.clone(), t.size))
+                    .collect();
+                report_dry_run(output, verb, &items);
+            }
+        }
+        status!(output, "Dry run, nothing changed");
+        return Ok(None);
+    }
 
-                print!("Uploading {:?} to {} ... ", file, remote_path);
+    if direction.pulls() {
+        tokio::fs::create_dir_all(&local).await?;
+    }
+    for dir in &plan.local_dirs {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    for dir in &plan.remote_dirs {
+        client.lock().await.create_directory(dir).await?;
+    }
+    let created = plan.local_dirs.len() + plan.remote_dirs.len();
+    if created > 0 {
+        status!(output, "Created {} directories", created);
+    }
 
-                let mut client_guard = client.lock().await;
-                match client_guard.upload_file(&file, &remote_path).await {
-                    Ok(_) => {
-                        println!("✓");
-                        successful += 1;
-                    }
-                    Err(e) => {
-                        println!("✗ Error: {}", e);
-                        failed += 1;
-                    }
+    // Files moved on one side since the last sync are moved on the other too
+    let renames = plan.local_renames.len() + plan.remote_renames.len();
+    if renames > 0 {
+        let mut moved = 0;
+        for rename in &plan.local_renames {
+            let to = Path::new(&rename.to_path);
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            match tokio::fs::rename(&rename.from_path, to).await {
+                Ok(()) => {
+                    moved += 1;
+                    plan.state.forget(&rename.from);
+                    plan.state.record(&rename.to, rename.state.clone());
                 }
+                Err(e) => eprintln!("Failed to move {}: {}", rename.from, e),
             }
-
-            println!(
-                "\nUpload complete: {} successful, {} failed",
-                successful, failed
-            );
         }
-        Some(Commands::Download { path, dest }) => {
-            let mut config = Config::load()?;
-            ensure_password(&mut config)?;
-            let mut conn_mgr = connection::ConnectionManager::new(config);
-            let client = conn_mgr.connect().await?;
-
-            // Check if path contains wildcards
-            if path.contains('*') {
-                // Handle wildcard download
-                let dir = path.split('*').next().unwrap_or("/");
-                let pattern = path.split('/').last().unwrap_or("*");
-
-                let mut client_guard = client.lock().await;
-                let files = client_guard.list_files(dir).await?;
-                drop(client_guard);
-
-                // Filter files based on pattern
-                let matching_files: Vec<_> = files
-                    .into_iter()
-                    .filter(|f| !f.is_dir && glob_match(&f.name, pattern))
-                    .map(|f| (f.path.clone(), dest.join(&f.name)))
-                    .collect();
-
-                if matching_files.is_empty() {
-                    println!("No files match pattern: {}", pattern);
-                    return Ok(());
+        for rename in &plan.remote_renames {
+            match client.lock().await.rename(&rename.from_path, &rename.to_path).await {
+                Ok(()) => {
+                    moved += 1;
+                    plan.state.forget(&rename.from);
+                    plan.state.record(&rename.to, rename.state.clone());
                 }
+                Err(e) => eprintln!("Failed to move {}: {}", rename.from, e),
+            }
+        }
+        status!(output, "Moved {}/{} files", moved, renames);
+        summaries.push(TransferSummary {
+            operation: "move",
+            succeeded: moved,
+            skipped: 0,
+            failed: renames - moved,
+            recovered: 0,
+            bytes: 0,
+            elapsed_secs: 0.0,
+        });
+    }
 
-                println!(
-                    "Downloading {} files matching '{}'",
-                    matching_files.len(),
-                    pattern
-                );
-
-                let downloader = download::ParallelDownloader::new(client, 4);
-                let results = downloader.download_files(matching_files).await?;
-
-                let successful = results.iter().filter(|r| r.is_ok()).count();
-                println!(
-                    "Downloaded {}/{} files successfully",
-                    successful,
-                    results.len()
+    for (download, upload) in keep_both {
+        if upload.local_path.exists() {
+            eprintln!("Skipping {}: {} already exists", download.relative, upload.relative);
+            skipped += 1;
+            continue;
+        }
+        match tokio::fs::rename(&download.local_path, &upload.local_path).await {
+            Ok(()) => {
+                status!(
+                    output,
+                    "Keeping both copies of {}; the local one is now {}",
+                    download.relative,
+                    upload.relative
                 );
-            } else {
-                // Single file download
-                let filename = path.split('/').last().unwrap_or("file");
-                let local_path = dest.join(filename);
+                to_download.push(download);
+                plan.uploads.push(upload);
+            }
+            Err(e) => eprintln!("Failed to move {} aside: {}", download.relative, e),
+        }
+    }
 
-                println!("Downloading {} to {:?}", path, local_path);
+    // Copy both ways at once. Skipped files count against downloads: they're
+    // ones not to overwrite and conflicts left alone.
+    let jobs: Vec<_> = to_download
+        .iter()
+        .map(|t| TransferJob::download(t.remote_path.clone(), t.local_path.clone(), t.size))
+        .chain(plan.uploads.iter().map(|t| {
+            TransferJob::upload(t.local_path.clone(), t.remote_path.clone(), t.size)
+        }))
+        .collect();
+    if !jobs.is_empty() || skipped > 0 {
+        status!(
+            output,
+            "Copying {} files ({} down, {} up)...",
+            jobs.len(),
+            to_download.len(),
+            plan.uploads.len()
+        );
+        let transfers = ParallelTransfers::new(pool.clone(), pool.max_clients())
+            .with_retry_policy(retry_policy)
+            .with_safetensors_validation(args.validate.validate_safetensors)
+            .with_history(open_history(server));
+        let started = Instant::now();
+        let results = run_transfers(transfers, &jobs).await?;
+        let elapsed_secs = started.elapsed().as_secs_f64();
+        let (download_results, upload_results) = results.split_at(to_download.len());
+        let mut failures = Vec::new();
 
-                let mut client_guard = client.lock().await;
-                client_guard.download_file(&path, &local_path).await?;
+        let mut downloads = TransferSummary {
+            operation: "download",
+            succeeded: 0,
+            skipped,
+            failed: 0,
+            recovered: 0,
+            bytes: 0,
+            elapsed_secs,
+        };
+        for (transfer, result) in to_download.iter().zip(download_results) {
+            let retries = match result {
+                Ok(retries) => *retries,
+                Err(e) => {
+                    failures.push((format!("download {}", transfer.relative), e.to_string()));
+                    downloads.failed += 1;
+                    continue;
+                }
+            };
+            downloads.succeeded += 1;
+            downloads.recovered += usize::from(retries > 0);
+            downloads.bytes += transfer.size;
 
-                println!("Download complete!");
+            // Keep the server's times so the next sync sees these files as unchanged
+            if let Err(e) = set_local_modified(&transfer.local_path, transfer.modified) {
+                eprintln!("Could not set the time of {:?}: {}", transfer.local_path, e);
             }
+            let local_modified = std::fs::metadata(&transfer.local_path)
+                .and_then(|metadata| metadata.modified())
+                .map_or(transfer.modified, DateTime::from);
+            let record = FileState::new(transfer.size, local_modified)
+                .with_remote_modified(Some(transfer.modified))
+                .hashed(&transfer.local_path);
+            plan.state.record(&transfer.relative, record);
         }
-        Some(Commands::List {
-            path,
-            sort: _,
-            reverse: _,
-        }) => {
-            let mut config = Config::load()?;
-            ensure_password(&mut config)?;
-            let mut conn_mgr = connection::ConnectionManager::new(config);
-            let client = conn_mgr.connect().await?;
-            let mut client = client.lock().await;
 
-            let files = client.list_files(&path).await?;
-            println!("Files in {}:", path);
-            println!("{:<50} {:>10} {:>20}", "Name", "Size", "Modified");
-            println!("{}", "-".repeat(80));
+        let mut uploads = TransferSummary {
+            operation: "upload",
+            succeeded: 0,
+            skipped: 0,
+            failed: 0,
+            recovered: 0,
+            bytes: 0,
+            elapsed_secs,
+        };
+        let mut keep_times = client.lock().await.capabilities().set_modified;
+        for (transfer, result) in plan.uploads.iter().zip(upload_results) {
+            let retries = match result {
+                Ok(retries) => *retries,
+                Err(e) => {
+                    failures.push((format!("upload {}", transfer.relative), e.to_string()));
+                    uploads.failed += 1;
+                    continue;
+                }
+            };
+            uploads.succeeded += 1;
+            uploads.recovered += usize::from(retries > 0);
+            uploads.bytes += transfer.size;
 
-            for file in files {
-                let size_str = if file.is_dir {
-                    "DIR".to_string()
-                } else {
-                    human_bytes::human_bytes(file.size as f64).to_string()
-                };
-                println!(
-                    "{:<50} {:>10} {:>20}",
-                    file.name,
-                    size_str,
-                    file.modified.format("%Y-%m-%d %H:%M:%S")
-                );
+            // Without the local time, the next sync would see the upload as newer
+            if keep_times {
+                let mut client = client.lock().await;
+                if let Err(e) =
+                    client.set_modified(&transfer.remote_path, transfer.modified).await
+                {
+                    eprintln!("Uploaded files keep the server's times: {}", e);
+                    keep_times = false;
+                }
             }
+            let record = FileState::new(transfer.size, transfer.modified)
+                .with_remote_modified(keep_times.then_some(transfer.modified))
+                .hashed(&transfer.local_path);
+            plan.state.record(&transfer.relative, record);
         }
-        Some(Commands::Sync { local, remote }) => {
-            let mut config = Config::load()?;
-            ensure_password(&mut config)?;
-            let mut conn_mgr = connection::ConnectionManager::new(config);
-            let client = conn_mgr.connect().await?;
-
-            println!("Syncing {:?} with {}", local, remote);
-
-            // Get list of remote files
-            let mut client_guard = client.lock().await;
-            let remote_files = client_guard.list_files(&remote).await?;
-            drop(client_guard);
-
-            // Get list of local files
-            let mut local_files = std::collections::HashMap::new();
-            if local.exists() && local.is_dir() {
-                for entry in std::fs::read_dir(&local)? {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                            let metadata = entry.metadata()?;
-                            local_files.insert(filename.to_string(), (path, metadata.len()));
-                        }
-                    }
+        report_failures(output, &failures);
+        summaries.push(downloads);
+        summaries.push(uploads);
+    }
+
+    // Delete what the source doesn't have, once everything it does has arrived
+    if !deletions.is_empty() {
+        let history = open_history(server);
+        let mut deleted = 0;
+        for deletion in &deletions {
+            let started = Instant::now();
+            let result = match (direction, deletion.is_dir) {
+                (SyncDirection::Pull, true) => {
+                    tokio::fs::remove_dir_all(&deletion.local_path).await.map_err(Into::into)
                 }
-            } else {
-                tokio::fs::create_dir_all(&local).await?;
+                (SyncDirection::Pull, false) => {
+                    tokio::fs::remove_file(&deletion.local_path).await.map_err(Into::into)
+                }
+                (_, true) => {
+                    client.lock().await.delete_directory(&deletion.remote_path, true).await
+                }
+                (_, false) => client.lock().await.delete_file(&deletion.remote_path).await,
+            };
+            // Only what's deleted from the server is history; local deletions aren't shared
+            if let (Some(history), false) = (&history, direction == SyncDirection::Pull) {
+                history.record(
+                    Operation::Delete,
+                    &deletion.remote_path,
+                    None,
+                    deletion.totals.size,
+                    started.elapsed(),
+                    &result,
+                );
             }
-
-            // Download files that don't exist locally or are different sizes
-            let mut to_download = Vec::new();
-            for remote_file in remote_files.iter().filter(|f| !f.is_dir) {
-                if let Some((_, local_size)) = local_files.get(&remote_file.name) {
-                    if *local_size != remote_file.size {
-                        to_download.push((remote_file.path.clone(), local.join(&remote_file.name)));
-                    }
-                } else {
-                    to_download.push((remote_file.path.clone(), local.join(&remote_file.name)));
+            match result {
+                Ok(()) => {
+                    deleted += 1;
+                    plan.state.forget(&deletion.relative);
                 }
+                Err(e) => eprintln!("Failed to delete {}: {}", deletion.relative, e),
             }
+        }
+        status!(output, "Deleted {}/{} items", deleted, deletions.len());
+        summaries.push(TransferSummary {
+            operation: "delete",
+            succeeded: deleted,
+            skipped: 0,
+            failed: deletions.len() - deleted,
+            recovered: 0,
+            bytes: 0,
+            elapsed_secs: 0.0,
+        });
+    }
 
-            if !to_download.is_empty() {
-                println!("Downloading {} files...", to_download.len());
-                let downloader = download::ParallelDownloader::new(client.clone(), 4);
-                let results = downloader.download_files(to_download).await?;
-                let successful = results.iter().filter(|r| r.is_ok()).count();
-                println!("Downloaded {}/{} files", successful, results.len());
-            }
+    plan.state.fill_hashes(local);
+    if let Err(e) = plan.state.save(&state_file, &state_key) {
+        eprintln!("Could not save the sync state: {}", e);
+    }
 
-            // Upload files that don't exist remotely
-            let remote_names: std::collections::HashSet<_> = remote_files
-                .iter()
-                .filter(|f| !f.is_dir)
-                .map(|f| f.name.clone())
-                .collect();
+    Ok(Some(summaries))
+}
 
-            let mut to_upload = Vec::new();
-            for (name, (path, _)) in local_files {
-                if !remote_names.contains(&name) {
-                    to_upload.push((path, format!("{}/{}", remote.trim_end_matches('/'), name)));
-                }
+/// Print what a sync pass did, as records or as a table
+fn report_sync(output: OutputFormat, summaries: &[TransferSummary]) -> Result<()> {
+    if !print_records(output, summaries)? {
+        if !summaries.is_empty() {
+            println!();
+            for line in summary_table(summaries) {
+                println!("{}", line);
             }
+        }
+        println!("Sync complete!");
+    }
+    Ok(())
+}
 
-            if !to_upload.is_empty() {
-                println!("Uploading {} files...", to_upload.len());
-                let mut successful = 0;
-                for (local_path, remote_path) in to_upload {
-                    let mut client_guard = client.lock().await;
-                    if client_guard
-                        .upload_file(&local_path, &remote_path)
-                        .await
-                        .is_ok()
-                    {
-                        successful += 1;
-                    }
-                }
-                println!("Uploaded {} files", successful);
-            }
+/// Sync again each time local files settle after changing and, when pulling,
+/// every poll interval, until Ctrl-C. A failed pass is reported and retried on
+/// the next change.
+async fn watch_sync(
+    pool: &ClientPool,
+    pair: &SyncPair,
+    args: &SyncArgs,
+    server: &ServerArgs,
+    output: OutputFormat,
+) -> Result<()> {
+    let direction = pair.direction;
+    let path_filter = PathFilter::new(&pair.local, &pair.exclude, &pair.include)?;
+    let mut watcher = if direction.pushes() {
+        Some(LocalWatcher::new(&pair.local, DEFAULT_DEBOUNCE)?)
+    } else {
+        None
+    };
+    let poll_interval = Duration::from_secs(args.poll_interval.max(1));
+    let root = pair.local.canonicalize().unwrap_or_else(|_| pair.local.clone());
 
-            println!("Sync complete!");
-        }
-        Some(Commands::Config {
-            server,
-            username,
-            password,
-        }) => {
-            let mut config = Config::load()?;
+    let watching = if direction.pulls() {
+        format!(
+            "Watching {:?}, checking the server every {}s (Ctrl-C to stop)",
+            pair.local,
+            poll_interval.as_secs()
+        )
+    } else {
+        format!("Watching {:?} (Ctrl-C to stop)", pair.local)
+    };
 
-            // If no arguments provided, run interactive setup
-            if server.is_none() && username.is_none() && password.is_none() {
-                config.interactive_setup()?;
-            } else {
-                // Update only the provided fields
-                if let Some(server) = server {
-                    config.server_ip = server;
-                }
-                if let Some(username) = username {
-                    config.username = username;
+    status!(output, "{}", watching);
+    loop {
+        tokio::select! {
+            changed = async {
+                match watcher.as_mut() {
+                    Some(watcher) => watcher.changed().await,
+                    None => std::future::pending().await,
                 }
-                if let Some(password) = password {
-                    config.password = Some(password);
+            } => {
+                // Changes to excluded files alone aren't worth a sync
+                let relevant = changed?.iter().any(|path| {
+                    let relative = path.strip_prefix(&root).ok().and_then(Path::to_str);
+                    relative.is_some_and(|relative| {
+                        !relative.is_empty() && !path_filter.is_excluded(relative, path.is_dir())
+                    })
+                });
+                if !relevant {
+                    continue;
                 }
-                config.configured = true;
+            }
+            () = tokio::time::sleep(poll_interval), if direction.pulls() => {}
+            _ = tokio::signal::ctrl_c() => {
+                status!(output, "Stopped watching");
+                return Ok(());
+            }
+        }
 
-                config.save()?;
-                println!("Configuration saved successfully!");
+        let result = tokio::select! {
+            result = run_sync(pool, pair, args, server, false, output) => result,
+            _ = tokio::signal::ctrl_c() => {
+                status!(output, "Stopped watching");
+                return Ok(());
             }
+        };
+        match result {
+            Ok(Some(summaries)) => report_sync(output, &summaries)?,
+            Ok(None) => {}
+            Err(e) => eprintln!("Sync failed: {:#}", e),
         }
+        // What the pass wrote locally isn't a change to send back
+        if let Some(watcher) = watcher.as_mut() {
+            watcher.discard_pending();
+        }
+        status!(output, "{}", watching);
+    }
+}
+
+/// Look up a single remote file, `None` if it isn't there
+async fn find_remote_file(
+    client: &Arc<Mutex<Box<dyn FileServerClient>>>,
+    path: &str,
+) -> Result<Option<RemoteFile>> {
+    match client.lock().await.stat(path).await {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if is_not_found(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The remote entries `path` refers to. Wildcards, in any component, match
+/// every entry whose path fits.
+async fn expand_remote(
+    client: &Arc<Mutex<Box<dyn FileServerClient>>>,
+    path: &str,
+) -> Result<Vec<RemoteFile>> {
+    if !has_wildcards(path) {
+        return find_remote_file(client, path)
+            .await?
+            .map(|file| vec![file])
+            .ok_or_else(|| anyhow!("No such file or directory: {}", path));
+    }
+
+    Walker::new(client.clone(), 4).glob(path).await
+}
+
+/// Fail if deleting `path` would empty the whole share, even with --force
+fn refuse_root(path: &str) -> Result<()> {
+    if paths::is_root(path)? {
+        let message = if path.is_empty() {
+            "Refusing to delete the directory the server starts in".to_string()
+        } else {
+            format!("Refusing to delete the root of the share: {}", path)
+        };
+        return Err(ClientError::PermissionDenied(message).into());
     }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal. Anything but y means no.
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::Write;
+
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask how to settle a file changed on both sides. `None` leaves it as it is.
+fn prompt_conflict(conflict: &Conflict) -> Result<Option<ConflictPolicy>> {
+    use std::io::Write;
+
+    let describe = |size: u64, modified: DateTime<Local>| {
+        let size = human_bytes::human_bytes(size as f64);
+        format!("{}, {}", size, modified.format("%Y-%m-%d %H:%M:%S"))
+    };
+    println!("{} changed on both sides", conflict.relative);
+    println!("  local:  {}", describe(conflict.upload.size, conflict.upload.modified));
+    println!("  remote: {}", describe(conflict.download.size, conflict.download.modified));
+    print!("Keep [l]ocal, [r]emote, [b]oth, or [s]kip? ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "l" | "local" => Some(ConflictPolicy::Local),
+        "r" | "remote" => Some(ConflictPolicy::Remote),
+        "b" | "both" => Some(ConflictPolicy::KeepBoth),
+        _ => None,
+    })
+}
+
+/// Run `jobs`, showing their progress as `--progress` says, stopping at Ctrl-C
+async fn run_transfers(
+    transfers: ParallelTransfers,
+    jobs: &[TransferJob],
+) -> Result<Vec<Result<u32>>> {
+    let (sender, events) = event_channel();
+    let transfers = transfers.with_events(sender);
+    let progress = ProgressView::for_jobs(progress_mode(), jobs).show(events);
+    let (results, ()) =
+        interruptible(async { tokio::join!(transfers.run(jobs), progress) }).await?;
+    Ok(results)
+}
 
+/// Make the missing upload directory `dir` with its parents, unless
+/// `--no-create-dirs` says to fail instead
+async fn create_upload_dir(
+    client: &mut dyn FileServerClient,
+    dir: &str,
+    no_create_dirs: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    if no_create_dirs {
+        return Err(ClientError::NotFound(format!(
+            "{} does not exist on the server; upload without --no-create-dirs to create it",
+            dir
+        ))
+        .into());
+    }
+    create_directory_all(client, dir).await?;
+    status!(output, "Created {}", dir);
     Ok(())
 }
 
+/// `upload -`: everything on standard input, saved as `target`
+async fn upload_stdin(
+    server: &ServerArgs,
+    output: OutputFormat,
+    dry_run: bool,
+    target: Target,
+    no_create_dirs: bool,
+) -> Result<()> {
+    let remote_path = target.path();
+    if dry_run {
+        status!(output, "Would upload standard input to {}", remote_path);
+        return Ok(());
+    }
+
+    let pool = connect_pool(server, 1).await?;
+    let primary = pool.primary();
+    let mut client = primary.lock().await;
+    let listed = client.list_files(&target.dir).await;
+    if matches!(&listed, Err(e) if is_not_found(e)) {
+        create_upload_dir(client.as_mut(), &target.dir, no_create_dirs, output).await?;
+    }
+
+    // Sent as it's read, so nothing is kept here and a failed upload can't be retried
+    let started = Instant::now();
+    let stdin: Box<dyn io::Read + Send> = Box::new(io::stdin());
+    let result = interruptible(client.upload_reader(stdin, &remote_path, no_progress())).await?;
+    let elapsed = started.elapsed();
+    if let Some(history) = open_history(server) {
+        let size = *result.as_ref().unwrap_or(&0);
+        history.record(Operation::Upload, &remote_path, None, size, elapsed, &result);
+    }
+    let size = result.with_context(|| format!("Upload to {} failed", remote_path))?;
 
-async fn browse_mode(start_path: String) -> Result<()> {
-    // Connect to server
-    let mut config = Config::load()?;
-    ensure_password(&mut config)?;
-    
-    let mut conn_mgr = connection::ConnectionManager::new(config);
-    let client = conn_mgr.connect().await?;
+    let summary = TransferSummary {
+        operation: "upload",
+        succeeded: 1,
+        skipped: 0,
+        failed: 0,
+        recovered: 0,
+        bytes: size,
+        elapsed_secs: elapsed.as_secs_f64(),
+    };
+    if !print_records(output, &[summary])? {
+        println!(
+            "Uploaded standard input to {}: {}",
+            remote_path,
+            summary.stats()
+        );
+    }
+    Ok(())
+}
 
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    stdout.execute(EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+/// List the transfers that failed and why, once a batch is done. Shown even
+/// with `--quiet`.
+fn report_failures(output: OutputFormat, failures: &[(String, String)]) {
+    if failures.is_empty() {
+        return;
+    }
+    let lines = std::iter::once("\nFailed:".to_string())
+        .chain(failures.iter().map(|(what, error)| format!("  {}: {}", what, error)));
+    for line in lines {
+        if output.is_table() {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
 
-    // Run the browser
-    let mut browser = FileBrowser::new(start_path, client);
-    let res = browser.run(&mut terminal).await;
+fn retry_note(recovered: usize) -> String {
+    if recovered == 0 {
+        String::new()
+    } else {
+        format!(" ({} after retry)", recovered)
+    }
+}
 
-    // Restore terminal
-    disable_raw_mode()?;
-    terminal.backend_mut().execute(LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
+/// The saved config with the selected profile, environment variables, and flags applied
+fn resolve_config(server: &ServerArgs) -> Result<Config> {
+    let mut config = load_config(server)?
+        .resolve_profile(server.profile.as_deref())
+        .map_err(exit::config)?;
+    config.apply_overrides(&overrides(server).map_err(exit::config)?);
+
+    if !server.no_keyring {
+        config.load_keyring_password();
+    }
+    Ok(config)
+}
+
+/// The settings given as flags, then as environment variables
+fn overrides(server: &ServerArgs) -> Result<Overrides> {
+    let flags = Overrides {
+        server_ip: server.server.clone(),
+        username: server.username.clone(),
+        password: server.password.clone(),
+        protocol: server.protocol,
+    };
+    Ok(flags.or(Overrides::from_env()?))
+}
+
+/// Where this run records its transfers and deletions, stamped with the server,
+/// user, and profile they're done as. `None` when there's nowhere to keep it.
+fn open_history(server: &ServerArgs) -> Option<History> {
+    let mut config = Config::load_without_keyring()
+        .and_then(|config| config.resolve_profile(server.profile.as_deref()))
+        .ok()?;
+    config.apply_overrides(&overrides(server).ok()?);
+    let profile = server.profile.as_deref().or(config.default_profile.as_deref());
+    History::for_config(&config, profile)
+}
+
+/// The format `upload --compress` compresses a file named `name` in: none for
+/// files that are compressed already
+fn compression_for(name: &str, compress: Option<Compression>) -> Option<Compression> {
+    compress.filter(|_| Compression::from_name(name).is_none())
+}
+
+/// Write the copy of `file` that `upload` sends to `staged`: compressed in
+/// `format`, then encrypted with `key`. Returns its size.
+fn prepare_upload(
+    file: &Path,
+    staged: &Path,
+    format: Option<Compression>,
+    key: Option<&EncryptionKey>,
+) -> Result<u64> {
+    match (format, key) {
+        (Some(format), Some(key)) => {
+            let compressed = partial_path(staged);
+            let result = compress_file(file, &compressed, format)
+                .and_then(|_| encrypt_file(&compressed, staged, key));
+            let _ = std::fs::remove_file(&compressed);
+            result
+        }
+        (Some(format), None) => compress_file(file, staged, format),
+        (None, Some(key)) => encrypt_file(file, staged, key),
+        (None, None) => Ok(std::fs::copy(file, staged)?),
+    }
+}
+
+/// The key `upload --encrypt` encrypts with
+fn encryption_key(server: &ServerArgs) -> Result<EncryptionKey> {
+    EncryptionKey::load(!server.no_keyring)?.ok_or_else(|| {
+        anyhow!(
+            "No encryption key; create one with `comfy-fs config --new-encryption-key` or set {}",
+            crypt::KEY_VAR
+        )
+    })
+}
+
+fn store_encryption_key(key: &EncryptionKey) -> Result<()> {
+    key.store().map_err(|e| {
+        anyhow!(
+            "Could not save the encryption key in the OS keyring ({}); set {} instead",
+            e,
+            crypt::KEY_VAR
+        )
+    })?;
+    println!("Encryption key saved in the OS keyring");
+    Ok(())
+}
+
+/// `config test`: log in as any command would, then say how
+async fn test_login(server: &ServerArgs, output: OutputFormat) -> Result<()> {
+    let mut config = resolve_config(server)?;
+    if !config.is_configured() {
+        return Err(exit::config(anyhow!(
+            "No server is configured; run `comfy-fs config` to set one up"
+        )));
+    }
+    ensure_password(&mut config, server.prompt)?;
+    let (server_ip, username) = (config.server_ip.clone(), config.username.clone());
+
+    // Failed attempts are still printed, so fallbacks show which protocols didn't work
+    let mut manager = connection::ConnectionManager::new(config)
+        .with_quiet(true)
+        .with_prompt(server.prompt);
+    let started = Instant::now();
+    manager.connect().await?;
+    let protocol = manager.protocol().map(|protocol| protocol.to_string()).unwrap_or_default();
+    let elapsed_ms = started.elapsed().as_millis();
+
+    let record = serde_json::json!({
+        "server": server_ip,
+        "username": username,
+        "protocol": protocol,
+        "elapsed_ms": elapsed_ms,
+    });
+    if !print_records(output, &[record])? {
+        println!("Logged in to {} as {} via {} ({} ms)", server_ip, username, protocol, elapsed_ms);
+    }
+    Ok(())
+}
+
+/// `config set-password`: save a new password for the server and username in use
+fn set_password(server: &ServerArgs) -> Result<()> {
+    if server.no_keyring {
+        return Err(exit::config(anyhow!(
+            "Passwords are only saved in the OS keyring; without it, set COMFY_FS_PASSWORD or \
+             pass --password-stdin on each run"
+        )));
+    }
+    let mut config = resolve_config(server)?;
+    if !config.is_configured() {
+        return Err(exit::config(anyhow!(
+            "No server is configured; run `comfy-fs config` to set one up"
+        )));
+    }
+    let password = match &server.password {
+        Some(password) => password.clone(),
+        None if server.prompt => {
+            let password = rpassword::prompt_password("New password (hidden): ")?;
+            if rpassword::prompt_password("Again: ")? != password {
+                bail!("The passwords don't match; nothing was changed");
+            }
+            password
+        }
+        None => {
+            return Err(exit::config(anyhow!(
+                "`config set-password` asks for the password in a terminal; pass it with \
+                 --password-stdin instead"
+            )))
+        }
+    };
+    if password.is_empty() {
+        bail!("The password is empty; nothing was changed");
+    }
+
+    config.password = Some(password);
+    config
+        .store_keyring_password()
+        .context("Could not save the password in the OS keyring")?;
+    println!(
+        "Password for {} on {} saved; check it with `comfy-fs config test`",
+        config.username, config.server_ip
+    );
+    Ok(())
+}
+
+/// The saved queue of the selected profile, or of the default one
+fn open_queue(server: &ServerArgs) -> Result<JobQueue> {
+    let config = Config::load_without_keyring()?;
+    let profile = server.profile.as_deref().or(config.default_profile.as_deref());
+    Ok(JobQueue::new(queue_path(profile)?))
+}
+
+/// Load the saved config, apply per-run overrides, and connect to the server
+async fn connect_to_server(server: &ServerArgs) -> Result<Arc<Mutex<Box<dyn FileServerClient>>>> {
+    let mut config = resolve_config(server)?;
+    ensure_password(&mut config, server.prompt)?;
+
+    let mut conn_mgr = connection::ConnectionManager::new(config)
+        .with_quiet(QUIET.load(Ordering::Relaxed))
+        .with_prompt(server.prompt);
+    conn_mgr.connect().await
+}
+
+/// Files to copy at once: `--parallel` or the command's default when given,
+/// else the config's `max_concurrent_transfers`
+fn concurrency(parallel: Option<usize>, server: &ServerArgs) -> Result<usize> {
+    parallel.map_or_else(|| load_config(server)?.concurrency(), check_concurrency)
+}
+
+/// What the config file gives commands for flags left out
+fn command_defaults() -> Result<CommandDefaults> {
+    let defaults = Config::load_without_keyring().map_err(exit::config)?.defaults;
+    defaults.check().map_err(exit::config)?;
+    Ok(defaults)
+}
+
+/// Like [`connect_to_server`], opening up to `max_clients` connections as
+/// transfers need them so they run side by side
+async fn connect_pool(server: &ServerArgs, max_clients: usize) -> Result<ClientPool> {
+    let mut config = resolve_config(server)?;
+    ensure_password(&mut config, server.prompt)?;
+
+    let mut conn_mgr = connection::ConnectionManager::new(config)
+        .with_quiet(QUIET.load(Ordering::Relaxed))
+        .with_prompt(server.prompt);
+    conn_mgr.connect_pool(max_clients).await
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(propagate_version = true)]
+#[command(
+    after_help = "Environment variables COMFY_FS_SERVER, COMFY_FS_USERNAME, COMFY_FS_PASSWORD, and \
+                  COMFY_FS_PROTOCOL override the saved settings for a single run. \
+                  COMFY_FS_ENCRYPTION_KEY gives the key for upload --encrypt and download."
+)]
+// Flags, as clap takes them
+#[allow(clippy::struct_excessive_bools)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    server: ServerArgs,
+
+    /// Output format for list, find, du, stat, and transfer summaries: table, json, or ndjson
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "table")]
+    output: OutputFormat,
+
+    /// Show what upload, download, sync, rm, mv, mkdir, or rmdir would do without doing it
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Log connection attempts and timings to stderr; -vv adds protocol commands
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Also log to a daily file in the data directory, kept for a week
+    #[arg(long, global = true)]
+    log_file: bool,
+
+    /// Never prompt: go ahead without confirmations, and fail instead of
+    /// asking for a password or settings
+    #[arg(short, long, global = true, visible_alias = "non-interactive")]
+    yes: bool,
+
+    /// Print only errors and summaries, without progress
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// How transfers show progress: bars, json (one object per event on stderr), or
+    /// none. Defaults to bars, or none with --quiet.
+    #[arg(long, global = true, value_name = "MODE")]
+    progress: Option<ProgressMode>,
+}
+
+// Options that choose which server to talk to, accepted by every command. A
+// plain comment, since clap would take a doc comment for the program's description
+#[derive(Args)]
+struct ServerArgs {
+    /// Named server profile from the config file
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Server name or IP address, IPv4 or IPv6
+    #[arg(long, global = true)]
+    server: Option<String>,
+
+    /// Username
+    #[arg(long, global = true)]
+    username: Option<String>,
+
+    /// Read the password from the first line of stdin
+    #[arg(long, global = true)]
+    password_stdin: bool,
+
+    /// Password read from stdin when --password-stdin is given
+    #[arg(skip)]
+    password: Option<String>,
+
+    /// Protocol to use for this run: smb, sftp, ftp, or http
+    #[arg(long, global = true)]
+    protocol: Option<Protocol>,
+
+    /// Don't read or store the password in the OS keyring
+    #[arg(long, global = true)]
+    no_keyring: bool,
+
+    /// Whether a missing password or setting may be asked for, which needs a
+    /// terminal and no --yes
+    #[arg(skip)]
+    prompt: bool,
+}
+
+/// How to treat local files that a download would replace
+#[derive(Args)]
+#[group(multiple = false)]
+struct OverwriteArgs {
+    /// Replace existing local files without asking
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Keep existing local files
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Replace existing local files only if the remote copy is newer
+    #[arg(long)]
+    newer_only: bool,
+}
+
+impl OverwriteArgs {
+    /// Without a flag, ask when `prompt` allows and overwrite otherwise, so scripts keep
+    /// working. A dry run never asks; it shows everything a yes would replace.
+    const fn policy(&self, dry_run: bool, prompt: bool) -> OverwritePolicy {
+        if self.overwrite {
+            OverwritePolicy::Overwrite
+        } else if self.skip_existing {
+            OverwritePolicy::SkipExisting
+        } else if self.newer_only {
+            OverwritePolicy::NewerOnly
+        } else if prompt && !dry_run {
+            OverwritePolicy::Prompt
+        } else {
+            OverwritePolicy::Overwrite
+        }
+    }
+}
+
+/// Local paths to leave out of `sync` and `upload`
+#[derive(Args)]
+struct FilterArgs {
+    /// Leave out paths matching this gitignore-style pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Keep paths matching this pattern even if excluded (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+}
+
+/// Checks on model files copied either way
+#[derive(Args)]
+struct ValidateArgs {
+    /// Check .safetensors files are whole before uploading and after downloading
+    /// (true or false)
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    validate_safetensors: bool,
+}
+
+/// What `sync` copies between, and how
+#[derive(Args)]
+struct SyncArgs {
+    /// Local directory
+    #[arg(required_unless_present_any = ["job", "all"], conflicts_with_all = ["job", "all"])]
+    local: Option<PathBuf>,
+
+    /// Remote directory
+    #[arg(required_unless_present_any = ["job", "all"], conflicts_with_all = ["job", "all"])]
+    remote: Option<String>,
+
+    #[command(flatten)]
+    jobs: JobArgs,
+
+    #[command(flatten)]
+    direction: DirectionArgs,
+
+    #[command(flatten)]
+    overwrite: OverwriteArgs,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    /// Retry each failed file up to N times (default from config, 3)
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Copy up to N files at once (default from config, 4)
+    #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+    parallel: Option<usize>,
+
+    /// Descend at most this many levels below the two directories
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// With --pull or --push, delete what the source doesn't have
+    #[arg(long)]
+    delete: bool,
+
+    /// Don't ask before deleting; set from the global --yes
+    #[arg(skip)]
+    yes: bool,
+
+    /// Seconds modification times may differ by and still match (default from config, 2)
+    #[arg(long, value_name = "SECS")]
+    time_tolerance: Option<u64>,
+
+    /// Files changed on both sides: newer, local, remote, keep-both, or ask
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "newer",
+        conflicts_with_all = ["pull", "push"]
+    )]
+    conflict: ConflictPolicy,
+
+    /// Keep running: sync local changes as they happen, and check the server
+    /// every --poll-interval unless pushing
+    #[arg(long)]
+    watch: bool,
+
+    /// With --watch, check the server for changes this often
+    #[arg(long, value_name = "SECS", default_value_t = 30, requires = "watch")]
+    poll_interval: u64,
+}
+
+/// Sync pairs saved in the config file, run instead of two directories
+#[derive(Args)]
+#[group(multiple = false)]
+struct JobArgs {
+    /// Run the sync job of this name from the config file
+    #[arg(long, value_name = "NAME")]
+    job: Option<String>,
+
+    /// Run every sync job in the config file, one after another
+    #[arg(long)]
+    all: bool,
+}
+
+/// Which way `sync` copies files
+#[derive(Args)]
+#[group(multiple = false)]
+struct DirectionArgs {
+    /// Only download: mirror the server without uploading anything
+    #[arg(long)]
+    pull: bool,
+
+    /// Only upload: copy local files up without downloading anything
+    #[arg(long)]
+    push: bool,
+
+    /// Copy missing files both ways (the default)
+    #[arg(long)]
+    two_way: bool,
+}
+
+impl DirectionArgs {
+    /// The direction given on the command line, if any
+    const fn chosen(&self) -> Option<SyncDirection> {
+        if self.pull {
+            Some(SyncDirection::Pull)
+        } else if self.push {
+            Some(SyncDirection::Push)
+        } else if self.two_way {
+            Some(SyncDirection::TwoWay)
+        } else {
+            None
+        }
+    }
+}
+
+/// Two directories for one sync pass and which way it copies, from the command
+/// line or a sync job
+struct SyncPair {
+    job: Option<String>,
+    local: PathBuf,
+    remote: String,
+    direction: SyncDirection,
+    exclude: Vec<String>,
+    include: Vec<String>,
+}
+
+impl SyncPair {
+    /// A saved job, with the direction and patterns given on the command line
+    /// taking over its direction and adding to its patterns
+    fn from_job(name: &str, job: &SyncJob, args: &SyncArgs) -> Self {
+        Self {
+            job: Some(name.to_string()),
+            local: job.local.clone(),
+            remote: job.remote.clone(),
+            direction: args.direction.chosen().unwrap_or(job.direction),
+            exclude: [&job.exclude[..], &args.filter.exclude[..]].concat(),
+            include: [&job.include[..], &args.filter.include[..]].concat(),
+        }
+    }
+}
+
+/// The pairs `sync` runs: the two directories given, `--job`, or every job
+/// with `--all`
+fn sync_pairs(args: &SyncArgs, config: &Config) -> Result<Vec<SyncPair>> {
+    if let Some(name) = &args.jobs.job {
+        return Ok(vec![SyncPair::from_job(name, config.sync_job(name)?, args)]);
+    }
+    if args.jobs.all {
+        if config.sync_jobs.is_empty() {
+            bail!("No sync jobs are defined in the config file");
+        }
+        return Ok(config
+            .sync_jobs
+            .iter()
+            .map(|(name, job)| SyncPair::from_job(name, job, args))
+            .collect());
+    }
+    let (Some(local), Some(remote)) = (&args.local, &args.remote) else {
+        bail!("Give a local and a remote directory, --job, or --all");
+    };
+    Ok(vec![SyncPair {
+        job: None,
+        local: local.clone(),
+        remote: remote.clone(),
+        direction: args.direction.chosen().unwrap_or_default(),
+        exclude: args.filter.exclude.clone(),
+        include: args.filter.include.clone(),
+    }])
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Upload files to the server
+    Upload {
+        /// Files to upload, or - for standard input, which is sent as it's read and
+        /// can't be retried
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Destination directory on server, created with its parents if missing; with -,
+        /// the path of the file to write
+        #[arg(short, long)]
+        dest: Option<String>,
+
+        /// Fail instead of creating a destination directory that doesn't exist
+        #[arg(long)]
+        no_create_dirs: bool,
+
+        /// Keep the directories each file was named with below the destination,
+        /// e.g. dir1/a.png goes to DEST/dir1/a.png
+        #[arg(long)]
+        preserve_paths: bool,
+
+        /// When two files would go to the same remote path: error, rename, or skip
+        #[arg(long, value_name = "POLICY", default_value = "error")]
+        on_conflict: NameConflict,
+
+        /// Retry each failed file up to N times (default from config, 3)
+        #[arg(long, value_name = "N")]
+        retries: Option<u32>,
+
+        /// Upload up to N files at once (default from config, 4)
+        #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+        parallel: Option<usize>,
+
+        /// Compress each file first, zstd or gzip, and store it as file.zst or file.gz
+        #[arg(long, value_name = "FORMAT")]
+        compress: Option<Compression>,
+
+        /// Encrypt each file with the key from `config --new-encryption-key`; `download`
+        /// decrypts it again
+        #[arg(long)]
+        encrypt: bool,
+
+        /// Compare checksums too before skipping files already on the server at the same size
+        #[arg(long)]
+        verify: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        validate: ValidateArgs,
+    },
+
+    /// Download files from the server
+    Download {
+        /// Remote path (supports wildcards)
+        path: String,
+
+        /// Local destination directory (default from config, the current one)
+        #[arg(short, long)]
+        dest: Option<PathBuf>,
+
+        /// Save the file under this name instead, or write it to standard output with -
+        #[arg(short = 'O', long, value_name = "FILE", conflicts_with = "dest")]
+        output_file: Option<PathBuf>,
+
+        /// Choose which of the files a wildcard matched to download from a checklist
+        #[arg(short, long, conflicts_with = "output_file")]
+        interactive: bool,
+
+        #[command(flatten)]
+        overwrite: OverwriteArgs,
+
+        /// Retry each failed file up to N times (default from config, 3)
+        #[arg(long, value_name = "N")]
+        retries: Option<u32>,
+
+        /// Download up to N files at once (default from config, 4)
+        #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+        parallel: Option<usize>,
+
+        /// Decompress .zst and .gz files after downloading them, dropping the extension
+        #[arg(long)]
+        decompress: bool,
+
+        #[command(flatten)]
+        validate: ValidateArgs,
+    },
+
+    /// List files on the server
+    List {
+        /// Directory to list
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Sort by: modified, name, size, or type (default from config, modified)
+        #[arg(short, long)]
+        sort: Option<SortMode>,
+
+        /// Reverse sort order
+        #[arg(short, long)]
+        reverse: bool,
+
+        /// Show the total size of each directory (lists everything under it)
+        #[arg(long, conflicts_with = "recursive")]
+        du: bool,
+
+        /// List subdirectories too, naming entries by their path below the directory
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// With --recursive, descend at most this many levels
+        #[arg(long, value_name = "N", requires = "recursive")]
+        max_depth: Option<usize>,
+
+        /// Detailed listing: name, type, size, modified, and permissions
+        #[arg(short = 'l', long = "long", conflicts_with = "columns")]
+        long: bool,
+
+        /// Only names, one per line, for piping into xargs and the like
+        #[arg(short = '1', long, conflicts_with_all = ["long", "columns"])]
+        names_only: bool,
+
+        /// Columns to show: name, size, modified, path, type, permissions
+        #[arg(long, value_delimiter = ',', value_name = "LIST")]
+        columns: Vec<Column>,
+    },
+
+    /// Search a remote directory tree for matching names
+    Find {
+        /// Directory to search
+        path: String,
+
+        /// Name pattern, a glob like "*.safetensors" (a regex with --regex)
+        pattern: String,
+
+        /// Treat the pattern as a regular expression
+        #[arg(long)]
+        regex: bool,
+
+        /// Only report files (f) or directories (d)
+        #[arg(long = "type", value_name = "f|d")]
+        kind: Option<EntryKind>,
+
+        /// Only report files at least this big, e.g. 500M or 2G
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        min_size: Option<u64>,
+
+        /// Only report entries modified within this age (e.g. 7d, 12h) or since a date
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        newer_than: Option<DateTime<Local>>,
+    },
+
+    /// Delete files or directories on the server
+    Rm {
+        /// Remote paths, which may contain wildcards
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Delete directories and everything in them
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+
+        /// Delete for good instead of moving to the trash
+        #[arg(long)]
+        permanent: bool,
+    },
+
+    /// Move or rename a file or directory on the server
+    Mv {
+        /// Path to move
+        src: String,
+
+        /// New path, or an existing directory to move it into
+        dst: String,
+
+        /// Replace what's already at the destination
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Create directories on the server
+    Mkdir {
+        /// Directories to create
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Create missing parents too, and don't fail if the directory exists
+        #[arg(short, long)]
+        parents: bool,
+    },
+
+    /// Remove empty directories from the server
+    Rmdir {
+        /// Directories to remove
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Print a remote file to stdout without saving it
+    Cat {
+        /// File to print
+        path: String,
+
+        /// Only print the first N lines
+        #[arg(long, value_name = "N", conflicts_with = "tail")]
+        head: Option<usize>,
+
+        /// Only print the last N lines
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+    },
+
+    /// Show details about a remote file or directory
+    Stat {
+        /// Path to describe
+        path: String,
+
+        /// Also compute a checksum of the file (reads the whole file)
+        #[arg(long, value_name = "ALGORITHM")]
+        hash: Option<HashAlgorithm>,
+
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print checksums of remote files in `sha256sum` format
+    Hash {
+        /// Files to hash, which may contain wildcards
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// sha256 or blake3
+        #[arg(short, long, default_value = "sha256")]
+        algorithm: HashAlgorithm,
+    },
+
+    /// Exit 0 if a remote path exists, 1 if it doesn't, or another status if it can't tell
+    /// (6 if the server can't be reached)
+    Exists {
+        /// Path to check
+        path: String,
+
+        /// Only succeed if it's a directory
+        #[arg(long, conflicts_with = "file")]
+        dir: bool,
+
+        /// Only succeed if it's a file
+        #[arg(long)]
+        file: bool,
+    },
+
+    /// Show a remote directory as a tree
+    Tree {
+        /// Directory to show
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Descend at most this many levels
+        #[arg(short = 'L', long = "level", value_name = "N")]
+        level: Option<usize>,
+
+        /// Show sizes, and file counts for directories (walks the whole tree even with -L)
+        #[arg(long)]
+        du: bool,
+    },
+
+    /// Show how much space each entry in a remote directory takes up
+    Du {
+        /// Directory to measure
+        #[arg(default_value = "/")]
+        path: String,
+    },
+
+    /// Browse server files interactively
+    Browse {
+        /// Starting directory
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Browse a local directory (e.g. a mounted network drive) instead of the server
+        #[arg(long)]
+        local: Option<PathBuf>,
+    },
+
+    /// Sync a local directory with the server
+    Sync(SyncArgs),
+
+    /// Interactive TUI mode
+    Interactive,
+
+    /// Check the config, network, login, and write access, with hints for what fails
+    Doctor,
+
+    /// Look for file servers on the local network, and pick one to use
+    Discover {
+        /// Network to search, e.g. 192.168.1.0/24 (default: the /24 this machine is on)
+        #[arg(long, value_name = "CIDR")]
+        subnet: Option<Subnet>,
+
+        /// How long to wait for each host to answer, in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        timeout: u64,
+    },
+
+    /// Show past uploads, downloads, and deletions, with who made them
+    History {
+        /// Only entries newer than this: 30m, 2h, 7d, 2w, or a date like 2024-05-01
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        since: Option<DateTime<Local>>,
+
+        /// Only transfers and deletions that failed
+        #[arg(long)]
+        failed_only: bool,
+
+        /// Totals of files and bytes per operation instead of the entries
+        #[arg(long)]
+        stats: bool,
+    },
+
+    /// Save transfers to run later, e.g. overnight, resuming where they stopped
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+
+    /// List, restore, or empty what rm and the browser moved to the trash
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+
+    /// Get the workflow saved in a PNG on the server without downloading the image
+    Workflow {
+        #[command(subcommand)]
+        command: WorkflowCommand,
+    },
+
+    /// Print a remote path as an address a file manager opens, and copy it to the clipboard
+    Path {
+        /// Remote path to give the address of
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Print the address for every protocol, not just the configured one
+        #[arg(long)]
+        all: bool,
+
+        /// Only print the address
+        #[arg(long)]
+        no_copy: bool,
+    },
+
+    /// Send generated images and videos to a folder per user and day on the server
+    Outputs {
+        #[command(subcommand)]
+        command: OutputsCommand,
+    },
+
+    /// Configure server settings
+    #[command(args_conflicts_with_subcommands = true)]
+    Config {
+        #[command(subcommand)]
+        command: Option<ConfigCommand>,
+
+        /// Password, saved in the OS keyring (will prompt if not provided)
+        #[arg(long)]
+        password: Option<String>,
+
+        /// FTP encryption: auto, require, or off
+        #[arg(long)]
+        ftp_tls: Option<FtpTls>,
+
+        /// SFTP servers whose host key isn't known yet: ask in a terminal,
+        /// accept-new to trust and save them, or strict to refuse them
+        #[arg(long, value_name = "POLICY")]
+        sftp_host_keys: Option<SftpHostKeys>,
+
+        /// Try other protocols when the preferred one fails (true or false)
+        #[arg(long)]
+        fallback: Option<bool>,
+
+        /// SMB share name
+        #[arg(long)]
+        share: Option<String>,
+
+        /// SMB port
+        #[arg(long)]
+        smb_port: Option<u16>,
+
+        /// FTP port
+        #[arg(long)]
+        ftp_port: Option<u16>,
+
+        /// Which addresses to try first when the server's name has both: auto, ipv4, or ipv6
+        #[arg(long, value_name = "PREF")]
+        ip_preference: Option<IpPreference>,
+
+        /// Connect through a SOCKS5 or HTTP proxy, given as a socks5 or http URL with an
+        /// optional user and password (none to connect directly)
+        #[arg(long, value_name = "URL")]
+        proxy: Option<String>,
+
+        /// Logged-in FTP sessions to keep for reuse (0 logs in for every operation)
+        #[arg(long, value_name = "N")]
+        ftp_sessions: Option<usize>,
+
+        /// Files upload, download, and sync copy at once when --parallel isn't given
+        #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+        max_concurrent_transfers: Option<usize>,
+
+        /// Seconds to wait for the server to accept a connection
+        #[arg(long, value_name = "SECS")]
+        connect_timeout: Option<u64>,
+
+        /// Seconds to wait for each reply or chunk of data before giving up
+        #[arg(long, value_name = "SECS")]
+        read_timeout: Option<u64>,
+
+        /// Profile to use when --profile isn't given
+        #[arg(long)]
+        default_profile: Option<String>,
+
+        /// Where the browser saves downloaded files
+        #[arg(long, value_name = "DIR")]
+        download_dir: Option<PathBuf>,
+
+        /// Seconds sync allows between modification times before treating a file as changed
+        #[arg(long, value_name = "SECS")]
+        sync_time_tolerance: Option<u64>,
+
+        /// Create a key for upload --encrypt, save it in the OS keyring, and print it
+        #[arg(long, conflicts_with = "encryption_key")]
+        new_encryption_key: bool,
+
+        /// Save a key printed by --new-encryption-key, e.g. on another machine
+        #[arg(long, value_name = "KEY")]
+        encryption_key: Option<EncryptionKey>,
+    },
+}
+
+/// What `config` does besides saving settings
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Log in with the saved settings and say which protocol got through
+    Test,
+
+    /// Change the saved password, leaving every other setting as it is. Reads
+    /// it from --password-stdin, or asks for it twice.
+    SetPassword,
+}
+
+/// What `queue` does with the saved jobs
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Save a download, or with --upload an upload, without running it
+    Add {
+        /// Remote file
+        remote: String,
+
+        /// Local file or directory to download into, or the file to upload
+        local: PathBuf,
+
+        /// Upload the local file to the remote path instead
+        #[arg(long)]
+        upload: bool,
+    },
+
+    /// Run the pending jobs, continuing partial downloads
+    Run {
+        /// Put failed jobs back in line first
+        #[arg(long)]
+        retry_failed: bool,
+
+        /// Retry each failed file up to N times
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        retries: u32,
+
+        /// Copy up to N files at once (default from config, 4)
+        #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+        parallel: Option<usize>,
+
+        #[command(flatten)]
+        validate: ValidateArgs,
+    },
+
+    /// Show the saved jobs and how each stands
+    Status,
+
+    /// Take jobs out of the queue by ID
+    Remove {
+        /// IDs shown by `queue status`
+        #[arg(required = true)]
+        ids: Vec<u64>,
+    },
+
+    /// Take out the jobs that are done or failed
+    Clear,
+}
+
+/// What `workflow` does with the metadata of generated images
+#[derive(Subcommand)]
+enum WorkflowCommand {
+    /// Print or save the workflow JSON embedded in a PNG
+    Extract {
+        /// PNG on the server
+        path: String,
+
+        /// Save it to this file instead of printing it
+        #[arg(short = 'o', long = "out", value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// The API-format prompt instead of the editor's workflow
+        #[arg(long)]
+        prompt: bool,
+    },
+}
+
+/// What `outputs` does with generated images and videos
+#[derive(Subcommand)]
+enum OutputsCommand {
+    /// Upload the images and videos that aren't on the server yet
+    Push {
+        /// The output directory images and videos are saved in
+        #[arg(default_value = "output")]
+        dir: PathBuf,
+
+        /// Name of your folder (default: the user you log in as)
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Where the folders for each user are on the server
+        #[arg(long, value_name = "PATH", default_value = outputs::DEFAULT_DEST)]
+        dest: String,
+
+        /// Keep watching and push outputs as they're saved
+        #[arg(long)]
+        watch: bool,
+
+        /// Retry each failed file up to N times
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        retries: u32,
+
+        /// Upload up to N files at once (default from config, 4)
+        #[arg(long, value_name = "N", value_parser = parse_concurrency)]
+        parallel: Option<usize>,
+    },
+}
+
+/// What `trash` does with deleted files
+#[derive(Subcommand)]
+enum TrashCommand {
+    /// Show what's in the trash and where it was deleted from
+    List,
+
+    /// Put items back where they were deleted from
+    Restore {
+        /// Paths they were deleted from, or batches shown by `trash list` to restore whole
+        #[arg(required = true)]
+        items: Vec<String>,
+    },
+
+    /// Delete what's in the trash for good
+    Empty {
+        /// Only what was deleted before this age (e.g. 30d) or date
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<DateTime<Local>>,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(ExitStatus::of(&e).code())
+        }
+    }
+}
+
+/// Run the command given on the command line. Its error decides the exit status.
+#[allow(clippy::future_not_send)]
+async fn run() -> Result<()> {
+    let mut cli = Cli::parse();
+    logging::init(cli.verbose, cli.log_file)?;
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    let progress = cli.progress.unwrap_or(if cli.quiet {
+        ProgressMode::None
+    } else {
+        ProgressMode::Bars
+    });
+    let _ = PROGRESS.set(progress);
+    if cli.server.password_stdin {
+        let mut password = String::new();
+        io::stdin().read_line(&mut password)?;
+        cli.server.password = Some(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+    // Prompts would wait forever in CI, where stdin isn't a terminal
+    cli.server.prompt = !cli.yes && io::stdin().is_terminal();
+
+    // Check if we need to run first-time setup
+    let needs_server = !matches!(
+        cli.command,
+        Some(
+            Commands::Config { .. }
+                | Commands::Doctor
+                | Commands::Discover { .. }
+                | Commands::History { .. }
+                | Commands::Queue {
+                    command: QueueCommand::Add { .. }
+                        | QueueCommand::Status
+                        | QueueCommand::Remove { .. }
+                        | QueueCommand::Clear
+                }
+                | Commands::Browse { local: Some(_), .. }
+        )
+    );
+    if needs_server && !resolve_config(&cli.server)?.is_configured() {
+        if !cli.server.prompt {
+            return Err(exit::config(anyhow!(
+                "No server is configured; run `comfy-fs config` in a terminal, or give \
+                 --server and --username (or COMFY_FS_SERVER and COMFY_FS_USERNAME)"
+            )));
+        }
+        let mut config = load_config(&cli.server)?;
+        config.interactive_setup().await?;
+        store_password(&config, &cli.server);
+        if !resolve_config(&cli.server)?.is_configured() {
+            return Err(exit::config(anyhow!(
+                "No server is configured; run `comfy-fs config` to set one up"
+            )));
+        }
+    }
+
+    let output = cli.output;
+    match cli.command {
+        None => {
+            // No command provided, launch interactive TUI
+            browse_mode("/".to_string(), None, &cli.server).await?;
+        }
+        Some(Commands::Interactive) => {
+            browse_mode("/".to_string(), None, &cli.server).await?;
+        }
+        Some(Commands::Browse { path, local }) => {
+            browse_mode(path, local, &cli.server).await?;
+        }
+        Some(Commands::Upload {
+            files,
+            dest,
+            no_create_dirs,
+            preserve_paths,
+            on_conflict,
+            retries,
+            parallel,
+            compress,
+            encrypt,
+            verify,
+            filter,
+            validate,
+        }) => {
+            let defaults = command_defaults()?.upload;
+            let retries = retries.or(defaults.retries).unwrap_or(DEFAULT_RETRIES);
+            let parallel = parallel.or(defaults.parallel);
+            let exclude = [defaults.exclude, filter.exclude].concat();
+            if files.iter().any(|file| file.as_os_str() == upload::STDIN) {
+                if files.len() > 1 {
+                    return Err(exit::config(anyhow!(
+                        "Standard input (-) can't be uploaded along with other files"
+                    )));
+                }
+                if cli.server.password_stdin {
+                    return Err(exit::config(anyhow!(
+                        "--password-stdin can't be used when uploading standard input"
+                    )));
+                }
+                if compress.is_some() || encrypt {
+                    return Err(exit::config(anyhow!(
+                        "--compress and --encrypt can't be used when uploading standard input"
+                    )));
+                }
+                let target = stdin_target(dest.as_deref()).map_err(exit::config)?;
+                return upload_stdin(&cli.server, output, cli.dry_run, target, no_create_dirs)
+                    .await;
+            }
+            let dest_path = dest.unwrap_or_else(|| "/".to_string());
+
+            // Wildcards the shell left alone, as Windows' do
+            let files = expand_globs(files)?;
+
+            // Named files are matched by name, as if they sat in the current directory
+            let path_filter = PathFilter::new(Path::new("."), &exclude, &filter.include)?;
+            let (files, excluded): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+                let name = file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                !path_filter.is_excluded(name, file.is_dir())
+            });
+            if !excluded.is_empty() {
+                status!(output, "Leaving out {} excluded file(s)", excluded.len());
+            }
+
+            // Where each file goes, under the name it's sent with
+            let named: Vec<_> = files
+                .into_iter()
+                .map(|file| {
+                    let filename = file.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+                    let name = compression_for(filename, compress)
+                        .map_or_else(|| filename.to_string(), |f| compressed_name(filename, f));
+                    (file, name)
+                })
+                .collect();
+            let targets = plan_targets(&dest_path, &named, preserve_paths, on_conflict)
+                .map_err(exit::config)?;
+            let mut clashing = 0;
+            let mut files = Vec::new();
+            for ((file, _), target) in named.into_iter().zip(targets) {
+                if let Some(target) = target {
+                    files.push((file, target));
+                } else {
+                    status!(output, "Skipping {:?}: another file goes to the same path", file);
+                    clashing += 1;
+                }
+            }
+
+            // Uploads are planned from local files alone, so a dry run doesn't connect
+            if cli.dry_run {
+                let mut items = Vec::new();
+                for (file, target) in &files {
+                    let Ok(metadata) = std::fs::metadata(file) else {
+                        eprintln!("File not found: {:?}", file);
+                        continue;
+                    };
+                    let item = format!("{} to {}", file.display(), target.path());
+                    items.push((item, metadata.len()));
+                }
+                report_dry_run(output, "upload", &items);
+                return Ok(());
+            }
+
+            let key = if encrypt { Some(encryption_key(&cli.server)?) } else { None };
+            let pool = connect_pool(&cli.server, concurrency(parallel, &cli.server)?).await?;
+            let retry_policy = RetryPolicy::new(retries);
+
+            status!(output, "Uploading {} files to {}", files.len(), dest_path);
+
+            let mut successful = 0;
+            let mut recovered = 0;
+            let mut failed = 0;
+            let mut up_to_date = 0;
+            let mut failures = Vec::new();
+
+            // Like rsync, files already there at the same size (and with --verify the
+            // same checksum) aren't sent again. A destination that can't be listed
+            // doesn't exist yet, so has nothing to skip.
+            let primary = pool.primary();
+            let mut existing: HashMap<String, u64> = HashMap::new();
+            let mut missing_dirs = BTreeSet::new();
+            let dirs: BTreeSet<_> = files.iter().map(|(_, target)| target.dir.clone()).collect();
+            for dir in dirs {
+                match primary.lock().await.list_files(&dir).await {
+                    Ok(entries) => existing.extend(
+                        entries
+                            .into_iter()
+                            .filter(|e| !e.is_dir)
+                            .map(|e| (format!("{}/{}", dir.trim_end_matches('/'), e.name), e.size)),
+                    ),
+                    Err(e) if is_not_found(&e) => {
+                        missing_dirs.insert(dir);
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            // Compressed and encrypted copies are made here, and removed once uploaded
+            let staging = (compress.is_some() || key.is_some())
+                .then(tempfile::tempdir)
+                .transpose()?;
+            let mut jobs = Vec::new();
+            let mut sources = Vec::new();
+            let mut needed_dirs = BTreeSet::new();
+            for (file, target) in files {
+                let Ok(metadata) = std::fs::metadata(&file) else {
+                    failures.push((file.display().to_string(), "file not found".to_string()));
+                    failed += 1;
+                    continue;
+                };
+                // Checked before it's compressed or encrypted, when it can still be read
+                if validate.validate_safetensors && safetensors::is_safetensors(&file) {
+                    let path = file.clone();
+                    let checked =
+                        tokio::task::spawn_blocking(move || safetensors::validate(&path)).await?;
+                    if let Err(e) = checked {
+                        failures.push((file.display().to_string(), format!("{:#}", e)));
+                        failed += 1;
+                        continue;
+                    }
+                }
+                let filename = file.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+                let format = compression_for(filename, compress);
+                let name = &target.name;
+                let (upload_path, size) = match &staging {
+                    Some(staging) if format.is_some() || key.is_some() => {
+                        let staged = staging.path().join(format!("{}-{}", jobs.len(), name));
+                        let (input, staged_copy, key) = (file.clone(), staged.clone(), key.clone());
+                        let prepared = tokio::task::spawn_blocking(move || {
+                            prepare_upload(&input, &staged_copy, format, key.as_ref())
+                        })
+                        .await?;
+                        let size = match prepared {
+                            Ok(size) => size,
+                            Err(e) => {
+                                let error = format!("could not prepare it: {:#}", e);
+                                failures.push((file.display().to_string(), error));
+                                failed += 1;
+                                continue;
+                            }
+                        };
+                        if format.is_some() {
+                            status!(
+                                output,
+                                "Compressed {:?} from {} to {}",
+                                file,
+                                human_bytes::human_bytes(metadata.len() as f64),
+                                human_bytes::human_bytes(size as f64)
+                            );
+                        }
+                        (staged, size)
+                    }
+                    _ => (file.clone(), metadata.len()),
+                };
+                let remote_path = target.path();
+                if existing.get(&remote_path) == Some(&size) {
+                    let same = !verify || {
+                        let mut client = primary.lock().await;
+                        // A checksum that can't be worked out isn't a match
+                        same_content(client.as_mut(), &upload_path, &remote_path)
+                            .await
+                            .unwrap_or(false)
+                    };
+                    if same {
+                        status!(output, "{:?} is up to date", file);
+                        up_to_date += 1;
+                        continue;
+                    }
+                }
+                if missing_dirs.contains(&target.dir) {
+                    needed_dirs.insert(target.dir);
+                }
+                jobs.push(TransferJob::upload(upload_path, remote_path, size));
+                sources.push(file);
+            }
+
+            for dir in needed_dirs {
+                create_upload_dir(primary.lock().await.as_mut(), &dir, no_create_dirs, output)
+                    .await?;
+            }
+
+            let transfers = ParallelTransfers::new(pool.clone(), pool.max_clients())
+                .with_retry_policy(retry_policy)
+                .with_history(open_history(&cli.server));
+            let started = Instant::now();
+            let results = run_transfers(transfers, &jobs).await?;
+            let mut bytes = 0;
+            for ((job, file), result) in jobs.iter().zip(&sources).zip(results) {
+                let remote_path = &job.remote_path;
+                match result {
+                    Ok(retries) => {
+                        status!(output, "Uploading {:?} to {} ... ✓", file, remote_path);
+                        successful += 1;
+                        bytes += job.size;
+                        if retries > 0 {
+                            recovered += 1;
+                        }
+                    }
+                    Err(e) => {
+                        status!(output, "Uploading {:?} to {} ... ✗", file, remote_path);
+                        failures.push((file.display().to_string(), e.to_string()));
+                        failed += 1;
+                    }
+                }
+            }
+            report_failures(output, &failures);
+
+            let summary = TransferSummary {
+                operation: "upload",
+                succeeded: successful,
+                skipped: excluded.len() + clashing + up_to_date,
+                failed,
+                recovered,
+                bytes,
+                elapsed_secs: started.elapsed().as_secs_f64(),
+            };
+            if !print_records(output, &[summary])? {
+                println!(
+                    "\nUpload complete: {} successful{}, {} up to date, {} failed",
+                    successful,
+                    retry_note(recovered),
+                    up_to_date,
+                    failed
+                );
+                println!("Sent {}", summary.stats());
+            }
+            exit::check_transfers(&[summary])?;
+        }
+        Some(Commands::Download {
+            path,
+            dest,
+            output_file,
+            interactive,
+            overwrite,
+            retries,
+            parallel,
+            decompress,
+            validate,
+        }) => {
+            let defaults = command_defaults()?.download;
+            let dest = dest.or(defaults.dest).unwrap_or_else(|| PathBuf::from("."));
+            let retries = retries.or(defaults.retries).unwrap_or(DEFAULT_RETRIES);
+            let parallel = parallel.or(defaults.parallel);
+            if interactive && !cli.server.prompt {
+                return Err(exit::config(anyhow!(
+                    "--interactive needs a terminal, and can't be used with --yes"
+                )));
+            }
+            if output_file.is_some() && has_wildcards(&path) {
+                return Err(exit::config(anyhow!(
+                    "-O names a single file; use --dest to download files matching a pattern"
+                )));
+            }
+            if output_file.as_deref() == Some(Path::new("-")) {
+                if decompress {
+                    return Err(exit::config(anyhow!(
+                        "--decompress can't be used when writing to standard output"
+                    )));
+                }
+                // Standard output is for the file alone, so anything else goes to stderr
+                if cli.dry_run {
+                    eprintln!("Would download {} to standard output", path);
+                    return Ok(());
+                }
+                let client = connect_to_server(&cli.server).await?;
+                let mut client = client.lock().await;
+                let mut stdout = io::stdout().lock();
+                interruptible(cat::copy_to(client.as_mut(), &path, &mut stdout)).await??;
+                io::Write::flush(&mut stdout)?;
+                return Ok(());
+            }
+
+            // Encrypted files are decrypted whenever there's a key to do it with
+            let key = EncryptionKey::load(!cli.server.no_keyring)?;
+            let pool = connect_pool(&cli.server, concurrency(parallel, &cli.server)?).await?;
+            let client = pool.primary();
+            let retry_policy = RetryPolicy::new(retries);
+            let mut overwrite_guard = OverwriteGuard::new(overwrite.policy(cli.dry_run, cli.server.prompt));
+
+            // Check if path contains wildcards
+            if has_wildcards(&path) {
+                let files = Walker::new(client.clone(), 4).glob(&path).await?;
+                let mut matching: Vec<_> = files.into_iter().filter(|f| !f.is_dir).collect();
+
+                if matching.is_empty() {
+                    status!(output, "No files match pattern: {}", path);
+                    return Ok(());
+                }
+                // Files keep their place below the first wildcard, so ones with
+                // the same name in different directories don't collide
+                let base = format!("{}/", split_glob(&path).0);
+
+                if interactive {
+                    let items = matching
+                        .iter()
+                        .map(|f| {
+                            let name = f.path.strip_prefix(&base).unwrap_or(&f.path);
+                            (name.to_string(), f.size)
+                        })
+                        .collect();
+                    let title = format!("Download {}", path);
+                    let chosen = tokio::task::spawn_blocking(move || choose(title, items)).await??;
+                    let Some(chosen) = chosen else {
+                        status!(output, "Cancelled");
+                        return Ok(());
+                    };
+                    let chosen: HashSet<usize> = chosen.into_iter().collect();
+                    matching = matching
+                        .into_iter()
+                        .enumerate()
+                        .filter_map(|(i, file)| chosen.contains(&i).then_some(file))
+                        .collect();
+                    if matching.is_empty() {
+                        status!(output, "Nothing to download");
+                        return Ok(());
+                    }
+                }
+
+                let sizes: HashMap<String, u64> =
+                    matching.iter().map(|f| (f.path.clone(), f.size)).collect();
+                let matched = matching.len();
+                let mut matching_files = Vec::new();
+                for file in matching {
+                    let local_path = dest.join(file.path.strip_prefix(&base).unwrap_or(&file.name));
+                    if overwrite_guard.allow(&local_path, Some(file.modified), prompt_overwrite)? {
+                        matching_files.push((file.path, local_path));
+                    } else {
+                        status!(output, "Skipping existing {:?}", local_path);
+                    }
+                }
+
+                if matching_files.is_empty() {
+                    status!(output, "Nothing to download");
+                    return Ok(());
+                }
+                if cli.dry_run {
+                    let items: Vec<_> = matching_files
+                        .iter()
+                        .map(|(remote, local)| {
+                            let size = sizes.get(remote).copied().unwrap_or(0);
+                            (format!("{} to {}", remote, local.display()), size)
+                        })
+                        .collect();
+                    report_dry_run(output, "download", &items);
+                    return Ok(());
+                }
+
+                status!(
+                    output,
+                    "Downloading {} files matching '{}'",
+                    matching_files.len(),
+                    path
+                );
+
+                let labels = matching_files
+                    .iter()
+                    .map(|(remote, _)| {
+                        format!("Downloading {}", remote.rsplit('/').next().unwrap_or(remote))
+                    })
+                    .collect();
+                let (sender, events) = event_channel();
+                let max_concurrent = pool.max_clients();
+                let downloader = download::ParallelDownloader::new(pool, max_concurrent)
+                    .with_retry_policy(retry_policy)
+                    .with_history(open_history(&cli.server))
+                    .with_encryption_key(key)
+                    .with_decompress(decompress)
+                    .with_safetensors_validation(validate.validate_safetensors)
+                    .with_events(sender);
+                let files = matching_files.clone();
+                let progress = ProgressView::new(progress_mode(), labels).show(events);
+                let started = Instant::now();
+                let (results, ()) = interruptible(async {
+                    tokio::join!(downloader.download_files(matching_files), progress)
+                })
+                .await?;
+                let results = results?;
+
+                let mut bytes = 0;
+                let mut failures = Vec::new();
+                for ((remote, local), result) in files.into_iter().zip(&results) {
+                    match result {
+                        // Listings don't always have sizes, and a decompressed file has moved
+                        Ok(()) => {
+                            bytes += std::fs::metadata(&local).map_or_else(
+                                |_| sizes.get(&remote).copied().unwrap_or(0),
+                                |m| m.len(),
+                            );
+                        }
+                        Err(e) => failures.push((remote, format!("{:#}", e))),
+                    }
+                }
+                report_failures(output, &failures);
+
+                let successful = results.len() - failures.len();
+                let summary = TransferSummary {
+                    operation: "download",
+                    succeeded: successful,
+                    skipped: matched - results.len(),
+                    failed: failures.len(),
+                    recovered: downloader.recovered_count(),
+                    bytes,
+                    elapsed_secs: started.elapsed().as_secs_f64(),
+                };
+                if !print_records(output, &[summary])? {
+                    println!(
+                        "Downloaded {}/{} files successfully{}",
+                        successful,
+                        results.len(),
+                        retry_note(downloader.recovered_count())
+                    );
+                    println!("Received {}", summary.stats());
+                }
+                exit::check_transfers(&[summary])?;
+            } else {
+                // Single file download
+                let filename = path.rsplit('/').next().unwrap_or("file");
+                let local_path = output_file.unwrap_or_else(|| dest.join(filename));
+
+                // Only look up the remote file when there's a local file to compare
+                // with, or a dry run to report its size
+                let remote_file = if local_path.exists() || cli.dry_run {
+                    find_remote_file(&client, &path).await?
+                } else {
+                    None
+                };
+                let remote_modified = remote_file.as_ref().map(|f| f.modified);
+                if !overwrite_guard.allow(&local_path, remote_modified, prompt_overwrite)? {
+                    status!(output, "Skipping existing {:?}", local_path);
+                    return Ok(());
+                }
+                if cli.dry_run {
+                    let file = remote_file.ok_or_else(|| anyhow!("No such file: {}", path))?;
+                    let item = format!("{} to {}", path, local_path.display());
+                    report_dry_run(output, "download", &[(item, file.size)]);
+                    return Ok(());
+                }
+
+                status!(output, "Downloading {} to {:?}", path, local_path);
+
+                let started = Instant::now();
+                let download = retry_policy.run(
+                    || async {
+                        let partial = PartialFile::new(&local_path);
+                        client.lock().await.download_file(&path, &local_path).await?;
+                        partial.keep();
+                        Ok(())
+                    },
+                    |attempt, e| {
+                        eprintln!(
+                            "Download failed ({}), retrying ({}/{})",
+                            e, attempt, retry_policy.max_retries
+                        );
+                    },
+                );
+                let result = interruptible(download).await?;
+                let size = std::fs::metadata(&local_path).map_or(0, |m| m.len());
+                let elapsed = started.elapsed();
+                if let Some(history) = open_history(&cli.server) {
+                    history.record(
+                        Operation::Download,
+                        &path,
+                        Some(&local_path),
+                        size,
+                        elapsed,
+                        &result,
+                    );
+                }
+                let ((), retries) = result?;
+                let downloaded = local_path.clone();
+                let saved = tokio::task::spawn_blocking(move || {
+                    download::finish_download(
+                        downloaded,
+                        key.as_ref(),
+                        decompress,
+                        validate.validate_safetensors,
+                    )
+                })
+                .await??;
+                if saved != local_path {
+                    status!(output, "Decompressed to {:?}", saved);
+                }
+
+                let summary = TransferSummary {
+                    operation: "download",
+                    succeeded: 1,
+                    skipped: 0,
+                    failed: 0,
+                    recovered: usize::from(retries > 0),
+                    bytes: size,
+                    elapsed_secs: elapsed.as_secs_f64(),
+                };
+                if !print_records(output, &[summary])? {
+                    println!("Download complete: {}", summary.stats());
+                }
+            }
+        }
+        Some(Commands::List {
+            path,
+            sort,
+            reverse,
+            du,
+            recursive,
+            max_depth,
+            long,
+            names_only,
+            columns,
+        }) => {
+            let sort = sort.or(command_defaults()?.list.sort).unwrap_or(SortMode::Modified);
+            let client = connect_to_server(&cli.server).await?;
+
+            // Each entry with the name to show for it
+            let rows: Vec<(String, RemoteFile)> = if recursive {
+                let walker = Walker::new(client.clone(), 4).with_max_depth(max_depth);
+                let entries = sort_tree(walker.walk(&path).await?, sort, reverse);
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                entries
+                    .into_iter()
+                    .map(|e| {
+                        let relative = e.file.path.strip_prefix(&prefix).unwrap_or(&e.file.path);
+                        (relative.to_string(), e.file)
+                    })
+                    .collect()
+            } else {
+                let mut files = client.lock().await.list_files(&path).await?;
+                sort_entries(&mut files, sort, reverse);
+                files.into_iter().map(|f| (f.name.clone(), f)).collect()
+            };
+            let mut client = client.lock().await;
+
+            if !output.is_table() {
+                let mut records = Vec::with_capacity(rows.len());
+                for (_, file) in &rows {
+                    let record = FileRecord::from(file);
+                    records.push(if file.is_dir && du {
+                        record.with_size(directory_size(client.as_mut(), &file.path).await?)
+                    } else {
+                        record
+                    });
+                }
+                print_records(output, &records)?;
+                return Ok(());
+            }
+
+            if names_only {
+                for (name, _) in rows {
+                    println!("{}", name);
+                }
+                return Ok(());
+            }
+
+            let columns = if long {
+                Column::LONG.to_vec()
+            } else if columns.is_empty() {
+                Column::DEFAULT.to_vec()
+            } else {
+                columns
+            };
+            let headers: Vec<_> = columns.iter().map(|c| c.header()).collect();
+            println!("Files in {}:", path);
+            println!("{}", table_row(&columns, &headers));
+            println!("{}", "-".repeat(table_width(&columns)));
+
+            for (name, file) in rows {
+                let size_str = if file.is_dir && du {
+                    let size = directory_size(client.as_mut(), &file.path).await?;
+                    human_bytes::human_bytes(size as f64)
+                } else if file.is_dir {
+                    "DIR".to_string()
+                } else {
+                    human_bytes::human_bytes(file.size as f64)
+                };
+                let cells: Vec<String> = columns
+                    .iter()
+                    .map(|column| match column {
+                        Column::Name => name.clone(),
+                        Column::Size => size_str.clone(),
+                        Column::Modified => file.modified.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        Column::Path => file.path.clone(),
+                        Column::Type => if file.is_dir { "directory" } else { "file" }.to_string(),
+                        Column::Permissions => {
+                            file.permissions.clone().unwrap_or_else(|| "-".to_string())
+                        }
+                    })
+                    .collect();
+                println!("{}", table_row(&columns, &cells));
+            }
+        }
+        Some(Commands::Find {
+            path,
+            pattern,
+            regex,
+            kind,
+            min_size,
+            newer_than,
+        }) => {
+            let pattern = if regex {
+                NamePattern::regex(&pattern)?
+            } else {
+                NamePattern::glob(&pattern)?
+            };
+            let filter = FindFilter::new(pattern)
+                .with_kind(kind)
+                .with_min_size(min_size)
+                .with_newer_than(newer_than);
+
+            let client = connect_to_server(&cli.server).await?;
+            let entries = Walker::new(client, 4).walk(&path).await?;
+            let matches: Vec<_> = entries.iter().filter(|e| filter.matches(&e.file)).collect();
+
+            let records: Vec<_> = matches.iter().map(|e| FileRecord::from(&e.file)).collect();
+            if !print_records(output, &records)? {
+                for entry in matches {
+                    println!("{}", entry.file.path);
+                }
+            }
+        }
+        Some(Commands::Rm {
+            paths,
+            recursive,
+            force,
+            permanent,
+        }) => {
+            for path in &paths {
+                refuse_root(path)?;
+            }
+            let client = connect_to_server(&cli.server).await?;
+
+            let mut targets = Vec::new();
+            for path in &paths {
+                let matches = expand_remote(&client, path).await?;
+                if matches.is_empty() {
+                    bail!("No matches for {}", path);
+                }
+                targets.extend(matches);
+            }
+            for target in &targets {
+                refuse_root(&target.path)?;
+            }
+            if let Some(dir) = targets.iter().find(|f| f.is_dir && !recursive) {
+                bail!("{} is a directory (use --recursive to delete it)", dir.path);
+            }
+
+            for target in &targets {
+                if target.is_dir {
+                    let entries = Walker::new(client.clone(), 4).walk(&target.path).await?;
+                    let totals = DirTotals::of(&entries);
+                    println!("{}/ ({})", target.path, totals_label(totals));
+                } else {
+                    println!("{} ({})", target.path, human_bytes::human_bytes(target.size as f64));
+                }
+            }
+
+            if cli.dry_run {
+                println!("Dry run, nothing deleted");
+                return Ok(());
+            }
+            if !force && !cli.yes {
+                if !cli.server.prompt {
+                    bail!("Refusing to delete without confirmation; pass --force");
+                }
+                let question = if permanent {
+                    format!("Delete {} item(s) for good?", targets.len())
+                } else {
+                    format!("Move {} item(s) to the trash?", targets.len())
+                };
+                if !confirm(&question)? {
+                    println!("Cancelled");
+                    return Ok(());
+                }
+            }
+
+            let history = open_history(&cli.server);
+            let mut trash = Trash::new();
+            let mut failed = 0;
+            for target in &targets {
+                let mut client = client.lock().await;
+                let started = Instant::now();
+                let result = if !permanent {
+                    trash.put(client.as_mut(), &target.path).await.map(drop)
+                } else if target.is_dir {
+                    client.delete_directory(&target.path, true).await
+                } else {
+                    client.delete_file(&target.path).await
+                };
+                if let Some(history) = &history {
+                    let elapsed = started.elapsed();
+                    history.record(
+                        Operation::Delete,
+                        &target.path,
+                        None,
+                        target.size,
+                        elapsed,
+                        &result,
+                    );
+                }
+                match result {
+                    Ok(()) if permanent => println!("Deleted {}", target.path),
+                    Ok(()) => println!("Moved {} to the trash", target.path),
+                    Err(e) => {
+                        eprintln!("Failed to delete {}: {}", target.path, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if !permanent && failed < targets.len() {
+                println!("Undo with `comfy-fs trash restore {}`", trash.batch());
+            }
+            if failed > 0 {
+                return Err(exit::partial(format!(
+                    "{} of {} deletions failed",
+                    failed,
+                    targets.len()
+                )));
+            }
+        }
+        Some(Commands::Mv { src, dst, force }) => {
+            let client = connect_to_server(&cli.server).await?;
+
+            let source = find_remote_file(&client, &src)
+                .await?
+                .ok_or_else(|| anyhow!("No such file or directory: {}", src))?;
+
+            // Moving into an existing directory keeps the name
+            let into_dir = dst.ends_with('/')
+                || find_remote_file(&client, &dst).await?.is_some_and(|f| f.is_dir);
+            let target = if into_dir {
+                format!("{}/{}", dst.trim_end_matches('/'), source.name)
+            } else {
+                dst
+            };
+
+            if cli.dry_run {
+                println!("Would move {} to {}", source.path, target);
+                println!("Dry run, nothing moved");
+                return Ok(());
+            }
+            let mut client = client.lock().await;
+            if move_path(client.as_mut(), &source, &target, force).await? {
+                println!("Copied {} to {} and deleted the original", source.path, target);
+            } else {
+                println!("Moved {} to {}", source.path, target);
+            }
+        }
+        Some(Commands::Mkdir { paths, parents }) => {
+            if cli.dry_run {
+                for path in &paths {
+                    println!("Would create {}", path);
+                }
+                println!("Dry run, nothing created");
+                return Ok(());
+            }
+            let client = connect_to_server(&cli.server).await?;
+            let mut client = client.lock().await;
+
+            let mut failed = 0;
+            for path in &paths {
+                let result = if parents {
+                    create_directory_all(client.as_mut(), path).await
+                } else {
+                    client.create_directory(path).await
+                };
+                if let Err(e) = result {
+                    eprintln!("Failed to create {}: {}", path, e);
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(exit::partial(format!(
+                    "{} of {} directories could not be created",
+                    failed,
+                    paths.len()
+                )));
+            }
+        }
+        Some(Commands::Rmdir { paths }) => {
+            if cli.dry_run {
+                for path in &paths {
+                    println!("Would remove {}", path);
+                }
+                println!("Dry run, nothing removed");
+                return Ok(());
+            }
+            let client = connect_to_server(&cli.server).await?;
+            let mut client = client.lock().await;
+
+            let mut failed = 0;
+            for path in &paths {
+                if let Err(e) = client.delete_directory(path, false).await {
+                    eprintln!("Failed to remove {}: {}", path, e);
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(exit::partial(format!(
+                    "{} of {} directories could not be removed",
+                    failed,
+                    paths.len()
+                )));
+            }
+        }
+        Some(Commands::Cat { path, head, tail }) => {
+            use std::io::Write;
+
+            let client = connect_to_server(&cli.server).await?;
+            let mut client = client.lock().await;
+            let mut stdout = io::stdout().lock();
+
+            if let Some(lines) = head {
+                stdout.write_all(&cat::head_lines(client.as_mut(), &path, lines).await?)?;
+            } else if let Some(lines) = tail {
+                stdout.write_all(&cat::tail_lines(client.as_mut(), &path, lines).await?)?;
+            } else {
+                cat::copy_to(client.as_mut(), &path, &mut stdout).await?;
+            }
+            stdout.flush()?;
+        }
+        Some(Commands::Stat { path, hash, json }) => {
+            let client = connect_to_server(&cli.server).await?;
+            let file = find_remote_file(&client, &path)
+                .await?
+                .ok_or_else(|| anyhow!("No such file or directory: {}", path))?;
+
+            let checksum = match hash {
+                Some(_) if file.is_dir => bail!("Can't hash {}: it's a directory", file.path),
+                Some(algorithm) => {
+                    Some(hash_remote(client.lock().await.as_mut(), &file.path, algorithm).await?)
+                }
+                None => None,
+            };
+
+            let kind = if file.is_dir { "directory" } else { "file" };
+            if json || !output.is_table() {
+                let mut record = serde_json::to_value(FileRecord::from(&file))?;
+                record["permissions"] = file.permissions.clone().into();
+                if let (Some(algorithm), Some(checksum)) = (hash, checksum) {
+                    record[algorithm.as_str()] = checksum.into();
+                }
+                if output == OutputFormat::Ndjson {
+                    println!("{}", record);
+                } else {
+                    println!("{}", serde_json::to_string_pretty(&record)?);
+                }
+            } else {
+                println!("{:>12} {}", "Path:", file.path);
+                println!("{:>12} {}", "Type:", kind);
+                if !file.is_dir {
+                    println!(
+                        "{:>12} {} ({} bytes)",
+                        "Size:",
+                        human_bytes::human_bytes(file.size as f64),
+                        file.size
+                    );
+                }
+                println!("{:>12} {}", "Modified:", file.modified.format("%Y-%m-%d %H:%M:%S %z"));
+                if let Some(permissions) = &file.permissions {
+                    println!("{:>12} {}", "Permissions:", permissions);
+                }
+                if let (Some(algorithm), Some(checksum)) = (hash, checksum) {
+                    println!("{:>12} {}", format!("{}:", algorithm), checksum);
+                }
+            }
+        }
+        Some(Commands::Hash { paths, algorithm }) => {
+            let client = connect_to_server(&cli.server).await?;
+
+            let mut failed = 0;
+            for path in &paths {
+                let files = match expand_remote(&client, path).await {
+                    Ok(files) if files.is_empty() => Err(anyhow!("No matches")),
+                    other => other,
+                };
+                let files = match files {
+                    Ok(files) => files,
+                    Err(e) => {
+                        eprintln!("{}: {}", path, e);
+                        failed += 1;
+                        continue;
+                    }
+                };
+
+                for file in files.iter().filter(|f| !f.is_dir) {
+                    let mut client = client.lock().await;
+                    match hash_remote(client.as_mut(), &file.path, algorithm).await {
+                        Ok(checksum) => println!("{}  {}", checksum, file.path),
+                        Err(e) => {
+                            eprintln!("{}: {}", file.path, e);
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(exit::partial(format!("{} path(s) could not be hashed", failed)));
+            }
+        }
+        Some(Commands::Exists { path, dir, file }) => {
+            let client = match connect_to_server(&cli.server).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(ExitStatus::of(&e).code().into());
+                }
+            };
+
+            // Only a missing path, or one of the wrong type, is a no; a server that
+            // can't say gets the status of why
+            match find_remote_file(&client, &path).await {
+                Ok(Some(entry)) if (!dir || entry.is_dir) && (!file || !entry.is_dir) => {}
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(ExitStatus::of(&e).code().into());
+                }
+            }
+        }
+        Some(Commands::Tree { path, level, du }) => {
+            let client = connect_to_server(&cli.server).await?;
+
+            // Sizes need everything below the cut-off too
+            let walker = Walker::new(client, 4).with_max_depth(if du { None } else { level });
+            let entries = walker.walk(&path).await?;
+            let totals = du.then(|| (DirTotals::of(&entries), directory_totals(&entries)));
+
+            let shown: Vec<_> = entries
+                .into_iter()
+                .filter(|e| level.is_none_or(|max| e.depth <= max))
+                .collect();
+
+            match &totals {
+                Some((root, _)) => println!("{}  [{}]", path, totals_label(*root)),
+                None => println!("{}", path),
+            }
+            for line in render_tree(&shown, totals.as_ref().map(|(_, dirs)| dirs)) {
+                println!("{}", line);
+            }
+
+            let dirs = shown.iter().filter(|e| e.file.is_dir).count();
+            let files = shown.len() - dirs;
+            println!(
+                "\n{} {}, {} {}",
+                dirs,
+                if dirs == 1 { "directory" } else { "directories" },
+                files,
+                if files == 1 { "file" } else { "files" }
+            );
+        }
+        Some(Commands::Du { path }) => {
+            let client = connect_to_server(&cli.server).await?;
+            let entries = Walker::new(client, 4).walk(&path).await?;
+
+            let usage = child_usage(&entries);
+            let records: Vec<_> = usage
+                .iter()
+                .map(|(file, size)| FileRecord::from(*file).with_size(*size))
+                .collect();
+            if print_records(output, &records)? {
+                return Ok(());
+            }
+
+            for (file, size) in usage {
+                let suffix = if file.is_dir { "/" } else { "" };
+                println!("{:>12}  {}{}", human_bytes::human_bytes(size as f64), file.name, suffix);
+            }
+            let total = DirTotals::of(&entries);
+            println!("{:>12}  total", human_bytes::human_bytes(total.size as f64));
+        }
+        Some(Commands::Sync(mut args)) => {
+            args.yes = cli.yes;
+            let config = load_config(&cli.server)?;
+            config.defaults.check().map_err(exit::config)?;
+            let defaults = &config.defaults.sync;
+            args.retries = args.retries.or(defaults.retries);
+            args.parallel = args.parallel.or(defaults.parallel);
+            args.filter.exclude.splice(0..0, defaults.exclude.iter().cloned());
+            let pairs = sync_pairs(&args, &config)?;
+            for pair in &pairs {
+                if args.delete && pair.direction == SyncDirection::TwoWay {
+                    match &pair.job {
+                        Some(job) => bail!("--delete needs --pull or --push (sync job '{}')", job),
+                        None => bail!("--delete needs --pull or --push"),
+                    }
+                }
+            }
+            if args.conflict == ConflictPolicy::Ask && !cli.dry_run && !cli.server.prompt {
+                bail!("--conflict ask needs a terminal and no --yes; pick another policy");
+            }
+            if args.watch && cli.dry_run {
+                bail!("--watch can't be combined with --dry-run");
+            }
+            if args.watch && args.delete && !args.yes {
+                bail!("--watch with --delete needs --yes");
+            }
+            if args.watch && pairs.len() > 1 {
+                bail!("--watch runs one sync; pick a job with --job");
+            }
+            let pool = connect_pool(&cli.server, concurrency(args.parallel, &cli.server)?).await?;
+
+            // With --all, a job that fails doesn't stop the ones after it
+            let mut failed = Vec::new();
+            let mut transfers = Vec::new();
+            for pair in &pairs {
+                if let Some(job) = &pair.job {
+                    status!(output, "Running sync job '{}'", job);
+                }
+                match run_sync(&pool, pair, &args, &cli.server, cli.dry_run, output).await {
+                    Ok(Some(summaries)) => {
+                        report_sync(output, &summaries)?;
+                        transfers.extend(summaries);
+                    }
+                    Ok(None) => {}
+                    Err(e) if pairs.len() > 1 => {
+                        let job = pair.job.clone().unwrap_or_default();
+                        eprintln!("Sync job '{}' failed: {:#}", job, e);
+                        failed.push(job);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if !failed.is_empty() {
+                let count = failed.len();
+                return Err(exit::partial(format!(
+                    "{} of {} sync jobs failed: {}",
+                    count,
+                    pairs.len(),
+                    failed.join(", ")
+                )));
+            }
+            if args.watch {
+                watch_sync(&pool, &pairs[0], &args, &cli.server, output).await?;
+            } else {
+                exit::check_transfers(&transfers)?;
+            }
+        }
+        Some(Commands::Doctor) => {
+            // Never prompts: a missing password is one of the things to report
+            let checks = doctor::diagnose(&resolve_config(&cli.server)?).await;
+            if !print_records(output, &checks)? {
+                for line in doctor::table(&checks) {
+                    println!("{}", line);
+                }
+            }
+            let failed = checks.iter().filter(|c| c.status == doctor::Status::Fail).count();
+            if failed > 0 {
+                bail!("{} of {} checks failed", failed, checks.len());
+            }
+        }
+        Some(Commands::Discover { subnet, timeout }) => {
+            let subnet = match subnet {
+                Some(subnet) => subnet,
+                None => Subnet::local().map_err(exit::config)?,
+            };
+            status!(output, "Searching {} for file servers...", subnet);
+            let timeout = Duration::from_millis(timeout);
+            let hosts =
+                tokio::task::spawn_blocking(move || discover::discover(&subnet, timeout)).await?;
+            if print_records(output, &hosts)? {
+                return Ok(());
+            }
+            if hosts.is_empty() {
+                println!("No file servers found on {}", subnet);
+                return Ok(());
+            }
+            for line in discover::table(&hosts) {
+                println!("{}", line);
+            }
+            // Listing is all there is to do without someone to choose
+            if !cli.server.prompt {
+                return Ok(());
+            }
+            let Some(host) = discover::choose(&hosts)? else {
+                return Ok(());
+            };
+            save_discovered(host, &cli.server)?;
+        }
+        Some(Commands::History {
+            since,
+            failed_only,
+            stats,
+        }) => {
+            let path = history_path().ok_or_else(|| anyhow!("Could not find the data directory"))?;
+            // `--profile` narrows it to what was done as that profile
+            let filter = HistoryFilter {
+                since,
+                failed_only,
+                profile: cli.server.profile.clone(),
+            };
+            let entries: Vec<_> = read_history(&path)?
+                .into_iter()
+                .filter(|entry| filter.matches(entry))
+                .collect();
+
+            if stats {
+                let totals = history_stats(&entries);
+                if !print_records(output, &totals)? {
+                    for line in history::stats_table(&totals) {
+                        println!("{}", line);
+                    }
+                }
+            } else if !print_records(output, &entries)? {
+                if entries.is_empty() {
+                    println!("Nothing recorded in {} matches", path.display());
+                    return Ok(());
+                }
+                for line in history::history_table(&entries) {
+                    println!("{}", line);
+                }
+            }
+        }
+        Some(Commands::Queue { command }) => {
+            run_queue_command(command, &cli.server, output, cli.dry_run).await?;
+        }
+        Some(Commands::Trash { command }) => {
+            run_trash_command(command, &cli.server, output, cli.dry_run, cli.yes).await?;
+        }
+        Some(Commands::Workflow {
+            command: WorkflowCommand::Extract { path, out, prompt },
+        }) => {
+            let client = connect_to_server(&cli.server).await?;
+            let embedded = if prompt { Embedded::Prompt } else { Embedded::Workflow };
+            let json = workflow::extract(client.lock().await.as_mut(), &path, embedded).await?;
+            let text = serde_json::to_string_pretty(&json)?;
+            match out {
+                Some(out) => {
+                    std::fs::write(&out, text + "\n")?;
+                    status!(output, "Saved the {} from {} to {:?}", embedded.keyword(), path, out);
+                }
+                None => println!("{}", text),
+            }
+        }
+        Some(Commands::Path { path, all, no_copy }) => {
+            let addresses = Addresses::new(&resolve_config(&cli.server)?);
+            let preferred = addresses.preferred(&path)?;
+            if all {
+                let locations = addresses.list(&path)?;
+                if !print_records(output, &locations)? {
+                    for location in &locations {
+                        println!("{:<8} {}", location.kind, location.address);
+                    }
+                }
+            } else if !print_records(output, std::slice::from_ref(&preferred))? {
+                println!("{}", preferred.address);
+            }
+            // On stderr, so the address alone can be piped on
+            if !no_copy {
+                match clipboard::copy(&preferred.address) {
+                    Ok(_) => eprintln!("Copied {} to the clipboard", preferred.address),
+                    Err(e) => eprintln!("Could not copy to the clipboard: {}", e),
+                }
+            }
+        }
+        Some(Commands::Outputs { command }) => {
+            run_outputs_command(command, &cli.server, output, cli.dry_run).await?;
+        }
+        Some(Commands::Config {
+            command: Some(command),
+            ..
+        }) => match command {
+            ConfigCommand::Test => test_login(&cli.server, output).await?,
+            ConfigCommand::SetPassword => set_password(&cli.server)?,
+        },
+        Some(Commands::Config {
+            command: None,
+            password,
+            ftp_tls,
+            sftp_host_keys,
+            fallback,
+            share,
+            smb_port,
+            ftp_port,
+            ip_preference,
+            proxy,
+            ftp_sessions,
+            max_concurrent_transfers,
+            connect_timeout,
+            read_timeout,
+            default_profile,
+            download_dir,
+            sync_time_tolerance,
+            new_encryption_key,
+            encryption_key,
+        }) => {
+            // The key isn't tied to a server or profile, so it's saved on its own
+            let key_given = new_encryption_key || encryption_key.is_some();
+            if let Some(key) = encryption_key {
+                store_encryption_key(&key)?;
+            } else if new_encryption_key {
+                // Printed before it's saved, so it isn't lost if saving fails
+                let key = EncryptionKey::generate();
+                println!("New encryption key:\n\n    {}\n", key.to_base64());
+                println!(
+                    "Keep a copy somewhere safe: files uploaded with --encrypt can't be read \
+                     without it. Give it to `config --encryption-key` on other machines."
+                );
+                store_encryption_key(&key)?;
+            }
+
+            let mut config = load_config(&cli.server)?;
+            // `--server`, `--username`, and `--protocol` are saved here rather than used for one run
+            let server = cli.server.server.clone();
+            let username = cli.server.username.clone();
+            let password = password.or_else(|| cli.server.password.clone());
+            let no_arguments = server.is_none()
+                && username.is_none()
+                && password.is_none()
+                && ftp_tls.is_none()
+                && sftp_host_keys.is_none()
+                && fallback.is_none()
+                && share.is_none()
+                && smb_port.is_none()
+                && ftp_port.is_none()
+                && ip_preference.is_none()
+                && proxy.is_none()
+                && ftp_sessions.is_none()
+                && max_concurrent_transfers.is_none()
+                && connect_timeout.is_none()
+                && read_timeout.is_none()
+                && default_profile.is_none()
+                && download_dir.is_none()
+                && sync_time_tolerance.is_none()
+                && cli.server.protocol.is_none();
+
+            if no_arguments {
+                if key_given {
+                    return Ok(());
+                }
+                if let Some(name) = &cli.server.profile {
+                    print_profile(&config, name)?;
+                } else {
+                    // If no arguments provided, run interactive setup
+                    if !cli.server.prompt {
+                        return Err(exit::config(anyhow!(
+                            "`comfy-fs config` asks for the settings in a terminal; give them \
+                             as flags instead, e.g. --server and --username"
+                        )));
+                    }
+                    config.interactive_setup().await?;
+                    store_password(&config, &cli.server);
+                }
+                return Ok(());
+            }
+
+            // Settings shared by every profile
+            if let Some(ftp_tls) = ftp_tls {
+                config.ftp_tls = ftp_tls;
+            }
+            if let Some(sftp_host_keys) = sftp_host_keys {
+                config.sftp_host_keys = sftp_host_keys;
+            }
+            if let Some(fallback) = fallback {
+                config.fallback_enabled = fallback;
+            }
+            if let Some(ip_preference) = ip_preference {
+                config.ip_preference = ip_preference;
+            }
+            if let Some(proxy) = proxy {
+                config.proxy = match proxy.as_str() {
+                    "" | "none" => None,
+                    url => Some(url.parse().map_err(exit::config)?),
+                };
+            }
+            if let Some(ftp_sessions) = ftp_sessions {
+                config.ftp_sessions = ftp_sessions;
+            }
+            if let Some(max_concurrent_transfers) = max_concurrent_transfers {
+                config.max_concurrent_transfers = max_concurrent_transfers;
+            }
+            if let Some(connect_timeout) = connect_timeout {
+                config.connect_timeout = connect_timeout;
+            }
+            if let Some(read_timeout) = read_timeout {
+                config.read_timeout = read_timeout;
+            }
+            if let Some(download_dir) = download_dir {
+                config.download_dir = Some(download_dir);
+            }
+            if let Some(sync_time_tolerance) = sync_time_tolerance {
+                config.sync_time_tolerance = sync_time_tolerance;
+            }
+
+            // Update only the provided fields, in the named profile if one was given
+            if let Some(name) = &cli.server.profile {
+                let profile = config.profiles.entry(name.clone()).or_default();
+                if let Some(server) = server {
+                    profile.server_ip = Some(server);
+                }
+                if let Some(username) = username {
+                    profile.username = Some(username);
+                }
+                if let Some(share) = share {
+                    profile.smb_share = Some(share);
+                }
+                if let Some(smb_port) = smb_port {
+                    profile.smb_port = Some(smb_port);
+                }
+                if let Some(ftp_port) = ftp_port {
+                    profile.ftp_port = Some(ftp_port);
+                }
+                if let Some(protocol) = cli.server.protocol {
+                    profile.protocol = Some(protocol);
+                }
+            } else {
+                if let Some(server) = server {
+                    config.server_ip = server;
+                }
+                if let Some(username) = username {
+                    config.username = username;
+                }
+                if let Some(share) = share {
+                    config.smb_share = share;
+                }
+                if let Some(smb_port) = smb_port {
+                    config.smb_port = smb_port;
+                }
+                if let Some(ftp_port) = ftp_port {
+                    config.ftp_port = ftp_port;
+                }
+                if let Some(protocol) = cli.server.protocol {
+                    config.default_protocol = protocol;
+                }
+                config.configured = true;
+            }
+
+            if let Some(default_profile) = default_profile {
+                if !config.profiles.contains_key(&default_profile) {
+                    anyhow::bail!("Unknown profile '{}'", default_profile);
+                }
+                config.default_profile = Some(default_profile);
+            }
+
+            config.save()?;
+
+            // Stored under the server and username it belongs to, so each profile keeps its own
+            if let Some(password) = password {
+                let mut target = match &cli.server.profile {
+                    Some(name) => config.resolve_profile(Some(name))?,
+                    None => config.clone(),
+                };
+                target.password = Some(password);
+                store_password(&target, &cli.server);
+            }
+            println!("Configuration saved successfully!");
+        }
+    }
+
+    Ok(())
+}
+
+
+async fn run_queue_command(
+    command: QueueCommand,
+    server: &ServerArgs,
+    output: OutputFormat,
+    dry_run: bool,
+) -> Result<()> {
+    let queue = open_queue(server)?;
+    match command {
+        QueueCommand::Add {
+            remote,
+            local,
+            upload,
+        } => {
+            // Jobs may run from another directory, so they keep absolute paths
+            let local = std::path::absolute(local)?;
+            let job = if upload {
+                let metadata = std::fs::metadata(&local)
+                    .map_err(|e| anyhow!("Cannot upload {}: {}", local.display(), e))?;
+                if metadata.is_dir() {
+                    bail!("{} is a directory; queue the files in it", local.display());
+                }
+                let remote = if remote.ends_with('/') {
+                    let name = local.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+                    format!("{}{}", remote, name)
+                } else {
+                    remote
+                };
+                queue.add(TransferKind::Upload, remote, local, metadata.len())?
+            } else {
+                let local = if local.is_dir() {
+                    let name = remote.rsplit('/').next().unwrap_or_default();
+                    if name.is_empty() {
+                        bail!("Nothing to download at {}", remote);
+                    }
+                    local.join(name)
+                } else {
+                    local
+                };
+                // The size is looked up when the job runs, so adding doesn't connect
+                queue.add(TransferKind::Download, remote, local, 0)?
+            };
+            status!(output, "Queued job {} in {}", job.id, queue.path().display());
+        }
+        QueueCommand::Run {
+            retry_failed,
+            retries,
+            parallel,
+            validate,
+        } => {
+            if retry_failed {
+                let count = queue.retry_failed()?;
+                if count > 0 {
+                    status!(output, "Retrying {} failed job(s)", count);
+                }
+            }
+            let pending = queue.pending()?;
+            if pending.is_empty() {
+                status!(output, "Nothing pending in {}", queue.path().display());
+                return Ok(());
+            }
+            if dry_run {
+                let items: Vec<_> = pending
+                    .iter()
+                    .map(|job| {
+                        let item = match job.kind {
+                            TransferKind::Download => format!(
+                                "download {} to {}",
+                                job.remote_path,
+                                job.local_path.display()
+                            ),
+                            TransferKind::Upload => format!(
+                                "upload {} to {}",
+                                job.local_path.display(),
+                                job.remote_path
+                            ),
+                        };
+                        (item, job.size)
+                    })
+                    .collect();
+                report_dry_run(output, "run", &items);
+                return Ok(());
+            }
+
+            let pool = connect_pool(server, concurrency(parallel, server)?).await?;
+            let client = pool.primary();
+            let mut jobs = Vec::new();
+            for job in &pending {
+                let size = match job.kind {
+                    TransferKind::Download if job.size == 0 => {
+                        client.lock().await.get_file_size(&job.remote_path).await.unwrap_or(0)
+                    }
+                    TransferKind::Download => job.size,
+                    // The file may have changed since it was queued
+                    TransferKind::Upload => {
+                        std::fs::metadata(&job.local_path).map_or(job.size, |m| m.len())
+                    }
+                };
+                jobs.push(TransferJob {
+                    kind: job.kind,
+                    remote_path: job.remote_path.clone(),
+                    local_path: job.local_path.clone(),
+                    size,
+                });
+            }
+
+            // Each job is saved as it finishes, so an interrupted run loses nothing
+            let ids: Vec<u64> = pending.iter().map(|job| job.id).collect();
+            let store = queue.clone();
+            let transfers = ParallelTransfers::new(pool.clone(), pool.max_clients())
+                .with_retry_policy(RetryPolicy::new(retries))
+                .with_resume(true)
+                .with_safetensors_validation(validate.validate_safetensors)
+                .with_history(open_history(server))
+                .with_on_finished(move |index, result| {
+                    let state = match result {
+                        Ok(_) => JobState::Done,
+                        Err(e) => JobState::Failed {
+                            error: format!("{:#}", e),
+                        },
+                    };
+                    if let Err(e) = store.set_state(ids[index], state) {
+                        eprintln!("Could not update job {} in the queue: {:#}", ids[index], e);
+                    }
+                });
+            status!(output, "Running {} queued job(s)", jobs.len());
+            let started = Instant::now();
+            let results = run_transfers(transfers, &jobs).await.map_err(|e| {
+                let message =
+                    format!("{}; `queue run` picks up the unfinished jobs where they stopped", e);
+                Exit::new(ExitStatus::of(&e), message)
+            })?;
+
+            let (mut succeeded, mut recovered, mut failed, mut bytes) = (0, 0, 0, 0);
+            let mut failures = Vec::new();
+            for ((job, transfer), result) in pending.iter().zip(&jobs).zip(results) {
+                match result {
+                    Ok(retries) => {
+                        status!(output, "Job {}: {} ... ✓", job.id, job.remote_path);
+                        succeeded += 1;
+                        bytes += transfer.size;
+                        if retries > 0 {
+                            recovered += 1;
+                        }
+                    }
+                    Err(e) => {
+                        status!(output, "Job {}: {} ... ✗", job.id, job.remote_path);
+                        failures.push((format!("job {}", job.id), e.to_string()));
+                        failed += 1;
+                    }
+                }
+            }
+            report_failures(output, &failures);
+            let summary = TransferSummary {
+                operation: "queue",
+                succeeded,
+                skipped: 0,
+                failed,
+                recovered,
+                bytes,
+                elapsed_secs: started.elapsed().as_secs_f64(),
+            };
+            if !print_records(output, &[summary])? {
+                println!(
+                    "\nQueue run complete: {} successful{}, {} failed",
+                    succeeded,
+                    retry_note(recovered),
+                    failed
+                );
+                println!("Copied {}", summary.stats());
+            }
+            exit::check_transfers(&[summary])?;
+        }
+        QueueCommand::Status => {
+            let jobs = queue.jobs()?;
+            if !print_records(output, &jobs)? {
+                if jobs.is_empty() {
+                    println!("The queue in {} is empty", queue.path().display());
+                    return Ok(());
+                }
+                for line in queue::queue_table(&jobs) {
+                    println!("{}", line);
+                }
+            }
+        }
+        QueueCommand::Remove { ids } => {
+            let mut missing = Vec::new();
+            for id in ids {
+                if queue.remove(id)? {
+                    status!(output, "Removed job {}", id);
+                } else {
+                    missing.push(id.to_string());
+                }
+            }
+            if !missing.is_empty() {
+                bail!("No job {} in the queue", missing.join(", "));
+            }
+        }
+        QueueCommand::Clear => {
+            let count = queue.clear_finished()?;
+            status!(output, "Cleared {} finished job(s)", count);
+        }
+    }
+    Ok(())
+}
+
+async fn run_outputs_command(
+    command: OutputsCommand,
+    server: &ServerArgs,
+    output: OutputFormat,
+    dry_run: bool,
+) -> Result<()> {
+    let OutputsCommand::Push {
+        dir,
+        user,
+        dest,
+        watch,
+        retries,
+        parallel,
+    } = command;
+    if !dir.is_dir() {
+        bail!("{} is not a directory; give ComfyUI's output directory", dir.display());
+    }
+    let user = match user {
+        Some(user) => user,
+        None => resolve_config(server)?.username,
+    };
+    let pool = connect_pool(server, concurrency(parallel, server)?).await?;
+    let push = PushOutputs {
+        dir: &dir,
+        dest: &dest,
+        user: &user,
+        retry_policy: RetryPolicy::new(retries),
+        dry_run,
+        output,
+    };
+
+    push.run(&pool, server).await?;
+    if !watch || dry_run {
+        return Ok(());
+    }
+
+    let mut watcher = LocalWatcher::new(&dir, DEFAULT_DEBOUNCE)?;
+    let watching = format!("Watching {:?} for new outputs (Ctrl-C to stop)", dir);
+    status!(output, "{}", watching);
+    loop {
+        tokio::select! {
+            changed = watcher.changed() => {
+                let saved = changed?.iter().any(|path| {
+                    path.file_name().and_then(|n| n.to_str()).is_some_and(outputs::is_media)
+                });
+                if !saved {
+                    continue;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                status!(output, "Stopped watching");
+                return Ok(());
+            }
+        }
+        if let Err(e) = push.run(&pool, server).await {
+            eprintln!("Push failed: {:#}", e);
+        }
+        status!(output, "{}", watching);
+    }
+}
+
+/// One pass of `outputs push`
+struct PushOutputs<'a> {
+    dir: &'a Path,
+    dest: &'a str,
+    user: &'a str,
+    retry_policy: RetryPolicy,
+    dry_run: bool,
+    output: OutputFormat,
+}
+
+impl PushOutputs<'_> {
+    async fn run(&self, pool: &ClientPool, server: &ServerArgs) -> Result<()> {
+        let output = self.output;
+        let files = outputs::scan_outputs(self.dir, self.dest, self.user)?;
+        let total = files.len();
+        let missing = {
+            let client = pool.primary();
+            let mut client = client.lock().await;
+            outputs::not_pushed(client.as_mut(), files).await
+        };
+        let up_to_date = total - missing.len();
+
+        if self.dry_run {
+            let items: Vec<_> = missing
+                .iter()
+                .map(|f| (format!("{} to {}", f.local.display(), f.remote_path), f.size))
+                .collect();
+            report_dry_run(output, "upload", &items);
+            return Ok(());
+        }
+
+        // Each day's folder, and any subfolders in it, before the files
+        let mut dirs: Vec<_> = missing
+            .iter()
+            .filter_map(|f| f.remote_path.rsplit_once('/').map(|(dir, _)| dir))
+            .collect();
+        dirs.sort_unstable();
+        dirs.dedup();
+        for dir in dirs {
+            let client = pool.primary();
+            create_directory_all(client.lock().await.as_mut(), dir).await?;
+        }
+
+        let jobs: Vec<_> = missing
+            .iter()
+            .map(|f| TransferJob::upload(f.local.clone(), f.remote_path.clone(), f.size))
+            .collect();
+        let transfers = ParallelTransfers::new(pool.clone(), pool.max_clients())
+            .with_retry_policy(self.retry_policy)
+            .with_history(open_history(server));
+        let started = Instant::now();
+        let results = run_transfers(transfers, &jobs).await?;
+
+        let (mut succeeded, mut recovered, mut failed, mut bytes) = (0, 0, 0, 0);
+        let mut failures = Vec::new();
+        for (job, result) in jobs.iter().zip(results) {
+            match result {
+                Ok(retries) => {
+                    status!(output, "Pushed {:?} to {}", job.local_path, job.remote_path);
+                    succeeded += 1;
+                    bytes += job.size;
+                    if retries > 0 {
+                        recovered += 1;
+                    }
+                }
+                Err(e) => {
+                    failures.push((job.local_path.display().to_string(), e.to_string()));
+                    failed += 1;
+                }
+            }
+        }
+        report_failures(output, &failures);
+
+        let summary = TransferSummary {
+            operation: "push",
+            succeeded,
+            skipped: up_to_date,
+            failed,
+            recovered,
+            bytes,
+            elapsed_secs: started.elapsed().as_secs_f64(),
+        };
+        if !print_records(output, &[summary])? {
+            println!(
+                "Push complete: {} uploaded{}, {} up to date, {} failed",
+                succeeded,
+                retry_note(recovered),
+                up_to_date,
+                failed
+            );
+            println!("Sent {}", summary.stats());
+        }
+        exit::check_transfers(&[summary])
+    }
+}
+
+async fn run_trash_command(
+    command: TrashCommand,
+    server: &ServerArgs,
+    output: OutputFormat,
+    dry_run: bool,
+    yes: bool,
+) -> Result<()> {
+    let client = connect_to_server(server).await?;
+    let mut client = client.lock().await;
+    let items = trash::list_trash(client.as_mut()).await?;
+    match command {
+        TrashCommand::List => {
+            if !print_records(output, &items)? {
+                if items.is_empty() {
+                    println!("The trash is empty");
+                    return Ok(());
+                }
+                for line in trash::trash_table(&items) {
+                    println!("{}", line);
+                }
+            }
+        }
+        TrashCommand::Restore { items: targets } => {
+            let mut selected = Vec::new();
+            for target in &targets {
+                let matches = trash::select_items(&items, target);
+                if matches.is_empty() {
+                    bail!("Nothing in the trash matches {}", target);
+                }
+                selected.extend(matches);
+            }
+            if dry_run {
+                for item in &selected {
+                    println!("Would restore {}", item.original_path);
+                }
+                return Ok(());
+            }
+            let mut failed = 0;
+            for item in &selected {
+                match trash::restore(client.as_mut(), item).await {
+                    Ok(()) => status!(output, "Restored {}", item.original_path),
+                    Err(e) => {
+                        eprintln!("Failed to restore {}: {}", item.original_path, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(exit::partial(format!(
+                    "{} of {} restores failed",
+                    failed,
+                    selected.len()
+                )));
+            }
+        }
+        TrashCommand::Empty { older_than, force } => {
+            let expired = trash::deleted_before(items, older_than);
+            if expired.is_empty() {
+                status!(output, "Nothing in the trash to delete");
+                return Ok(());
+            }
+            let bytes = expired.iter().map(|item| item.size).sum::<u64>();
+            let label = format!(
+                "{} item(s), {} in files",
+                expired.len(),
+                human_bytes::human_bytes(bytes as f64)
+            );
+            if dry_run {
+                println!("Would delete {} for good", label);
+                return Ok(());
+            }
+            if !force && !yes {
+                if !server.prompt {
+                    bail!("Refusing to empty the trash without confirmation; pass --force");
+                }
+                if !confirm(&format!("Delete {} for good?", label))? {
+                    println!("Cancelled");
+                    return Ok(());
+                }
+            }
+            let count = trash::empty_trash(client.as_mut(), &expired).await?;
+            status!(output, "Deleted {} item(s) from the trash", count);
+        }
+    }
+    Ok(())
+}
+
+fn print_profile(config: &Config, name: &str) -> Result<()> {
+    let resolved = config.resolve_profile(Some(name))?;
+    let is_default = config.default_profile.as_deref() == Some(name);
+
+    println!("Profile: {}{}", name, if is_default { " (default)" } else { "" });
+    println!("  Server:   {}", resolved.server_ip);
+    println!("  Username: {}", resolved.username);
+    println!("  Protocol: {}", resolved.default_protocol);
+    println!("  Share:    {}", resolved.smb_share);
+    Ok(())
+}
+
+async fn browse_mode(
+    start_path: String,
+    local_root: Option<PathBuf>,
+    server: &ServerArgs,
+) -> Result<()> {
+    // Only changes to the server are history, and only its transfers are queued
+    let (client, history, queue, addresses) = if let Some(root) = local_root {
+        let mut local_client = LocalClient::new(root);
+        local_client.connect().await?;
+        let client: Box<dyn FileServerClient> = Box::new(PathGuard::new(Box::new(local_client)));
+        (Arc::new(Mutex::new(client)), None, None, None)
+    } else {
+        (
+            connect_to_server(server).await?,
+            open_history(server),
+            Some(open_queue(server)?),
+            Some(Addresses::new(&resolve_config(server)?)),
+        )
+    };
+
+    // Read settings before taking over the terminal so errors are readable
+    let download_dir = Config::load_without_keyring()?.download_dir();
+    let keymap = KeyMap::load()?;
+    let mut browser = FileBrowser::new(start_path, client)
+        .with_download_dir(download_dir)
+        .with_keymap(keymap)
+        .with_history(history)
+        .with_addresses(addresses);
+    if let Some(queue) = queue {
+        browser = browser.with_job_queue(queue)?;
+    }
 
-    res
+    // The terminal is restored when the guard drops, whichever way the browser exits
+    let mut guard = TerminalGuard::enter()?;
+    browser.run(guard.terminal()).await
 }
 
@@ -0,0 +1,364 @@
+//! Structured output for scripts: JSON arrays or one JSON object per line
+
+use crate::client::RemoteFile;
+use crate::utils::format_duration;
+use anyhow::{anyhow, Result};
+use human_bytes::human_bytes;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Table,
+    /// One JSON array per command
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub const fn is_table(self) -> bool {
+        matches!(self, Self::Table)
+    }
+
+    /// `records` as this format's text, or `None` for tables, which each command
+    /// lays out itself
+    pub fn render<T: Serialize>(self, records: &[T]) -> Result<Option<String>> {
+        match self {
+            Self::Table => Ok(None),
+            Self::Json => Ok(Some(serde_json::to_string_pretty(records)?)),
+            Self::Ndjson => {
+                let lines = records
+                    .iter()
+                    .map(serde_json::to_string)
+                    .collect::<serde_json::Result<Vec<_>>>()?;
+                Ok(Some(lines.join("\n")))
+            }
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" | "text" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            _ => Err(anyhow!(
+                "Invalid output format '{}' (expected table, json, or ndjson)",
+                s
+            )),
+        }
+    }
+}
+
+/// A column of the `list` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Name,
+    Size,
+    Modified,
+    Path,
+    Type,
+    Permissions,
+}
+
+impl Column {
+    /// What `list` shows without options
+    pub const DEFAULT: [Self; 3] = [Self::Name, Self::Size, Self::Modified];
+
+    /// What `list -l` shows
+    pub const LONG: [Self; 5] = [
+        Self::Name,
+        Self::Type,
+        Self::Size,
+        Self::Modified,
+        Self::Permissions,
+    ];
+
+    pub const fn header(self) -> &'static str {
+        match self {
+            Self::Name => "Name",
+            Self::Size => "Size",
+            Self::Modified => "Modified",
+            Self::Path => "Path",
+            Self::Type => "Type",
+            Self::Permissions => "Permissions",
+        }
+    }
+
+    const fn width(self) -> usize {
+        match self {
+            Self::Name | Self::Path => 50,
+            Self::Size => 10,
+            Self::Modified => 20,
+            Self::Type => 9,
+            Self::Permissions => 11,
+        }
+    }
+
+    const fn right_aligned(self) -> bool {
+        matches!(self, Self::Size | Self::Modified)
+    }
+}
+
+impl FromStr for Column {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            "modified" | "mtime" => Ok(Self::Modified),
+            "path" => Ok(Self::Path),
+            "type" => Ok(Self::Type),
+            "permissions" | "perms" => Ok(Self::Permissions),
+            _ => Err(anyhow!(
+                "Invalid column '{}' (expected name, size, modified, path, type, or permissions)",
+                s
+            )),
+        }
+    }
+}
+
+/// One table line with each cell padded to its column's width. The last column
+/// isn't padded on the right, so lines have no trailing spaces.
+pub fn table_row<S: AsRef<str>>(columns: &[Column], cells: &[S]) -> String {
+    let last = columns.len().saturating_sub(1);
+    columns
+        .iter()
+        .zip(cells)
+        .enumerate()
+        .map(|(i, (column, cell))| {
+            let cell = cell.as_ref();
+            if column.right_aligned() {
+                format!("{:>width$}", cell, width = column.width())
+            } else if i == last {
+                cell.to_string()
+            } else {
+                format!("{:<width$}", cell, width = column.width())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Width of a full table line, for the rule under the header
+pub fn table_width(columns: &[Column]) -> usize {
+    columns.iter().map(|c| c.width()).sum::<usize>() + columns.len().saturating_sub(1)
+}
+
+/// A file or directory as scripts see it
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    /// RFC 3339 timestamp
+    pub mtime: String,
+    pub is_dir: bool,
+}
+
+impl From<&RemoteFile> for FileRecord {
+    fn from(file: &RemoteFile) -> Self {
+        Self {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            size: file.size,
+            mtime: file.modified.to_rfc3339(),
+            is_dir: file.is_dir,
+        }
+    }
+}
+
+impl FileRecord {
+    /// The record with `size` replaced, e.g. by a directory's total
+    #[must_use]
+    pub const fn with_size(mut self, size: u64) -> Self {
+        self.size = size;
+        self
+    }
+}
+
+/// The outcome of an upload, download, or sync
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TransferSummary {
+    pub operation: &'static str,
+    pub succeeded: usize,
+    /// Files left alone on purpose, e.g. ones not to be overwritten
+    pub skipped: usize,
+    pub failed: usize,
+    /// Transfers that only succeeded after a retry
+    pub recovered: usize,
+    /// Bytes in the files that were copied
+    pub bytes: u64,
+    /// Seconds the copying took
+    pub elapsed_secs: f64,
+}
+
+impl TransferSummary {
+    /// Average bytes per second, once something has been copied
+    #[allow(clippy::cast_precision_loss)]
+    pub fn throughput(&self) -> Option<f64> {
+        (self.bytes > 0 && self.elapsed_secs > 0.0).then(|| self.bytes as f64 / self.elapsed_secs)
+    }
+
+    /// How much was copied and how fast, e.g. `1.2 GB in 0:42 (29.3 MB/s)`
+    #[allow(clippy::cast_precision_loss)]
+    pub fn stats(&self) -> String {
+        let mut stats = format!(
+            "{} in {}",
+            human_bytes(self.bytes as f64),
+            format_duration(Duration::from_secs_f64(self.elapsed_secs))
+        );
+        if let Some(throughput) = self.throughput() {
+            stats.push_str(&format!(" ({}/s)", human_bytes(throughput)));
+        }
+        stats
+    }
+}
+
+/// The summaries as a table, one line per operation under a header
+#[allow(clippy::cast_precision_loss)]
+pub fn summary_table(summaries: &[TransferSummary]) -> Vec<String> {
+    let row = |cells: [&str; 7]| {
+        format!(
+            "{:<10} {:>9} {:>8} {:>7} {:>10} {:>7} {:>11}",
+            cells[0], cells[1], cells[2], cells[3], cells[4], cells[5], cells[6]
+        )
+    };
+    let mut lines = vec![row([
+        "",
+        "Succeeded",
+        "Skipped",
+        "Failed",
+        "Copied",
+        "Time",
+        "Speed",
+    ])];
+    for summary in summaries {
+        let counts = [summary.succeeded, summary.skipped, summary.failed].map(|n| n.to_string());
+        // Deletes and moves copy nothing
+        let copied = if summary.bytes > 0 {
+            human_bytes(summary.bytes as f64)
+        } else {
+            "-".to_string()
+        };
+        let time = if summary.elapsed_secs > 0.0 {
+            format_duration(Duration::from_secs_f64(summary.elapsed_secs))
+        } else {
+            "-".to_string()
+        };
+        let speed = summary
+            .throughput()
+            .map_or_else(|| "-".to_string(), |t| format!("{}/s", human_bytes(t)));
+        lines.push(row([
+            summary.operation,
+            &counts[0],
+            &counts[1],
+            &counts[2],
+            &copied,
+            &time,
+            &speed,
+        ]));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn file() -> RemoteFile {
+        RemoteFile {
+            name: "a.png".to_string(),
+            path: "/output/a.png".to_string(),
+            size: 42,
+            modified: Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            is_dir: false,
+            permissions: None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let records = vec![FileRecord::from(&file()), FileRecord::from(&file()).with_size(7)];
+
+        assert!(OutputFormat::Table.render(&records).unwrap().is_none());
+
+        let ndjson = OutputFormat::Ndjson.render(&records).unwrap().unwrap();
+        let lines: Vec<serde_json::Value> =
+            ndjson.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], "/output/a.png");
+        assert_eq!(lines[0]["is_dir"], false);
+        assert_eq!(lines[1]["size"], 7);
+        assert!(lines[0]["mtime"].as_str().unwrap().starts_with("2024-05-01T12:00:00"));
+
+        let json = OutputFormat::Json.render(&records).unwrap().unwrap();
+        let array: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(array.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_table_row() {
+        assert_eq!(
+            table_row(&Column::DEFAULT, &["a.png", "42 B", "2024-05-01 12:00:00"]),
+            format!("{:<50} {:>10} {:>20}", "a.png", "42 B", "2024-05-01 12:00:00")
+        );
+        assert_eq!(table_width(&Column::DEFAULT), 82);
+        assert_eq!(
+            table_row(&[Column::Size, Column::Path], &["42 B", "/output/a.png"]),
+            "      42 B /output/a.png"
+        );
+    }
+
+    #[test]
+    fn test_summary_table() {
+        let summary = TransferSummary {
+            operation: "download",
+            succeeded: 12,
+            skipped: 1,
+            failed: 0,
+            recovered: 2,
+            bytes: 3_000_000,
+            elapsed_secs: 65.0,
+        };
+        let delete = TransferSummary {
+            operation: "delete",
+            bytes: 0,
+            elapsed_secs: 0.0,
+            ..summary
+        };
+        assert_eq!(
+            summary_table(&[summary, delete]),
+            [
+                "           Succeeded  Skipped  Failed     Copied    Time       Speed",
+                "download          12        1       0    2.9 MiB    1:05  45.1 KiB/s",
+                "delete            12        1       0          -       -           -",
+            ]
+        );
+        assert_eq!(summary.stats(), "2.9 MiB in 1:05 (45.1 KiB/s)");
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!("Path".parse::<Column>().unwrap(), Column::Path);
+        assert_eq!("mtime".parse::<Column>().unwrap(), Column::Modified);
+        assert!("owner".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("json".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert_eq!("NDJSON".parse::<OutputFormat>().unwrap(), OutputFormat::Ndjson);
+        assert_eq!("table".parse::<OutputFormat>().unwrap(), OutputFormat::Table);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
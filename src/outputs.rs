@@ -0,0 +1,157 @@
+//! Sending the images and videos ComfyUI generates to the server, for
+//! `comfy-fs outputs push`
+//!
+//! Files go into a folder per user and per day they were made, like
+//! `/outputs/alice/2024-05-01/`, keeping any subfolder ComfyUI saved them in.
+
+use crate::client::FileServerClient;
+use crate::sync::walk_local;
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Where pushed outputs go on the server when `--dest` isn't given
+pub const DEFAULT_DEST: &str = "/outputs";
+
+/// Extensions of the files pushed, as ComfyUI and its video nodes save them
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "mp4", "webm", "mov", "mkv", "avi",
+];
+
+/// Whether `name` is an image or video by its extension
+pub fn is_media(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        MEDIA_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+/// The folder for `user`'s outputs from `date`
+pub fn dated_dir(dest: &str, user: &str, date: NaiveDate) -> Result<String> {
+    if user.is_empty() || user.contains('/') || user == "." || user == ".." {
+        bail!("Invalid user name '{}' for the outputs folder", user);
+    }
+    Ok(format!(
+        "{}/{}/{}",
+        dest.trim_end_matches('/'),
+        user,
+        date.format("%Y-%m-%d")
+    ))
+}
+
+/// An output and where it goes on the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFile {
+    pub local: PathBuf,
+    pub remote_path: String,
+    pub size: u64,
+}
+
+/// Every image and video under `root`, dated by when it was last written
+pub fn scan_outputs(root: &Path, dest: &str, user: &str) -> Result<Vec<OutputFile>> {
+    let mut files = Vec::new();
+    for entry in walk_local(root, None)? {
+        if entry.is_dir || !is_media(&entry.relative) {
+            continue;
+        }
+        let dir = dated_dir(dest, user, entry.modified.date_naive())?;
+        files.push(OutputFile {
+            remote_path: format!("{}/{}", dir, entry.relative),
+            local: entry.path,
+            size: entry.size,
+        });
+    }
+    Ok(files)
+}
+
+/// The files that aren't on the server yet at the same size, listing each
+/// folder once. Folders that can't be listed don't exist yet.
+pub async fn not_pushed(
+    client: &mut dyn FileServerClient,
+    files: Vec<OutputFile>,
+) -> Vec<OutputFile> {
+    let mut listings: HashMap<String, HashMap<String, u64>> = HashMap::new();
+    let mut missing = Vec::new();
+    for file in files {
+        let (dir, name) = file
+            .remote_path
+            .rsplit_once('/')
+            .unwrap_or(("", &file.remote_path));
+        if !listings.contains_key(dir) {
+            let sizes = client
+                .list_files(dir)
+                .await
+                .map(|entries| {
+                    entries
+                        .into_iter()
+                        .filter(|e| !e.is_dir)
+                        .map(|e| (e.name, e.size))
+                        .collect()
+                })
+                .unwrap_or_default();
+            listings.insert(dir.to_string(), sizes);
+        }
+        if listings[dir].get(name) != Some(&file.size) {
+            missing.push(file);
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use chrono::{DateTime, Local};
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_scan_and_skip_pushed() {
+        let output = tempfile::tempdir().unwrap();
+        fs::create_dir(output.path().join("portraits")).unwrap();
+        fs::write(output.path().join("ComfyUI_00001_.png"), b"png").unwrap();
+        fs::write(output.path().join("portraits/face_00001_.webp"), b"webp").unwrap();
+        fs::write(output.path().join("clip.MP4"), b"mp4").unwrap();
+        fs::write(output.path().join("notes.txt"), b"notes").unwrap();
+
+        let modified = fs::metadata(output.path().join("clip.MP4"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let date = DateTime::<Local>::from(modified).date_naive();
+        let dir = dated_dir("/outputs/", "alice", date).unwrap();
+        let files = scan_outputs(output.path(), "/outputs/", "alice").unwrap();
+        let remote: Vec<_> = files.iter().map(|f| f.remote_path.as_str()).collect();
+        assert_eq!(
+            remote,
+            [
+                format!("{}/ComfyUI_00001_.png", dir),
+                format!("{}/clip.MP4", dir),
+                format!("{}/portraits/face_00001_.webp", dir),
+            ]
+        );
+
+        // Only what isn't there at the same size is left
+        let server = tempfile::tempdir().unwrap();
+        let server_dir = server.path().join(dir.trim_start_matches('/'));
+        fs::create_dir_all(&server_dir).unwrap();
+        fs::write(server_dir.join("ComfyUI_00001_.png"), b"png").unwrap();
+        fs::write(server_dir.join("clip.MP4"), b"older").unwrap();
+        let mut client = LocalClient::new(server.path().to_path_buf());
+        let missing = not_pushed(&mut client, files).await;
+        assert_eq!(missing.len(), 2);
+        assert!(missing[0].remote_path.ends_with("/clip.MP4"));
+    }
+
+    #[test]
+    fn test_dated_dir() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        assert_eq!(
+            dated_dir("/outputs", "alice", date).unwrap(),
+            "/outputs/alice/2024-05-01"
+        );
+        assert!(dated_dir("/outputs", "../bob", date).is_err());
+        assert!(is_media("a.JPEG"));
+        assert!(!is_media("workflow.json"));
+    }
+}
@@ -0,0 +1,476 @@
+//! Previews of ComfyUI outputs and models, read from the start of the file so
+//! a multi-gigabyte checkpoint doesn't have to be downloaded to inspect it.
+
+use crate::client::FileServerClient;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// How much of the file the first read fetches. Enough for the metadata of
+/// most PNG outputs and LoRAs in one round trip.
+const FIRST_READ: usize = 64 * 1024;
+
+/// Stop reading once the metadata claims to be bigger than this
+const MAX_METADATA: usize = 100 * 1024 * 1024;
+
+/// The 8 bytes every PNG file starts with
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Model weight extensions, used to pick model names out of a ComfyUI prompt
+const MODEL_EXTENSIONS: [&str; 6] = [".safetensors", ".ckpt", ".pt", ".pth", ".bin", ".gguf"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Preview {
+    Png(PngInfo),
+    Safetensors(SafetensorsInfo),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PngInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Text chunks in file order. ComfyUI stores its `prompt` and `workflow`
+    /// JSON here.
+    pub text: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SafetensorsInfo {
+    pub tensor_count: usize,
+    pub parameters: u64,
+    /// How many tensors use each dtype
+    pub dtypes: BTreeMap<String, usize>,
+    /// The free-form `__metadata__` section, e.g. training settings of a LoRA
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Result of parsing the bytes read so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed<T> {
+    Done(T),
+    /// The metadata goes on past the end of the data, up to this many bytes
+    /// from the start of the file
+    NeedBytes(usize),
+}
+
+/// Whether `name` is a file type that can be previewed
+pub fn is_previewable(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".png") || name.ends_with(".safetensors")
+}
+
+/// Read just enough of the file at `path` to preview it
+pub async fn fetch(client: &mut dyn FileServerClient, path: &str) -> Result<Preview> {
+    if path.to_lowercase().ends_with(".png") {
+        fetch_png(client, path).await.map(Preview::Png)
+    } else if path.to_lowercase().ends_with(".safetensors") {
+        read_until_parsed(client, path, parse_safetensors)
+            .await
+            .map(Preview::Safetensors)
+    } else {
+        Err(anyhow!("No preview for {}", path))
+    }
+}
+
+/// The size and text chunks of the PNG at `path`, without its image data
+pub async fn fetch_png(client: &mut dyn FileServerClient, path: &str) -> Result<PngInfo> {
+    read_until_parsed(client, path, parse_png).await
+}
+
+/// Read the start of the file and keep reading more until `parse` has what it needs
+async fn read_until_parsed<T>(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    parse: fn(&[u8]) -> Result<Parsed<T>>,
+) -> Result<T> {
+    // Without ranged reads every read downloads the whole file, so take it all at once
+    let first_read = if client.capabilities().ranged_reads {
+        FIRST_READ as u64
+    } else {
+        u64::MAX
+    };
+    let mut data = client.read_range(path, 0, first_read).await?;
+    loop {
+        match parse(&data)? {
+            Parsed::Done(parsed) => return Ok(parsed),
+            Parsed::NeedBytes(needed) => {
+                if needed > MAX_METADATA {
+                    anyhow::bail!("The metadata of {} is too large to preview", path);
+                }
+                let more = (needed - data.len()).max(FIRST_READ);
+                let next = client.read_range(path, data.len() as u64, more as u64).await?;
+                if next.is_empty() {
+                    anyhow::bail!("{} ends in the middle of its metadata", path);
+                }
+                data.extend_from_slice(&next);
+            }
+        }
+    }
+}
+
+/// Read the size and text chunks that come before the image data
+pub fn parse_png(data: &[u8]) -> Result<Parsed<PngInfo>> {
+    if data.len() < PNG_SIGNATURE.len() {
+        return Ok(Parsed::NeedBytes(PNG_SIGNATURE.len()));
+    }
+    if data[..PNG_SIGNATURE.len()] != PNG_SIGNATURE {
+        anyhow::bail!("Not a PNG file");
+    }
+
+    let mut info = PngInfo::default();
+    let mut has_header = false;
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        // Length, type, data, CRC
+        let Some(header) = data.get(pos..pos + 8) else {
+            return Ok(Parsed::NeedBytes(pos + 8));
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = &header[4..8];
+        let end = pos + 12 + len;
+
+        match kind {
+            // Metadata after the image data is rare enough to not read the whole file for
+            b"IDAT" | b"IEND" => break,
+            b"IHDR" | b"tEXt" | b"iTXt" | b"zTXt" => {
+                let Some(body) = data.get(pos + 8..pos + 8 + len) else {
+                    return Ok(Parsed::NeedBytes(end));
+                };
+                match kind {
+                    b"IHDR" => {
+                        if body.len() < 8 {
+                            anyhow::bail!("PNG header is too short");
+                        }
+                        info.width = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                        info.height = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+                        has_header = true;
+                    }
+                    b"tEXt" => info.text.push(parse_text_chunk(body)),
+                    b"iTXt" => info.text.push(parse_international_text_chunk(body)),
+                    _ => {
+                        let (keyword, _) = split_at_nul(body);
+                        info.text
+                            .push((latin1(keyword), format!("(compressed, {} bytes)", len)));
+                    }
+                }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+
+    if !has_header {
+        anyhow::bail!("PNG file has no header chunk");
+    }
+    Ok(Parsed::Done(info))
+}
+
+/// `keyword\0text`, both Latin-1
+fn parse_text_chunk(body: &[u8]) -> (String, String) {
+    let (keyword, text) = split_at_nul(body);
+    (latin1(keyword), latin1(text))
+}
+
+/// `keyword\0 flag method language\0 translated\0 text`, with UTF-8 text
+fn parse_international_text_chunk(body: &[u8]) -> (String, String) {
+    let (keyword, rest) = split_at_nul(body);
+    let compressed = rest.first().is_some_and(|flag| *flag != 0);
+    let (_language, rest) = split_at_nul(rest.get(2..).unwrap_or_default());
+    let (_translated, text) = split_at_nul(rest);
+
+    let text = if compressed {
+        format!("(compressed, {} bytes)", text.len())
+    } else {
+        String::from_utf8_lossy(text).to_string()
+    };
+    (latin1(keyword), text)
+}
+
+fn split_at_nul(bytes: &[u8]) -> (&[u8], &[u8]) {
+    bytes
+        .iter()
+        .position(|b| *b == 0)
+        .map_or((bytes, &[]), |i| (&bytes[..i], &bytes[i + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| char::from(*b)).collect()
+}
+
+/// Read the JSON header: 8 bytes of little-endian length, then the JSON
+pub fn parse_safetensors(data: &[u8]) -> Result<Parsed<SafetensorsInfo>> {
+    let Some(len) = data.get(..8) else {
+        return Ok(Parsed::NeedBytes(8));
+    };
+    let len = u64::from_le_bytes(len.try_into()?);
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(8))
+        .filter(|end| *end <= MAX_METADATA)
+        .ok_or_else(|| anyhow!("safetensors header claims to be {} bytes", len))?;
+    let Some(header) = data.get(8..end) else {
+        return Ok(Parsed::NeedBytes(end));
+    };
+
+    let header: BTreeMap<String, Value> = serde_json::from_slice(header)
+        .map_err(|e| anyhow!("Invalid safetensors header: {}", e))?;
+    let mut info = SafetensorsInfo::default();
+    for (name, value) in header {
+        if name == "__metadata__" {
+            if let Value::Object(metadata) = value {
+                info.metadata = metadata
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        Value::String(s) => (key, s),
+                        other => (key, other.to_string()),
+                    })
+                    .collect();
+            }
+            continue;
+        }
+
+        info.tensor_count += 1;
+        let dtype = value["dtype"].as_str().unwrap_or("unknown").to_string();
+        *info.dtypes.entry(dtype).or_default() += 1;
+        if let Some(shape) = value["shape"].as_array() {
+            info.parameters += shape.iter().filter_map(Value::as_u64).product::<u64>();
+        }
+    }
+    Ok(Parsed::Done(info))
+}
+
+impl Preview {
+    /// A human-readable summary, one line per fact
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            Self::Png(info) => png_lines(info),
+            Self::Safetensors(info) => safetensors_lines(info),
+        }
+    }
+}
+
+fn png_lines(info: &PngInfo) -> Vec<String> {
+    let mut lines = vec![format!("PNG image, {} × {}", info.width, info.height)];
+    for (keyword, text) in &info.text {
+        match (keyword.as_str(), serde_json::from_str::<Value>(text)) {
+            ("prompt", Ok(Value::Object(nodes))) => {
+                lines.push(format!("Prompt: {} nodes", nodes.len()));
+                lines.extend(prompt_lines(&nodes));
+            }
+            ("workflow", Ok(workflow)) => {
+                let nodes = workflow["nodes"].as_array().map_or(0, Vec::len);
+                lines.push(format!("Workflow: {} nodes", nodes));
+            }
+            _ => lines.push(format!("{}: {}", keyword, truncate(text, 200))),
+        }
+    }
+    lines
+}
+
+/// Models, seeds, and prompt text from the inputs of a ComfyUI prompt
+fn prompt_lines(nodes: &serde_json::Map<String, Value>) -> Vec<String> {
+    let mut models = Vec::new();
+    let mut lines = Vec::new();
+    for node in nodes.values() {
+        let Some(inputs) = node["inputs"].as_object() else {
+            continue;
+        };
+        for (name, value) in inputs {
+            match value {
+                Value::String(s) if is_model_file(s) && !models.contains(s) => {
+                    models.push(s.clone());
+                }
+                Value::String(s) if name == "text" && !s.trim().is_empty() => {
+                    lines.push(format!("Text: {}", truncate(s.trim(), 200)));
+                }
+                Value::Number(n) if name == "seed" || name == "noise_seed" => {
+                    lines.push(format!("Seed: {}", n));
+                }
+                _ => {}
+            }
+        }
+    }
+    models
+        .into_iter()
+        .map(|model| format!("Model: {}", model))
+        .chain(lines)
+        .collect()
+}
+
+fn is_model_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    MODEL_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+fn safetensors_lines(info: &SafetensorsInfo) -> Vec<String> {
+    let dtypes: Vec<String> = info
+        .dtypes
+        .iter()
+        .map(|(dtype, count)| format!("{} × {}", dtype, count))
+        .collect();
+    let mut lines = vec![
+        format!(
+            "safetensors, {} tensors, {} parameters",
+            info.tensor_count,
+            format_count(info.parameters)
+        ),
+        format!("dtypes: {}", dtypes.join(", ")),
+    ];
+    if !info.metadata.is_empty() {
+        lines.push("Metadata:".to_string());
+        lines.extend(
+            info.metadata
+                .iter()
+                .map(|(key, value)| format!("  {}: {}", key, truncate(value, 200))),
+        );
+    }
+    lines
+}
+
+/// 2_567_000_000 as `2.57B`
+fn format_count(count: u64) -> String {
+    let units = [(1e12, "T"), (1e9, "B"), (1e6, "M"), (1e3, "K")];
+    let value = count as f64;
+    units
+        .iter()
+        .find(|(scale, _)| value >= *scale)
+        .map_or_else(
+            || count.to_string(),
+            |(scale, unit)| format!("{:.2}{}", value / scale, unit),
+        )
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    let text = text.replace('\n', " ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    let mut truncated: String = text.chars().take(max_chars).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+
+    fn chunk(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(body);
+        // The CRC isn't checked
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn comfy_png(workflow_padding: usize) -> Vec<u8> {
+        let mut ihdr = 832u32.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&1216u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let prompt = r#"{"4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "sdxl_base.safetensors"}},
+            "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "a cat in a hat", "clip": ["4", 1]}},
+            "3": {"class_type": "KSampler", "inputs": {"seed": 42, "model": ["4", 0]}}}"#;
+        let workflow = format!(
+            r#"{{"nodes": [{{"id": 3}}, {{"id": 4}}, {{"id": 6}}], "extra": "{}"}}"#,
+            "x".repeat(workflow_padding)
+        );
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &ihdr));
+        png.extend(chunk(b"tEXt", format!("prompt\0{}", prompt).as_bytes()));
+        png.extend(chunk(b"tEXt", format!("workflow\0{}", workflow).as_bytes()));
+        png.extend(chunk(b"IDAT", &[0; 32]));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    fn safetensors_file() -> Vec<u8> {
+        let header = r#"{"__metadata__": {"ss_base_model_version": "sdxl_base_v1-0"},
+            "lora_up.weight": {"dtype": "F16", "shape": [320, 8], "data_offsets": [0, 5120]},
+            "lora_down.weight": {"dtype": "F16", "shape": [8, 320], "data_offsets": [5120, 10240]},
+            "alpha": {"dtype": "F32", "shape": [], "data_offsets": [10240, 10244]}}"#;
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        file.extend(vec![0u8; 10244]);
+        file
+    }
+
+    #[test]
+    fn test_parse_png_metadata() {
+        let png = comfy_png(0);
+        let Parsed::Done(info) = parse_png(&png).unwrap() else {
+            panic!("expected the whole PNG to parse");
+        };
+
+        assert_eq!((info.width, info.height), (832, 1216));
+        assert_eq!(
+            Preview::Png(info).lines(),
+            vec![
+                "PNG image, 832 × 1216",
+                "Prompt: 3 nodes",
+                "Model: sdxl_base.safetensors",
+                "Seed: 42",
+                "Text: a cat in a hat",
+                "Workflow: 3 nodes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_png_asks_for_more_bytes() {
+        let png = comfy_png(0);
+        assert_eq!(parse_png(&png[..4]).unwrap(), Parsed::NeedBytes(8));
+        // Cut off in the middle of the prompt chunk
+        assert!(matches!(parse_png(&png[..60]).unwrap(), Parsed::NeedBytes(n) if n > 60));
+        assert!(parse_png(b"GIF89a\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_parse_safetensors_header() {
+        let file = safetensors_file();
+        assert_eq!(parse_safetensors(&file[..4]).unwrap(), Parsed::NeedBytes(8));
+
+        let Parsed::Done(info) = parse_safetensors(&file).unwrap() else {
+            panic!("expected the header to parse");
+        };
+        assert_eq!(info.tensor_count, 3);
+        assert_eq!(info.parameters, 320 * 8 * 2 + 1);
+        assert_eq!(
+            Preview::Safetensors(info).lines(),
+            vec![
+                "safetensors, 3 tensors, 5.12K parameters",
+                "dtypes: F16 × 2, F32 × 1",
+                "Metadata:",
+                "  ss_base_model_version: sdxl_base_v1-0",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_reads_past_the_first_chunk() {
+        let server_dir = tempfile::TempDir::new().unwrap();
+        // The workflow alone is bigger than the first read
+        std::fs::write(server_dir.path().join("out.png"), comfy_png(FIRST_READ * 2)).unwrap();
+        std::fs::write(server_dir.path().join("lora.safetensors"), safetensors_file()).unwrap();
+        let mut client = LocalClient::new(server_dir.path().to_path_buf());
+
+        let Preview::Png(info) = fetch(&mut client, "/out.png").await.unwrap() else {
+            panic!("expected a PNG preview");
+        };
+        assert_eq!(info.text.len(), 2);
+        assert!(matches!(
+            fetch(&mut client, "/lora.safetensors").await.unwrap(),
+            Preview::Safetensors(_)
+        ));
+        assert!(fetch(&mut client, "/notes.txt").await.is_err());
+    }
+
+    #[test]
+    fn test_format_count() {
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(2_567_000_000), "2.57B");
+        assert_eq!(format_count(12_500_000), "12.50M");
+    }
+}
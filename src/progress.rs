@@ -0,0 +1,366 @@
+//! Progress bars on the terminal, or JSON lines for other programs, from the
+//! [`TransferEvent`]s of a batch
+
+use crate::transfer::{TransferEvent, TransferEvents, TransferJob};
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How transfers show their progress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Bars on the terminal
+    #[default]
+    Bars,
+    /// A JSON object per event on stderr
+    Json,
+    /// Nothing
+    None,
+}
+
+impl FromStr for ProgressMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "bars" => Ok(Self::Bars),
+            "json" => Ok(Self::Json),
+            "none" => Ok(Self::None),
+            _ => Err(anyhow!(
+                "Invalid progress mode '{}' (expected bars, json, or none)",
+                s
+            )),
+        }
+    }
+}
+
+/// The progress of a batch, shown the way a [`ProgressMode`] says
+pub enum ProgressView {
+    Bars(ProgressBars),
+    Json(JsonProgress),
+    Hidden,
+}
+
+impl ProgressView {
+    /// A view of jobs doing what `labels` say, e.g. `Downloading a.png`
+    pub fn new(mode: ProgressMode, labels: Vec<String>) -> Self {
+        match mode {
+            ProgressMode::Bars => Self::Bars(ProgressBars::new(labels)),
+            ProgressMode::Json => Self::Json(JsonProgress::new(labels)),
+            ProgressMode::None => Self::Hidden,
+        }
+    }
+
+    /// A view of `jobs`, with a bar for the whole batch
+    pub fn for_jobs(mode: ProgressMode, jobs: &[TransferJob]) -> Self {
+        match mode {
+            ProgressMode::Bars => Self::Bars(ProgressBars::for_jobs(jobs)),
+            mode => Self::new(mode, jobs.iter().map(TransferJob::label).collect()),
+        }
+    }
+
+    /// Show `events` until every job has finished or failed, or nothing is
+    /// left to send them
+    pub async fn show(self, events: TransferEvents) {
+        match self {
+            Self::Bars(bars) => bars.draw(events).await,
+            Self::Json(json) => json.write(events).await,
+            // Jobs don't wait on anyone following their events
+            Self::Hidden => {}
+        }
+    }
+}
+
+/// Progress as one JSON object per line on stderr, for scripts and GUIs to
+/// follow: each [`TransferEvent`] with the job's label, and for copied bytes
+/// the running total out of the job's size
+pub struct JsonProgress {
+    labels: Vec<String>,
+    sizes: Vec<u64>,
+    copied: Vec<u64>,
+    ended: usize,
+}
+
+impl JsonProgress {
+    pub fn new(labels: Vec<String>) -> Self {
+        Self {
+            sizes: vec![0; labels.len()],
+            copied: vec![0; labels.len()],
+            labels,
+            ended: 0,
+        }
+    }
+
+    /// Write `events` until every job has finished or failed, or nothing is
+    /// left to send them
+    pub async fn write(mut self, mut events: TransferEvents) {
+        while self.ended < self.labels.len() {
+            let Some(event) = events.next().await else {
+                break;
+            };
+            let record = self.record(&event);
+            // Progress is only for show, so a closed stderr doesn't stop the copying
+            let _ = writeln!(io::stderr().lock(), "{}", record);
+        }
+    }
+
+    fn record(&mut self, event: &TransferEvent) -> serde_json::Value {
+        let index = event.index();
+        let mut record = serde_json::to_value(event).unwrap_or_default();
+        record["label"] = self.labels[index].clone().into();
+        match *event {
+            TransferEvent::Started { size, .. } => {
+                self.sizes[index] = size;
+                self.copied[index] = 0;
+            }
+            TransferEvent::Bytes { bytes, .. } => {
+                self.copied[index] += bytes;
+                record["copied"] = self.copied[index].into();
+                record["size"] = self.sizes[index].into();
+            }
+            TransferEvent::Finished { .. } | TransferEvent::Failed { .. } => self.ended += 1,
+            TransferEvent::Retrying { .. } => {}
+        }
+        record
+    }
+}
+
+/// A bar for each file in flight, and optionally one for the bytes of the
+/// whole batch
+pub struct ProgressBars {
+    multi_progress: MultiProgress,
+    /// What each job is doing, by index
+    labels: Vec<String>,
+    /// Bytes each job is expected to copy, from its last start
+    sizes: Vec<u64>,
+    bars: HashMap<usize, ProgressBar>,
+    total: Option<ProgressBar>,
+    /// Jobs that have finished or failed
+    ended: usize,
+}
+
+impl ProgressBars {
+    /// Bars for jobs doing what `labels` say, e.g. `Downloading a.png`
+    pub fn new(labels: Vec<String>) -> Self {
+        Self {
+            multi_progress: MultiProgress::new(),
+            sizes: vec![0; labels.len()],
+            labels,
+            bars: HashMap::new(),
+            total: None,
+            ended: 0,
+        }
+    }
+
+    /// Bars for `jobs`, with one for the whole batch
+    pub fn for_jobs(jobs: &[TransferJob]) -> Self {
+        let mut bars = Self::new(jobs.iter().map(TransferJob::label).collect());
+        bars.sizes = jobs.iter().map(|job| job.size).collect();
+        let total = bars
+            .multi_progress
+            .add(ProgressBar::new(bars.sizes.iter().sum()));
+        total.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} \
+                 (ETA {eta}) {msg}",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("#>-"),
+        );
+        total.set_message(format!("0/{} files", jobs.len()));
+        bars.total = Some(total);
+        bars
+    }
+
+    /// Draw `events` until every job has finished or failed, or nothing is
+    /// left to send them
+    pub async fn draw(mut self, mut events: TransferEvents) {
+        while self.ended < self.labels.len() {
+            let Some(event) = events.next().await else {
+                break;
+            };
+            self.handle(&event);
+        }
+        if let Some(total) = &self.total {
+            total.finish_and_clear();
+        }
+    }
+
+    pub fn handle(&mut self, event: &TransferEvent) {
+        match *event {
+            TransferEvent::Started { index, size } => {
+                let bar = self.bar(index);
+                // A retry starts the file over
+                if let Some(total) = &self.total {
+                    total.dec(bar.position());
+                    total.set_length(
+                        (total.length().unwrap_or(0) + size).saturating_sub(self.sizes[index]),
+                    );
+                }
+                bar.set_position(0);
+                bar.set_length(size);
+                bar.set_message(self.labels[index].clone());
+                self.sizes[index] = size;
+            }
+            TransferEvent::Bytes { index, bytes } => {
+                self.bar(index).inc(bytes);
+                if let Some(total) = &self.total {
+                    total.inc(bytes);
+                }
+            }
+            TransferEvent::Retrying {
+                index,
+                attempt,
+                ref error,
+            } => {
+                let message = format!("{} (retry {}): {}", self.labels[index], attempt, error);
+                self.bar(index).set_message(message);
+            }
+            TransferEvent::Finished { index, .. } => {
+                let copied = self.end(index);
+                // Chunks already counted; make up any shortfall from a stale size
+                if let Some(total) = &self.total {
+                    total.inc(self.sizes[index].saturating_sub(copied));
+                }
+            }
+            // Failures are left for the caller to report once the bars are gone
+            TransferEvent::Failed { index, .. } => {
+                let copied = self.end(index);
+                if let Some(total) = &self.total {
+                    total.dec(copied);
+                    total.set_length(
+                        total
+                            .length()
+                            .unwrap_or(0)
+                            .saturating_sub(self.sizes[index]),
+                    );
+                }
+            }
+        }
+    }
+
+    /// The bar of job `index`, added when it first starts
+    fn bar(&mut self, index: usize) -> ProgressBar {
+        let bar = self.bars.entry(index).or_insert_with(|| {
+            let bar = self.multi_progress.add(ProgressBar::new(self.sizes[index]));
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "  {spinner:.green} {msg} [{bar:20.cyan/blue}] {bytes}/{total_bytes} \
+                     {bytes_per_sec} ETA {eta}",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_spinner())
+                .progress_chars("#>-"),
+            );
+            bar.set_message(self.labels[index].clone());
+            bar.enable_steady_tick(Duration::from_millis(120));
+            bar
+        });
+        bar.clone()
+    }
+
+    /// Take away the bar of job `index`, returning the bytes it showed
+    fn end(&mut self, index: usize) -> u64 {
+        self.ended += 1;
+        if let Some(total) = &self.total {
+            total.set_message(format!("{}/{} files", self.ended, self.labels.len()));
+        }
+        self.bars.remove(&index).map_or(0, |bar| {
+            // Finishing fills the bar, so what it showed is read first
+            let copied = bar.position();
+            bar.finish_and_clear();
+            self.multi_progress.remove(&bar);
+            copied
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_json_progress() {
+        let mut json = JsonProgress::new(vec!["Uploading a.png".to_string()]);
+        json.record(&TransferEvent::Started { index: 0, size: 10 });
+        json.record(&TransferEvent::Bytes { index: 0, bytes: 4 });
+        let record = json.record(&TransferEvent::Bytes { index: 0, bytes: 6 });
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "event": "bytes",
+                "index": 0,
+                "bytes": 6,
+                "copied": 10,
+                "size": 10,
+                "label": "Uploading a.png",
+            })
+        );
+        let record = json.record(&TransferEvent::Finished {
+            index: 0,
+            retries: 1,
+        });
+        assert_eq!(record["event"], "finished");
+        assert_eq!(json.ended, 1);
+        assert_eq!("JSON".parse::<ProgressMode>().unwrap(), ProgressMode::Json);
+    }
+
+    #[test]
+    fn test_total() {
+        let jobs = [
+            TransferJob::download("/a.bin".to_string(), PathBuf::from("a.bin"), 100),
+            TransferJob::upload(PathBuf::from("b.bin"), "/b.bin".to_string(), 50),
+            TransferJob::upload(PathBuf::from("c.bin"), "/c.bin".to_string(), 10),
+        ];
+        let mut bars = ProgressBars::for_jobs(&jobs);
+        let total = bars.total.clone().unwrap();
+
+        bars.handle(&TransferEvent::Started {
+            index: 0,
+            size: 100,
+        });
+        bars.handle(&TransferEvent::Bytes {
+            index: 0,
+            bytes: 30,
+        });
+        // Started over after a failed try
+        bars.handle(&TransferEvent::Started {
+            index: 0,
+            size: 100,
+        });
+        bars.handle(&TransferEvent::Bytes {
+            index: 0,
+            bytes: 90,
+        });
+        assert_eq!(total.position(), 90);
+        // Its size was out of date
+        bars.handle(&TransferEvent::Finished {
+            index: 0,
+            retries: 1,
+        });
+        assert_eq!(total.position(), 100);
+
+        bars.handle(&TransferEvent::Started { index: 1, size: 50 });
+        bars.handle(&TransferEvent::Bytes {
+            index: 1,
+            bytes: 20,
+        });
+        bars.handle(&TransferEvent::Failed {
+            index: 1,
+            error: "connection reset".to_string(),
+        });
+        // Failed before it started
+        bars.handle(&TransferEvent::Failed {
+            index: 2,
+            error: "damaged".to_string(),
+        });
+        assert_eq!(total.position(), 100);
+        assert_eq!(total.length(), Some(100));
+        assert_eq!(bars.ended, 3);
+        assert!(bars.bars.is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+//! Transfers saved to disk to run later, for `comfy-fs queue` and the browser's
+//! transfer panel
+
+use crate::config::data_dir;
+use crate::transfer::TransferKind;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a queued transfer stands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum JobState {
+    /// Not run yet, or interrupted; the next run starts or resumes it
+    Pending,
+    Done,
+    Failed {
+        error: String,
+    },
+}
+
+impl JobState {
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Done => "done",
+            Self::Failed { .. } => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedJob {
+    /// Stays the same while the job is in the queue
+    pub id: u64,
+    pub kind: TransferKind,
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    /// Bytes to copy, 0 until known
+    pub size: u64,
+    pub added: DateTime<Local>,
+    #[serde(flatten)]
+    pub state: JobState,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueFile {
+    jobs: Vec<QueuedJob>,
+}
+
+/// Where the queue for `profile` is kept, e.g.
+/// `~/.local/share/comfy-fs/queue/default.json`. Each profile has its own, as
+/// its jobs belong to its server.
+pub fn queue_path(profile: Option<&str>) -> Result<PathBuf> {
+    let dir = data_dir().ok_or_else(|| anyhow!("Could not find the data directory"))?;
+    Ok(dir
+        .join("queue")
+        .join(format!("{}.json", profile.unwrap_or("default"))))
+}
+
+/// The queue file. Every change reads the file, applies itself, and writes
+/// it back, so the CLI and a browser working on the same queue see each
+/// other's changes.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    path: PathBuf,
+}
+
+impl JobQueue {
+    pub const fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every job, in the order they were added
+    pub fn jobs(&self) -> Result<Vec<QueuedJob>> {
+        Ok(self.read()?.jobs)
+    }
+
+    /// The jobs the next run should do
+    pub fn pending(&self) -> Result<Vec<QueuedJob>> {
+        Ok(self
+            .jobs()?
+            .into_iter()
+            .filter(|job| job.state == JobState::Pending)
+            .collect())
+    }
+
+    pub fn add(
+        &self,
+        kind: TransferKind,
+        remote_path: String,
+        local_path: PathBuf,
+        size: u64,
+    ) -> Result<QueuedJob> {
+        self.update(|jobs| {
+            let job = QueuedJob {
+                id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                kind,
+                remote_path,
+                local_path,
+                size,
+                added: Local::now(),
+                state: JobState::Pending,
+            };
+            jobs.push(job.clone());
+            job
+        })
+    }
+
+    /// Set the state of job `id`, if it's still queued
+    pub fn set_state(&self, id: u64, state: JobState) -> Result<()> {
+        self.update(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                job.state = state;
+            }
+        })
+    }
+
+    /// Put failed jobs back to pending. Returns how many there were.
+    pub fn retry_failed(&self) -> Result<usize> {
+        self.update(|jobs| {
+            let failed: Vec<_> = jobs
+                .iter_mut()
+                .filter(|job| matches!(job.state, JobState::Failed { .. }))
+                .collect();
+            let count = failed.len();
+            for job in failed {
+                job.state = JobState::Pending;
+            }
+            count
+        })
+    }
+
+    /// Take job `id` out of the queue. Returns whether it was there.
+    pub fn remove(&self, id: u64) -> Result<bool> {
+        self.update(|jobs| {
+            let before = jobs.len();
+            jobs.retain(|job| job.id != id);
+            jobs.len() < before
+        })
+    }
+
+    /// Take out the jobs that are done or failed. Returns how many.
+    pub fn clear_finished(&self) -> Result<usize> {
+        self.update(|jobs| {
+            let before = jobs.len();
+            jobs.retain(|job| job.state == JobState::Pending);
+            before - jobs.len()
+        })
+    }
+
+    fn read(&self) -> Result<QueueFile> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Could not read the queue in {}: {}", self.path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(QueueFile::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut Vec<QueuedJob>) -> T) -> Result<T> {
+        let mut file = self.read()?;
+        let result = change(&mut file.jobs);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written whole and then renamed, so a crash never leaves half a queue
+        let temp = self.path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(&file)?)?;
+        fs::rename(&temp, &self.path)?;
+        Ok(result)
+    }
+}
+
+/// The jobs as a table under a header, one line each
+pub fn queue_table(jobs: &[QueuedJob]) -> Vec<String> {
+    let row = |cells: [&str; 5]| {
+        format!(
+            "{:>4}  {:<8}  {:<8}  {:>10}  {}",
+            cells[0], cells[1], cells[2], cells[3], cells[4]
+        )
+    };
+    let mut lines = vec![row(["ID", "State", "Action", "Size", "Transfer"])];
+    for job in jobs {
+        let (action, transfer) = match job.kind {
+            TransferKind::Download => (
+                "download",
+                format!("{} -> {}", job.remote_path, job.local_path.display()),
+            ),
+            TransferKind::Upload => (
+                "upload",
+                format!("{} -> {}", job.local_path.display(), job.remote_path),
+            ),
+        };
+        let size = if job.size > 0 {
+            human_bytes::human_bytes(job.size as f64)
+        } else {
+            "?".to_string()
+        };
+        let mut line = row([
+            &job.id.to_string(),
+            job.state.label(),
+            action,
+            &size,
+            &transfer,
+        ]);
+        if let JobState::Failed { error } = &job.state {
+            line.push_str(&format!("  ({})", error));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue/default.json");
+        let queue = JobQueue::new(path.clone());
+
+        let first = queue
+            .add(
+                TransferKind::Download,
+                "/models/a.safetensors".to_string(),
+                PathBuf::from("a.safetensors"),
+                0,
+            )
+            .unwrap();
+        let second = queue
+            .add(
+                TransferKind::Upload,
+                "/input/b.png".to_string(),
+                PathBuf::from("b.png"),
+                5,
+            )
+            .unwrap();
+        assert_eq!((first.id, second.id), (1, 2));
+
+        // Another process opening the same file
+        let reopened = JobQueue::new(path);
+        reopened
+            .set_state(
+                1,
+                JobState::Failed {
+                    error: "gone".to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(queue.pending().unwrap(), vec![second.clone()]);
+
+        assert_eq!(queue.retry_failed().unwrap(), 1);
+        assert_eq!(queue.pending().unwrap().len(), 2);
+
+        queue.set_state(2, JobState::Done).unwrap();
+        assert_eq!(queue.clear_finished().unwrap(), 1);
+        assert!(queue.remove(1).unwrap());
+        assert!(!queue.remove(1).unwrap());
+        assert!(reopened.jobs().unwrap().is_empty());
+
+        // Ids aren't reused while the queue has jobs, and restart once it's empty
+        assert_eq!(
+            queue
+                .add(
+                    TransferKind::Upload,
+                    "/c".to_string(),
+                    PathBuf::from("c"),
+                    1
+                )
+                .unwrap()
+                .id,
+            1
+        );
+    }
+
+    #[test]
+    fn test_queue_table() {
+        let job = QueuedJob {
+            id: 3,
+            kind: TransferKind::Download,
+            remote_path: "/output/a.png".to_string(),
+            local_path: PathBuf::from("a.png"),
+            size: 0,
+            added: Local::now(),
+            state: JobState::Failed {
+                error: "timed out".to_string(),
+            },
+        };
+        let lines = queue_table(&[job]);
+        assert_eq!(
+            lines[1],
+            "   3  failed    download           ?  /output/a.png -> a.png  (timed out)"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use crate::client::ClientError;
+use anyhow::Result;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Retries a failed transfer gets unless `--retries` or the config says
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// How often and how patiently to retry a failed transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_RETRIES,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `attempt` (starting at 1). The delay doubles on each
+    /// attempt up to `max_delay`, and a random half of it is jittered away so parallel
+    /// transfers that failed together don't all retry at the same moment.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        backoff / 2 + backoff.mul_f64(Self::jitter() / 2.0)
+    }
+
+    /// Run `operation` until it succeeds or the retries are used up, calling
+    /// `on_retry` with the attempt number and error before each retry. Errors
+    /// that would only fail the same way again, like a missing file or a
+    /// refused password, are returned without retrying.
+    ///
+    /// Returns the value along with the number of retries it took.
+    pub async fn run<T, F, Fut, R>(&self, mut operation: F, mut on_retry: R) -> Result<(T, u32)>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        R: FnMut(u32, &anyhow::Error),
+    {
+        let mut retries = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok((value, retries)),
+                Err(e)
+                    if retries < self.max_retries && ClientError::classify(&e).is_retryable() =>
+                {
+                    retries += 1;
+                    on_retry(retries, &e);
+                    tokio::time::sleep(self.delay(retries)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// A random value in `[0, 1)`.
+    fn jitter() -> f64 {
+        let random = RandomState::new().build_hasher().finish();
+        (random % 1_000) as f64 / 1_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn instant_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_delay_backs_off_with_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+
+        let first = policy.delay(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+
+        let second = policy.delay(2);
+        assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+
+        // Capped at max_delay
+        let later = policy.delay(10);
+        assert!(later >= Duration::from_millis(150) && later <= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_until_success() {
+        let mut calls = 0;
+        let mut retried = Vec::new();
+
+        let (value, retries) = instant_policy(3)
+            .run(
+                || {
+                    calls += 1;
+                    let attempt = calls;
+                    async move {
+                        if attempt < 3 {
+                            Err(anyhow!("transient"))
+                        } else {
+                            Ok(attempt)
+                        }
+                    }
+                },
+                |attempt, _| retried.push(attempt),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(value, 3);
+        assert_eq!(retries, 2);
+        assert_eq!(retried, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_run_gives_up_after_max_retries() {
+        let mut calls = 0;
+
+        let result: Result<((), u32)> = instant_policy(2)
+            .run(
+                || {
+                    calls += 1;
+                    async { Err(anyhow!("still down")) }
+                },
+                |_, _| {},
+            )
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_does_not_retry_fatal_errors() {
+        let mut calls = 0;
+
+        let result: Result<((), u32)> = instant_policy(3)
+            .run(
+                || {
+                    calls += 1;
+                    async { Err(ClientError::not_found("/output/a.png").into()) }
+                },
+                |_, _| {},
+            )
+            .await;
+
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No such file or directory: /output/a.png"
+        );
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,179 @@
+//! Checking that `.safetensors` model files are whole before they're uploaded
+//! and after they're downloaded
+//!
+//! A safetensors file is 8 bytes of little-endian header length, a JSON header
+//! giving each tensor's dtype, shape, and byte range, and then the tensor data.
+//! A file cut short or damaged in transit fails here instead of when ComfyUI
+//! loads it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+/// Headers bigger than this are taken to be damage, as the reference loader does
+const MAX_HEADER: u64 = 100 * 1024 * 1024;
+
+/// Whether `path` is named like a safetensors file
+pub fn is_safetensors(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("safetensors"))
+}
+
+/// Check the file at `path` has a readable header and exactly the tensor data
+/// it describes
+pub fn validate(path: &Path) -> Result<()> {
+    check_file(path).with_context(|| format!("{} is not a valid safetensors file", path.display()))
+}
+
+/// [`validate`] a downloaded file, removing it if it's not valid so a broken
+/// model isn't left where ComfyUI would load it
+pub fn check_download(path: &Path) -> Result<()> {
+    validate(path).map_err(|e| {
+        let _ = fs::remove_file(path);
+        anyhow!("{:#} (removed the download)", e)
+    })
+}
+
+fn check_file(path: &Path) -> Result<()> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut len = [0u8; 8];
+    file.read_exact(&mut len)
+        .map_err(|_| anyhow!("it's only {} bytes, too short for a header", file_len))?;
+    let header_len = u64::from_le_bytes(len);
+    if header_len > MAX_HEADER {
+        bail!("its header claims to be {} bytes", header_len);
+    }
+    let data_len = file_len
+        .checked_sub(8 + header_len)
+        .ok_or_else(|| anyhow!("it's truncated inside its {}-byte header", header_len))?;
+
+    let mut header = Vec::new();
+    file.take(header_len).read_to_end(&mut header)?;
+    check_header(&header, data_len)
+}
+
+/// Check the tensors `header` describes fill exactly `data_len` bytes
+fn check_header(header: &[u8], data_len: u64) -> Result<()> {
+    let header: BTreeMap<String, Value> =
+        serde_json::from_slice(header).map_err(|e| anyhow!("its header is damaged: {}", e))?;
+
+    let mut data_end = 0;
+    for (name, tensor) in header {
+        if name == "__metadata__" {
+            continue;
+        }
+        let (begin, end) = match tensor["data_offsets"].as_array().map(Vec::as_slice) {
+            Some([begin, end]) => (begin.as_u64(), end.as_u64()),
+            _ => (None, None),
+        };
+        let (Some(begin), Some(end)) = (begin, end) else {
+            bail!("tensor {} has no valid data_offsets", name);
+        };
+        if begin > end {
+            bail!("tensor {} ends before it starts", name);
+        }
+        let dtype_size = tensor["dtype"].as_str().and_then(dtype_size);
+        let shape = tensor["shape"].as_array().map(|shape| {
+            shape
+                .iter()
+                .map(|dim| dim.as_u64().unwrap_or(0))
+                .try_fold(1u64, u64::checked_mul)
+        });
+        if let (Some(size), Some(Some(elements))) = (dtype_size, shape) {
+            let expected = elements.checked_mul(size);
+            if expected != Some(end - begin) {
+                bail!(
+                    "tensor {} takes {} bytes but its dtype and shape need {}",
+                    name,
+                    end - begin,
+                    expected.map_or_else(|| "more".to_string(), |n| n.to_string())
+                );
+            }
+        }
+        data_end = data_end.max(end);
+    }
+
+    if data_end > data_len {
+        bail!(
+            "it's truncated: its tensors need {} bytes of data but it has {}",
+            data_end,
+            data_len
+        );
+    }
+    if data_end < data_len {
+        bail!("it has {} bytes after its last tensor", data_len - data_end);
+    }
+    Ok(())
+}
+
+/// Bytes per element of the dtypes safetensors defines
+fn dtype_size(dtype: &str) -> Option<u64> {
+    match dtype {
+        "BOOL" | "U8" | "I8" | "F8_E4M3" | "F8_E5M2" => Some(1),
+        "U16" | "I16" | "F16" | "BF16" => Some(2),
+        "U32" | "I32" | "F32" => Some(4),
+        "U64" | "I64" | "F64" => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn safetensors_file() -> Vec<u8> {
+        let header = r#"{"__metadata__": {"format": "pt"},
+            "lora_up.weight": {"dtype": "F16", "shape": [320, 8], "data_offsets": [0, 5120]},
+            "alpha": {"dtype": "F32", "shape": [], "data_offsets": [5120, 5124]}}"#;
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        file.extend(vec![0u8; 5124]);
+        file
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lora.safetensors");
+        let file = safetensors_file();
+        fs::write(&path, &file).unwrap();
+        validate(&path).unwrap();
+
+        // Cut short in the data, or in the header
+        for len in [file.len() - 1, 100, 4] {
+            fs::write(&path, &file[..len]).unwrap();
+            assert!(validate(&path).is_err(), "{} bytes passed", len);
+        }
+        let mut longer = file.clone();
+        longer.push(0);
+        fs::write(&path, longer).unwrap();
+        assert!(validate(&path).is_err());
+
+        fs::write(&path, b"\x05\0\0\0\0\0\0\0{oops").unwrap();
+        let error = format!("{:#}", check_download(&path).unwrap_err());
+        assert!(error.contains("not a valid safetensors file: its header is damaged"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_tensor_sizes_must_match_shapes() {
+        let header = br#"{"w": {"dtype": "F32", "shape": [2, 2], "data_offsets": [0, 8]}}"#;
+        let error = check_header(header, 8).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "tensor w takes 8 bytes but its dtype and shape need 16"
+        );
+
+        // Unknown dtypes are only checked against the file's length
+        let header = br#"{"w": {"dtype": "F4", "shape": [2, 2], "data_offsets": [0, 2]}}"#;
+        check_header(header, 2).unwrap();
+        assert!(is_safetensors(Path::new("model.SafeTensors")));
+        assert!(!is_safetensors(Path::new("model.ckpt")));
+    }
+}
@@ -0,0 +1,163 @@
+//! Ordering of directory listings, shared by `list` and the browser
+
+use crate::client::RemoteFile;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    Modified,
+    Name,
+    Size,
+    Type,
+}
+
+impl SortMode {
+    pub const fn next(self) -> Self {
+        match self {
+            Self::Modified => Self::Name,
+            Self::Name => Self::Size,
+            Self::Size => Self::Type,
+            Self::Type => Self::Modified,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Modified => "Modified",
+            Self::Name => "Name",
+            Self::Size => "Size",
+            Self::Type => "Type",
+        }
+    }
+}
+
+impl FromStr for SortMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "modified" | "mtime" | "date" => Ok(Self::Modified),
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            "type" | "ext" | "extension" => Ok(Self::Type),
+            _ => Err(anyhow!(
+                "Invalid sort key '{}' (expected modified, name, size, or type)",
+                s
+            )),
+        }
+    }
+}
+
+/// The parts of a listing entry that sorting looks at
+pub trait SortKey {
+    fn name(&self) -> &str;
+    fn is_dir(&self) -> bool;
+    fn size(&self) -> u64;
+    fn modified(&self) -> DateTime<Local>;
+    /// Compared by the type sort; empty for directories and files without one
+    fn extension(&self) -> &str;
+}
+
+impl SortKey for RemoteFile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn modified(&self) -> DateTime<Local> {
+        self.modified
+    }
+
+    fn extension(&self) -> &str {
+        if self.is_dir {
+            return "";
+        }
+        self.name.rsplit_once('.').map_or("", |(_, ext)| ext)
+    }
+}
+
+/// Directories first, then by `mode`: newest, A to Z, biggest, or by extension.
+/// `reverse` flips the order within each group.
+pub fn sort_entries<T: SortKey>(entries: &mut [T], mode: SortMode, reverse: bool) {
+    entries.sort_by(|a, b| {
+        if a.is_dir() != b.is_dir() {
+            return if a.is_dir() { Ordering::Less } else { Ordering::Greater };
+        }
+
+        let ordering = match mode {
+            SortMode::Modified => b.modified().cmp(&a.modified()),
+            SortMode::Name => a.name().to_lowercase().cmp(&b.name().to_lowercase()),
+            SortMode::Size => b.size().cmp(&a.size()),
+            SortMode::Type => a.extension().cmp(b.extension()),
+        };
+
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn file(name: &str, size: u64, day: u32, is_dir: bool) -> RemoteFile {
+        RemoteFile {
+            name: name.to_string(),
+            path: format!("/{}", name),
+            size,
+            modified: Local.with_ymd_and_hms(2024, 5, day, 12, 0, 0).unwrap(),
+            is_dir,
+            permissions: None,
+        }
+    }
+
+    fn names(files: &[RemoteFile]) -> Vec<&str> {
+        files.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let mut files = vec![
+            file("b.png", 300, 1, false),
+            file("models", 0, 2, true),
+            file("A.json", 100, 3, false),
+            file("c.ckpt", 200, 2, false),
+        ];
+
+        sort_entries(&mut files, SortMode::Modified, false);
+        assert_eq!(names(&files), ["models", "A.json", "c.ckpt", "b.png"]);
+
+        sort_entries(&mut files, SortMode::Name, false);
+        assert_eq!(names(&files), ["models", "A.json", "b.png", "c.ckpt"]);
+
+        sort_entries(&mut files, SortMode::Size, true);
+        assert_eq!(names(&files), ["models", "A.json", "c.ckpt", "b.png"]);
+
+        sort_entries(&mut files, SortMode::Type, false);
+        assert_eq!(names(&files), ["models", "c.ckpt", "A.json", "b.png"]);
+    }
+
+    #[test]
+    fn test_parse_sort_mode() {
+        assert_eq!("name".parse::<SortMode>().unwrap(), SortMode::Name);
+        assert_eq!("Modified".parse::<SortMode>().unwrap(), SortMode::Modified);
+        let err = "colour".parse::<SortMode>().unwrap_err();
+        assert!(err.to_string().contains("expected modified, name, size, or type"));
+    }
+}
@@ -0,0 +1,1152 @@
+//! Working out what `sync` needs to copy between a local and a remote tree
+
+pub mod state;
+pub mod watch;
+
+use crate::client::{FileServerClient, RemoteFile};
+use crate::walk::{DirTotals, WalkEntry};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use serde::{Deserialize, Serialize};
+use state::{hash_file, FileState, SyncState};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::sync::Mutex;
+
+/// A file or directory found under the local sync root
+#[derive(Debug, Clone)]
+pub struct LocalEntry {
+    /// Path below the root, with `/` separators
+    pub relative: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: DateTime<Local>,
+    pub is_dir: bool,
+}
+
+/// Every entry under `root`, parents before their children and siblings by
+/// name. `max_depth` counts like the remote walk: 1 is the root's own entries.
+pub fn walk_local(root: &Path, max_depth: Option<usize>) -> Result<Vec<LocalEntry>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![(root.to_path_buf(), String::new(), 1)];
+
+    while let Some((dir, prefix, depth)) = pending.pop() {
+        let listing =
+            std::fs::read_dir(&dir).with_context(|| format!("Failed to list {}", dir.display()))?;
+        for entry in listing {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(ToString::to_string) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            let relative = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if metadata.is_dir() && max_depth.is_none_or(|max| depth < max) {
+                pending.push((entry.path(), relative.clone(), depth + 1));
+            }
+            entries.push(LocalEntry {
+                relative,
+                path: entry.path(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: metadata
+                    .modified()
+                    .map_or_else(|_| Local::now(), DateTime::from),
+                is_dir: metadata.is_dir(),
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.relative.split('/').cmp(b.relative.split('/')));
+    Ok(entries)
+}
+
+/// Which way a sync copies files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncDirection {
+    /// Both ways
+    #[default]
+    TwoWay,
+    /// Only from the server
+    Pull,
+    /// Only to the server
+    Push,
+}
+
+impl SyncDirection {
+    pub const fn pulls(self) -> bool {
+        matches!(self, Self::TwoWay | Self::Pull)
+    }
+
+    pub const fn pushes(self) -> bool {
+        matches!(self, Self::TwoWay | Self::Push)
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::TwoWay => "two-way",
+            Self::Pull => "pull",
+            Self::Push => "push",
+        }
+    }
+}
+
+impl fmt::Display for SyncDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One file to copy, in either direction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    pub relative: String,
+    pub local_path: PathBuf,
+    pub remote_path: String,
+    pub size: u64,
+    /// The source's modification time
+    pub modified: DateTime<Local>,
+    /// Whether a file is already at the destination
+    pub replaces: bool,
+}
+
+impl Transfer {
+    fn download(relative: &str, local_path: PathBuf, file: &RemoteFile, replaces: bool) -> Self {
+        Self {
+            relative: relative.to_string(),
+            local_path,
+            remote_path: file.path.clone(),
+            size: file.size,
+            modified: file.modified,
+            replaces,
+        }
+    }
+
+    fn upload(entry: &LocalEntry, remote_path: String, replaces: bool) -> Self {
+        Self {
+            relative: entry.relative.clone(),
+            local_path: entry.path.clone(),
+            remote_path,
+            size: entry.size,
+            modified: entry.modified,
+            replaces,
+        }
+    }
+}
+
+/// Something a one-way sync's destination has that its source doesn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deletion {
+    pub relative: String,
+    pub local_path: PathBuf,
+    pub remote_path: String,
+    pub is_dir: bool,
+    /// Everything it holds, as far down as the sync walked
+    pub totals: DirTotals,
+}
+
+/// How a sync decides what to copy
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    direction: SyncDirection,
+    time_tolerance: TimeDelta,
+    max_depth: Option<usize>,
+    conflict: ConflictPolicy,
+    state: SyncState,
+}
+
+impl SyncOptions {
+    pub fn new(direction: SyncDirection) -> Self {
+        Self {
+            direction,
+            time_tolerance: TimeDelta::seconds(DEFAULT_TIME_TOLERANCE_SECS as i64),
+            max_depth: None,
+            conflict: ConflictPolicy::Newer,
+            state: SyncState::default(),
+        }
+    }
+
+    /// Treat modification times this many seconds apart as the same, to allow
+    /// for clock skew and filesystems that round timestamps
+    #[must_use]
+    pub fn with_time_tolerance(mut self, seconds: u64) -> Self {
+        self.time_tolerance = TimeDelta::seconds(i64::try_from(seconds).unwrap_or(i64::MAX / 1000));
+        self
+    }
+
+    /// How deep both trees were walked, so records below that are kept as they are
+    #[must_use]
+    pub const fn with_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// What the last sync of these directories left on both sides
+    #[must_use]
+    pub fn with_state(mut self, state: SyncState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// How a two-way sync settles files changed on both sides
+    #[must_use]
+    pub const fn with_conflict(mut self, conflict: ConflictPolicy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// What to do with a file on both sides.
+    ///
+    /// With a record from the last sync, only the side that changed since is
+    /// copied, and a local file that was touched but hashes the same hasn't
+    /// changed. Without one, files match when their sizes are equal and their
+    /// times are within the tolerance. A two-way sync settles files that
+    /// changed on both sides, or that differ with no record, by its conflict
+    /// policy; a one-way sync always copies from its source.
+    fn compare(&self, local: &LocalEntry, remote: &RemoteFile) -> Outcome {
+        let skew = local.modified - remote.modified;
+        if local.size == remote.size && skew.abs() <= self.time_tolerance {
+            return Outcome::Same;
+        }
+        let changed = self.state.files.get(&local.relative).map(|last| {
+            (
+                last.local_changed(&local.path, local.size, local.modified, self.time_tolerance),
+                last.remote_changed(remote.size, remote.modified, self.time_tolerance),
+            )
+        });
+        match (self.direction, changed) {
+            (_, Some((false, false))) => Outcome::Same,
+            (SyncDirection::Pull, _) | (SyncDirection::TwoWay, Some((false, true))) => {
+                Outcome::Download
+            }
+            (SyncDirection::Push, _) | (SyncDirection::TwoWay, Some((true, false))) => {
+                Outcome::Upload
+            }
+            (SyncDirection::TwoWay, _) => match self.conflict {
+                ConflictPolicy::Newer if skew > self.time_tolerance => Outcome::Upload,
+                ConflictPolicy::Newer | ConflictPolicy::Remote => Outcome::Download,
+                ConflictPolicy::Local => Outcome::Upload,
+                ConflictPolicy::KeepBoth | ConflictPolicy::Ask => Outcome::Conflict,
+            },
+        }
+    }
+
+    /// The record of a file that already matches on both sides
+    fn matched(&self, local: &LocalEntry, remote: &RemoteFile) -> FileState {
+        // The last hash still holds if the local file hasn't been touched since
+        let hash = self
+            .state
+            .files
+            .get(&local.relative)
+            .filter(|last| {
+                last.size == local.size
+                    && state::within(local.modified, last.local_modified, self.time_tolerance)
+            })
+            .and_then(|last| last.hash.clone());
+        FileState::new(local.size, local.modified)
+            .with_remote_modified(Some(remote.modified))
+            .with_hash(hash)
+    }
+
+    /// Whether the walks looked as deep as `relative`
+    fn walked(&self, relative: &str) -> bool {
+        self.max_depth
+            .is_none_or(|max| relative.split('/').count() <= max)
+    }
+}
+
+/// What to do with a file on both sides
+enum Outcome {
+    Same,
+    Download,
+    Upload,
+    Conflict,
+}
+
+/// How a two-way sync settles a file that changed on both sides
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Copy the newer one over the older
+    #[default]
+    Newer,
+    /// Keep the local copy
+    Local,
+    /// Keep the server's copy
+    Remote,
+    /// Keep both, moving the local copy aside under a conflict name
+    KeepBoth,
+    /// Ask for each file
+    Ask,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "newer" => Ok(Self::Newer),
+            "local" => Ok(Self::Local),
+            "remote" => Ok(Self::Remote),
+            "keep-both" => Ok(Self::KeepBoth),
+            "ask" => Ok(Self::Ask),
+            _ => Err(anyhow!(
+                "Invalid conflict policy '{}' (expected newer, local, remote, keep-both, or ask)",
+                s
+            )),
+        }
+    }
+}
+
+/// A file changed on both sides, left for the caller to settle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub relative: String,
+    /// Bringing the server's copy down over the local one
+    pub download: Transfer,
+    /// Sending the local copy up over the server's
+    pub upload: Transfer,
+}
+
+impl Conflict {
+    /// Both copies kept: the local one is moved aside to its conflict name and
+    /// uploaded there, and the server's comes down in its place. Returns the
+    /// download and the upload.
+    pub fn keep_both(&self, date: NaiveDate) -> (Transfer, Transfer) {
+        let relative = conflict_name(&self.relative, date);
+        let name = relative.rsplit('/').next().unwrap_or(&relative);
+        let mut upload = self.upload.clone();
+        upload.local_path = upload.local_path.with_file_name(name);
+        upload.remote_path = match upload.remote_path.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/{}", parent, name),
+            None => name.to_string(),
+        };
+        upload.relative = relative;
+        upload.replaces = false;
+
+        let mut download = self.download.clone();
+        download.replaces = false;
+        (download, upload)
+    }
+}
+
+/// `models/a.safetensors` becomes `models/a (conflict 2024-06-01).safetensors`
+pub fn conflict_name(relative: &str, date: NaiveDate) -> String {
+    let (parent, name) = match relative.rsplit_once('/') {
+        Some((parent, name)) => (format!("{}/", parent), name),
+        None => (String::new(), relative),
+    };
+    let suffix = format!(" (conflict {})", date.format("%Y-%m-%d"));
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}{}{}.{}", parent, stem, suffix, extension)
+        }
+        _ => format!("{}{}{}", parent, name, suffix),
+    }
+}
+
+/// Seconds apart two modification times can be and still count as the same
+pub const DEFAULT_TIME_TOLERANCE_SECS: u64 = 2;
+
+/// How far behind the real time a listing that stops at the minute can be
+const LISTING_PRECISION_SECS: i64 = 60;
+
+/// Ask the server for the exact times of the `remote` files that need them to
+/// be compared with their `local` copies, when its listings stop at the minute
+pub async fn refine_times(
+    client: &Mutex<Box<dyn FileServerClient>>,
+    local: &[LocalEntry],
+    remote_root: &str,
+    remote: &mut [WalkEntry],
+    time_tolerance_secs: u64,
+) {
+    if client.lock().await.capabilities().exact_times {
+        return;
+    }
+    for index in coarse_times(local, remote_root, remote, time_tolerance_secs) {
+        let file = &mut remote[index].file;
+        // The listed time is still close, so one that can't be had is left as it is
+        if let Ok(exact) = client.lock().await.stat(&file.path).await {
+            file.modified = exact.modified;
+        }
+    }
+}
+
+/// The remote files whose listed times are too coarse to compare with their
+/// local copies: the same size, and close enough in time that the missing
+/// seconds decide whether they match
+fn coarse_times(
+    local: &[LocalEntry],
+    remote_root: &str,
+    remote: &[WalkEntry],
+    time_tolerance_secs: u64,
+) -> Vec<usize> {
+    let prefix = format!("{}/", remote_root.trim_end_matches('/'));
+    let local_by_path: HashMap<&str, &LocalEntry> =
+        local.iter().map(|e| (e.relative.as_str(), e)).collect();
+    let window = TimeDelta::seconds(
+        i64::try_from(time_tolerance_secs).unwrap_or(i64::MAX / 1000) + LISTING_PRECISION_SECS,
+    );
+
+    remote
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            let file = &entry.file;
+            let relative = file.path.strip_prefix(&prefix).unwrap_or(&file.path);
+            local_by_path.get(relative).is_some_and(|local| {
+                !file.is_dir
+                    && !local.is_dir
+                    && local.size == file.size
+                    && (local.modified - file.modified).abs() <= window
+            })
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// A file moved on one side since the last sync, to be moved the same way on
+/// the other instead of copied again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// Where it is now on the side that has yet to move it
+    pub from_path: String,
+    /// Where it goes on that side
+    pub to_path: String,
+    /// The record to keep under `to` once it's moved
+    pub state: FileState,
+}
+
+/// What a sync will do, worked out from listings of both sides
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Remote directories missing locally, parents first
+    pub local_dirs: Vec<PathBuf>,
+    /// Local directories missing remotely, parents first
+    pub remote_dirs: Vec<String>,
+    /// Remote files that are missing locally or changed on the server
+    pub downloads: Vec<Transfer>,
+    /// Local files that are missing remotely or changed locally
+    pub uploads: Vec<Transfer>,
+    /// When pulling, local files and directories the server doesn't have.
+    /// Only the top of each missing tree is listed.
+    pub local_deletions: Vec<Deletion>,
+    /// When pushing, remote files and directories missing locally
+    pub remote_deletions: Vec<Deletion>,
+    /// Remote files the server moved since the last sync, to move locally.
+    /// Paths are local.
+    pub local_renames: Vec<Rename>,
+    /// Local files moved since the last sync, to move on the server.
+    /// Paths are remote.
+    pub remote_renames: Vec<Rename>,
+    /// Files changed on both sides that the conflict policy leaves to the
+    /// caller: to keep both, or to ask about
+    pub conflicts: Vec<Conflict>,
+    /// The records to save once the sync is done: files that already match,
+    /// and the last records of everything else still around. Copies and
+    /// moves are added as they succeed.
+    pub state: SyncState,
+}
+
+impl SyncPlan {
+    /// Compare both trees by path below their roots, keeping only the copies
+    /// the options' direction allows. A path that's a file on one side and a
+    /// directory on the other is left alone.
+    pub fn new(
+        local_root: &Path,
+        local: &[LocalEntry],
+        remote_root: &str,
+        remote: &[WalkEntry],
+        options: &SyncOptions,
+    ) -> Self {
+        let direction = options.direction;
+        let prefix = format!("{}/", remote_root.trim_end_matches('/'));
+        let remote: Vec<(&str, &WalkEntry)> = remote
+            .iter()
+            .map(|e| (e.file.path.strip_prefix(&prefix).unwrap_or(&e.file.path), e))
+            .collect();
+        let remote_by_path: HashMap<&str, &WalkEntry> = remote.iter().copied().collect();
+        let local_by_path: HashMap<&str, &LocalEntry> =
+            local.iter().map(|e| (e.relative.as_str(), e)).collect();
+
+        let mut plan = Self::default();
+        // Keep the records of files still on some side, or too deep to have been seen
+        plan.state.files = options
+            .state
+            .files
+            .iter()
+            .filter(|(relative, _)| {
+                let relative = relative.as_str();
+                local_by_path.contains_key(relative)
+                    || remote_by_path.contains_key(relative)
+                    || !options.walked(relative)
+            })
+            .map(|(relative, file)| (relative.clone(), file.clone()))
+            .collect();
+
+        if direction.pulls() {
+            plan.local_renames = find_local_renames(local_root, &local_by_path, &remote, options);
+        }
+        if direction.pushes() {
+            plan.remote_renames = find_remote_renames(&prefix, local, &remote_by_path, options);
+        }
+        let moved: HashSet<&str> = plan
+            .local_renames
+            .iter()
+            .chain(&plan.remote_renames)
+            .flat_map(|rename| [rename.from.as_str(), rename.to.as_str()])
+            .collect();
+
+        for (relative, entry) in &remote {
+            if moved.contains(relative) {
+                continue;
+            }
+            let file = &entry.file;
+            match local_by_path.get(relative) {
+                None if !direction.pulls() => note_deletion(
+                    &mut plan.remote_deletions,
+                    Deletion {
+                        relative: relative.to_string(),
+                        local_path: local_root.join(relative),
+                        remote_path: file.path.clone(),
+                        is_dir: file.is_dir,
+                        totals: DirTotals::default(),
+                    },
+                    file.size,
+                ),
+                None if file.is_dir => plan.local_dirs.push(local_root.join(relative)),
+                None => plan.downloads.push(Transfer::download(
+                    relative,
+                    local_root.join(relative),
+                    file,
+                    false,
+                )),
+                Some(existing) if !file.is_dir && !existing.is_dir => {
+                    let download =
+                        || Transfer::download(relative, existing.path.clone(), file, true);
+                    let upload = || Transfer::upload(existing, file.path.clone(), true);
+                    match options.compare(existing, file) {
+                        Outcome::Same => {
+                            plan.state.record(relative, options.matched(existing, file))
+                        }
+                        Outcome::Download => plan.downloads.push(download()),
+                        Outcome::Upload => plan.uploads.push(upload()),
+                        Outcome::Conflict => plan.conflicts.push(Conflict {
+                            relative: relative.to_string(),
+                            download: download(),
+                            upload: upload(),
+                        }),
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        for entry in local {
+            if remote_by_path.contains_key(entry.relative.as_str())
+                || moved.contains(entry.relative.as_str())
+            {
+                continue;
+            }
+            let remote_path = format!("{}{}", prefix, entry.relative);
+            if !direction.pushes() {
+                note_deletion(
+                    &mut plan.local_deletions,
+                    Deletion {
+                        relative: entry.relative.clone(),
+                        local_path: entry.path.clone(),
+                        remote_path,
+                        is_dir: entry.is_dir,
+                        totals: DirTotals::default(),
+                    },
+                    entry.size,
+                );
+            } else if entry.is_dir {
+                plan.remote_dirs.push(remote_path);
+            } else {
+                plan.uploads
+                    .push(Transfer::upload(entry, remote_path, false));
+            }
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local_dirs.is_empty()
+            && self.remote_dirs.is_empty()
+            && self.downloads.is_empty()
+            && self.uploads.is_empty()
+            && self.local_deletions.is_empty()
+            && self.remote_deletions.is_empty()
+            && self.local_renames.is_empty()
+            && self.remote_renames.is_empty()
+            && self.conflicts.is_empty()
+    }
+}
+
+/// Remote files that are new since the last sync but match the size and time
+/// of a recorded file the server no longer has. If the local copy of that file
+/// hasn't changed, it's moved to the new path instead of downloaded again.
+fn find_local_renames(
+    local_root: &Path,
+    local_by_path: &HashMap<&str, &LocalEntry>,
+    remote: &[(&str, &WalkEntry)],
+    options: &SyncOptions,
+) -> Vec<Rename> {
+    let remote_paths: HashSet<&str> = remote.iter().map(|(relative, _)| *relative).collect();
+    let mut gone: Vec<(&str, &FileState, &LocalEntry)> = options
+        .state
+        .files
+        .iter()
+        .filter(|(relative, _)| !remote_paths.contains(relative.as_str()))
+        .filter_map(|(relative, last)| {
+            let entry = local_by_path.get(relative.as_str()).filter(|e| !e.is_dir)?;
+            let unchanged = !last.local_changed(
+                &entry.path,
+                entry.size,
+                entry.modified,
+                options.time_tolerance,
+            );
+            unchanged.then_some((relative.as_str(), last, *entry))
+        })
+        .collect();
+
+    let mut renames = Vec::new();
+    for (relative, entry) in remote {
+        let file = &entry.file;
+        if file.is_dir
+            || local_by_path.contains_key(relative)
+            || options.state.files.contains_key(*relative)
+        {
+            continue;
+        }
+        let found = gone.iter().position(|(_, last, _)| {
+            last.size == file.size
+                && last.remote_modified.is_some_and(|recorded| {
+                    state::within(file.modified, recorded, options.time_tolerance)
+                })
+        });
+        if let Some(index) = found {
+            let (from, last, local) = gone.remove(index);
+            renames.push(Rename {
+                from: from.to_string(),
+                to: relative.to_string(),
+                from_path: local.path.display().to_string(),
+                to_path: local_root.join(relative).display().to_string(),
+                state: last.clone(),
+            });
+        }
+    }
+    renames
+}
+
+/// Local files that are new since the last sync but hash the same as a
+/// recorded file that's gone locally. If the server's copy of that file hasn't
+/// changed, it's moved there instead of uploaded again.
+fn find_remote_renames(
+    prefix: &str,
+    local: &[LocalEntry],
+    remote_by_path: &HashMap<&str, &WalkEntry>,
+    options: &SyncOptions,
+) -> Vec<Rename> {
+    let local_paths: HashSet<&str> = local.iter().map(|e| e.relative.as_str()).collect();
+    let mut gone: Vec<(&str, &FileState, &RemoteFile)> = options
+        .state
+        .files
+        .iter()
+        .filter(|(relative, last)| !local_paths.contains(relative.as_str()) && last.hash.is_some())
+        .filter_map(|(relative, last)| {
+            let file = &remote_by_path.get(relative.as_str())?.file;
+            let unchanged = !file.is_dir
+                && !last.remote_changed(file.size, file.modified, options.time_tolerance);
+            unchanged.then_some((relative.as_str(), last, file))
+        })
+        .collect();
+
+    let mut renames = Vec::new();
+    for entry in local {
+        let relative = entry.relative.as_str();
+        if entry.is_dir
+            || remote_by_path.contains_key(relative)
+            || options.state.files.contains_key(relative)
+            || !gone.iter().any(|(_, last, _)| last.size == entry.size)
+        {
+            continue;
+        }
+        // Only hash new files that could be a match
+        let Some(hash) = hash_file(&entry.path) else {
+            continue;
+        };
+        let found = gone
+            .iter()
+            .position(|(_, last, _)| last.size == entry.size && last.hash.as_ref() == Some(&hash));
+        if let Some(index) = found {
+            let (from, last, file) = gone.remove(index);
+            let mut state = last.clone();
+            state.local_modified = entry.modified.timestamp();
+            renames.push(Rename {
+                from: from.to_string(),
+                to: relative.to_string(),
+                from_path: file.path.clone(),
+                to_path: format!("{}{}", prefix, relative),
+                state,
+            });
+        }
+    }
+    renames
+}
+
+/// Add `candidate` to `deletions`, or count it towards the directory before it
+/// if it's inside it. Entries come parents first, so that's the only one to check.
+fn note_deletion(deletions: &mut Vec<Deletion>, mut candidate: Deletion, size: u64) {
+    let file = DirTotals {
+        size,
+        files: usize::from(!candidate.is_dir),
+    };
+    let parent = deletions.last_mut().filter(|last| {
+        last.is_dir
+            && candidate
+                .relative
+                .strip_prefix(&last.relative)
+                .is_some_and(|rest| rest.starts_with('/'))
+    });
+    if let Some(parent) = parent {
+        parent.totals.size += file.size;
+        parent.totals.files += file.files;
+    } else {
+        candidate.totals = file;
+        deletions.push(candidate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(path: &str, size: u64, is_dir: bool) -> WalkEntry {
+        WalkEntry {
+            file: RemoteFile {
+                name: path.rsplit('/').next().unwrap().to_string(),
+                path: path.to_string(),
+                size,
+                modified: Local::now(),
+                is_dir,
+                permissions: None,
+            },
+            depth: path.matches('/').count() - 1,
+        }
+    }
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("loras/style")).unwrap();
+        std::fs::write(dir.path().join("loras/style/ink.safetensors"), b"ink").unwrap();
+        std::fs::write(dir.path().join("loras/a.safetensors"), b"abc").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"hi").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_walk_local() {
+        let dir = tree();
+        let entries = walk_local(dir.path(), None).unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| e.relative.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            [
+                "loras",
+                "loras/a.safetensors",
+                "loras/style",
+                "loras/style/ink.safetensors",
+                "notes.txt"
+            ]
+        );
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].size, 3);
+
+        let shallow = walk_local(dir.path(), Some(2)).unwrap();
+        assert_eq!(shallow.len(), 4);
+        assert!(walk_local(&dir.path().join("missing"), None).is_err());
+    }
+
+    #[test]
+    fn test_plan_compares_relative_paths() {
+        let dir = tree();
+        let local = walk_local(dir.path(), None).unwrap();
+        let remote = vec![
+            remote("/models/loras", 0, true),
+            remote("/models/loras/a.safetensors", 5, false),
+            remote("/models/vae", 0, true),
+            remote("/models/vae/sdxl.safetensors", 10, false),
+            remote("/models/notes.txt", 2, false),
+        ];
+
+        let plan = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::TwoWay),
+        );
+
+        assert_eq!(plan.local_dirs, [dir.path().join("vae")]);
+        assert_eq!(plan.remote_dirs, ["/models/loras/style"]);
+
+        let downloads: Vec<_> = plan
+            .downloads
+            .iter()
+            .map(|t| (t.relative.as_str(), t.replaces))
+            .collect();
+        assert_eq!(
+            downloads,
+            [
+                ("loras/a.safetensors", true),
+                ("vae/sdxl.safetensors", false)
+            ]
+        );
+        assert_eq!(
+            plan.downloads[1].local_path,
+            dir.path().join("vae/sdxl.safetensors")
+        );
+
+        assert_eq!(plan.uploads.len(), 1);
+        assert_eq!(
+            plan.uploads[0].remote_path,
+            "/models/loras/style/ink.safetensors"
+        );
+    }
+
+    #[test]
+    fn test_coarse_times() {
+        let dir = tree();
+        let local = walk_local(dir.path(), None).unwrap();
+        let notes = local.iter().find(|e| e.relative == "notes.txt").unwrap().modified;
+        let mut remote = vec![
+            remote("/models/loras/a.safetensors", 3, false),
+            remote("/models/loras/style", 0, true),
+            remote("/models/notes.txt", 2, false),
+            remote("/models/vae.safetensors", 7, false),
+        ];
+        remote[0].file.modified = notes - TimeDelta::hours(3);
+        remote[2].file.modified = notes - TimeDelta::seconds(59);
+
+        // Hours apart is a change whatever the seconds; a minute may not be
+        assert_eq!(coarse_times(&local, "/models", &remote, 2), [2]);
+        remote[2].file.size = 3;
+        assert!(coarse_times(&local, "/models", &remote, 2).is_empty());
+    }
+
+    #[test]
+    fn test_plan_from_remote_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+        let local = walk_local(dir.path(), None).unwrap();
+
+        let plan = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/",
+            &[remote("/a.txt", 1, false)],
+            &SyncOptions::new(SyncDirection::TwoWay),
+        );
+        assert!(plan.is_empty());
+
+        // A directory on one side and a file on the other is skipped
+        let plan = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/",
+            &[remote("/a.txt", 0, true)],
+            &SyncOptions::new(SyncDirection::TwoWay),
+        );
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_plan_direction() {
+        let dir = tree();
+        let local = walk_local(dir.path(), None).unwrap();
+        let remote = vec![
+            remote("/models/vae", 0, true),
+            remote("/models/vae/sdxl.safetensors", 10, false),
+        ];
+
+        let pull = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::Pull),
+        );
+        assert_eq!(pull.downloads.len(), 1);
+        assert_eq!(pull.local_dirs.len(), 1);
+        assert!(pull.uploads.is_empty() && pull.remote_dirs.is_empty());
+
+        let push = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::Push),
+        );
+        assert!(push.downloads.is_empty() && push.local_dirs.is_empty());
+        assert_eq!(push.uploads.len(), 3);
+        assert_eq!(push.remote_dirs, ["/models/loras", "/models/loras/style"]);
+    }
+
+    #[test]
+    fn test_plan_deletions() {
+        let dir = tree();
+        let local = walk_local(dir.path(), None).unwrap();
+        let remote = vec![
+            remote("/models/loras", 0, true),
+            remote("/models/loras/a.safetensors", 3, false),
+            remote("/models/vae", 0, true),
+            remote("/models/vae/sdxl.safetensors", 10, false),
+        ];
+
+        let pull = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::Pull),
+        );
+        let deletions: Vec<_> = pull
+            .local_deletions
+            .iter()
+            .map(|d| (d.relative.as_str(), d.totals.files, d.totals.size))
+            .collect();
+        assert_eq!(deletions, [("loras/style", 1, 3), ("notes.txt", 1, 2)]);
+        assert_eq!(
+            pull.local_deletions[0].local_path,
+            dir.path().join("loras/style")
+        );
+        assert!(pull.remote_deletions.is_empty());
+
+        let push = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::Push),
+        );
+        assert_eq!(push.remote_deletions.len(), 1);
+        assert_eq!(push.remote_deletions[0].remote_path, "/models/vae");
+        assert_eq!(
+            push.remote_deletions[0].totals,
+            DirTotals { size: 10, files: 1 }
+        );
+
+        let both = SyncPlan::new(
+            dir.path(),
+            &local,
+            "/models",
+            &remote,
+            &SyncOptions::new(SyncDirection::TwoWay),
+        );
+        assert!(both.local_deletions.is_empty() && both.remote_deletions.is_empty());
+    }
+
+    #[test]
+    fn test_plan_compares_modification_times() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+        let local = walk_local(dir.path(), None).unwrap();
+        let at = |offset: i64| {
+            let mut entry = remote("/a.txt", 1, false);
+            entry.file.modified = local[0].modified + TimeDelta::seconds(offset);
+            vec![entry]
+        };
+        let plan = |remote: &[WalkEntry], options: SyncOptions| {
+            let plan = SyncPlan::new(dir.path(), &local, "/", remote, &options);
+            (plan.downloads.len(), plan.uploads.len())
+        };
+        let two_way = SyncOptions::new(SyncDirection::TwoWay);
+
+        // Within the tolerance the files match
+        assert_eq!(plan(&at(1), two_way.clone()), (0, 0));
+        assert_eq!(
+            plan(&at(-30), two_way.clone().with_time_tolerance(60)),
+            (0, 0)
+        );
+
+        // Otherwise the newer side wins, or the source of a one-way sync
+        assert_eq!(plan(&at(3600), two_way.clone()), (1, 0));
+        assert_eq!(plan(&at(-3600), two_way.clone()), (0, 1));
+        assert_eq!(
+            plan(&at(-3600), SyncOptions::new(SyncDirection::Pull)),
+            (1, 0)
+        );
+        assert_eq!(
+            plan(&at(3600), SyncOptions::new(SyncDirection::Push)),
+            (0, 1)
+        );
+
+        let upload = SyncPlan::new(dir.path(), &local, "/", &at(-3600), &two_way).uploads;
+        assert!(upload[0].replaces);
+        assert_eq!(upload[0].remote_path, "/a.txt");
+    }
+
+    #[test]
+    fn test_plan_uses_last_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+        let local = walk_local(dir.path(), None).unwrap();
+        let synced = local[0].modified - TimeDelta::hours(2);
+        let record = FileState::new(3, synced).with_remote_modified(Some(synced));
+        let with_record = |record: FileState| {
+            let mut last = SyncState::default();
+            last.record("a.txt", record);
+            SyncOptions::new(SyncDirection::TwoWay).with_state(last)
+        };
+        let at = |modified| {
+            let mut entry = remote("/a.txt", 3, false);
+            entry.file.modified = modified;
+            vec![entry]
+        };
+        let plan = |remote: &[WalkEntry], options: &SyncOptions| {
+            let plan = SyncPlan::new(dir.path(), &local, "/", remote, options);
+            (plan.downloads.len(), plan.uploads.len())
+        };
+
+        // The local file was only touched since the sync, so the remote change
+        // wins even though it's older
+        let touched = with_record(record.clone().hashed(&local[0].path));
+        let changed = at(synced + TimeDelta::hours(1));
+        assert_eq!(plan(&changed, &touched), (1, 0));
+        assert_eq!(plan(&at(synced), &touched), (0, 0));
+
+        // Without a hash to tell, both changed and the newer one wins
+        let unhashed = with_record(record);
+        assert_eq!(plan(&changed, &unhashed), (0, 1));
+
+        // What matches is recorded as it is now
+        let state = SyncPlan::new(dir.path(), &local, "/", &at(synced), &touched).state;
+        let file = &state.files["a.txt"];
+        assert_eq!(file.local_modified, local[0].modified.timestamp());
+        assert_eq!(file.remote_modified, Some(synced.timestamp()));
+    }
+
+    #[test]
+    fn test_plan_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("new.txt"), b"abc").unwrap();
+        std::fs::write(dir.path().join("kept.txt"), b"xyz").unwrap();
+        let local = walk_local(dir.path(), None).unwrap();
+        let synced = Local::now() - TimeDelta::hours(2);
+        let record = |path: &Path| {
+            FileState::new(3, synced)
+                .with_remote_modified(Some(synced))
+                .hashed(path)
+        };
+        let mut last = SyncState::default();
+        // Moved from old.txt to new.txt locally; kept.txt moved on the server
+        last.record("old.txt", record(&dir.path().join("new.txt")));
+        last.record("kept.txt", record(&dir.path().join("kept.txt")));
+        let mut old = remote("/old.txt", 3, false);
+        old.file.modified = synced;
+        let mut moved = remote("/moved.txt", 3, false);
+        moved.file.modified = synced;
+        let remote = vec![moved, old];
+
+        // The local file looks changed by time, so pin its record to it
+        let mut state = last;
+        state.files.get_mut("kept.txt").unwrap().local_modified = local[0].modified.timestamp();
+        let options = SyncOptions::new(SyncDirection::TwoWay).with_state(state);
+        let plan = SyncPlan::new(dir.path(), &local, "/", &remote, &options);
+
+        let moves = |renames: &[Rename]| -> Vec<(String, String)> {
+            renames
+                .iter()
+                .map(|r| (r.from.clone(), r.to_path.clone()))
+                .collect()
+        };
+        assert_eq!(
+            moves(&plan.remote_renames),
+            [("old.txt".to_string(), "/new.txt".to_string())]
+        );
+        assert_eq!(
+            moves(&plan.local_renames),
+            [(
+                "kept.txt".to_string(),
+                dir.path().join("moved.txt").display().to_string()
+            )]
+        );
+        assert!(plan.downloads.is_empty() && plan.uploads.is_empty());
+
+        // A pull doesn't move anything on the server
+        let pull_options = SyncOptions::new(SyncDirection::Pull).with_state(options.state.clone());
+        let pull = SyncPlan::new(dir.path(), &local, "/", &remote, &pull_options);
+        assert_eq!(pull.local_renames.len(), 1);
+        assert!(pull.remote_renames.is_empty());
+        assert_eq!(pull.local_deletions.len(), 1);
+    }
+
+    #[test]
+    fn test_plan_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("loras")).unwrap();
+        std::fs::write(dir.path().join("loras/a.safetensors"), b"local").unwrap();
+        let local = walk_local(dir.path(), None).unwrap();
+        let mut newer = remote("/models/loras/a.safetensors", 6, false);
+        newer.file.modified = local[1].modified + TimeDelta::hours(1);
+        let remote = vec![remote("/models/loras", 0, true), newer];
+        let plan = |conflict| {
+            let options = SyncOptions::new(SyncDirection::TwoWay).with_conflict(conflict);
+            let plan = SyncPlan::new(dir.path(), &local, "/models", &remote, &options);
+            (plan.downloads.len(), plan.uploads.len(), plan.conflicts)
+        };
+
+        assert_eq!(plan(ConflictPolicy::Newer), (1, 0, vec![]));
+        assert_eq!(plan(ConflictPolicy::Remote), (1, 0, vec![]));
+        assert_eq!(plan(ConflictPolicy::Local), (0, 1, vec![]));
+
+        let (downloads, uploads, conflicts) = plan(ConflictPolicy::KeepBoth);
+        assert_eq!((downloads, uploads, conflicts.len()), (0, 0, 1));
+        let date = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let (download, upload) = conflicts[0].keep_both(date);
+        assert_eq!(download.local_path, dir.path().join("loras/a.safetensors"));
+        assert!(!download.replaces && !upload.replaces);
+        assert_eq!(upload.relative, "loras/a (conflict 2024-06-01).safetensors");
+        assert_eq!(
+            upload.local_path,
+            dir.path().join("loras/a (conflict 2024-06-01).safetensors")
+        );
+        assert_eq!(
+            upload.remote_path,
+            "/models/loras/a (conflict 2024-06-01).safetensors"
+        );
+    }
+
+    #[test]
+    fn test_conflict_name_and_policy() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        assert_eq!(
+            conflict_name("file.txt", date),
+            "file (conflict 2024-06-01).txt"
+        );
+        assert_eq!(
+            conflict_name("a/Makefile", date),
+            "a/Makefile (conflict 2024-06-01)"
+        );
+        assert_eq!(conflict_name(".env", date), ".env (conflict 2024-06-01)");
+
+        assert_eq!(
+            "keep-both".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::KeepBoth
+        );
+        assert_eq!(
+            "Ask".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Ask
+        );
+        assert!("both".parse::<ConflictPolicy>().is_err());
+    }
+}
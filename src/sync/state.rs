@@ -0,0 +1,283 @@
+//! What the last sync of a directory pair left on both sides, so the next one
+//! can tell which side changed
+
+use crate::config::config_dir;
+use crate::hash::{hash_local, HashAlgorithm};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The hash kept for each synced file
+const STATE_HASH: HashAlgorithm = HashAlgorithm::Blake3;
+
+/// One file as it was on both sides after it was last synced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    pub size: u64,
+    /// Unix seconds
+    pub local_modified: i64,
+    /// Unix seconds, unless the server couldn't keep the time of an upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_modified: Option<i64>,
+    /// BLAKE3 of the contents, for files whose contents were read during a sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl FileState {
+    pub fn new(size: u64, local_modified: DateTime<Local>) -> Self {
+        Self {
+            size,
+            local_modified: local_modified.timestamp(),
+            remote_modified: None,
+            hash: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_remote_modified(mut self, modified: Option<DateTime<Local>>) -> Self {
+        self.remote_modified = modified.map(|m| m.timestamp());
+        self
+    }
+
+    #[must_use]
+    pub fn with_hash(mut self, hash: Option<String>) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Hash the local copy at `path` for the record
+    #[must_use]
+    pub fn hashed(self, path: &Path) -> Self {
+        let hash = hash_file(path);
+        self.with_hash(hash)
+    }
+
+    /// Whether the local file at `path` changed since this record. A new time
+    /// alone doesn't count when the contents still hash the same.
+    pub fn local_changed(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: DateTime<Local>,
+        tolerance: TimeDelta,
+    ) -> bool {
+        if size != self.size {
+            return true;
+        }
+        if within(modified, self.local_modified, tolerance) {
+            return false;
+        }
+        self.hash
+            .as_ref()
+            .is_none_or(|hash| hash_file(path).as_ref() != Some(hash))
+    }
+
+    /// Whether the remote file changed since this record. Without a recorded
+    /// time, only the size can tell.
+    pub fn remote_changed(
+        &self,
+        size: u64,
+        modified: DateTime<Local>,
+        tolerance: TimeDelta,
+    ) -> bool {
+        size != self.size
+            || self
+                .remote_modified
+                .is_some_and(|recorded| !within(modified, recorded, tolerance))
+    }
+}
+
+/// Whether `modified` is within `tolerance` of the recorded unix seconds
+pub fn within(modified: DateTime<Local>, recorded: i64, tolerance: TimeDelta) -> bool {
+    (modified.timestamp() - recorded).abs() <= tolerance.num_seconds()
+}
+
+/// The digest a record keeps of the local file at `path`, if it can be read
+pub fn hash_file(path: &Path) -> Option<String> {
+    hash_local(path, STATE_HASH).ok()
+}
+
+/// Every file the last sync of one directory pair left matching on both sides,
+/// by path below the two roots
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    #[serde(default)]
+    pub files: BTreeMap<String, FileState>,
+}
+
+/// All the pairs synced with one profile
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    pairs: BTreeMap<String, SyncState>,
+}
+
+impl StateFile {
+    fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid sync state in {}: {}", path.display(), e))
+    }
+}
+
+impl SyncState {
+    /// Note a file as matching on both sides
+    pub fn record(&mut self, relative: &str, file: FileState) {
+        self.files.insert(relative.to_string(), file);
+    }
+
+    /// Drop the record of `relative` and of everything under it
+    pub fn forget(&mut self, relative: &str) {
+        self.files.retain(|path, _| {
+            path.strip_prefix(relative)
+                .is_none_or(|rest| !rest.is_empty() && !rest.starts_with('/'))
+        });
+    }
+
+    /// Hash every recorded file under `local_root` that has no hash yet, so the
+    /// next sync can see past touched files and spot renames
+    pub fn fill_hashes(&mut self, local_root: &Path) {
+        for (relative, file) in &mut self.files {
+            if file.hash.is_none() {
+                file.hash = hash_file(&local_root.join(relative));
+            }
+        }
+    }
+
+    /// The state stored under `key` in the file at `path`, empty when there's none yet
+    pub fn load(path: &Path, key: &str) -> Result<Self> {
+        Ok(StateFile::read(path)?.pairs.remove(key).unwrap_or_default())
+    }
+
+    /// Replace the state stored under `key`, keeping the other pairs in the file
+    pub fn save(&self, path: &Path, key: &str) -> Result<()> {
+        let mut file = StateFile::read(path)?;
+        file.pairs.insert(key.to_string(), self.clone());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+}
+
+/// Where the sync state for `profile` is kept, e.g.
+/// `~/.config/comfy-fs/sync-state/default.json`
+pub fn state_path(profile: Option<&str>) -> Result<PathBuf> {
+    let dir = config_dir().ok_or_else(|| anyhow!("Could not find the config directory"))?;
+    Ok(dir
+        .join("sync-state")
+        .join(format!("{}.json", profile.unwrap_or("default"))))
+}
+
+/// The key a local and remote directory's state is stored under
+pub fn pair_key(local: &Path, remote: &str) -> String {
+    let local = fs::canonicalize(local).unwrap_or_else(|_| local.to_path_buf());
+    let remote = match remote.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    };
+    format!("{} -> {}", local.display(), remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn test_local_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, b"abc").unwrap();
+        let tolerance = TimeDelta::seconds(2);
+
+        let record = FileState::new(3, at(1_000)).hashed(&path);
+        assert!(!record.local_changed(&path, 3, at(1_001), tolerance));
+        assert!(record.local_changed(&path, 4, at(1_000), tolerance));
+        // Touched but not edited
+        assert!(!record.local_changed(&path, 3, at(5_000), tolerance));
+
+        fs::write(&path, b"abd").unwrap();
+        assert!(record.local_changed(&path, 3, at(5_000), tolerance));
+        assert!(FileState::new(3, at(1_000)).local_changed(&path, 3, at(5_000), tolerance));
+    }
+
+    #[test]
+    fn test_remote_changed() {
+        let tolerance = TimeDelta::seconds(2);
+        let record = FileState::new(3, at(1_000)).with_remote_modified(Some(at(2_000)));
+
+        assert!(!record.remote_changed(3, at(2_001), tolerance));
+        assert!(record.remote_changed(3, at(3_000), tolerance));
+        assert!(record.remote_changed(4, at(2_000), tolerance));
+
+        let untimed = FileState::new(3, at(1_000));
+        assert!(!untimed.remote_changed(3, at(3_000), tolerance));
+    }
+
+    #[test]
+    fn test_forget_and_fill_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("loras")).unwrap();
+        fs::write(dir.path().join("loras/a.bin"), b"abc").unwrap();
+
+        let mut state = SyncState::default();
+        for path in ["loras/a.bin", "loras/b.bin", "loras-old/c.bin", "notes.txt"] {
+            state.record(path, FileState::new(3, at(1_000)));
+        }
+        state.forget("loras");
+        assert_eq!(
+            state.files.keys().collect::<Vec<_>>(),
+            ["loras-old/c.bin", "notes.txt"]
+        );
+
+        state.record("loras/a.bin", FileState::new(3, at(1_000)));
+        state.fill_hashes(dir.path());
+        assert_eq!(
+            state.files["loras/a.bin"].hash.as_deref(),
+            Some(blake3::hash(b"abc").to_hex().as_str())
+        );
+        assert!(state.files["notes.txt"].hash.is_none());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/default.json");
+        assert_eq!(SyncState::load(&path, "a").unwrap(), SyncState::default());
+
+        let mut first = SyncState::default();
+        first
+            .files
+            .insert("models/a.bin".to_string(), FileState::new(3, at(1_000)));
+        first.save(&path, "a").unwrap();
+        SyncState::default().save(&path, "b").unwrap();
+
+        assert_eq!(SyncState::load(&path, "a").unwrap(), first);
+        assert!(SyncState::load(&path, "b").unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn test_pair_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = fs::canonicalize(dir.path()).unwrap();
+
+        assert_eq!(
+            pair_key(dir.path(), "/models/"),
+            format!("{} -> /models", local.display())
+        );
+        assert!(pair_key(dir.path(), "/").ends_with(" -> /"));
+    }
+}
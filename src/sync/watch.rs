@@ -0,0 +1,75 @@
+//! Waiting for files under a local directory to change, for `sync --watch`
+
+use anyhow::{anyhow, Context, Result};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// How long a directory has to be quiet before its changes are synced
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watches a directory and everything under it
+pub struct LocalWatcher {
+    // Stops watching when dropped
+    _debouncer: Debouncer<RecommendedWatcher>,
+    events: UnboundedReceiver<DebounceEventResult>,
+}
+
+impl LocalWatcher {
+    /// Start watching `root`, grouping changes that come less than `debounce` apart
+    pub fn new(root: &Path, debounce: Duration) -> Result<Self> {
+        let (sender, events) = unbounded_channel();
+        let mut debouncer = new_debouncer(debounce, move |result| {
+            let _ = sender.send(result);
+        })?;
+        debouncer
+            .watcher()
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", root.display()))?;
+        Ok(Self {
+            _debouncer: debouncer,
+            events,
+        })
+    }
+
+    /// The paths in the next group of changes
+    pub async fn changed(&mut self) -> Result<Vec<PathBuf>> {
+        let result = self
+            .events
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("Stopped watching for changes"))?;
+        let events = result.map_err(|e| anyhow!("Failed to watch for changes: {}", e))?;
+        Ok(events.into_iter().map(|event| event.path).collect())
+    }
+
+    /// Forget changes already reported, such as the files a sync just wrote
+    pub fn discard_pending(&mut self) {
+        while self.events.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("loras")).unwrap();
+        let mut watcher = LocalWatcher::new(&root, Duration::from_millis(50)).unwrap();
+
+        std::fs::write(root.join("loras/a.safetensors"), b"abc").unwrap();
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(changed.contains(&root.join("loras/a.safetensors")));
+
+        watcher.discard_pending();
+        assert!(watcher.events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,533 @@
+//! Copying a batch of files both ways at once, reporting progress as a stream
+//! of events
+
+use crate::cancel::PartialFile;
+use crate::client::Progress;
+use crate::connection::ClientPool;
+use crate::history::{History, Operation};
+use crate::retry::RetryPolicy;
+use crate::safetensors::{self, is_safetensors};
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::info;
+
+/// Told the index and outcome of each job as soon as it finishes
+pub type OnFinished = Box<dyn Fn(usize, &Result<u32>) + Send + Sync>;
+
+/// What happens to the jobs of a batch, by their index, as it happens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TransferEvent {
+    /// A job began copying `size` bytes, or began again from the start after
+    /// a failed try
+    Started { index: usize, size: u64 },
+    /// More bytes of a job were copied
+    Bytes { index: usize, bytes: u64 },
+    /// A try failed and the job will start again
+    Retrying {
+        index: usize,
+        attempt: u32,
+        error: String,
+    },
+    /// A job is done, after this many retries
+    Finished { index: usize, retries: u32 },
+    /// A job failed for good
+    Failed { index: usize, error: String },
+}
+
+impl TransferEvent {
+    /// The job this happened to
+    pub const fn index(&self) -> usize {
+        match self {
+            Self::Started { index, .. }
+            | Self::Bytes { index, .. }
+            | Self::Retrying { index, .. }
+            | Self::Finished { index, .. }
+            | Self::Failed { index, .. } => *index,
+        }
+    }
+}
+
+/// Where a batch sends its [`TransferEvent`]s. The default sends them nowhere.
+#[derive(Clone, Default)]
+pub struct EventSender(Option<UnboundedSender<TransferEvent>>);
+
+impl EventSender {
+    pub fn send(&self, event: TransferEvent) {
+        if let Some(sender) = &self.0 {
+            // Nobody listening any more is no reason to stop copying
+            let _ = sender.send(event);
+        }
+    }
+
+    /// A client progress callback sending what it's told as
+    /// [`TransferEvent::Bytes`] of job `index`
+    pub fn progress(&self, index: usize) -> Progress {
+        let events = self.clone();
+        Arc::new(move |bytes| events.send(TransferEvent::Bytes { index, bytes }))
+    }
+}
+
+/// The [`TransferEvent`]s of a batch as they happen. The stream ends once every
+/// [`EventSender`] for it is dropped.
+pub struct TransferEvents(UnboundedReceiver<TransferEvent>);
+
+impl TransferEvents {
+    /// The next event if one has already happened, for callers that can't wait
+    pub fn try_next(&mut self) -> Option<TransferEvent> {
+        self.0.try_recv().ok()
+    }
+}
+
+impl Stream for TransferEvents {
+    type Item = TransferEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_recv(cx)
+    }
+}
+
+/// A sender to give a batch and the stream of what it sends
+pub fn event_channel() -> (EventSender, TransferEvents) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    (EventSender(Some(sender)), TransferEvents(receiver))
+}
+
+/// Transfers run at once when neither `--parallel` nor the config says
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Most transfers allowed at once, since each holds its own connection
+pub const MAX_CONCURRENCY: usize = 32;
+
+/// `count` if it's a usable number of transfers to run at once
+pub fn check_concurrency(count: usize) -> Result<usize> {
+    if (1..=MAX_CONCURRENCY).contains(&count) {
+        Ok(count)
+    } else {
+        Err(anyhow!(
+            "Invalid number of parallel transfers '{}' (expected 1 to {})",
+            count,
+            MAX_CONCURRENCY
+        ))
+    }
+}
+
+/// Parse `--parallel N`
+pub fn parse_concurrency(input: &str) -> Result<usize> {
+    let count = input.trim().parse().map_err(|_| {
+        anyhow!(
+            "Invalid number of parallel transfers '{}' (expected 1 to {})",
+            input,
+            MAX_CONCURRENCY
+        )
+    })?;
+    check_concurrency(count)
+}
+
+/// Which way a file is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferKind {
+    Download,
+    Upload,
+}
+
+/// One file to copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferJob {
+    pub kind: TransferKind,
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    /// Bytes to copy, for the overall progress
+    pub size: u64,
+}
+
+impl TransferJob {
+    pub const fn download(remote_path: String, local_path: PathBuf, size: u64) -> Self {
+        Self {
+            kind: TransferKind::Download,
+            remote_path,
+            local_path,
+            size,
+        }
+    }
+
+    pub const fn upload(local_path: PathBuf, remote_path: String, size: u64) -> Self {
+        Self {
+            kind: TransferKind::Upload,
+            remote_path,
+            local_path,
+            size,
+        }
+    }
+
+    /// What the job is doing, e.g. `Downloading a.png`
+    pub fn label(&self) -> String {
+        let verb = match self.kind {
+            TransferKind::Download => "Downloading",
+            TransferKind::Upload => "Uploading",
+        };
+        format!("{} {}", verb, self.name())
+    }
+
+    fn name(&self) -> &str {
+        self.remote_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.remote_path)
+    }
+}
+
+/// Runs several transfers at a time, retrying each on its own, and sends what
+/// happens to each as [`TransferEvent`]s
+pub struct ParallelTransfers {
+    pool: ClientPool,
+    max_concurrent: usize,
+    retry_policy: RetryPolicy,
+    history: Option<History>,
+    /// Downloads go through partial files that outlive a failed run
+    resume: bool,
+    /// Check `.safetensors` files before uploading and after downloading them
+    validate_safetensors: bool,
+    on_finished: Option<OnFinished>,
+    events: EventSender,
+}
+
+impl ParallelTransfers {
+    /// Transfers only overlap when `client` is a pool that can open more clients
+    pub fn new(client: impl Into<ClientPool>, max_concurrent: usize) -> Self {
+        Self {
+            pool: client.into(),
+            max_concurrent: max_concurrent.max(1),
+            retry_policy: RetryPolicy::default(),
+            history: None,
+            resume: false,
+            validate_safetensors: false,
+            on_finished: None,
+            events: EventSender::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Record each file's outcome, after its retries, in `history`
+    #[must_use]
+    pub fn with_history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Keep what an interrupted download got, and continue from it next time,
+    /// instead of removing it
+    #[must_use]
+    pub const fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Fail uploads of `.safetensors` files that are cut short or damaged, and
+    /// remove downloads that are, rather than leave them for ComfyUI to load
+    #[must_use]
+    pub const fn with_safetensors_validation(mut self, validate: bool) -> Self {
+        self.validate_safetensors = validate;
+        self
+    }
+
+    /// Call `on_finished` with each job's index and outcome as it finishes,
+    /// so the caller can save progress a Ctrl-C would otherwise lose
+    #[must_use]
+    pub fn with_on_finished(
+        mut self,
+        on_finished: impl Fn(usize, &Result<u32>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_finished = Some(Box::new(on_finished));
+        self
+    }
+
+    /// Send what happens to each job to `events` as it happens, e.g. for
+    /// [`ProgressBars`](crate::progress::ProgressBars) to draw
+    #[must_use]
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Run every job, returning for each, in the same order, how many retries
+    /// it took or why it failed
+    pub async fn run(&self, jobs: &[TransferJob]) -> Vec<Result<u32>> {
+        // Indexing rather than iterating keeps borrows out of the closure's
+        // argument, which would stop the run being spawned as a task
+        stream::iter(0..jobs.len())
+            .map(|index| async move {
+                let result = self.run_one(index, &jobs[index]).await;
+                self.events.send(match &result {
+                    Ok(retries) => TransferEvent::Finished {
+                        index,
+                        retries: *retries,
+                    },
+                    Err(e) => TransferEvent::Failed {
+                        index,
+                        error: format!("{:#}", e),
+                    },
+                });
+                if let Some(on_finished) = &self.on_finished {
+                    on_finished(index, &result);
+                }
+                result
+            })
+            .buffered(self.max_concurrent)
+            .collect()
+            .await
+    }
+
+    async fn run_one(&self, index: usize, job: &TransferJob) -> Result<u32> {
+        let started = Instant::now();
+        let result = self.copy_checked(index, job).await;
+        if let Some(history) = &self.history {
+            let operation = match job.kind {
+                TransferKind::Download => Operation::Download,
+                TransferKind::Upload => Operation::Upload,
+            };
+            history.record(
+                operation,
+                &job.remote_path,
+                Some(&job.local_path),
+                job.size,
+                started.elapsed(),
+                &result,
+            );
+        }
+        let ((), retries) = result?;
+        Ok(retries)
+    }
+
+    /// Copy with retries, checking `.safetensors` files on the way
+    async fn copy_checked(&self, index: usize, job: &TransferJob) -> Result<((), u32)> {
+        let validate = self.validate_safetensors && is_safetensors(&job.local_path);
+        // A damaged file is no better on a second try, so it's checked outside the retries
+        if validate && job.kind == TransferKind::Upload {
+            check_file(&job.local_path, safetensors::validate).await?;
+        }
+        let copied = self
+            .retry_policy
+            .run(
+                || {
+                    // A retry starts the file over
+                    self.events.send(TransferEvent::Started {
+                        index,
+                        size: job.size,
+                    });
+                    self.transfer(job, self.events.progress(index))
+                },
+                |attempt, e| {
+                    self.events.send(TransferEvent::Retrying {
+                        index,
+                        attempt,
+                        error: format!("{:#}", e),
+                    });
+                },
+            )
+            .await?;
+        if validate && job.kind == TransferKind::Download {
+            check_file(&job.local_path, safetensors::check_download).await?;
+        }
+        Ok(copied)
+    }
+
+    async fn transfer(&self, job: &TransferJob, progress: Progress) -> Result<()> {
+        let started = Instant::now();
+        let result = self.copy(job, progress).await;
+        match &result {
+            Ok(()) => info!(
+                bytes = job.size,
+                elapsed = ?started.elapsed(),
+                "{:?} of {} done",
+                job.kind,
+                job.remote_path
+            ),
+            Err(e) => info!(
+                elapsed = ?started.elapsed(),
+                "{:?} of {} failed: {:#}",
+                job.kind,
+                job.remote_path,
+                e
+            ),
+        }
+        result
+    }
+
+    async fn copy(&self, job: &TransferJob, progress: Progress) -> Result<()> {
+        match job.kind {
+            TransferKind::Download => {
+                if let Some(parent) = job.local_path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let mut client = self.pool.acquire().await;
+                // Servers that can't resume would only download it all again
+                if self.resume && client.capabilities().resume {
+                    return client
+                        .resume_download(&job.remote_path, &job.local_path, progress)
+                        .await;
+                }
+                let partial = PartialFile::new(&job.local_path);
+                client
+                    .download_file_with_progress(&job.remote_path, &job.local_path, progress)
+                    .await?;
+                partial.keep();
+                Ok(())
+            }
+            TransferKind::Upload => {
+                let mut client = self.pool.acquire().await;
+                client
+                    .upload_file_with_progress(&job.local_path, &job.remote_path, progress)
+                    .await
+            }
+        }
+    }
+}
+
+/// Run `check` on a local file away from the async threads, as it reads the disk
+async fn check_file(path: &Path, check: fn(&Path) -> Result<()>) -> Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || check(&path)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use crate::client::FileServerClient;
+    use crate::config::Config;
+    use crate::history::read_history;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn test_parse_concurrency() {
+        assert_eq!(parse_concurrency("8").unwrap(), 8);
+        assert_eq!(parse_concurrency(" 1 ").unwrap(), 1);
+        assert_eq!(parse_concurrency("32").unwrap(), MAX_CONCURRENCY);
+        assert!(parse_concurrency("0").is_err());
+        assert!(parse_concurrency("33").is_err());
+        assert!(parse_concurrency("-1").is_err());
+        assert!(parse_concurrency("many").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_both_ways_in_order() {
+        let server = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        std::fs::write(server.path().join("down.txt"), b"down").unwrap();
+        std::fs::write(local.path().join("up.txt"), b"up").unwrap();
+
+        let client: Box<dyn FileServerClient> =
+            Box::new(LocalClient::new(server.path().to_path_buf()));
+        let history_file = local.path().join("history.jsonl");
+        let history = History::new(history_file.clone(), &Config::default(), None);
+        let failed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let failed_jobs = failed.clone();
+        let (sender, mut events) = event_channel();
+        let transfers = ParallelTransfers::new(Arc::new(Mutex::new(client)), 4)
+            .with_retry_policy(RetryPolicy::new(0))
+            .with_history(Some(history))
+            .with_events(sender)
+            .with_on_finished(move |index, result| {
+                if result.is_err() {
+                    failed_jobs.lock().unwrap().push(index);
+                }
+            });
+        let jobs = [
+            TransferJob::download(
+                "/down.txt".to_string(),
+                local.path().join("nested/down.txt"),
+                4,
+            ),
+            TransferJob::upload(
+                local.path().join("missing.txt"),
+                "/missing.txt".to_string(),
+                1,
+            ),
+            TransferJob::upload(local.path().join("up.txt"), "/up.txt".to_string(), 2),
+        ];
+
+        let results = transfers.run(&jobs).await;
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert_eq!(*failed.lock().unwrap(), vec![1]);
+        assert_eq!(
+            std::fs::read(local.path().join("nested/down.txt")).unwrap(),
+            b"down"
+        );
+        assert_eq!(std::fs::read(server.path().join("up.txt")).unwrap(), b"up");
+
+        let mut bytes = [0; 3];
+        let mut ended = Vec::new();
+        while let Some(event) = events.try_next() {
+            match event {
+                TransferEvent::Bytes { index, bytes: n } => bytes[index] += n,
+                TransferEvent::Finished { index, .. } | TransferEvent::Failed { index, .. } => {
+                    ended.push(index);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(bytes, [4, 0, 2]);
+        ended.sort_unstable();
+        assert_eq!(ended, [0, 1, 2]);
+
+        let recorded = read_history(&history_file).unwrap();
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(recorded.iter().filter(|e| !e.succeeded()).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_damaged_safetensors_are_not_copied() {
+        let server = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        // A header promising 4 bytes of tensor data, with 2 of them there
+        let header = br#"{"w": {"dtype": "F16", "shape": [2], "data_offsets": [0, 4]}}"#;
+        let mut truncated = (header.len() as u64).to_le_bytes().to_vec();
+        truncated.extend_from_slice(header);
+        truncated.extend_from_slice(&[0, 0]);
+        std::fs::write(server.path().join("down.safetensors"), &truncated).unwrap();
+        std::fs::write(local.path().join("up.safetensors"), &truncated).unwrap();
+
+        let client: Box<dyn FileServerClient> =
+            Box::new(LocalClient::new(server.path().to_path_buf()));
+        let transfers = ParallelTransfers::new(Arc::new(Mutex::new(client)), 2)
+            .with_retry_policy(RetryPolicy::new(0))
+            .with_safetensors_validation(true);
+        let size = truncated.len() as u64;
+        let jobs = [
+            TransferJob::download(
+                "/down.safetensors".to_string(),
+                local.path().join("down.safetensors"),
+                size,
+            ),
+            TransferJob::upload(
+                local.path().join("up.safetensors"),
+                "/up.safetensors".to_string(),
+                size,
+            ),
+        ];
+
+        let results = transfers.run(&jobs).await;
+        let error = format!("{:#}", results[0].as_ref().unwrap_err());
+        assert!(error.contains("truncated"), "{}", error);
+        assert!(!local.path().join("down.safetensors").exists());
+        assert!(results[1].is_err());
+        assert!(!server.path().join("up.safetensors").exists());
+    }
+}
@@ -0,0 +1,360 @@
+//! The trash on the server that `rm` and the browser move deletions into, for
+//! `comfy-fs trash`
+//!
+//! Each run that deletes something gets a batch directory named for when it
+//! ran, e.g. `/.comfy-trash/20240501-143000-3fa9c2/`, with a random suffix so
+//! runs in the same second don't share one. What's deleted is moved into it
+//! under its whole original path with the slashes escaped, such as
+//! `output%2Fimg_001.png`, so it can be put back without a separate record.
+
+use crate::client::{create_directory_all, FileServerClient};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Serialize;
+use std::hash::{BuildHasher, Hasher};
+
+/// Where the trash lives on the server
+pub const TRASH_DIR: &str = "/.comfy-trash";
+
+/// How batch directories are named, before their suffix
+const BATCH_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Length of a name in [`BATCH_FORMAT`]
+const BATCH_TIME_LEN: usize = 15;
+
+/// Characters escaped in the names of trashed entries, so a path fits in one name
+const ESCAPED: &AsciiSet = &CONTROLS.add(b'/').add(b'%');
+
+/// Whether `path` is the trash or something in it
+pub fn is_in_trash(path: &str) -> bool {
+    path.strip_prefix(TRASH_DIR)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Moves deletions into one batch of the trash, created when first needed
+pub struct Trash {
+    batch: String,
+    created: bool,
+}
+
+impl Trash {
+    /// A batch named for now
+    pub fn new() -> Self {
+        // Each RandomState is seeded afresh, which is all the randomness this needs
+        let suffix = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        Self {
+            batch: format!(
+                "{}-{:06x}",
+                Local::now().format(BATCH_FORMAT),
+                suffix & 0xff_ffff
+            ),
+            created: false,
+        }
+    }
+
+    /// The batch's name, which `trash restore` accepts to undo the whole run
+    pub fn batch(&self) -> &str {
+        &self.batch
+    }
+
+    /// Move `path` into the trash. Returns where it went.
+    pub async fn put(&mut self, client: &mut dyn FileServerClient, path: &str) -> Result<String> {
+        if !client.capabilities().rename {
+            bail!(
+                "This server can't move {} to the trash; delete it with --permanent",
+                path
+            );
+        }
+        if is_in_trash(path) {
+            bail!(
+                "{} is in the trash already; delete it with --permanent",
+                path
+            );
+        }
+        let batch_dir = format!("{}/{}", TRASH_DIR, self.batch);
+        if !self.created {
+            create_directory_all(client, &batch_dir).await?;
+            self.created = true;
+        }
+        let to = format!("{}/{}", batch_dir, trashed_name(path));
+        client.rename(path, &to).await?;
+        Ok(to)
+    }
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn trashed_name(path: &str) -> String {
+    utf8_percent_encode(path.trim_start_matches('/'), ESCAPED).to_string()
+}
+
+fn original_path(name: &str) -> String {
+    format!("/{}", percent_decode_str(name).decode_utf8_lossy())
+}
+
+/// When a batch was made, from its name. Batches from before they had a
+/// suffix are named for the time alone.
+fn batch_time(batch: &str) -> Option<DateTime<Local>> {
+    let time = batch.get(..BATCH_TIME_LEN)?;
+    if !matches!(batch.as_bytes().get(BATCH_TIME_LEN), None | Some(b'-')) {
+        return None;
+    }
+    let time = NaiveDateTime::parse_from_str(time, BATCH_FORMAT).ok()?;
+    Local.from_local_datetime(&time).earliest()
+}
+
+/// Something in the trash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrashedItem {
+    pub batch: String,
+    /// When it was deleted, if the batch is named like [`Trash`] names them
+    pub deleted: Option<DateTime<Local>>,
+    /// Where it was deleted from, and where restoring it puts it
+    pub original_path: String,
+    pub trash_path: String,
+    /// Bytes, for files
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Everything in the trash, oldest batch first
+pub async fn list_trash(client: &mut dyn FileServerClient) -> Result<Vec<TrashedItem>> {
+    let trash_name = TRASH_DIR.trim_start_matches('/');
+    let exists = client
+        .list_files("/")
+        .await?
+        .iter()
+        .any(|entry| entry.is_dir && entry.name == trash_name);
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let mut batches: Vec<_> = client
+        .list_files(TRASH_DIR)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.is_dir && entry.name != "." && entry.name != "..")
+        .collect();
+    batches.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut items = Vec::new();
+    for batch in batches {
+        let mut entries: Vec<_> = client
+            .list_files(&batch.path)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        items.extend(entries.into_iter().map(|entry| TrashedItem {
+            batch: batch.name.clone(),
+            deleted: batch_time(&batch.name),
+            original_path: original_path(&entry.name),
+            trash_path: entry.path,
+            size: if entry.is_dir { 0 } else { entry.size },
+            is_dir: entry.is_dir,
+        }));
+    }
+    Ok(items)
+}
+
+/// The items `restore` means: every item of a batch named `target`, or else
+/// the most recently deleted item that was at the path `target`
+pub fn select_items<'a>(items: &'a [TrashedItem], target: &str) -> Vec<&'a TrashedItem> {
+    let batch: Vec<_> = items.iter().filter(|item| item.batch == target).collect();
+    if !batch.is_empty() {
+        return batch;
+    }
+    let path = format!("/{}", target.trim_matches('/'));
+    items
+        .iter()
+        .filter(|item| item.original_path == path)
+        .max_by(|a, b| a.batch.cmp(&b.batch))
+        .into_iter()
+        .collect()
+}
+
+/// Move `item` back where it was deleted from. Fails rather than replace
+/// something that's there now.
+pub async fn restore(client: &mut dyn FileServerClient, item: &TrashedItem) -> Result<()> {
+    let (parent, name) = item
+        .original_path
+        .rsplit_once('/')
+        .unwrap_or(("", &item.original_path));
+    let parent = if parent.is_empty() { "/" } else { parent };
+    match client.list_files(parent).await {
+        Ok(entries) if entries.iter().any(|entry| entry.name == name) => {
+            bail!(
+                "{} exists; move it out of the way first",
+                item.original_path
+            )
+        }
+        Ok(_) => {}
+        // Its directory was deleted too
+        Err(_) => create_directory_all(client, parent).await?,
+    }
+    client.rename(&item.trash_path, &item.original_path).await?;
+
+    // Batches that are emptied go with their last item
+    let batch_dir = format!("{}/{}", TRASH_DIR, item.batch);
+    let emptied = client
+        .list_files(&batch_dir)
+        .await
+        .is_ok_and(|entries| entries.iter().all(|e| e.name == "." || e.name == ".."));
+    if emptied {
+        client.delete_directory(&batch_dir, false).await?;
+    }
+    Ok(())
+}
+
+/// The items deleted before `before`, or all of them when it's `None`
+pub fn deleted_before(
+    items: Vec<TrashedItem>,
+    before: Option<DateTime<Local>>,
+) -> Vec<TrashedItem> {
+    items
+        .into_iter()
+        .filter(|item| {
+            before.is_none_or(|before| item.deleted.is_some_and(|deleted| deleted < before))
+        })
+        .collect()
+}
+
+/// Delete the batches `items` are in for good, with everything in them.
+/// Returns how many items went.
+pub async fn empty_trash(
+    client: &mut dyn FileServerClient,
+    items: &[TrashedItem],
+) -> Result<usize> {
+    let mut batches: Vec<_> = items.iter().map(|item| item.batch.as_str()).collect();
+    batches.dedup();
+    for batch in &batches {
+        client
+            .delete_directory(&format!("{}/{}", TRASH_DIR, batch), true)
+            .await?;
+    }
+    Ok(items.len())
+}
+
+/// The items as a table under a header, one line each
+pub fn trash_table(items: &[TrashedItem]) -> Vec<String> {
+    let row = |cells: [&str; 3]| format!("{:<15}  {:>10}  {}", cells[0], cells[1], cells[2]);
+    let mut lines = vec![row(["Batch", "Size", "Deleted from"])];
+    for item in items {
+        let size = if item.is_dir {
+            "dir".to_string()
+        } else {
+            human_bytes::human_bytes(item.size as f64)
+        };
+        lines.push(row([&item.batch, &size, &item.original_path]));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_trash_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("output/run")).unwrap();
+        fs::write(dir.path().join("output/a.png"), b"png").unwrap();
+        fs::write(dir.path().join("output/run/b.png"), b"b").unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        let mut trash = Trash::new();
+        let moved = trash.put(&mut client, "/output/a.png").await.unwrap();
+        assert_eq!(
+            moved,
+            format!("{}/{}/output%2Fa.png", TRASH_DIR, trash.batch())
+        );
+        trash.put(&mut client, "/output/run").await.unwrap();
+        assert!(!dir.path().join("output/a.png").exists());
+        assert!(trash.put(&mut client, &moved).await.is_err());
+
+        let items = list_trash(&mut client).await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].original_path, "/output/a.png");
+        assert_eq!(items[0].size, 3);
+        assert!(items[0].deleted.is_some());
+        assert!(items[1].is_dir);
+
+        // By path, then the rest of the batch, which goes once it's empty
+        let selected = select_items(&items, "output/a.png");
+        assert_eq!(selected, vec![&items[0]]);
+        restore(&mut client, selected[0]).await.unwrap();
+        assert_eq!(fs::read(dir.path().join("output/a.png")).unwrap(), b"png");
+        for item in select_items(&items, trash.batch()) {
+            if item.original_path == "/output/run" {
+                restore(&mut client, item).await.unwrap();
+            }
+        }
+        assert!(dir.path().join("output/run/b.png").exists());
+        assert!(list_trash(&mut client).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_keeps_newer_file_and_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"old").unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        Trash::new().put(&mut client, "/a.txt").await.unwrap();
+        fs::write(dir.path().join("a.txt"), b"new").unwrap();
+        let items = list_trash(&mut client).await.unwrap();
+        assert!(restore(&mut client, &items[0]).await.is_err());
+        assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"new");
+
+        // Batches from before the cutoff only
+        let an_hour_ago = Local::now() - chrono::Duration::hours(1);
+        assert!(deleted_before(items.clone(), Some(an_hour_ago)).is_empty());
+        let expired = deleted_before(items, None);
+        assert_eq!(empty_trash(&mut client, &expired).await.unwrap(), 1);
+        assert!(list_trash(&mut client).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_same_name_twice_in_a_second() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        // Two runs each delete a.png, and neither overwrites the other's
+        let mut batches = Vec::new();
+        for content in [&b"first"[..], b"second"] {
+            fs::write(dir.path().join("a.png"), content).unwrap();
+            let mut trash = Trash::new();
+            trash.put(&mut client, "/a.png").await.unwrap();
+            batches.push(trash.batch().to_string());
+        }
+        assert_ne!(batches[0], batches[1]);
+
+        let items = list_trash(&mut client).await.unwrap();
+        assert_eq!(items.len(), 2);
+        let mut sizes: Vec<_> = items.iter().map(|item| item.size).collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, [5, 6]);
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(is_in_trash("/.comfy-trash"));
+        assert!(is_in_trash("/.comfy-trash/20240501-143000/a"));
+        assert!(!is_in_trash("/.comfy-trashcan"));
+        assert_eq!(trashed_name("/a/100%/b.png"), "a%2F100%25%2Fb.png");
+        assert_eq!(original_path("a%2F100%25%2Fb.png"), "/a/100%/b.png");
+        assert!(batch_time("20240501-143000").is_some());
+        assert!(batch_time("20240501-143000-3fa9c2").is_some());
+        assert!(batch_time(Trash::new().batch()).is_some());
+        assert!(batch_time("old").is_none() && batch_time("20240501-1430001").is_none());
+    }
+}
@@ -0,0 +1,331 @@
+//! Where `comfy-fs upload` puts each file on the server
+
+use crate::sync::walk_local;
+use crate::utils::{glob_matcher, has_wildcards, split_glob};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// The file name that stands for standard input
+pub const STDIN: &str = "-";
+
+/// `files` with each pattern in them replaced by the files it matches, for
+/// shells that pass wildcards on as they are, like Windows'. Patterns match as
+/// they do on the server, and as in a shell, hidden files only match a pattern
+/// that starts their name with a dot. A file that exists under the name of a
+/// pattern is taken as it is.
+pub fn expand_globs(files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for file in files {
+        match file.to_str() {
+            Some(pattern) if has_wildcards(pattern) && !file.exists() => {
+                let matches = glob_local(pattern)?;
+                if matches.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No files match '{}'", pattern),
+                    )
+                    .into());
+                }
+                expanded.extend(matches);
+            }
+            _ => expanded.push(file),
+        }
+    }
+    Ok(expanded)
+}
+
+/// The local files `pattern` matches, in name order
+fn glob_local(pattern: &str) -> Result<Vec<PathBuf>> {
+    // Windows paths may use either separator
+    let pattern = if cfg!(windows) {
+        pattern.replace('\\', "/")
+    } else {
+        pattern.to_string()
+    };
+    let (base, rest) = split_glob(&pattern);
+    let matcher = glob_matcher(rest)?;
+    let max_depth = if rest.contains("**") {
+        None
+    } else {
+        Some(rest.split('/').count())
+    };
+    let root = match base {
+        "" if pattern.starts_with('/') => "/",
+        "" => ".",
+        base => base,
+    };
+    let hidden = rest.starts_with('.') || rest.contains("/.");
+    Ok(walk_local(Path::new(root), max_depth)?
+        .into_iter()
+        .filter(|entry| !entry.is_dir && matcher.is_match(&entry.relative))
+        .filter(|entry| hidden || !entry.relative.split('/').any(|part| part.starts_with('.')))
+        .map(|entry| {
+            if base.is_empty() && root == "." {
+                PathBuf::from(entry.relative)
+            } else {
+                entry.path
+            }
+        })
+        .collect())
+}
+
+/// What `upload` does when two files would go to the same remote path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameConflict {
+    /// Upload nothing, naming the files that clash
+    #[default]
+    Error,
+    /// Give each later file a numbered name, e.g. `a (1).png`
+    Rename,
+    /// Upload only the first of them
+    Skip,
+}
+
+impl FromStr for NameConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "rename" => Ok(Self::Rename),
+            "skip" => Ok(Self::Skip),
+            _ => Err(anyhow!(
+                "Invalid conflict policy '{}' (expected error, rename, or skip)",
+                s
+            )),
+        }
+    }
+}
+
+/// Where one file is uploaded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// The remote directory, which may not exist yet
+    pub dir: String,
+    pub name: String,
+}
+
+impl Target {
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.dir.trim_end_matches('/'), self.name)
+    }
+}
+
+/// Where each of `files` goes under `dest`, given the name it's uploaded under,
+/// which compression may have changed. With `preserve_paths` each keeps the
+/// directories it was named with; otherwise all go straight into `dest`. A
+/// file left out because of `conflict` has no target.
+pub fn plan_targets(
+    dest: &str,
+    files: &[(PathBuf, String)],
+    preserve_paths: bool,
+    conflict: NameConflict,
+) -> Result<Vec<Option<Target>>> {
+    let mut taken: HashMap<String, &Path> = HashMap::new();
+    let mut targets = Vec::new();
+    for (file, name) in files {
+        let dir = if preserve_paths {
+            remote_dir(dest, file)
+        } else {
+            remote_dir(dest, Path::new(""))
+        };
+        let mut target = Target {
+            dir,
+            name: name.clone(),
+        };
+        if let Some(first) = taken.get(&target.path()) {
+            match conflict {
+                NameConflict::Error => bail!(
+                    "{:?} and {:?} would both be uploaded to {}; use --preserve-paths, or \
+                     --on-conflict rename or skip",
+                    first,
+                    file,
+                    target.path()
+                ),
+                NameConflict::Skip => {
+                    targets.push(None);
+                    continue;
+                }
+                NameConflict::Rename => {
+                    let mut n = 1;
+                    while taken.contains_key(&target.path()) {
+                        target.name = numbered_name(name, n);
+                        n += 1;
+                    }
+                }
+            }
+        }
+        taken.insert(target.path(), file);
+        targets.push(Some(target));
+    }
+    Ok(targets)
+}
+
+/// `dest` with the directories of `file` below it. Only named directories are
+/// kept, so a leading `/`, `.`, and `..` are left out.
+fn remote_dir(dest: &str, file: &Path) -> String {
+    let mut dir = dest.trim_end_matches('/').to_string();
+    for component in file.parent().into_iter().flat_map(Path::components) {
+        if let Component::Normal(part) = component {
+            dir.push('/');
+            dir.push_str(&part.to_string_lossy());
+        }
+    }
+    if dir.is_empty() {
+        dir.push('/');
+    }
+    dir
+}
+
+/// Where standard input goes: `dest` names the file itself, as there's no
+/// local name to give it
+pub fn stdin_target(dest: Option<&str>) -> Result<Target> {
+    let dest = dest.filter(|dest| !dest.ends_with('/')).ok_or_else(|| {
+        anyhow!("Uploading standard input needs --dest with a file path, e.g. --dest /logs/run.txt")
+    })?;
+    let (dir, name) = dest.rsplit_once('/').unwrap_or(("", dest));
+    Ok(Target {
+        dir: if dir.is_empty() { "/" } else { dir }.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// `name` with ` (n)` before its extension
+fn numbered_name(name: &str, n: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, extension),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> Vec<(PathBuf, String)> {
+        paths
+            .iter()
+            .map(|path| {
+                let path = PathBuf::from(path);
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (path, name)
+            })
+            .collect()
+    }
+
+    fn paths(targets: &[Option<Target>]) -> Vec<Option<String>> {
+        targets
+            .iter()
+            .map(|t| t.as_ref().map(Target::path))
+            .collect()
+    }
+
+    #[test]
+    fn test_preserve_paths() {
+        let files = files(&[
+            "dir1/file.txt",
+            "./dir2/sub/file.txt",
+            "/tmp/a.png",
+            "b.png",
+        ]);
+        let targets = plan_targets("/x/", &files, true, NameConflict::Error).unwrap();
+        assert_eq!(
+            paths(&targets),
+            [
+                Some("/x/dir1/file.txt".to_string()),
+                Some("/x/dir2/sub/file.txt".to_string()),
+                Some("/x/tmp/a.png".to_string()),
+                Some("/x/b.png".to_string()),
+            ]
+        );
+        assert_eq!(targets[1].as_ref().unwrap().dir, "/x/dir2/sub");
+
+        let flat = plan_targets("/", &files[2..], false, NameConflict::Error).unwrap();
+        assert_eq!(
+            paths(&flat),
+            [Some("/a.png".to_string()), Some("/b.png".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_stdin() {
+        let target = stdin_target(Some("/logs/run.txt")).unwrap();
+        assert_eq!(
+            (target.dir.as_str(), target.name.as_str()),
+            ("/logs", "run.txt")
+        );
+        assert_eq!(stdin_target(Some("run.txt")).unwrap().path(), "/run.txt");
+        assert!(stdin_target(Some("/logs/")).is_err());
+        assert!(stdin_target(None).is_err());
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for file in [
+            "a.png",
+            "b.png",
+            ".hidden.png",
+            "notes.txt",
+            "run/c.png",
+            "run/d/e.png",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+        let names = |pattern: &str| -> Vec<String> {
+            expand_globs(vec![PathBuf::from(format!("{}/{}", root, pattern))])
+                .unwrap()
+                .iter()
+                .map(|path| {
+                    path.strip_prefix(root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect()
+        };
+
+        assert_eq!(names("*.png"), ["a.png", "b.png"]);
+        assert_eq!(names(".*.png"), [".hidden.png"]);
+        assert_eq!(
+            names("**/*.png"),
+            ["a.png", "b.png", "run/c.png", "run/d/e.png"]
+        );
+        assert_eq!(names("r?n/*.png"), ["run/c.png"]);
+        assert_eq!(names("notes.txt"), ["notes.txt"]);
+
+        let error = expand_globs(vec![dir.path().join("*.jpg")]).unwrap_err();
+        assert!(error.to_string().starts_with("No files match"));
+    }
+
+    #[test]
+    fn test_name_conflicts() {
+        let files = files(&["dir1/file.txt", "dir2/file.txt", "dir3/file.txt", "notes"]);
+        let error = plan_targets("/x", &files, false, NameConflict::Error).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("would both be uploaded to /x/file.txt"));
+
+        let renamed = plan_targets("/x", &files, false, NameConflict::Rename).unwrap();
+        assert_eq!(
+            paths(&renamed),
+            [
+                Some("/x/file.txt".to_string()),
+                Some("/x/file (1).txt".to_string()),
+                Some("/x/file (2).txt".to_string()),
+                Some("/x/notes".to_string()),
+            ]
+        );
+
+        let skipped = plan_targets("/x", &files, false, NameConflict::Skip).unwrap();
+        assert_eq!(paths(&skipped)[1..3], [None, None]);
+        assert_eq!(numbered_name("notes", 1), "notes (1)");
+        assert_eq!(numbered_name(".env", 2), ".env (2)");
+    }
+}
@@ -1,18 +1,109 @@
 // Utility functions extracted for testing
 
-pub fn glob_match(filename: &str, pattern: &str) -> bool {
-    if pattern == "*" {
-        return true;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Whether `path` matches the glob `pattern`. `*` and `?` stay within one path
+/// component, `**` spans any number of them, and `[abc]` and `{a,b}` match one
+/// of a set. A pattern that isn't a valid glob only matches itself.
+pub fn glob_match(path: &str, pattern: &str) -> bool {
+    glob_matcher(pattern).map_or_else(|_| path == pattern, |glob| glob.is_match(path))
+}
+
+/// `pattern` compiled once, for matching many paths
+pub fn glob_matcher(pattern: &str) -> Result<GlobMatcher> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| anyhow!("Invalid pattern '{}': {}", pattern, e.kind()))
+}
+
+/// Whether `pattern` has any glob syntax in it
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+/// `path` split before its first component with wildcards: the directory to
+/// start from, and the pattern for paths below it, empty when there are no
+/// wildcards at all
+pub fn split_glob(path: &str) -> (&str, &str) {
+    let mut start = 0;
+    for component in path.split('/') {
+        if has_wildcards(component) {
+            return (path[..start].trim_end_matches('/'), &path[start..]);
+        }
+        start += component.len() + 1;
+    }
+    (path, "")
+}
+
+/// Parse a size like `500`, `10K`, `1.5GB`, or `2GiB`. Units are powers of 1024.
+pub fn parse_size(input: &str) -> Result<u64> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid size '{}' (expected e.g. 500, 10K, 1.5GB)", input))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(anyhow!("Invalid size unit in '{}' (expected K, M, G, or T)", input)),
+    };
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Parse an age like `30m`, `12h`, `7d`, `2w`, or a date like `2024-05-01`, into the
+/// moment it refers to
+pub fn parse_age(input: &str) -> Result<DateTime<Local>> {
+    parse_age_from(input, Local::now())
+}
+
+fn parse_age_from(input: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let trimmed = input.trim();
+
+    if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+        return Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .earliest()
+            .ok_or_else(|| anyhow!("'{}' doesn't exist in the local time zone", input));
     }
 
-    if let Some(ext) = pattern.strip_prefix("*.") {
-        filename.ends_with(&format!(".{}", ext))
-    } else if let Some(prefix) = pattern.strip_suffix('*') {
-        filename.starts_with(prefix)
-    } else if let Some(suffix) = pattern.strip_prefix('*') {
-        filename.ends_with(suffix)
+    let invalid = || anyhow!("Invalid age '{}' (expected e.g. 30m, 12h, 7d, 2w, or 2024-05-01)", input);
+    let unit = trimmed.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = trimmed[..trimmed.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let age = match unit {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+
+    Ok(now - age)
+}
+
+/// `duration` as `m:ss`, or `h:mm:ss` once it's an hour or more
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
     } else {
-        filename == pattern
+        format!("{}:{:02}", secs / 60, secs % 60)
     }
 }
 
@@ -20,6 +111,15 @@ pub fn glob_match(filename: &str, pattern: &str) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_format_duration() {
+        use std::time::Duration;
+
+        assert_eq!(format_duration(Duration::from_secs(5)), "0:05");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2:05");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+
     #[test]
     fn test_glob_match_wildcard() {
         assert!(glob_match("anything.txt", "*"));
@@ -68,7 +168,68 @@ mod tests {
         assert!(!glob_match("file", ""));
         assert!(!glob_match("", "pattern"));
         assert!(glob_match("*", "*"));
-        assert!(glob_match("*", "**")); // "*" matches "**" because "*" matches everything after the initial "*"
-        assert!(!glob_match("file", "**file")); // Double wildcard isn't supported
+        assert!(glob_match("*", "**"));
+        assert!(glob_match("file", "**file"));
+        // Not a valid glob, so only itself
+        assert!(glob_match("a[b", "a[b"));
+        assert!(!glob_match("ab", "a[b"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_glob_match_sets_and_separators() {
+        assert!(glob_match("img_7.png", "img_?.png"));
+        assert!(!glob_match("img_10.png", "img_?.png"));
+        assert!(glob_match("b.png", "[abc].png"));
+        assert!(!glob_match("d.png", "[abc].png"));
+        assert!(glob_match("model.ckpt", "*.{safetensors,ckpt}"));
+        assert!(!glob_match("model.pt", "*.{safetensors,ckpt}"));
+
+        // `*` stays within a component; `**` crosses them
+        assert!(glob_match("2024/a.png", "*/*.png"));
+        assert!(!glob_match("2024/05/a.png", "*/*.png"));
+        assert!(glob_match("2024/05/a.png", "**/*.png"));
+        assert!(glob_match("a.png", "**/*.png"));
+    }
+
+    #[test]
+    fn test_split_glob() {
+        assert_eq!(split_glob("/output/*.png"), ("/output", "*.png"));
+        assert_eq!(split_glob("/output/2024-*/img_?.png"), ("/output", "2024-*/img_?.png"));
+        assert_eq!(split_glob("/models/**/*.safetensors"), ("/models", "**/*.safetensors"));
+        assert_eq!(split_glob("/*.png"), ("", "*.png"));
+        assert_eq!(split_glob("*.png"), ("", "*.png"));
+        assert_eq!(split_glob("/output/a.png"), ("/output/a.png", ""));
+        assert!(has_wildcards("{a,b}.png"));
+        assert!(!has_wildcards("/output/a.png"));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("1.5GB").unwrap(), 3 * 512 * 1024 * 1024);
+        assert_eq!(parse_size("2 mib").unwrap(), 2 * 1024 * 1024);
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("10X").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        let now = Local.with_ymd_and_hms(2024, 5, 10, 12, 0, 0).unwrap();
+        assert_eq!(
+            parse_age_from("7d", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_age_from("90m", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 5, 10, 10, 30, 0).unwrap()
+        );
+        assert_eq!(
+            parse_age_from("2024-05-01", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap()
+        );
+        assert!(parse_age_from("7y", now).is_err());
+        assert!(parse_age_from("d", now).is_err());
+        assert!(parse_age_from("", now).is_err());
+    }
+}
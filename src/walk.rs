@@ -0,0 +1,603 @@
+//! Recursive listing of remote directory trees
+
+use crate::client::{FileServerClient, RemoteFile};
+use crate::sort::{sort_entries, SortKey, SortMode};
+use crate::utils::{glob_matcher, split_glob};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use futures::stream::{FuturesUnordered, StreamExt};
+use globset::GlobMatcher;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// An entry found while walking a tree
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    pub file: RemoteFile,
+    /// 1 for entries directly inside the starting directory
+    pub depth: usize,
+}
+
+/// Lists every directory under a starting point, several listings at a time
+pub struct Walker {
+    client: Arc<Mutex<Box<dyn FileServerClient>>>,
+    max_concurrent: usize,
+    max_depth: Option<usize>,
+}
+
+impl Walker {
+    pub fn new(client: Arc<Mutex<Box<dyn FileServerClient>>>, max_concurrent: usize) -> Self {
+        Self {
+            client,
+            max_concurrent: max_concurrent.max(1),
+            max_depth: None,
+        }
+    }
+
+    /// Don't list directories deeper than `max_depth` levels below the start
+    #[must_use]
+    pub const fn with_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Every entry under `root`, parents before their children and siblings by name
+    pub async fn walk(&self, root: &str) -> Result<Vec<WalkEntry>> {
+        let mut entries = Vec::new();
+        let mut pending = vec![(root.to_string(), 1)];
+        let mut in_flight = FuturesUnordered::new();
+
+        loop {
+            while in_flight.len() < self.max_concurrent {
+                let Some((dir, depth)) = pending.pop() else {
+                    break;
+                };
+                let client = self.client.clone();
+                in_flight.push(async move {
+                    let listing = client.lock().await.list_files(&dir).await;
+                    (listing.with_context(|| format!("Failed to list {}", dir)), depth)
+                });
+            }
+
+            let Some((listing, depth)) = in_flight.next().await else {
+                break;
+            };
+            let descend = self.max_depth.is_none_or(|max| depth < max);
+            for file in listing? {
+                if file.name == "." || file.name == ".." {
+                    continue;
+                }
+                if file.is_dir && descend {
+                    pending.push((file.path.clone(), depth + 1));
+                }
+                entries.push(WalkEntry { file, depth });
+            }
+        }
+
+        entries.sort_by(|a, b| a.file.path.split('/').cmp(b.file.path.split('/')));
+        Ok(entries)
+    }
+
+    /// The entries a path with wildcards matches, in any component. Only the
+    /// directories the pattern can reach are listed.
+    pub async fn glob(&self, path: &str) -> Result<Vec<RemoteFile>> {
+        let (base, pattern) = split_glob(path);
+        if pattern.is_empty() {
+            return Err(anyhow!("'{}' has no wildcards", path));
+        }
+        let matcher = glob_matcher(pattern)?;
+        let max_depth = if pattern.contains("**") {
+            self.max_depth
+        } else {
+            Some(pattern.split('/').count())
+        };
+        let walker = Self {
+            client: self.client.clone(),
+            max_concurrent: self.max_concurrent,
+            max_depth,
+        };
+
+        let root = if base.is_empty() { "/" } else { base };
+        let prefix = format!("{}/", base);
+        Ok(walker
+            .walk(root)
+            .await?
+            .into_iter()
+            .map(|entry| entry.file)
+            .filter(|file| {
+                let relative = file.path.strip_prefix(&prefix).unwrap_or(&file.path);
+                matcher.is_match(relative)
+            })
+            .collect())
+    }
+}
+
+/// Put the entries of each directory in `mode` order, keeping every directory's
+/// contents right after it
+pub fn sort_tree(entries: Vec<WalkEntry>, mode: SortMode, reverse: bool) -> Vec<WalkEntry> {
+    let mut children: HashMap<String, Vec<WalkEntry>> = HashMap::new();
+    for entry in entries {
+        let parent = entry.file.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        children.entry(parent.to_string()).or_default().push(entry);
+    }
+    for siblings in children.values_mut() {
+        sort_entries(siblings, mode, reverse);
+    }
+
+    // Only the walk's top level has a parent that isn't itself an entry
+    let all_paths: HashSet<String> = children
+        .values()
+        .flatten()
+        .map(|e| e.file.path.clone())
+        .collect();
+    let mut tops: Vec<_> = children
+        .keys()
+        .filter(|parent| !all_paths.contains(*parent))
+        .cloned()
+        .collect();
+    tops.sort();
+
+    let mut sorted = Vec::new();
+    let mut stack: Vec<WalkEntry> = Vec::new();
+    for top in tops {
+        stack.extend(children.remove(&top).unwrap_or_default().into_iter().rev());
+        while let Some(entry) = stack.pop() {
+            if entry.file.is_dir {
+                if let Some(inner) = children.remove(&entry.file.path) {
+                    stack.extend(inner.into_iter().rev());
+                }
+            }
+            sorted.push(entry);
+        }
+    }
+    sorted
+}
+
+impl SortKey for WalkEntry {
+    fn name(&self) -> &str {
+        &self.file.name
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file.is_dir
+    }
+
+    fn size(&self) -> u64 {
+        self.file.size
+    }
+
+    fn modified(&self) -> DateTime<Local> {
+        self.file.modified
+    }
+
+    fn extension(&self) -> &str {
+        self.file.extension()
+    }
+}
+
+/// Size and file count of everything under a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirTotals {
+    pub size: u64,
+    pub files: usize,
+}
+
+impl DirTotals {
+    /// Totals of all the files in `entries`
+    pub fn of(entries: &[WalkEntry]) -> Self {
+        entries
+            .iter()
+            .filter(|e| !e.file.is_dir)
+            .fold(Self::default(), |totals, e| Self {
+                size: totals.size + e.file.size,
+                files: totals.files + 1,
+            })
+    }
+}
+
+/// Totals for every directory in `entries`, keyed by path
+pub fn directory_totals(entries: &[WalkEntry]) -> HashMap<String, DirTotals> {
+    let mut totals: HashMap<String, DirTotals> = entries
+        .iter()
+        .filter(|e| e.file.is_dir)
+        .map(|e| (e.file.path.clone(), DirTotals::default()))
+        .collect();
+
+    for entry in entries.iter().filter(|e| !e.file.is_dir) {
+        let path = &entry.file.path;
+        for (i, _) in path.match_indices('/').filter(|(i, _)| *i > 0) {
+            if let Some(dir) = totals.get_mut(&path[..i]) {
+                dir.size += entry.file.size;
+                dir.files += 1;
+            }
+        }
+    }
+
+    totals
+}
+
+/// Each entry directly inside the starting directory with the size of everything in
+/// it, biggest first
+pub fn child_usage(entries: &[WalkEntry]) -> Vec<(&RemoteFile, u64)> {
+    let totals = directory_totals(entries);
+    let mut usage: Vec<_> = entries
+        .iter()
+        .filter(|e| e.depth == 1)
+        .map(|e| {
+            let size = if e.file.is_dir {
+                totals.get(&e.file.path).map_or(0, |t| t.size)
+            } else {
+                e.file.size
+            };
+            (&e.file, size)
+        })
+        .collect();
+
+    usage.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+    usage
+}
+
+/// Draw `entries` as a tree, one line each, with sizes when `totals` is given.
+///
+/// Entries have to be in the order `Walker::walk` returns them.
+pub fn render_tree(
+    entries: &[WalkEntry],
+    totals: Option<&HashMap<String, DirTotals>>,
+) -> Vec<String> {
+    // Walking backwards, the first entry seen for each parent is its last child
+    let mut seen_parents = HashSet::new();
+    let mut is_last = vec![false; entries.len()];
+    for (i, entry) in entries.iter().enumerate().rev() {
+        let parent = entry.file.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        is_last[i] = seen_parents.insert(parent);
+    }
+
+    // Whether the ancestor at each level still has siblings below it
+    let mut more_below: Vec<bool> = Vec::new();
+    let mut lines = Vec::with_capacity(entries.len());
+    for (entry, last) in entries.iter().zip(is_last) {
+        more_below.truncate(entry.depth.saturating_sub(1));
+
+        let mut line: String = more_below
+            .iter()
+            .map(|more| if *more { "│   " } else { "    " })
+            .collect();
+        line.push_str(if last { "└── " } else { "├── " });
+        line.push_str(&entry.file.name);
+        if entry.file.is_dir {
+            line.push('/');
+        }
+
+        if let Some(totals) = totals {
+            let label = if entry.file.is_dir {
+                let dir = totals.get(&entry.file.path).copied().unwrap_or_default();
+                totals_label(dir)
+            } else {
+                human_bytes::human_bytes(entry.file.size as f64)
+            };
+            line.push_str(&format!("  [{}]", label));
+        }
+
+        lines.push(line);
+        more_below.push(!last);
+    }
+
+    lines
+}
+
+/// "1.2 GB, 14 files"
+pub fn totals_label(totals: DirTotals) -> String {
+    format!(
+        "{}, {} {}",
+        human_bytes::human_bytes(totals.size as f64),
+        totals.files,
+        if totals.files == 1 { "file" } else { "files" }
+    )
+}
+
+/// Whether `find` should report files, directories, or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+impl FromStr for EntryKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "f" | "file" => Ok(Self::File),
+            "d" | "dir" | "directory" => Ok(Self::Directory),
+            _ => Err(anyhow!("Invalid type '{}' (expected f or d)", s)),
+        }
+    }
+}
+
+/// How `find` matches entry names
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn glob(pattern: &str) -> Result<Self> {
+        glob_matcher(pattern).map(Self::Glob)
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Regex::new(pattern)
+            .map(Self::Regex)
+            .with_context(|| format!("Invalid regex '{}'", pattern))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.is_match(name),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// The conditions an entry has to meet to be reported by `find`
+#[derive(Debug, Clone)]
+pub struct FindFilter {
+    pattern: NamePattern,
+    kind: Option<EntryKind>,
+    min_size: Option<u64>,
+    newer_than: Option<DateTime<Local>>,
+}
+
+impl FindFilter {
+    pub const fn new(pattern: NamePattern) -> Self {
+        Self {
+            pattern,
+            kind: None,
+            min_size: None,
+            newer_than: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_kind(mut self, kind: Option<EntryKind>) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Only files at least this big; directories never match
+    #[must_use]
+    pub const fn with_min_size(mut self, min_size: Option<u64>) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_newer_than(mut self, newer_than: Option<DateTime<Local>>) -> Self {
+        self.newer_than = newer_than;
+        self
+    }
+
+    pub fn matches(&self, file: &RemoteFile) -> bool {
+        let kind_ok = match self.kind {
+            Some(EntryKind::File) => !file.is_dir,
+            Some(EntryKind::Directory) => file.is_dir,
+            None => true,
+        };
+        let size_ok = self.min_size.is_none_or(|min| !file.is_dir && file.size >= min);
+        let age_ok = self.newer_than.is_none_or(|since| file.modified > since);
+
+        kind_ok && size_ok && age_ok && self.pattern.matches(&file.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+    use chrono::Duration;
+
+    fn tree() -> (tempfile::TempDir, Walker) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("models/loras/styles")).unwrap();
+        std::fs::create_dir_all(dir.path().join("models-old")).unwrap();
+        std::fs::write(dir.path().join("models/base.safetensors"), vec![0; 2048]).unwrap();
+        std::fs::write(dir.path().join("models/loras/anime.safetensors"), b"lora").unwrap();
+        std::fs::write(dir.path().join("models/loras/styles/ink.safetensors"), b"ink").unwrap();
+        std::fs::write(dir.path().join("models/notes.txt"), b"notes").unwrap();
+
+        let client: Box<dyn FileServerClient> = Box::new(LocalClient::new(dir.path().to_path_buf()));
+        (dir, Walker::new(Arc::new(Mutex::new(client)), 4))
+    }
+
+    fn paths(entries: &[WalkEntry]) -> Vec<(&str, usize)> {
+        entries.iter().map(|e| (e.file.path.as_str(), e.depth)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_walk_lists_parents_before_children() {
+        let (_dir, walker) = tree();
+        let entries = walker.walk("/").await.unwrap();
+
+        assert_eq!(
+            paths(&entries),
+            vec![
+                ("/models", 1),
+                ("/models/base.safetensors", 2),
+                ("/models/loras", 2),
+                ("/models/loras/anime.safetensors", 3),
+                ("/models/loras/styles", 3),
+                ("/models/loras/styles/ink.safetensors", 4),
+                ("/models/notes.txt", 2),
+                ("/models-old", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_walk_max_depth() {
+        let (_dir, walker) = tree();
+        let entries = walker.with_max_depth(Some(2)).walk("/models").await.unwrap();
+
+        assert_eq!(
+            paths(&entries),
+            vec![
+                ("/models/base.safetensors", 1),
+                ("/models/loras", 1),
+                ("/models/loras/anime.safetensors", 2),
+                ("/models/loras/styles", 2),
+                ("/models/notes.txt", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sort_tree() {
+        let (_dir, walker) = tree();
+        let entries = walker.walk("/models").await.unwrap();
+
+        let sorted = sort_tree(entries, SortMode::Size, false);
+        assert_eq!(
+            paths(&sorted),
+            vec![
+                ("/models/loras", 1),
+                ("/models/loras/styles", 2),
+                ("/models/loras/styles/ink.safetensors", 3),
+                ("/models/loras/anime.safetensors", 2),
+                ("/models/base.safetensors", 1),
+                ("/models/notes.txt", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_glob() {
+        let (_dir, walker) = tree();
+        let names = |files: Vec<RemoteFile>| files.into_iter().map(|f| f.path).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(walker.glob("/models/*.safetensors").await.unwrap()),
+            ["/models/base.safetensors"]
+        );
+        assert_eq!(
+            names(walker.glob("/model?/*/*.safetensors").await.unwrap()),
+            ["/models/loras/anime.safetensors"]
+        );
+        assert_eq!(
+            names(walker.glob("/models/**/*.safetensors").await.unwrap()),
+            [
+                "/models/base.safetensors",
+                "/models/loras/anime.safetensors",
+                "/models/loras/styles/ink.safetensors"
+            ]
+        );
+        assert_eq!(
+            names(walker.glob("/models*").await.unwrap()),
+            ["/models", "/models-old"]
+        );
+        assert_eq!(
+            names(walker.glob("/models/{notes.txt,loras}").await.unwrap()),
+            ["/models/loras", "/models/notes.txt"]
+        );
+        assert!(walker.glob("/models/notes.txt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_walk_missing_directory() {
+        let (_dir, walker) = tree();
+        let err = walker.walk("/nope").await.unwrap_err();
+        assert!(err.to_string().contains("/nope"));
+    }
+
+    #[tokio::test]
+    async fn test_find_filter() {
+        let (_dir, walker) = tree();
+        let entries = walker.walk("/").await.unwrap();
+        let found = |filter: &FindFilter| -> Vec<String> {
+            entries
+                .iter()
+                .filter(|e| filter.matches(&e.file))
+                .map(|e| e.file.name.clone())
+                .collect()
+        };
+
+        let glob = FindFilter::new(NamePattern::glob("*.safetensors").unwrap());
+        assert_eq!(found(&glob), ["base.safetensors", "anime.safetensors", "ink.safetensors"]);
+
+        let big = glob.clone().with_min_size(Some(1024));
+        assert_eq!(found(&big), ["base.safetensors"]);
+
+        let dirs = FindFilter::new(NamePattern::regex("^models").unwrap())
+            .with_kind(Some(EntryKind::Directory));
+        assert_eq!(found(&dirs), ["models", "models-old"]);
+
+        let future = glob.with_newer_than(Some(Local::now() + Duration::hours(1)));
+        assert!(found(&future).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_directory_totals() {
+        let (_dir, walker) = tree();
+        let totals = directory_totals(&walker.walk("/").await.unwrap());
+
+        assert_eq!(totals["/models"], DirTotals { size: 2048 + 4 + 3 + 5, files: 4 });
+        assert_eq!(totals["/models/loras"], DirTotals { size: 4 + 3, files: 2 });
+        assert_eq!(totals["/models/loras/styles"], DirTotals { size: 3, files: 1 });
+        assert_eq!(totals["/models-old"], DirTotals::default());
+        assert_eq!(DirTotals::of(&walker.walk("/models/loras").await.unwrap()).files, 2);
+    }
+
+    #[tokio::test]
+    async fn test_child_usage() {
+        let (dir, walker) = tree();
+        std::fs::write(dir.path().join("models/loras/extra.bin"), vec![0; 4096]).unwrap();
+        let entries = walker.walk("/models").await.unwrap();
+
+        let usage: Vec<_> = child_usage(&entries)
+            .into_iter()
+            .map(|(file, size)| (file.name.as_str(), size))
+            .collect();
+        assert_eq!(
+            usage,
+            vec![("loras", 4096 + 4 + 3), ("base.safetensors", 2048), ("notes.txt", 5)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_tree() {
+        let (_dir, walker) = tree();
+        let entries = walker.walk("/").await.unwrap();
+
+        assert_eq!(
+            render_tree(&entries, None),
+            vec![
+                "├── models/",
+                "│   ├── base.safetensors",
+                "│   ├── loras/",
+                "│   │   ├── anime.safetensors",
+                "│   │   └── styles/",
+                "│   │       └── ink.safetensors",
+                "│   └── notes.txt",
+                "└── models-old/",
+            ]
+        );
+
+        let shallow: Vec<_> = entries.iter().filter(|e| e.depth <= 2).cloned().collect();
+        let totals = directory_totals(&entries);
+        let lines = render_tree(&shallow, Some(&totals));
+        assert_eq!(lines[0], "├── models/  [2 KiB, 4 files]");
+        assert_eq!(lines[2], "│   ├── loras/  [7 B, 2 files]");
+        assert_eq!(lines[4], "└── models-old/  [0 B, 0 files]");
+    }
+
+    #[test]
+    fn test_entry_kind_and_regex_errors() {
+        assert_eq!("f".parse::<EntryKind>().unwrap(), EntryKind::File);
+        assert_eq!("d".parse::<EntryKind>().unwrap(), EntryKind::Directory);
+        assert!("x".parse::<EntryKind>().is_err());
+        assert!(NamePattern::regex("(unclosed").is_err());
+    }
+}
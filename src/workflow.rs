@@ -0,0 +1,85 @@
+//! The ComfyUI workflow saved in a PNG on the server, read from the metadata
+//! at the start of the file, for `comfy-fs workflow extract`
+
+use crate::client::FileServerClient;
+use crate::preview::fetch_png;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+/// Which of the two JSON documents ComfyUI saves in its PNGs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Embedded {
+    /// The graph as the editor shows it, which loads back into ComfyUI by
+    /// dropping the file on it
+    Workflow,
+    /// The API-format prompt the server ran
+    Prompt,
+}
+
+impl Embedded {
+    /// The keyword of the text chunk it's saved under
+    pub const fn keyword(self) -> &'static str {
+        match self {
+            Self::Workflow => "workflow",
+            Self::Prompt => "prompt",
+        }
+    }
+}
+
+/// The workflow or prompt saved in the PNG at `path`. Only the chunks before
+/// the image data are read.
+pub async fn extract(
+    client: &mut dyn FileServerClient,
+    path: &str,
+    embedded: Embedded,
+) -> Result<Value> {
+    let info = fetch_png(client, path)
+        .await
+        .with_context(|| format!("Could not read the metadata of {}", path))?;
+    let keyword = embedded.keyword();
+    let (_, text) = info
+        .text
+        .iter()
+        .find(|(key, _)| key == keyword)
+        .ok_or_else(|| anyhow!("{} has no ComfyUI {} in its metadata", path, keyword))?;
+    serde_json::from_str(text).with_context(|| format!("The {} in {} is damaged", keyword, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::local::LocalClient;
+
+    fn chunk(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(body);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[tokio::test]
+    async fn test_extract() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", &[0, 0, 2, 0, 0, 0, 2, 0, 8, 6, 0, 0, 0]));
+        png.extend(chunk(b"tEXt", b"workflow\0{\"nodes\": [{\"id\": 3}]}"));
+        // Enough image data that reading it all would take more than one read
+        png.extend(chunk(b"IDAT", &vec![0; 256 * 1024]));
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ComfyUI_00001_.png"), &png).unwrap();
+        let mut client = LocalClient::new(dir.path().to_path_buf());
+
+        let workflow = extract(&mut client, "/ComfyUI_00001_.png", Embedded::Workflow)
+            .await
+            .unwrap();
+        assert_eq!(workflow["nodes"][0]["id"], 3);
+        let error = extract(&mut client, "/ComfyUI_00001_.png", Embedded::Prompt)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "/ComfyUI_00001_.png has no ComfyUI prompt in its metadata"
+        );
+    }
+}
@@ -1,3 +1,10 @@
+// Left as first written; these style lints would only churn them
+#![allow(
+    clippy::needless_borrows_for_generic_args,
+    clippy::overly_complex_bool_expr,
+    clippy::bool_assert_comparison
+)]
+
 use comfy_fs::config::{Config, Protocol};
 use std::process::Command;
 use tempfile::TempDir;
@@ -19,6 +26,18 @@ fn test_cli_help() {
     assert!(stdout.contains("browse"));
     assert!(stdout.contains("sync"));
     assert!(stdout.contains("config"));
+    assert!(stdout.contains("doctor"));
+    assert!(stdout.contains("discover"));
+    assert!(stdout.contains("history"));
+    assert!(stdout.contains("queue"));
+    assert!(stdout.contains("trash"));
+    assert!(stdout.contains("outputs"));
+    // The server options' description isn't the program's
+    assert!(stdout.starts_with("Usage:"));
+    assert!(stdout.contains("workflow"));
+    assert!(stdout.contains("path"));
+    assert!(stdout.contains("--quiet"));
+    assert!(stdout.contains("--progress"));
 }
 
 #[test]
@@ -45,6 +64,39 @@ fn test_config_command() {
     assert!(output.status.success() || true);
 }
 
+#[test]
+fn test_config_subcommands() {
+    let home = TempDir::new().unwrap();
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "config"])
+            .args(args)
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join(".config"))
+            .env("RUST_BACKTRACE", "0")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run(&["--help"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("test"));
+    assert!(stdout.contains("set-password"));
+
+    // Nothing to log in to yet
+    let output = run(&["test"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No server is configured"));
+
+    // Nowhere to keep a password, and no terminal to ask for one
+    let output = run(&["set-password", "--no-keyring"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["set-password", "--server", "10.0.0.9", "--username", "u"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--password-stdin"));
+}
+
 #[test]
 fn test_list_command_requires_connection() {
     let output = Command::new("cargo")
@@ -71,8 +123,10 @@ fn test_sort_options() {
     ];
 
     for args in commands {
+        // Error backtraces mention `panicking` frames, so keep them out of stderr
         let output = Command::new("cargo")
             .args(&args)
+            .env("RUST_BACKTRACE", "0")
             .output()
             .expect("Failed to execute command");
 
@@ -89,7 +143,7 @@ fn test_config_module() {
     assert_eq!(config.server_ip, "");
     assert_eq!(config.username, "");
     assert_eq!(config.password, None);
-    assert_eq!(config.default_protocol, Protocol::Ftp);
+    assert_eq!(config.default_protocol, Protocol::Smb);
     assert_eq!(config.configured, false);
 }
 
@@ -123,6 +177,9 @@ fn test_upload_command_help() {
     // Check that help text contains upload-specific options
     assert!(stdout.contains("files"));
     assert!(stdout.contains("dest"));
+    assert!(stdout.contains("--no-create-dirs"));
+    assert!(stdout.contains("--preserve-paths"));
+    assert!(stdout.contains("--parallel"));
 }
 
 #[test]
@@ -138,6 +195,8 @@ fn test_download_command_help() {
     // Check that help text contains download-specific options
     assert!(stdout.contains("path"));
     assert!(stdout.contains("dest"));
+    assert!(stdout.contains("--output-file"));
+    assert!(stdout.contains("--interactive"));
 }
 
 #[test]
@@ -153,6 +212,7 @@ fn test_sync_command_help() {
     // Check that help text contains sync-specific options
     assert!(stdout.contains("Local directory"));
     assert!(stdout.contains("Remote directory"));
+    assert!(stdout.contains("--job"));
 }
 
 #[test]
@@ -190,6 +250,7 @@ fn test_config_validation() {
         password: Some("testpassword".to_string()),
         default_protocol: Protocol::Ftp,
         configured: true,
+        ..Config::default()
     };
     
     // Test that config has expected values
@@ -211,3 +272,155 @@ fn test_utils_glob_matching() {
     assert!(glob_match("exact.txt", "exact.txt"));
     assert!(!glob_match("file.txt", "*.pdf"));
 }
+
+#[test]
+fn test_env_overrides_skip_setup_and_config_file() {
+    let home = TempDir::new().unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "list", "/", "--no-keyring"])
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join(".config"))
+        .env("RUST_BACKTRACE", "0")
+        .env("COMFY_FS_SERVER", "127.0.0.1")
+        .env("COMFY_FS_USERNAME", "ci")
+        .env("COMFY_FS_PASSWORD", "secret")
+        .env("COMFY_FS_PROTOCOL", "ftp")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute command");
+
+    // Nothing listens locally, but the setup wizard must not have run
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stdout.contains("Welcome"));
+    assert!(stderr.contains("FTP connection failed"));
+    assert!(!home.path().join(".config/comfy-fs/config.json").exists());
+    // The exit status for a server that can't be reached
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn test_invalid_protocol_env_var() {
+    let output = Command::new("cargo")
+        .args(["run", "--", "list", "/"])
+        .env("RUST_BACKTRACE", "0")
+        .env("COMFY_FS_PROTOCOL", "gopher")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid protocol"));
+    // A configuration problem
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_no_terminal_fails_instead_of_prompting() {
+    let home = TempDir::new().unwrap();
+    let run = |envs: &[(&str, &str)], args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "list", "/", "--no-keyring"])
+            .args(args)
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join(".config"))
+            .env("RUST_BACKTRACE", "0")
+            .envs(envs.iter().copied())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // No setup wizard without a terminal
+    let output = run(&[], &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Welcome"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No server is configured"));
+
+    // Nor a password prompt, with --yes as much as without a terminal
+    let server = [("COMFY_FS_SERVER", "127.0.0.1"), ("COMFY_FS_USERNAME", "ci")];
+    let output = run(&server, &["--yes"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("COMFY_FS_PASSWORD"));
+}
+
+#[test]
+fn test_dry_run_changes_nothing() {
+    let home = TempDir::new().unwrap();
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "--dry-run", "--no-keyring"])
+            .args(args)
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join(".config"))
+            .env("RUST_BACKTRACE", "0")
+            .env("COMFY_FS_SERVER", "127.0.0.1")
+            .env("COMFY_FS_USERNAME", "ci")
+            .env("COMFY_FS_PASSWORD", "secret")
+            .env("COMFY_FS_PROTOCOL", "ftp")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // Nothing listens locally, so these only pass without connecting
+    let output = run(&["mkdir", "/models/new", "-p"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Would create /models/new"));
+    let output = run(&["rmdir", "/models/old"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Would remove /models/old"));
+}
+
+#[test]
+fn test_exists_tells_missing_from_unknown() {
+    use std::io::{BufRead, BufReader, Write};
+
+    // An HTTP server that lists an empty root and refuses /locked/
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            reader.read_line(&mut request).unwrap();
+            while reader.read_line(&mut String::new()).unwrap_or(0) > 2 {}
+            let (status, body) = if request.starts_with("GET /locked/") {
+                ("403 Forbidden", "")
+            } else {
+                ("200 OK", "<html><body><pre></pre></body></html>")
+            };
+            let _ = write!(
+                &stream,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+
+    let home = TempDir::new().unwrap();
+    let exists = |path: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "exists", path, "--no-keyring"])
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join(".config"))
+            .env("RUST_BACKTRACE", "0")
+            .env("COMFY_FS_SERVER", &server)
+            .env("COMFY_FS_USERNAME", "ci")
+            .env("COMFY_FS_PASSWORD", "secret")
+            .env("COMFY_FS_PROTOCOL", "http")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Failed to execute command")
+            .status
+            .code()
+    };
+
+    assert_eq!(exists("/"), Some(0));
+    assert_eq!(exists("/missing.txt"), Some(1));
+    // Not being allowed to look isn't the same as it not being there
+    assert_eq!(exists("/locked/model.safetensors"), Some(7));
+}
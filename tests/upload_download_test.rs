@@ -5,7 +5,7 @@ use tokio::sync::Mutex;
 use mockall::mock;
 use async_trait::async_trait;
 use anyhow::Result;
-use comfy_fs::client::RemoteFile;
+use comfy_fs::client::{local::LocalClient, move_path, RemoteFile};
 use chrono::Local;
 
 mock! {
@@ -20,6 +20,8 @@ mock! {
         async fn upload_file(&mut self, local_path: &std::path::Path, remote_path: &str) -> Result<()>;
         async fn create_directory(&mut self, path: &str) -> Result<()>;
         async fn delete_file(&mut self, path: &str) -> Result<()>;
+        async fn delete_directory(&mut self, path: &str, recursive: bool) -> Result<()>;
+        async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
         async fn get_file_size(&mut self, path: &str) -> Result<u64>;
     }
 }
@@ -109,6 +111,35 @@ async fn test_parallel_downloads() {
     assert!(temp_dir.path().join("file3.txt").exists());
 }
 
+#[tokio::test]
+async fn test_parallel_downloads_from_local_client() {
+    let server_dir = TempDir::new().unwrap();
+    std::fs::create_dir(server_dir.path().join("models")).unwrap();
+    std::fs::write(server_dir.path().join("models/a.bin"), vec![1u8; 2048]).unwrap();
+    std::fs::write(server_dir.path().join("models/b.bin"), vec![2u8; 4096]).unwrap();
+
+    let mut local_client = LocalClient::new(server_dir.path().to_path_buf());
+    local_client.connect().await.unwrap();
+    let remote_files = local_client.list_files("/models").await.unwrap();
+
+    let client: Box<dyn FileServerClient> = Box::new(local_client);
+    let client = Arc::new(Mutex::new(client));
+
+    let temp_dir = TempDir::new().unwrap();
+    let files = remote_files
+        .iter()
+        .map(|f| (f.path.clone(), temp_dir.path().join("out").join(&f.name)))
+        .collect();
+
+    let downloader = ParallelDownloader::new(client, 2);
+    let results = downloader.download_files(files).await.unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(std::fs::read(temp_dir.path().join("out/a.bin")).unwrap().len(), 2048);
+    assert_eq!(std::fs::read(temp_dir.path().join("out/b.bin")).unwrap().len(), 4096);
+}
+
 #[tokio::test]
 async fn test_download_with_error_handling() {
     let mut mock_client = MockTestClient::new();
@@ -159,6 +190,70 @@ async fn test_download_with_error_handling() {
     assert!(!temp_dir.path().join("fail.txt").exists());
 }
 
+#[tokio::test]
+async fn test_mv_renames_in_the_same_directory() {
+    let mut mock_client = MockTestClient::new();
+    let a = RemoteFile {
+        name: "a.png".to_string(),
+        path: "/output/a.png".to_string(),
+        size: 3,
+        modified: Local::now(),
+        is_dir: false,
+        permissions: None,
+    };
+    
+    // Nothing is at the target yet, so one rename does it, without copying
+    let listed = a.clone();
+    mock_client.expect_list_files()
+        .with(mockall::predicate::eq("/output"))
+        .returning(move |_| Ok(vec![listed.clone()]));
+    mock_client.expect_rename()
+        .with(
+            mockall::predicate::eq("/output/a.png"),
+            mockall::predicate::eq("/output/b.png")
+        )
+        .times(1)
+        .returning(|_, _| Ok(()));
+    
+    let copied = move_path(&mut mock_client, &a, "/output/b.png", false).await.unwrap();
+    assert!(!copied);
+}
+
+#[tokio::test]
+async fn test_mv_between_directories() {
+    let server_dir = TempDir::new().unwrap();
+    std::fs::create_dir_all(server_dir.path().join("models/loras")).unwrap();
+    std::fs::create_dir(server_dir.path().join("archive")).unwrap();
+    std::fs::write(server_dir.path().join("models/loras/a.bin"), b"lora").unwrap();
+    
+    let mut client = LocalClient::new(server_dir.path().to_path_buf());
+    let source = client.stat("/models/loras").await.unwrap();
+    move_path(&mut client, &source, "/archive/loras", false).await.unwrap();
+    
+    assert!(!server_dir.path().join("models/loras").exists());
+    assert_eq!(std::fs::read(server_dir.path().join("archive/loras/a.bin")).unwrap(), b"lora");
+}
+
+#[tokio::test]
+async fn test_mv_onto_an_existing_file() {
+    let server_dir = TempDir::new().unwrap();
+    std::fs::write(server_dir.path().join("a.png"), b"new").unwrap();
+    std::fs::write(server_dir.path().join("b.png"), b"old").unwrap();
+    
+    let mut client = LocalClient::new(server_dir.path().to_path_buf());
+    let source = client.stat("/a.png").await.unwrap();
+    
+    // Refused, leaving both alone, until --force says to replace it
+    let error = move_path(&mut client, &source, "/b.png", false).await.unwrap_err();
+    assert!(error.to_string().contains("already exists"));
+    assert_eq!(std::fs::read(server_dir.path().join("a.png")).unwrap(), b"new");
+    assert_eq!(std::fs::read(server_dir.path().join("b.png")).unwrap(), b"old");
+    
+    move_path(&mut client, &source, "/b.png", true).await.unwrap();
+    assert!(!server_dir.path().join("a.png").exists());
+    assert_eq!(std::fs::read(server_dir.path().join("b.png")).unwrap(), b"new");
+}
+
 #[test]
 fn test_upload_file_validation() {
     // Test that upload validates file existence
@@ -187,6 +282,7 @@ async fn test_sync_directory_download_only() {
                 size: 100,
                 modified: Local::now(),
                 is_dir: false,
+                permissions: None,
             },
             RemoteFile {
                 name: "subdir".to_string(),
@@ -194,6 +290,7 @@ async fn test_sync_directory_download_only() {
                 size: 0,
                 modified: Local::now(),
                 is_dir: true,
+                permissions: None,
             },
         ]));
     
@@ -212,6 +309,7 @@ fn test_config_persistence() {
         password: Some("testpass".to_string()),
         default_protocol: comfy_fs::config::Protocol::Ftp,
         configured: true,
+        ..Config::default()
     };
     
     // Test serialization